@@ -0,0 +1,145 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Streaming `Content-Transfer-Encoding`s for part bodies.
+use std::io::{self, Read};
+
+const BASE64_CHARS: &'static [u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// The number of input bytes encoded on each line; 76 characters, the maximum allowed by RFC 2045.
+const BASE64_LINE_INPUT: usize = 57;
+
+/// The number of lines encoded at once.
+const BASE64_LINES_PER_READ: usize = 64;
+
+/// A `Read` adapter which base64-encodes the bytes read from `inner`, in lines of 76
+/// characters separated by CRLF.
+pub struct Base64Reader<R> {
+    inner: R,
+    encoded: Vec<u8>,
+    pos: usize,
+    lines_written: bool,
+    done: bool,
+}
+
+impl<R: Read> Base64Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Base64Reader {
+            inner: inner,
+            encoded: Vec::new(),
+            pos: 0,
+            lines_written: false,
+            done: false,
+        }
+    }
+
+    fn fill_encoded(&mut self) -> io::Result<()> {
+        let mut input = [0u8; BASE64_LINE_INPUT * BASE64_LINES_PER_READ];
+        let mut len = 0;
+
+        // Only the last line may be shorter than the others.
+        while len < input.len() {
+            match self.inner.read(&mut input[len..]) {
+                Ok(0) => {
+                    self.done = true;
+                    break;
+                },
+                Ok(read) => len += read,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.encoded.clear();
+        self.pos = 0;
+
+        for line in input[..len].chunks(BASE64_LINE_INPUT) {
+            if self.lines_written {
+                self.encoded.extend_from_slice(b"\r\n");
+            }
+
+            encode_base64(line, &mut self.encoded);
+            self.lines_written = true;
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Base64Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.encoded.len() {
+            if self.done {
+                return Ok(0);
+            }
+
+            try!(self.fill_encoded());
+        }
+
+        let read = try!((&self.encoded[self.pos..]).read(buf));
+        self.pos += read;
+        Ok(read)
+    }
+}
+
+/// The length of `len` bytes after encoding with `Base64Reader`.
+pub fn base64_len(len: u64) -> u64 {
+    if len == 0 {
+        return 0;
+    }
+
+    let line_input = BASE64_LINE_INPUT as u64;
+    let lines = (len + line_input - 1) / line_input;
+
+    (len + 2) / 3 * 4 + (lines - 1) * 2
+}
+
+fn encode_base64(input: &[u8], out: &mut Vec<u8>) {
+    for chunk in input.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize;
+
+        out.push(BASE64_CHARS[n >> 18 & 0x3F]);
+        out.push(BASE64_CHARS[n >> 12 & 0x3F]);
+        out.push(if chunk.len() > 1 { BASE64_CHARS[n >> 6 & 0x3F] } else { b'=' });
+        out.push(if chunk.len() > 2 { BASE64_CHARS[n & 0x3F] } else { b'=' });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{base64_len, Base64Reader};
+
+    use std::io::Read;
+
+    fn encode(input: &[u8]) -> String {
+        let mut out = String::new();
+        Base64Reader::new(input).read_to_string(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(encode(b""), "");
+        assert_eq!(encode(b"f"), "Zg==");
+        assert_eq!(encode(b"fo"), "Zm8=");
+        assert_eq!(encode(b"foo"), "Zm9v");
+        assert_eq!(encode(b"foobar"), "Zm9vYmFy");
+
+        let input = vec![0xFF; 200];
+        let encoded = encode(&input);
+        let lines: Vec<_> = encoded.split("\r\n").collect();
+
+        assert_eq!(lines.len(), 4);
+        assert!(lines[..3].iter().all(|line| line.len() == 76));
+        assert_eq!(encoded.len() as u64, base64_len(200));
+
+        for len in 0 .. 200 {
+            assert_eq!(encode(&input[..len]).len() as u64, base64_len(len as u64));
+        }
+    }
+}
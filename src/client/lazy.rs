@@ -4,13 +4,17 @@ use mime::Mime;
 
 use std::borrow::Cow;
 use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File};
 use std::path::Path;
 
 use std::io::prelude::*;
 use std::{fmt, io, mem};
 
-use super::{HttpRequest, HttpStream, MultipartWriter};
+#[cfg(feature = "server")]
+use server::Entries;
+
+use super::encoding::{base64_len, Base64Reader};
+use super::{HttpRequest, HttpStream, MultipartWriter, TransferEncoding};
 
 macro_rules! try_lazy (
     ($field:expr, $try:expr) => (
@@ -27,6 +31,15 @@ macro_rules! try_lazy (
     )
 );
 
+macro_rules! try_opt (
+    ($try:expr) => (
+        match $try {
+            Some(some) => some,
+            None => return None,
+        }
+    )
+);
+
 /// A `LazyError` wrapping `std::io::Error`.
 pub type LazyIoError<'a> = LazyError<'a, io::Error>;
 
@@ -58,6 +71,14 @@ impl<'a, E> LazyError<'a, E> {
             _priv: (),
         }
     }
+
+    fn convert<E_: From<E>>(self) -> LazyError<'a, E_> {
+        LazyError {
+            field_name: self.field_name,
+            error: self.error.into(),
+            _priv: (),
+        }
+    }
 }
 
 /// Take `self.error`, discarding `self.field_name`.
@@ -97,6 +118,41 @@ impl<'a, E: fmt::Display> fmt::Display for LazyError<'a, E> {
     }
 }
 
+/// The progress of a request body being written or read, passed to the callback set with
+/// [`Multipart::on_progress()`](struct.Multipart.html#method.on_progress).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress<'a> {
+    /// The number of bytes of the body written or read so far.
+    pub bytes_sent: u64,
+    /// The total length of the body, if known.
+    pub total: Option<u64>,
+    /// The name of the field currently being sent, or `None` for the closing boundary.
+    pub field_name: Option<&'a str>,
+}
+
+/// Whether a request is sent with a `Content-Length` or with chunked transfer encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferMode {
+    /// Send prepared requests with a content-length if the length of every field is known, or
+    /// chunked if any streams were added. Requests written with `send()` are always chunked. The
+    /// default.
+    Auto,
+    /// Always send requests chunked, even if their length is known.
+    Chunked,
+    /// Always send requests with a content-length, computing it before sending with `send()`.
+    ///
+    /// Requests with fields added with `add_stream()` or compressed with `gzip_part()` cannot be
+    /// sent this way; they can be read into memory first with
+    /// [`prepare_checked()`](struct.Multipart.html#method.prepare_checked).
+    Sized,
+}
+
+impl Default for TransferMode {
+    fn default() -> Self {
+        TransferMode::Auto
+    }
+}
+
 /// A multipart request which writes all fields at once upon being provided an output stream.
 ///
 /// Sacrifices static dispatch for support for dynamic construction. Reusable.
@@ -107,6 +163,10 @@ impl<'a, E: fmt::Display> fmt::Display for LazyError<'a, E> {
 #[derive(Debug, Default)]
 pub struct Multipart<'n, 'd> {
     fields: Vec<Field<'n, 'd>>,
+    boundary: Option<String>,
+    transfer_encoding: TransferEncoding,
+    transfer_mode: TransferMode,
+    progress: Option<ProgressFn<'d>>,
 }
 
 impl<'n, 'd> Multipart<'n, 'd> {
@@ -115,12 +175,55 @@ impl<'n, 'd> Multipart<'n, 'd> {
         Default::default()
     }
 
+    /// Use the given boundary for this request instead of a randomly generated one.
+    ///
+    /// The boundary must not occur in any of the field data;
+    /// [`prepare_checked()`](#method.prepare_checked) can be used to verify this.
+    ///
+    /// ## Panics
+    /// If `boundary` is not a valid boundary per RFC 2046: 1 to 70 characters, consisting of
+    /// ASCII letters and digits, or any of `'()+_,-./:=?` and space (not at the end).
+    pub fn set_boundary<B: Into<String>>(&mut self, boundary: B) -> &mut Self {
+        let boundary = boundary.into();
+        super::assert_valid_boundary(&boundary);
+        self.boundary = Some(boundary);
+        self
+    }
+
+    /// Set the `Content-Transfer-Encoding` of the file and stream fields in this request.
+    ///
+    /// Text fields are always sent as-is.
+    pub fn set_transfer_encoding(&mut self, encoding: TransferEncoding) -> &mut Self {
+        self.transfer_encoding = encoding;
+        self
+    }
+
+    /// Choose whether this request is sent with a content-length or chunked; see
+    /// [`TransferMode`](enum.TransferMode.html).
+    pub fn set_transfer_mode(&mut self, mode: TransferMode) -> &mut Self {
+        self.transfer_mode = mode;
+        self
+    }
+
+    /// Call `callback` as the request body is sent, with the number of bytes sent so far, the
+    /// total length of the body if it is known, and the name of the field being sent.
+    ///
+    /// The callback is used by [`send()`](#method.send) and [`write_to()`](#method.write_to),
+    /// which never know the total length, and moved into the `PreparedFields` returned by the
+    /// `prepare*()` methods, which call it as the body is read.
+    pub fn on_progress<F>(&mut self, callback: F) -> &mut Self where F: FnMut(Progress) + 'd {
+        self.progress = Some(ProgressFn(Box::new(callback)));
+        self
+    }
+
     /// Add a text field to this request. 
     pub fn add_text<N, T>(&mut self, name: N, text: T) -> &mut Self where N: Into<Cow<'n, str>>, T: Into<Cow<'d, str>> {
         self.fields.push(
             Field {
                 name: name.into(),
-                data: Data::Text(text.into())
+                data: Data::Text(text.into()),
+                headers: Vec::new(),
+                gzip: false,
             }
         );
 
@@ -135,14 +238,80 @@ impl<'n, 'd> Multipart<'n, 'd> {
         self.fields.push(
             Field {
                 name: name.into(),
-                data: Data::File(path.into()),
+                data: Data::File(path.into(), None),
+                headers: Vec::new(),
+                gzip: false,
+            }
+        );
+
+        self
+    }
+
+    /// Add a text field to this request for each name-value pair in `fields`, such as a
+    /// `HashMap<String, String>` or a reference to one.
+    pub fn add_texts<I, N, T>(&mut self, fields: I) -> &mut Self where I: IntoIterator<Item = (N, T)>, N: Into<Cow<'n, str>>, T: Into<Cow<'d, str>> {
+        for (name, text) in fields {
+            self.add_text(name, text);
+        }
+
+        self
+    }
+
+    /// Add several files to this request under the same field name, wrapped in a nested
+    /// `multipart/mixed` part with its own boundary, as described in RFC 2388 section 4.6.
+    ///
+    /// Most servers accept multiple files sent as separate fields with the same name, as
+    /// `add_file()` does; use this only for servers which expect the nested layout.
+    ///
+    /// ### Note
+    /// Does not check if the paths exist.
+    pub fn add_files<N, I, P>(&mut self, name: N, paths: I) -> &mut Self where N: Into<Cow<'n, str>>, I: IntoIterator<Item = P>, P: Into<Cow<'d, Path>> {
+        self.fields.push(
+            Field {
+                name: name.into(),
+                data: Data::Files(paths.into_iter().map(Into::into).collect()),
+                headers: Vec::new(),
+                gzip: false,
+            }
+        );
+
+        self
+    }
+
+    /// Add a file field to this request whose contents are `data`, supplying `filename` if
+    /// given, and `mime` as the `Content-Type` if given or `application/octet-stream` if not.
+    ///
+    /// `data` can be a `Vec<u8>` or `&[u8]`, so generated content can be uploaded without
+    /// writing it to a temporary file first. Unlike streams, its length is known.
+    pub fn add_bytes<N, F, D>(&mut self, name: N, filename: Option<F>, mime: Option<Mime>, data: D) -> &mut Self where N: Into<Cow<'n, str>>, F: Into<Cow<'n, str>>, D: Into<Cow<'d, [u8]>> {
+        self.fields.push(
+            Field {
+                name: name.into(),
+                data: Data::Bytes(Bytes {
+                    content_type: mime,
+                    filename: filename.map(|f| f.into()),
+                    data: data.into(),
+                }),
+                headers: Vec::new(),
+                gzip: false,
             }
         );
 
         self
     }
 
-    /// Add a generic stream field to this request,
+    /// Add a file field to this request whose contents are read from `stream`, supplying
+    /// `filename` if given, and `mime` as the `Content-Type` if given or
+    /// `application/octet-stream` if not.
+    ///
+    /// The stream is copied directly into the request body when it is sent or read, without
+    /// buffering its contents first, so any `Read` can be used: a decompressor, a network
+    /// stream, generated data, etc. As a result, the request will not have a content-length.
+    ///
+    /// ##Warning
+    /// The given `Read` **must** be able to read to EOF (end of file/no more data), meaning
+    /// `Read::read()` returns `Ok(0)`. If it never returns EOF it will be read to infinity
+    /// and the request will never be completed.
     pub fn add_stream<N, R, F>(&mut self, name: N, stream: R, filename: Option<F>, mime: Option<Mime>) -> &mut Self where N: Into<Cow<'n, str>>, R: Read + 'd, F: Into<Cow<'n, str>> {
         self.fields.push(
             Field {
@@ -152,23 +321,75 @@ impl<'n, 'd> Multipart<'n, 'd> {
                     filename: filename.map(|f| f.into()),
                     stream: Box::new(stream)
                 }),
+                headers: Vec::new(),
+                gzip: false,
             }
         );
 
         self
     }
 
+    /// Add a header to the part of the field which was added last, such as `Content-ID`, which
+    /// is required by some upload APIs.
+    ///
+    /// `Content-Disposition` and `Content-Type` are always written by this builder, and should
+    /// not be added again.
+    ///
+    /// ## Panics
+    /// If no field has been added yet, if `name` is not a valid header name, or if `value`
+    /// contains a line break.
+    pub fn add_part_header<N, V>(&mut self, name: N, value: V) -> &mut Self where N: Into<Cow<'n, str>>, V: Into<Cow<'d, str>> {
+        let (name, value) = (name.into(), value.into());
+        super::assert_valid_header(&name, &value);
+
+        self.fields.last_mut().expect("`add_part_header()` called before adding a field")
+            .headers.push((name, value));
+
+        self
+    }
+
     /// Convert `req` to `HttpStream`, write out the fields in this request, and finish the
     /// request, returning the response if successful, or the first error encountered.
+    ///
+    /// The content-length is only set if [`TransferMode::Sized`](enum.TransferMode.html) was set.
     pub fn send<R: HttpRequest>(&mut self, req: R) -> Result<<R::Stream as HttpStream>::Response, LazyError<'n, <R::Stream as HttpStream>::Error>> {
-        let (boundary, stream) = try_lazy!(super::open_stream(req, None));
-        let mut writer = MultipartWriter::new(stream, boundary);
+        let boundary = self.boundary();
 
-        for mut field in self.fields.drain(..) {
-            try_lazy!(field.name, field.write_out(&mut writer));
+        let content_len = match self.transfer_mode {
+            TransferMode::Sized => Some(try!(self.sized_len(&boundary).map_err(LazyError::convert))),
+            TransferMode::Auto | TransferMode::Chunked => None,
+        };
+
+        let stream = try_lazy!(super::open_stream(req, &boundary, content_len));
+        let stream = try!(self.write_fields(stream, &boundary).map_err(LazyError::convert));
+        stream.finish().map_err(LazyError::without_field)
+    }
+
+    /// Write out the entire body of this request to `writer`, such as a file, a socket or the
+    /// request body of another HTTP library, and return the boundary which was used.
+    ///
+    /// The boundary must be supplied in the `Content-Type` header of the request as
+    /// `multipart/form-data; boundary={boundary}`. Fields are streamed to `writer` without
+    /// being buffered first.
+    ///
+    /// `writer` can be taken by-value or with an `&mut` borrow.
+    pub fn write_to<W: Write>(&mut self, writer: W) -> Result<String, LazyIoError<'n>> {
+        let boundary = self.boundary();
+        try!(self.write_fields(writer, &boundary));
+        Ok(boundary)
+    }
+
+    fn write_fields<W: Write>(&mut self, stream: W, boundary: &str) -> Result<W, LazyIoError<'n>> {
+        let mut writer = MultipartWriter::new(ProgressWriter::new(stream, &mut self.progress), boundary);
+
+        for field in self.fields.drain(..) {
+            let name = field.name.clone();
+            writer.inner.field_name = Some(name.to_string());
+            try_lazy!(name, field.write_out(&mut writer, self.transfer_encoding));
         }
 
-        try_lazy!(writer.finish()).finish().map_err(LazyError::without_field)
+        writer.inner.field_name = None;
+        Ok(try_lazy!(writer.finish()).inner)
     }
 
     /// Export the multipart data contained in this lazy request as an adaptor which implements `Read`.
@@ -185,40 +406,368 @@ impl<'n, 'd> Multipart<'n, 'd> {
     /// For efficiency, text and file fields smaller than `buffer_threshold` are copied to an in-memory buffer. If `None`,
     /// all fields are copied to memory.
     ///
-    ///
+    /// ##Errors
+    /// If [`TransferMode::Sized`](enum.TransferMode.html) was set and a field was added with
+    /// `add_stream()`, or if a file could not be opened.
     pub fn prepare_threshold(&mut self, buffer_threshold: Option<u64>) -> Result<PreparedFields<'d>, LazyIoError<'n>> {
-        let boundary = super::gen_boundary();
-        PreparedFields::from_fields(&mut self.fields, boundary.into(), buffer_threshold)
+        if self.transfer_mode == TransferMode::Sized {
+            try!(self.check_sized());
+        }
+
+        let boundary = self.boundary();
+        let mut fields = try!(PreparedFields::from_fields(&mut self.fields, boundary, buffer_threshold, self.transfer_encoding));
+        fields.progress = self.progress.take();
+
+        if self.transfer_mode == TransferMode::Chunked {
+            fields.content_len = None;
+        }
+
+        Ok(fields)
+    }
+
+    /// Export the multipart data contained in this lazy request to an adaptor which implements
+    /// `Read`, after reading all fields into memory (including streams) and making sure the
+    /// boundary does not occur in any of them.
+    ///
+    /// If it does, a new random boundary is generated until one does not, even if a boundary was
+    /// set with [`set_boundary()`](#method.set_boundary).
+    pub fn prepare_checked(&mut self) -> Result<PreparedFields<'d>, LazyIoError<'n>> {
+        let mut contents = Vec::with_capacity(self.fields.len());
+
+        let encoding = self.transfer_encoding;
+
+        for mut field in self.fields.drain(..) {
+            field.use_base64(encoding);
+            let content = try_lazy!(field.name, field.data.read_to_vec(encoding, field.gzip));
+            contents.push((field.name, field.headers, content));
+        }
+
+        let mut boundary = self.boundary();
+
+        while contents.iter().any(|&(_, _, ref content)| contains(&content.data, boundary.as_bytes())) {
+            debug!("Boundary {:?} found in field data, generating a new one", boundary);
+            boundary = super::gen_boundary();
+        }
+
+        let mut body = Vec::new();
+        let mut starts = Vec::with_capacity(contents.len() + 1);
+
+        {
+            let mut writer = MultipartWriter::new(&mut body, &*boundary);
+
+            for (name, headers, content) in contents {
+                starts.push((writer.inner.len(), Some(name.to_string())));
+                let filename = content.filename.as_ref().map(|f| &**f);
+                try_lazy!(name, writer.write_field_headers(&name, filename, content.content_type, &headers));
+                try_lazy!(name, writer.inner.write_all(&content.data));
+            }
+        }
+
+        starts.push((body.len(), None));
+        try_lazy!(write!(body, "\r\n--{}--", boundary));
+
+        let content_len = match self.transfer_mode {
+            TransferMode::Chunked => None,
+            TransferMode::Auto | TransferMode::Sized => Some(body.len() as u64),
+        };
+
+        Ok(PreparedFields {
+            content_len: content_len,
+            fields: vec![PreparedField::Contiguous(io::Cursor::new(body))],
+            field_starts: vec![starts],
+            boundary: boundary,
+            bytes_read: 0,
+            field_pos: 0,
+            progress: self.progress.take(),
+        })
+    }
+
+    /// Compute the exact length of the body of this request when it is prepared or sent,
+    /// including boundaries and headers, without reading the contents of any fields. Useful for
+    /// progress indicators or checking quotas before sending, even if the request is sent
+    /// without a content-length.
+    ///
+    /// Returns `None` if any fields were added with `add_stream()`, or the metadata of a file
+    /// could not be read.
+    pub fn content_len_hint(&self) -> Option<u64> {
+        self.content_len_with(&self.boundary())
+    }
+
+    fn content_len_with(&self, boundary: &str) -> Option<u64> {
+        let mut writer = MultipartWriter::new(Vec::new(), boundary);
+        let mut content_len = 0;
+
+        for field in &self.fields {
+            if field.gzip { return None; }
+
+            let base64 = field.is_base64(self.transfer_encoding);
+            let mut headers = field.headers.clone();
+
+            if base64 {
+                headers.push(base64_header());
+            }
+
+            let (filename, content_type, len) = match field.data {
+                Data::Text(ref text) => (None, None, text.len() as u64),
+                Data::File(ref path, ref filename) => {
+                    let (content_type, filename) = file_meta(path, filename);
+                    let len = try_opt!(fs::metadata(path).ok()).len();
+                    (filename, Some(content_type), if base64 { base64_len(len) } else { len })
+                },
+                // The inner boundary is generated again when sending, but has the same length.
+                Data::Files(ref paths) => {
+                    let (content_type, _, len) = try_opt!(mixed_files(paths, self.transfer_encoding).ok());
+                    (None, Some(content_type), len)
+                },
+                Data::Bytes(ref bytes) => {
+                    let len = bytes.data.len() as u64;
+                    (bytes.filename(), Some(bytes.content_type()), if base64 { base64_len(len) } else { len })
+                },
+                Data::Stream(_) => return None,
+            };
+
+            content_len += len;
+
+            // Writing to a `Vec` cannot fail.
+            let _ = writer.write_field_headers(&field.name, filename, content_type, &headers);
+        }
+
+        let closing_len = format!("\r\n--{}--", boundary).len();
+
+        Some(content_len + (writer.inner.len() + closing_len) as u64)
+    }
+
+    /// Return an error naming the first field added with `add_stream()` or compressed, if any.
+    fn check_sized(&self) -> Result<(), LazyIoError<'n>> {
+        let unsized_field = self.fields.iter().find(|field| match field.data {
+            Data::Stream(_) => true,
+            _ => field.gzip,
+        });
+
+        match unsized_field {
+            Some(field) => Err(LazyError::with_field(field.name.clone(), io::Error::new(
+                io::ErrorKind::InvalidInput, "the length of streams and compressed fields is not known for `TransferMode::Sized`"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Compute the content-length for `TransferMode::Sized`.
+    fn sized_len(&self, boundary: &str) -> Result<u64, LazyIoError<'n>> {
+        try!(self.check_sized());
+
+        self.content_len_with(boundary).ok_or_else(|| LazyError::without_field(io::Error::new(
+            io::ErrorKind::Other, "could not read the metadata of a file field"
+        )))
+    }
+
+    fn boundary(&self) -> String {
+        self.boundary.clone().unwrap_or_else(super::gen_boundary)
+    }
+}
+
+#[cfg(feature = "server")]
+impl<'a> Multipart<'a, 'a> {
+    /// #### Feature: `server`
+    /// Create a new request with the text fields and saved files of `entries`, such as to
+    /// forward a request which was read with `server::Multipart::save_all()` to another server.
+    ///
+    /// Files are sent with the filename they were uploaded with, if any, instead of the
+    /// name they were saved under. Fields are added in no particular order.
+    ///
+    /// ### Note
+    /// The files are read when the request is sent or prepared, so `entries` stays borrowed
+    /// until then, which keeps a temporary save directory from being deleted in the meantime.
+    pub fn from_entries(entries: &'a Entries) -> Self {
+        let mut multipart = Multipart::new();
+        multipart.add_texts(&entries.fields);
+
+        for (name, file) in &entries.files {
+            multipart.fields.push(
+                Field {
+                    name: Cow::Borrowed(name),
+                    data: Data::File(Cow::Borrowed(&file.path), file.filename.as_ref().map(|f| Cow::Borrowed(&**f))),
+                    headers: Vec::new(),
+                    gzip: false,
+                }
+            );
+        }
+
+        multipart
     }
 }
 
 const DEFAULT_BUFFER_THRESHOLD: u64 = 8 * 1024;
 
+struct ProgressFn<'d>(Box<FnMut(Progress) + 'd>);
+
+impl<'d> fmt::Debug for ProgressFn<'d> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ProgressFn(Box<FnMut(Progress)>)")
+    }
+}
+
+/// A `Write` adapter which reports the bytes written to the progress callback, if set.
+struct ProgressWriter<'a, 'd: 'a, W> {
+    inner: W,
+    bytes_sent: u64,
+    field_name: Option<String>,
+    progress: &'a mut Option<ProgressFn<'d>>,
+}
+
+impl<'a, 'd, W> ProgressWriter<'a, 'd, W> {
+    fn new(inner: W, progress: &'a mut Option<ProgressFn<'d>>) -> Self {
+        ProgressWriter {
+            inner: inner,
+            bytes_sent: 0,
+            field_name: None,
+            progress: progress,
+        }
+    }
+}
+
+impl<'a, 'd, W: Write> Write for ProgressWriter<'a, 'd, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = try!(self.inner.write(buf));
+        self.bytes_sent += written as u64;
+
+        if let Some(ref mut progress) = *self.progress {
+            (progress.0)(Progress {
+                bytes_sent: self.bytes_sent,
+                total: None,
+                field_name: self.field_name.as_ref().map(|name| &**name),
+            });
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 #[derive(Debug)]
 struct Field<'n, 'd> {
     name: Cow<'n, str>,
     data: Data<'n, 'd>,
+    headers: Vec<(Cow<'n, str>, Cow<'d, str>)>,
+    /// Set by `gzip_part()`.
+    gzip: bool,
 }
 
 impl<'n, 'd> Field<'n, 'd> {
-    fn write_out<W: Write>(&mut self, writer: &mut MultipartWriter<W>) -> io::Result<()> {
+    /// Returns `true` if the contents of this field should be base64-encoded.
+    fn is_base64(&self, encoding: TransferEncoding) -> bool {
         match self.data {
-            Data::Text(ref text) => writer.write_text(&self.name, text),
-            Data::File(ref path) => writer.write_file(&self.name, path),
-            Data::Stream(ref mut stream) => 
-                writer.write_stream(
-                    &mut stream.stream, 
-                    &self.name, 
-                    stream.filename.as_ref().map(|f| &**f),
-                    stream.content_type.clone(),
-                ),
+            // The files in a `multipart/mixed` part are encoded individually.
+            Data::Text(_) | Data::Files(_) => false,
+            Data::File(..) | Data::Bytes(_) | Data::Stream(_) => encoding == TransferEncoding::Base64,
         }
     }
+
+    /// Returns `true` if the contents of this field should be base64-encoded, after adding the
+    /// `Content-Transfer-Encoding` header.
+    fn use_base64(&mut self, encoding: TransferEncoding) -> bool {
+        let use_base64 = self.is_base64(encoding);
+
+        if use_base64 {
+            self.headers.push(base64_header());
+        }
+
+        use_base64
+    }
+
+    fn write_out<W: Write>(mut self, writer: &mut MultipartWriter<W>, encoding: TransferEncoding) -> io::Result<()> {
+        self.use_base64(encoding);
+        let Content { filename, content_type, mut data } = try!(self.data.open(encoding, self.gzip));
+
+        chain_result! {
+            writer.write_field_headers(&self.name, filename.as_ref().map(|f| &**f), content_type, &self.headers),
+            io::copy(&mut data, &mut writer.inner),
+            Ok(())
+        }
+    }
+}
+
+fn base64_header<'n, 'd>() -> (Cow<'n, str>, Cow<'d, str>) {
+    (Cow::Borrowed("Content-Transfer-Encoding"), Cow::Borrowed("base64"))
+}
+
+#[cfg(feature = "flate2")]
+use self::flate2::compress;
+
+#[cfg(not(feature = "flate2"))]
+fn compress<'a>(reader: Box<Read + 'a>, _gzip: bool) -> Box<Read + 'a> {
+    reader
+}
+
+/// Wrap `reader` in a base64 encoder if `base64` is set.
+fn encode<'a, R: Read + 'a>(reader: R, base64: bool) -> Box<Read + 'a> {
+    if base64 {
+        Box::new(Base64Reader::new(reader))
+    } else {
+        Box::new(reader)
+    }
+}
+
+/// Open the files in `paths` as the body of a `multipart/mixed` part with a new boundary,
+/// returning its `Content-Type`, its contents and their length.
+fn mixed_files(paths: &[Cow<Path>], encoding: TransferEncoding) -> io::Result<(Mime, Box<Read>, u64)> {
+    let boundary = super::gen_boundary();
+    let base64 = encoding == TransferEncoding::Base64;
+
+    let mut body: Box<Read> = Box::new(io::empty());
+    let mut len = 0;
+
+    for path in paths {
+        let (content_type, filename) = super::mime_filename(path);
+        let file = try!(File::open(path));
+        let file_len = try!(file.metadata()).len();
+
+        let mut headers = Vec::new();
+        try!(write!(headers, "\r\n--{}\r\nContent-Disposition: file", boundary));
+
+        if let Some(filename) = filename {
+            try!(super::write_filename(&mut headers, filename));
+        }
+
+        try!(write!(headers, "\r\nContent-Type: {}", content_type));
+
+        if base64 {
+            headers.extend_from_slice(b"\r\nContent-Transfer-Encoding: base64");
+        }
+
+        headers.extend_from_slice(b"\r\n\r\n");
+
+        len += headers.len() as u64 + if base64 { base64_len(file_len) } else { file_len };
+        body = Box::new(body.chain(io::Cursor::new(headers)).chain(encode(file, base64)));
+    }
+
+    let end = format!("\r\n--{}--", boundary).into_bytes();
+    len += end.len() as u64;
+
+    let content_type = format!("multipart/mixed; boundary={}", boundary).parse()
+        .expect("generated boundaries are valid `Content-Type` parameters");
+
+    Ok((content_type, Box::new(body.chain(io::Cursor::new(end))), len))
+}
+
+/// The `Content-Type` and filename of a file field, guessed from `filename` if given, or from
+/// `path` if not.
+fn file_meta<'a>(path: &'a Path, filename: &'a Option<Cow<str>>) -> (Mime, Option<&'a str>) {
+    match *filename {
+        Some(ref filename) => (::mime_guess::guess_mime_type(&**filename), Some(filename)),
+        None => super::mime_filename(path),
+    }
 }
 
 enum Data<'n, 'd> {
     Text(Cow<'d, str>),
-    File(Cow<'d, Path>),
+    /// A file and the filename to send instead of its own, if given.
+    File(Cow<'d, Path>, Option<Cow<'n, str>>),
+    Files(Vec<Cow<'d, Path>>),
+    Bytes(Bytes<'n, 'd>),
     Stream(Stream<'n, 'd>),
 }
 
@@ -226,12 +775,92 @@ impl<'n, 'd> fmt::Debug for Data<'n, 'd> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {        
         match *self { 
             Data::Text(ref text) => write!(f, "Data::Text({:?})", text),
-            Data::File(ref path) => write!(f, "Data::File({:?})", path),
+            Data::File(ref path, ref filename) => write!(f, "Data::File({:?}, {:?})", path, filename),
+            Data::Files(ref paths) => write!(f, "Data::Files({:?})", paths),
+            Data::Bytes(ref bytes) => write!(f, "Data::Bytes({} bytes)", bytes.data.len()),
             Data::Stream(_) => f.write_str("Data::Stream(Box<Read>"),
         }
     }
 }
 
+impl<'n, 'd> Data<'n, 'd> {
+    /// Open the contents of this field for reading, compressed if `gzip` is set and then
+    /// encoded according to `encoding`.
+    fn open(self, encoding: TransferEncoding, gzip: bool) -> io::Result<Content<Box<Read + 'd>>> {
+        let base64 = encoding == TransferEncoding::Base64;
+
+        let (filename, content_type, data, base64): (_, _, Box<Read + 'd>, _) = match self {
+            Data::Text(text) => (None, None, Box::new(io::Cursor::new(CowStrAsRef(text))), false),
+            Data::File(path, filename) => {
+                let (content_type, filename) = file_meta(&path, &filename);
+                let file = try!(File::open(&path));
+                (filename.map(Into::into), Some(content_type), Box::new(file), base64)
+            },
+            // The files are already encoded.
+            Data::Files(paths) => {
+                let (content_type, body, _) = try!(mixed_files(&paths, encoding));
+                (None, Some(content_type), body, false)
+            },
+            Data::Bytes(bytes) =>
+                (bytes.filename().map(Into::into), Some(bytes.content_type()), Box::new(io::Cursor::new(bytes.data)), base64),
+            Data::Stream(stream) => (
+                stream.filename.map(Cow::into_owned),
+                // This is necessary to make sure it is interpreted as a file on the server end.
+                Some(stream.content_type.unwrap_or_else(::mime_guess::octet_stream)),
+                stream.stream,
+                base64,
+            ),
+        };
+
+        Ok(Content {
+            filename: filename,
+            content_type: content_type,
+            data: encode(compress(data, gzip), base64),
+        })
+    }
+
+    /// Read the contents of this field into memory.
+    fn read_to_vec(self, encoding: TransferEncoding, gzip: bool) -> io::Result<Content<Vec<u8>>> {
+        let mut content = try!(self.open(encoding, gzip));
+        let mut data = Vec::new();
+        try!(content.data.read_to_end(&mut data));
+
+        Ok(Content {
+            filename: content.filename,
+            content_type: content.content_type,
+            data: data,
+        })
+    }
+}
+
+/// The contents of a field, which are either a reader or were read into memory.
+struct Content<D> {
+    filename: Option<String>,
+    content_type: Option<Mime>,
+    data: D,
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+struct Bytes<'n, 'd> {
+    filename: Option<Cow<'n, str>>,
+    content_type: Option<Mime>,
+    data: Cow<'d, [u8]>,
+}
+
+impl<'n, 'd> Bytes<'n, 'd> {
+    fn filename(&self) -> Option<&str> {
+        self.filename.as_ref().map(|f| &**f)
+    }
+
+    fn content_type(&self) -> Mime {
+        // This is necessary to make sure it is interpreted as a file on the server end.
+        self.content_type.clone().unwrap_or_else(::mime_guess::octet_stream)
+    }
+}
+
 struct Stream<'n, 'd> {
     filename: Option<Cow<'n, str>>,
     content_type: Option<Mime>,
@@ -244,21 +873,29 @@ pub struct PreparedFields<'d> {
     // NOTE: the order of these fields have been reversed so fields can be popped one-by-one from
     // the end.
     fields: Vec<PreparedField<'d>>,
-    #[cfg_attr(not(feature = "hyper"), allow(dead_code))]
+    /// The offsets in each of `fields` where a field (or the closing boundary) starts.
+    field_starts: Vec<Vec<(usize, Option<String>)>>,
     boundary: String,
     content_len: Option<u64>,
+    bytes_read: u64,
+    /// The number of bytes read from the last of `fields`.
+    field_pos: usize,
+    progress: Option<ProgressFn<'d>>,
 }
 
 impl<'d> PreparedFields<'d> {
-    fn from_fields<'n>(fields: &mut Vec<Field<'n, 'd>>, boundary: String, buffer_threshold: Option<u64>) -> Result<Self, LazyIoError<'n>> {
+    fn from_fields<'n>(fields: &mut Vec<Field<'n, 'd>>, boundary: String, buffer_threshold: Option<u64>,
+                       encoding: TransferEncoding) -> Result<Self, LazyIoError<'n>> {
         let buffer_threshold = buffer_threshold.unwrap_or(::std::u64::MAX);
 
         let mut prep_fields = Vec::with_capacity(fields.len());
 
-        // We reverse so we can pop efficiently from the end
-        let mut fields = fields.drain(..).rev().peekable();
+        let mut fields = fields.drain(..).peekable();
+
+        let mut field_starts = Vec::with_capacity(fields.len());
 
         let mut contiguous = Vec::new();
+        let mut starts = Vec::new();
         let mut remainder: Option<Box<Read>> = None;
         let mut use_content_len = true;
         let mut content_len = 0;
@@ -267,32 +904,73 @@ impl<'d> PreparedFields<'d> {
             {
                 let mut writer = MultipartWriter::new(&mut contiguous, &*boundary);
 
-                while let Some(field) = fields.next() {
+                while let Some(mut field) = fields.next() {
+                    starts.push((writer.inner.len(), Some(field.name.to_string())));
+                    let base64 = field.use_base64(encoding);
+
+                    // The compressed length is not known in advance.
+                    if field.gzip {
+                        let content = try_lazy!(field.name, field.data.open(encoding, true));
+                        let filename = content.filename.as_ref().map(|f| &**f);
+                        try_lazy!(field.name, writer.write_field_headers(&field.name, filename, content.content_type, &field.headers));
+                        remainder = Some(content.data);
+                        use_content_len = false;
+                        break;
+                    }
+
                     match field.data {
                         Data::Text(text) => if text.len() as u64 <= buffer_threshold  {
-                            try_lazy!(field.name, writer.write_text(&field.name, &*text));
+                            try_lazy!(field.name, writer.write_text(&field.name, &*text, &field.headers));
                         } else {
-                            try_lazy!(field.name, writer.write_field_headers(&field.name, None, None));
+                            try_lazy!(field.name, writer.write_field_headers(&field.name, None, None, &field.headers));
                             content_len += text.len() as u64;
                             remainder = Some(Box::new(io::Cursor::new(CowStrAsRef(text))));
                         },
-                        Data::File(path) => {
-                            let (content_type, filename) = super::mime_filename(&*path);
-                            let mut file = try_lazy!(field.name, File::open(&*path));
+                        Data::File(path, filename) => {
+                            let (content_type, filename) = file_meta(&path, &filename);
+                            let file = try_lazy!(field.name, File::open(&*path));
                             let len = try_lazy!(field.name, file.metadata()).len();
+                            let len = if base64 { base64_len(len) } else { len };
 
                             if len <= buffer_threshold {
-                                try_lazy!(field.name, writer.write_stream(&mut file, &field.name, filename, Some(content_type)));
+                                try_lazy!(field.name, writer.write_stream(&mut encode(file, base64), &field.name, filename, Some(content_type), &field.headers));
                             } else {
-                                try_lazy!(field.name, writer.write_field_headers(&field.name, filename, Some(content_type)));
-                                remainder = Some(Box::new(file));
+                                try_lazy!(field.name, writer.write_field_headers(&field.name, filename, Some(content_type), &field.headers));
+                                remainder = Some(encode(file, base64));
+                                content_len += len;
+                            }
+                        },
+                        Data::Files(paths) => {
+                            let (content_type, mut body, len) = try_lazy!(field.name, mixed_files(&paths, encoding));
+
+                            if len <= buffer_threshold {
+                                try_lazy!(field.name, writer.write_stream(&mut body, &field.name, None, Some(content_type), &field.headers));
+                            } else {
+                                try_lazy!(field.name, writer.write_field_headers(&field.name, None, Some(content_type), &field.headers));
+                                remainder = Some(body);
+                                content_len += len;
+                            }
+                        },
+                        Data::Bytes(bytes) => {
+                            let len = bytes.data.len() as u64;
+                            let len = if base64 { base64_len(len) } else { len };
+                            let content_type = bytes.content_type();
+                            let filename = bytes.filename.as_ref().map(|f| &**f);
+
+                            if len <= buffer_threshold {
+                                try_lazy!(field.name, writer.write_stream(&mut encode(&*bytes.data, base64), &field.name, filename, Some(content_type), &field.headers));
+                            } else {
+                                try_lazy!(field.name, writer.write_field_headers(&field.name, filename, Some(content_type), &field.headers));
+                                remainder = Some(encode(io::Cursor::new(bytes.data), base64));
                                 content_len += len;
                             }
                         },
                         Data::Stream(stream) => {
                             let filename = stream.filename.as_ref().map(|f| &**f);
-                            try_lazy!(field.name, writer.write_field_headers(&field.name, filename, stream.content_type));
-                            remainder = Some(stream.stream);
+                            // This is necessary to make sure it is interpreted as a file on the server end.
+                            let content_type = stream.content_type.unwrap_or_else(::mime_guess::octet_stream);
+                            try_lazy!(field.name, writer.write_field_headers(&field.name, filename, Some(content_type), &field.headers));
+                            remainder = Some(encode(stream.stream, base64));
                             use_content_len = false;
                         },
                     }
@@ -302,6 +980,7 @@ impl<'d> PreparedFields<'d> {
             }
 
             content_len += contiguous.len() as u64;
+            field_starts.push(mem::replace(&mut starts, Vec::new()));
 
             let contiguous = io::Cursor::new(mem::replace(&mut contiguous, Vec::new()));
 
@@ -316,9 +995,12 @@ impl<'d> PreparedFields<'d> {
         let mut end_written = false;
         
         if let Some(&mut PreparedField::Contiguous(ref mut vec)) = prep_fields.last_mut() {
-            let start_len = vec.get_ref().len();
+            // Append to the buffer itself; writing to the cursor would overwrite its start.
+            let vec = vec.get_mut();
+            let start_len = vec.len();
+            field_starts.last_mut().map(|starts| starts.push((start_len, None)));
             try_lazy!(write!(vec, "\r\n--{}--", boundary));
-            content_len += (vec.get_ref().len() - start_len) as u64;
+            content_len += (vec.len() - start_len) as u64;
             end_written = true;
         } 
         
@@ -326,12 +1008,21 @@ impl<'d> PreparedFields<'d> {
             let vec = format!("\r\n--{}--", boundary).into_bytes();
             content_len += vec.len() as u64;
             prep_fields.push(PreparedField::Contiguous(io::Cursor::new(vec)));
+            field_starts.push(vec![(0, None)]);
         }
 
+        // We reverse so we can pop efficiently from the end
+        prep_fields.reverse();
+        field_starts.reverse();
+
         Ok(PreparedFields {
             fields: prep_fields,
+            field_starts: field_starts,
             boundary: boundary,
             content_len: if use_content_len { Some(content_len) } else { None },
+            bytes_read: 0,
+            field_pos: 0,
+            progress: None,
         })
     }
 
@@ -341,28 +1032,54 @@ impl<'d> PreparedFields<'d> {
         self.content_len
     }
 
-    #[cfg_attr(not(feature = "hyper"), allow(dead_code))]
-    fn boundary(&self) -> &str {
+    /// Get the boundary string of this request body, which must be supplied in the
+    /// `Content-Type` header as `multipart/form-data; boundary={boundary}`.
+    pub fn boundary(&self) -> &str {
         &self.boundary
-    } 
+    }
 }
 
 impl<'d> Read for PreparedFields<'d> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         if buf.is_empty() { return Ok(0) }
-        if self.fields.is_empty() { return Ok(0) }
 
-        let bytes_read = if let Some(mut curr) = self.fields.last_mut() {
-            try!(curr.read(buf))
-        } else {
-            0
-        };
+        loop {
+            let bytes_read = match self.fields.last_mut() {
+                Some(curr) => try!(curr.read(buf)),
+                None => return Ok(0),
+            };
+
+            // Returning `Ok(0)` here would signal EOF, so move on to the next field instead.
+            if bytes_read != 0 {
+                self.report_progress(bytes_read);
+                return Ok(bytes_read);
+            }
 
-        if bytes_read == 0 {
             let _ = self.fields.pop();
+            let _ = self.field_starts.pop();
+            self.field_pos = 0;
         }
+    }
+}
+
+impl<'d> PreparedFields<'d> {
+    fn report_progress(&mut self, bytes_read: usize) {
+        self.bytes_read += bytes_read as u64;
+        self.field_pos += bytes_read;
 
-        Ok(bytes_read)        
+        if let Some(ref mut progress) = self.progress {
+            let field_pos = self.field_pos;
+            // The field which the last byte read belongs to.
+            let field_name = self.field_starts.last()
+                .and_then(|starts| starts.iter().rev().find(|&&(start, _)| start < field_pos))
+                .and_then(|&(_, ref name)| name.as_ref().map(|name| &**name));
+
+            (progress.0)(Progress {
+                bytes_sent: self.bytes_read,
+                total: self.content_len,
+                field_name: field_name,
+            });
+        }
     }
 }
 
@@ -434,8 +1151,8 @@ mod hyper {
         /// Convert `self` to `hyper::client::Body`.
         pub fn to_body<'b>(&'b mut self) -> Body<'b> {
             use super::PreparedField;
-            // We have a single contiguous body, provide it directly
-            if self.fields.len() == 1 {
+            // We have a single contiguous, sized body, provide it directly, unless progress must be reported
+            if self.fields.len() == 1 && self.content_len.is_some() && self.progress.is_none() {
                 if let PreparedField::Contiguous(ref body) = self.fields[0] {
                     return Body::BufBody(body.get_ref(), body.get_ref().len());
                 } else {
@@ -451,3 +1168,602 @@ mod hyper {
         }
     }
 }
+
+#[cfg(feature = "curl")]
+mod curl {
+    use curl::easy::{Easy, List, ReadError};
+    use curl::Error as CurlError;
+
+    use std::io::Read;
+
+    impl<'d> super::PreparedFields<'d> {
+        /// #### Feature: `curl`
+        /// Get the headers required to send this request body with libcurl: `Content-Type` with
+        /// the boundary, plus `Transfer-Encoding: chunked` if the length of the body is not known.
+        pub fn curl_headers(&self) -> Result<List, CurlError> {
+            let mut headers = List::new();
+            try!(headers.append(&format!("Content-Type: multipart/form-data; boundary={}", self.boundary)));
+
+            if self.content_len.is_none() {
+                try!(headers.append("Transfer-Encoding: chunked"));
+            }
+
+            Ok(headers)
+        }
+
+        /// #### Feature: `curl`
+        /// Configure `easy` to POST this request body: sets the request method, the body size
+        /// if known, and the headers from [`curl_headers()`](#method.curl_headers), replacing any
+        /// custom headers set previously.
+        ///
+        /// The body itself is supplied by calling [`curl_read()`](#method.curl_read) from the
+        /// read callback of the transfer:
+        ///
+        /// ```no_run
+        /// # extern crate curl;
+        /// # extern crate multipart;
+        /// use curl::easy::Easy;
+        /// use multipart::client::lazy::Multipart;
+        ///
+        /// # fn main() {
+        /// let mut fields = Multipart::new()
+        ///     .add_text("hello", "world")
+        ///     .prepare().unwrap();
+        ///
+        /// let mut easy = Easy::new();
+        /// easy.url("http://localhost/upload").unwrap();
+        /// fields.curl_setup(&mut easy).unwrap();
+        ///
+        /// let mut transfer = easy.transfer();
+        /// transfer.read_function(|buf| fields.curl_read(buf)).unwrap();
+        /// transfer.perform().unwrap();
+        /// # }
+        /// ```
+        pub fn curl_setup(&self, easy: &mut Easy) -> Result<(), CurlError> {
+            try!(easy.post(true));
+
+            if let Some(content_len) = self.content_len {
+                try!(easy.post_field_size(content_len));
+            }
+
+            easy.http_headers(try!(self.curl_headers()))
+        }
+
+        /// #### Feature: `curl`
+        /// Read the next chunk of the request body into `buf`, for use in a libcurl read callback.
+        ///
+        /// I/O errors are logged and abort the transfer.
+        pub fn curl_read(&mut self, buf: &mut [u8]) -> Result<usize, ReadError> {
+            self.read(buf).map_err(|err| {
+                error!("Error reading multipart request body: {}", err);
+                ReadError::Abort
+            })
+        }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+mod reqwest {
+    use reqwest::blocking;
+    use reqwest::header::CONTENT_TYPE;
+    use reqwest::{Body, RequestBuilder};
+
+    use std::io::{self, Read};
+
+    use super::{LazyError, LazyIoError};
+
+    impl<'n, 'd> super::Multipart<'n, 'd> {
+        /// #### Feature: `reqwest`
+        /// Set the body of the given asynchronous `reqwest::RequestBuilder` to the fields in this
+        /// request, and its `Content-Type` header to `multipart/form-data` with the boundary.
+        ///
+        /// Because `reqwest` requires request bodies to be `Send + 'static`, the fields are
+        /// read into memory first.
+        pub fn reqwest_request(&mut self, builder: RequestBuilder) -> Result<RequestBuilder, LazyIoError<'n>> {
+            let mut fields = try!(self.prepare());
+            let body = try_lazy!(fields.to_reqwest_body());
+
+            Ok(builder.header(CONTENT_TYPE, content_type(fields.boundary())).body(body))
+        }
+
+        /// #### Feature: `reqwest`
+        /// The same as [`reqwest_request()`](#method.reqwest_request), but for the blocking
+        /// `reqwest::blocking::RequestBuilder`.
+        pub fn reqwest_blocking_request(&mut self, builder: blocking::RequestBuilder)
+        -> Result<blocking::RequestBuilder, LazyIoError<'n>> {
+            let mut fields = try!(self.prepare());
+            let body = try_lazy!(fields.to_reqwest_blocking_body());
+
+            Ok(builder.header(CONTENT_TYPE, content_type(fields.boundary())).body(body))
+        }
+    }
+
+    impl<'d> super::PreparedFields<'d> {
+        /// #### Feature: `reqwest`
+        /// Read the entire request body into an asynchronous `reqwest::Body`.
+        ///
+        /// The `Content-Type` header must be set to `multipart/form-data` with the boundary
+        /// from [`boundary()`](#method.boundary).
+        pub fn to_reqwest_body(&mut self) -> io::Result<Body> {
+            self.read_to_vec().map(Body::from)
+        }
+
+        /// #### Feature: `reqwest`
+        /// Read the entire request body into a `reqwest::blocking::Body`.
+        ///
+        /// The `Content-Type` header must be set to `multipart/form-data` with the boundary
+        /// from [`boundary()`](#method.boundary).
+        pub fn to_reqwest_blocking_body(&mut self) -> io::Result<blocking::Body> {
+            self.read_to_vec().map(blocking::Body::from)
+        }
+
+        fn read_to_vec(&mut self) -> io::Result<Vec<u8>> {
+            let mut buf = Vec::with_capacity(self.content_len.unwrap_or(0) as usize);
+            try!(self.read_to_end(&mut buf));
+            Ok(buf)
+        }
+    }
+
+    fn content_type(boundary: &str) -> String {
+        format!("multipart/form-data; boundary={}", boundary)
+    }
+}
+
+#[cfg(feature = "ureq")]
+mod ureq {
+    use ureq::{Error, Request, Response};
+
+    use super::LazyError;
+
+    impl<'n, 'd> super::Multipart<'n, 'd> {
+        /// #### Feature: `ureq`
+        /// Send the fields in this request as the body of the given `ureq::Request`, setting the
+        /// `Content-Type` header, and the `Content-Length` header if applicable (all added fields
+        /// were text or files, i.e. no streams).
+        ///
+        /// The body is streamed to the server; it is not read into memory first.
+        pub fn ureq_request(&mut self, req: Request) -> Result<Response, LazyError<'n, Error>> {
+            let fields = match self.prepare() {
+                Ok(fields) => fields,
+                Err(err) => return Err(LazyError {
+                    field_name: err.field_name,
+                    error: err.error.into(),
+                    _priv: (),
+                }),
+            };
+
+            let mut req = req.set("Content-Type", &format!("multipart/form-data; boundary={}", fields.boundary()));
+
+            if let Some(content_len) = fields.content_len() {
+                req = req.set("Content-Length", &content_len.to_string());
+            }
+
+            req.send(fields).map_err(LazyError::without_field)
+        }
+    }
+}
+
+#[cfg(feature = "wasm-bindgen_")]
+mod wasm {
+    use js_sys::{Array, Uint8Array};
+    use wasm_bindgen::JsValue;
+    use web_sys::{Blob, BlobPropertyBag};
+
+    use std::io::{self, Read};
+
+    use super::{LazyIoError, PreparedFields};
+
+    impl<'n, 'd> super::Multipart<'n, 'd> {
+        /// #### Feature: `wasm-bindgen_`
+        /// Read the fields in this request into a `Blob` whose type is the `Content-Type` of the
+        /// request, including the boundary, to be passed as the body of `fetch()`.
+        ///
+        /// The browser sets the `Content-Type` header of the request from the type of the `Blob`.
+        pub fn to_blob(&mut self) -> Result<Blob, LazyIoError<'n>> {
+            let mut fields = try!(self.prepare());
+            fields.to_blob().map_err(LazyIoError::without_field)
+        }
+    }
+
+    impl<'d> PreparedFields<'d> {
+        /// #### Feature: `wasm-bindgen_`
+        /// Read the rest of the request body into a `Uint8Array`.
+        pub fn to_uint8_array(&mut self) -> io::Result<Uint8Array> {
+            let mut body = Vec::new();
+            try!(self.read_to_end(&mut body));
+            Ok(Uint8Array::from(&body[..]))
+        }
+
+        /// #### Feature: `wasm-bindgen_`
+        /// Read the rest of the request body into a `Blob` whose type is
+        /// `multipart/form-data` with the boundary of these fields.
+        pub fn to_blob(&mut self) -> io::Result<Blob> {
+            let parts = Array::of1(&try!(self.to_uint8_array()).into());
+
+            let options = BlobPropertyBag::new();
+            options.set_type(&format!("multipart/form-data; boundary={}", self.boundary()));
+
+            Blob::new_with_u8_array_sequence_and_options(&parts, &options).map_err(js_error)
+        }
+    }
+
+    fn js_error(err: JsValue) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, format!("{:?}", err))
+    }
+}
+
+#[cfg(feature = "flate2")]
+mod flate2 {
+    use flate2::read::GzEncoder;
+    use flate2::Compression;
+
+    use std::borrow::Cow;
+    use std::io::Read;
+
+    impl<'n, 'd> super::Multipart<'n, 'd> {
+        /// #### Feature: `flate2`
+        /// Compress the contents of the field which was added last with gzip as it is sent,
+        /// and add the header `Content-Encoding: gzip` to its part. Useful for large text or
+        /// JSON payloads, if the server supports it.
+        ///
+        /// As the compressed length is not known in advance, the request will not have a
+        /// content-length unless it is prepared with
+        /// [`prepare_checked()`](#method.prepare_checked).
+        ///
+        /// ## Panics
+        /// If no field has been added yet.
+        pub fn gzip_part(&mut self) -> &mut Self {
+            {
+                let field = self.fields.last_mut().expect("`gzip_part()` called before adding a field");
+
+                if !field.gzip {
+                    field.gzip = true;
+                    field.headers.push((Cow::Borrowed("Content-Encoding"), Cow::Borrowed("gzip")));
+                }
+            }
+
+            self
+        }
+    }
+
+    /// Wrap `reader` in a gzip encoder if `gzip` is set.
+    pub fn compress<'a>(reader: Box<Read + 'a>, gzip: bool) -> Box<Read + 'a> {
+        if gzip {
+            Box::new(GzEncoder::new(reader, Compression::default()))
+        } else {
+            reader
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mock::ClientRequest;
+
+    use tempdir::TempDir;
+
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+
+    use client::TransferEncoding;
+
+    use super::{Multipart, TransferMode};
+
+    const BOUNDARY: &'static str = "test-boundary";
+
+    /// Create a temporary directory with the files `a.txt` and `ünïcödé.txt`.
+    fn test_files() -> (TempDir, PathBuf, PathBuf) {
+        let dir = TempDir::new("multipart-test").unwrap();
+        let a = dir.path().join("a.txt");
+        let unicode = dir.path().join("ünïcödé.txt");
+
+        File::create(&a).unwrap().write_all(b"first file").unwrap();
+        File::create(&unicode).unwrap().write_all(&[0xFF; 100]).unwrap();
+
+        (dir, a, unicode)
+    }
+
+    fn test_multipart<'a>(a: &'a PathBuf, unicode: &'a PathBuf, encoding: TransferEncoding) -> Multipart<'a, 'a> {
+        let mut multipart = Multipart::new();
+
+        multipart.set_boundary(BOUNDARY)
+            .set_transfer_encoding(encoding)
+            .add_text("text", "value")
+            .add_part_header("Content-ID", "<text>")
+            .add_file("file", &**unicode)
+            .add_files("files", vec![&**a, &**unicode])
+            .add_bytes("bytes", Some("dätä.bin"), None, &b"some bytes"[..]);
+
+        multipart
+    }
+
+    #[test]
+    fn test_content_len_hint() {
+        let (_dir, a, unicode) = test_files();
+
+        for &encoding in &[TransferEncoding::Binary, TransferEncoding::Base64] {
+            let hint = test_multipart(&a, &unicode, encoding).content_len_hint().unwrap();
+
+            let mut body = Vec::new();
+            test_multipart(&a, &unicode, encoding).write_to(&mut body).unwrap();
+            assert_eq!(hint, body.len() as u64, "{:?}", encoding);
+
+            let buf = test_multipart(&a, &unicode, encoding)
+                .set_transfer_mode(TransferMode::Sized)
+                .send(ClientRequest::default())
+                .unwrap();
+            assert_eq!(buf.content_len, Some(buf.buf.len() as u64), "{:?}", encoding);
+            assert_eq!(buf.buf.len(), body.len());
+        }
+
+        let mut multipart = Multipart::new();
+        multipart.add_stream("stream", &b"data"[..], None::<&str>, None);
+        assert_eq!(multipart.content_len_hint(), None);
+    }
+
+    #[test]
+    fn test_progress() {
+        let (_dir, a, unicode) = test_files();
+        let mut sent = 0;
+        let mut body = Vec::new();
+
+        test_multipart(&a, &unicode, TransferEncoding::Binary)
+            .on_progress(|progress| sent = progress.bytes_sent)
+            .write_to(&mut body)
+            .unwrap();
+
+        assert_eq!(sent, body.len() as u64);
+
+        let mut total = None;
+        let mut prepared = test_multipart(&a, &unicode, TransferEncoding::Binary)
+            .on_progress(|progress| total = progress.total)
+            .prepare()
+            .unwrap();
+
+        body.clear();
+        prepared.read_to_end(&mut body).unwrap();
+        drop(prepared);
+
+        assert_eq!(total, Some(body.len() as u64));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_boundary() {
+        let _ = Multipart::new().set_boundary("invalid\r\nboundary").send(ClientRequest::default());
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_round_trip() {
+        use server::Multipart as Server;
+
+        let (_dir, a, unicode) = test_files();
+        let buf = test_multipart(&a, &unicode, TransferEncoding::Binary)
+            .send(ClientRequest::default())
+            .unwrap();
+
+        let mut server = Server::from_request(buf.for_server()).ok().unwrap();
+
+        {
+            let part = server.read_part().unwrap().unwrap();
+            assert_eq!(part.header("Content-Disposition"), Some("form-data; name=\"text\""));
+            assert_eq!(part.header("Content-ID"), Some("<text>"));
+        }
+
+        {
+            let part = server.read_part().unwrap().unwrap();
+            assert_eq!(part.header("Content-Disposition"),
+                       Some("form-data; name=\"file\"; filename=\"_n_c_d_.txt\"; \
+                             filename*=UTF-8''%C3%BCn%C3%AFc%C3%B6d%C3%A9.txt"));
+        }
+
+        {
+            let part = server.read_part().unwrap().unwrap();
+            assert!(part.is_multipart());
+
+            let mut files = part.into_nested().unwrap();
+            let mut contents = Vec::new();
+
+            while let Some(mut file) = files.read_part().unwrap() {
+                let mut data = Vec::new();
+                file.read_to_end(&mut data).unwrap();
+                contents.push(data);
+            }
+
+            assert_eq!(contents, [b"first file".to_vec(), vec![0xFF; 100]]);
+        }
+
+        {
+            let mut field = server.read_entry().unwrap().unwrap();
+            assert_eq!(field.name, "bytes");
+
+            let file = field.data.as_file().unwrap();
+            // The server reads the ASCII fallback rather than `filename*`.
+            assert_eq!(file.filename(), Some("d_t_.bin"));
+            assert_eq!(file.content_type().to_string(), "application/octet-stream");
+
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).unwrap();
+            assert_eq!(data, b"some bytes");
+        }
+
+        assert!(server.read_entry().unwrap().is_none());
+    }
+
+    /// Accept a single HTTP request on a local port and respond with `200 OK`, returning the URL
+    /// to send it to and a handle resolving to the request, to test clients which open their own
+    /// connections.
+    #[cfg(all(feature = "server", any(feature = "curl", feature = "ureq")))]
+    fn serve_once() -> (String, ::std::thread::JoinHandle<::mock::MockHttpRequest>) {
+        use mock::MockHttpRequest;
+
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+        use std::thread;
+
+        fn read_line<R: BufRead>(reader: &mut R) -> String {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            line.trim_end().to_string()
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/upload", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let method = read_line(&mut reader).split(' ').next().unwrap().to_string();
+            let mut headers = Vec::new();
+
+            loop {
+                let line = read_line(&mut reader);
+                if line.is_empty() { break; }
+
+                let (name, value) = line.split_at(line.find(':').unwrap());
+                headers.push((name.to_string(), value[1..].trim().to_string()));
+            }
+
+            let header = |name: &str| headers.iter()
+                .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+                .map(|&(_, ref value)| value.clone());
+
+            if header("Expect").map_or(false, |expect| expect.eq_ignore_ascii_case("100-continue")) {
+                stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").unwrap();
+            }
+
+            let mut body = Vec::new();
+
+            if let Some(len) = header("Content-Length") {
+                body.resize(len.parse().unwrap(), 0);
+                reader.read_exact(&mut body).unwrap();
+            } else {
+                assert_eq!(header("Transfer-Encoding").as_ref().map(|s| &**s), Some("chunked"));
+
+                loop {
+                    let len = usize::from_str_radix(&read_line(&mut reader), 16).unwrap();
+                    let start = body.len();
+                    body.resize(start + len, 0);
+                    reader.read_exact(&mut body[start..]).unwrap();
+                    read_line(&mut reader);
+
+                    if len == 0 { break; }
+                }
+            }
+
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+
+            let mut req = MockHttpRequest::new(method, body);
+
+            for (name, value) in headers {
+                req.header(name, value);
+            }
+
+            req
+        });
+
+        (url, handle)
+    }
+
+    /// Check a request sent by a client with the fields of `sent_multipart()`.
+    #[cfg(all(feature = "server", any(feature = "curl", feature = "ureq")))]
+    fn check_sent(req: ::mock::MockHttpRequest, stream: bool) {
+        use server::Multipart as Server;
+
+        let mut server = Server::from_request(req).ok().expect("not multipart");
+
+        {
+            let field = server.read_entry().unwrap().unwrap();
+            assert_eq!(field.name, "text");
+            assert_eq!(field.data.as_text(), Some("value"));
+        }
+
+        {
+            let mut field = server.read_entry().unwrap().unwrap();
+            assert_eq!(field.name, "file");
+
+            let file = field.data.as_file().unwrap();
+            assert_eq!(file.filename(), Some("a.txt"));
+
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).unwrap();
+            assert_eq!(data, b"first file");
+        }
+
+        if stream {
+            let mut field = server.read_entry().unwrap().unwrap();
+            assert_eq!(field.name, "stream");
+
+            let mut data = Vec::new();
+            field.data.as_file().unwrap().read_to_end(&mut data).unwrap();
+            assert_eq!(data, b"streamed data");
+        }
+
+        assert!(server.read_entry().unwrap().is_none());
+    }
+
+    #[cfg(all(feature = "server", any(feature = "curl", feature = "ureq")))]
+    fn sent_multipart<'a>(a: &'a PathBuf, stream: bool) -> Multipart<'a, 'a> {
+        let mut multipart = Multipart::new();
+        multipart.add_text("text", "value").add_file("file", &**a);
+
+        if stream {
+            multipart.add_stream("stream", &b"streamed data"[..], None::<&str>, None);
+        }
+
+        multipart
+    }
+
+    #[cfg(all(feature = "curl", feature = "server"))]
+    #[test]
+    fn test_curl() {
+        use curl::easy::Easy;
+
+        let (_dir, a, _) = test_files();
+
+        for &stream in &[false, true] {
+            let mut fields = sent_multipart(&a, stream).prepare().unwrap();
+
+            let headers: Vec<_> = fields.curl_headers().unwrap().iter()
+                .map(|header| String::from_utf8(header.to_vec()).unwrap())
+                .collect();
+            assert_eq!(headers[0], format!("Content-Type: multipart/form-data; boundary={}", fields.boundary()));
+            assert_eq!(headers.get(1).map(|s| &**s), if stream { Some("Transfer-Encoding: chunked") } else { None });
+
+            let (url, server) = serve_once();
+
+            let mut easy = Easy::new();
+            easy.url(&url).unwrap();
+            fields.curl_setup(&mut easy).unwrap();
+
+            {
+                let mut transfer = easy.transfer();
+                transfer.read_function(|buf| fields.curl_read(buf)).unwrap();
+                transfer.perform().unwrap();
+            }
+
+            assert_eq!(easy.response_code().unwrap(), 200);
+            check_sent(server.join().unwrap(), stream);
+        }
+    }
+
+    #[cfg(all(feature = "ureq", feature = "server"))]
+    #[test]
+    fn test_ureq() {
+        let (_dir, a, _) = test_files();
+
+        for &stream in &[false, true] {
+            let (url, server) = serve_once();
+
+            let res = sent_multipart(&a, stream).ureq_request(::ureq::post(&url)).unwrap();
+            assert_eq!(res.status(), 200);
+
+            let req = server.join().unwrap();
+            assert_eq!(req.get_header("Content-Length").is_some(), !stream);
+            check_sent(req, stream);
+        }
+    }
+}
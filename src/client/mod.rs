@@ -17,16 +17,30 @@ use std::io::prelude::*;
 
 use std::path::Path;
 
+use {assert_valid_boundary, assert_valid_header};
+
 #[cfg(feature = "hyper")]
 pub mod hyper;
 
+mod encoding;
+
 pub mod lazy;
 
+pub mod related;
+
+pub mod replace;
+
+#[cfg(feature = "serde")]
+pub mod serialize;
+
 mod sized;
 
+#[cfg(feature = "async")]
+pub mod stream;
+
 pub use self::sized::SizedRequest;
 
-const BOUNDARY_LEN: usize = 16;
+const BOUNDARY_LEN: usize = 32;
 
 macro_rules! map_self {
     ($selff:expr, $try:expr) => (
@@ -37,6 +51,25 @@ macro_rules! map_self {
     )
 }
 
+/// The `Content-Transfer-Encoding` applied to the contents of file and stream fields.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferEncoding {
+    /// Send the contents as-is, without a `Content-Transfer-Encoding` header. The default.
+    Binary,
+    /// Encode the contents as base64, in lines of 76 characters, and add the header
+    /// `Content-Transfer-Encoding: base64`.
+    ///
+    /// Only needed for legacy MIME processors which are not 8-bit clean, as it increases the
+    /// size of the contents by about a third.
+    Base64,
+}
+
+impl Default for TransferEncoding {
+    fn default() -> Self {
+        TransferEncoding::Binary
+    }
+}
+
 /// The entry point of the client-side multipart API.
 ///
 /// Though they perform I/O, the `.write_*()` methods do not return `io::Result<_>` in order to
@@ -52,7 +85,26 @@ impl Multipart<()> {
     /// ## Returns Error
     /// If `req.open_stream()` returns an error.
     pub fn from_request<R: HttpRequest>(req: R) -> Result<Multipart<R::Stream>, R::Error> {
-        let (boundary, stream) = try!(open_stream(req, None));
+        Multipart::from_request_with_boundary(req, gen_boundary())
+    }
+
+    /// Create a new `Multipart` to wrap a request, using the given boundary instead of a
+    /// randomly generated one.
+    ///
+    /// The boundary must not occur in any of the data written to the request.
+    ///
+    /// ## Panics
+    /// If `boundary` is not a valid boundary per RFC 2046: 1 to 70 characters, consisting of
+    /// ASCII letters and digits, or any of `'()+_,-./:=?` and space (not at the end).
+    ///
+    /// ## Returns Error
+    /// If `req.open_stream()` returns an error.
+    pub fn from_request_with_boundary<R: HttpRequest, B: Into<String>>(req: R, boundary: B)
+    -> Result<Multipart<R::Stream>, R::Error> {
+        let boundary = boundary.into();
+        assert_valid_boundary(&boundary);
+
+        let stream = try!(open_stream(req, &boundary, None));
 
         Ok(Multipart {
             writer: MultipartWriter::new(stream, boundary),
@@ -67,7 +119,7 @@ impl<S: HttpStream> Multipart<S> {
     /// ##Errors
     /// If something went wrong with the HTTP stream.
     pub fn write_text<N: AsRef<str>, V: AsRef<str>>(&mut self, name: N, val: V) -> Result<&mut Self, S::Error> {
-        map_self!(self, self.writer.write_text(name.as_ref(), val.as_ref()))
+        map_self!(self, self.writer.write_text(name.as_ref(), val.as_ref(), &[]))
     }
     
     /// Open a file pointed to by `path` and write its contents to the multipart request, 
@@ -113,7 +165,7 @@ impl<S: HttpStream> Multipart<S> {
     ) -> Result<&mut Self, S::Error> {
         let name = name.as_ref();
 
-        map_self!(self, self.writer.write_stream(stream, name, filename, content_type))
+        map_self!(self, self.writer.write_stream(stream, name, filename, content_type, &[]))
     } 
 
     /// Finalize the request and return the response from the server, or the last error if set.
@@ -180,14 +232,21 @@ impl HttpStream for io::Sink {
     fn finish(self) -> Result<Self::Response, Self::Error> { Ok(()) }
 }
 
+/// Generate a boundary from the OS random number generator, so it cannot be predicted (and
+/// deliberately included in field data) and collisions with field data are negligible.
 fn gen_boundary() -> String {
-    ::random_alphanumeric(BOUNDARY_LEN)
+    match ::secure_random_alphanumeric(BOUNDARY_LEN) {
+        Ok(boundary) => boundary,
+        Err(err) => {
+            warn!("OS random number generator unavailable, falling back to thread RNG: {}", err);
+            ::random_alphanumeric(BOUNDARY_LEN)
+        },
+    }
 }
 
-fn open_stream<R: HttpRequest>(mut req: R, content_len: Option<u64>) -> Result<(String, R::Stream), R::Error> {
-    let boundary = gen_boundary();
-    req.apply_headers(&boundary, content_len);
-    req.open_stream().map(|stream| (boundary, stream))
+fn open_stream<R: HttpRequest>(mut req: R, boundary: &str, content_len: Option<u64>) -> Result<R::Stream, R::Error> {
+    req.apply_headers(boundary, content_len);
+    req.open_stream()
 }
 
 struct MultipartWriter<'a, W> {
@@ -209,9 +268,9 @@ impl<'a, W: Write> MultipartWriter<'a, W> {
         write!(self.inner, "\r\n--{}\r\n", self.boundary)
     }
 
-    fn write_text(&mut self, name: &str, text: &str) -> io::Result<()> {
+    fn write_text(&mut self, name: &str, text: &str, headers: &[(Cow<str>, Cow<str>)]) -> io::Result<()> {
         chain_result! {
-            self.write_field_headers(name, None, None),
+            self.write_field_headers(name, None, None, headers),
             self.inner.write_all(text.as_bytes())
         }
     }
@@ -219,31 +278,35 @@ impl<'a, W: Write> MultipartWriter<'a, W> {
     fn write_file(&mut self, name: &str, path: &Path) -> io::Result<()> {
         let (content_type, filename) = mime_filename(path);
         let mut file = try!(File::open(path));
-        self.write_stream(&mut file, name, filename, Some(content_type))
+        self.write_stream(&mut file, name, filename, Some(content_type), &[])
     }
 
-    fn write_stream<S: Read>(&mut self, stream: &mut S, name: &str, filename: Option<&str>, content_type: Option<Mime>) -> io::Result<()> {
+    fn write_stream<S: Read>(&mut self, stream: &mut S, name: &str, filename: Option<&str>, content_type: Option<Mime>,
+                             headers: &[(Cow<str>, Cow<str>)]) -> io::Result<()> {
         // This is necessary to make sure it is interpreted as a file on the server end.
         let content_type = Some(content_type.unwrap_or_else(::mime_guess::octet_stream));
 
         chain_result! {
-            self.write_field_headers(name, filename, content_type),
+            self.write_field_headers(name, filename, content_type, headers),
             io::copy(stream, &mut self.inner),
             Ok(()) 
         }
     }
 
-    fn write_field_headers(&mut self, name: &str, filename: Option<&str>, content_type: Option<Mime>) 
-    -> io::Result<()> {
+    fn write_field_headers(&mut self, name: &str, filename: Option<&str>, content_type: Option<Mime>,
+                           headers: &[(Cow<str>, Cow<str>)]) -> io::Result<()> {
         chain_result! {
             // Write the first boundary, or the boundary for the previous field.
             self.write_boundary(),
             { self.data_written = true; Ok(()) },
             write!(self.inner, "Content-Disposition: form-data; name=\"{}\"", name),
-            filename.map(|filename| write!(self.inner, "; filename=\"{}\"", filename))
+            filename.map(|filename| write_filename(&mut self.inner, filename))
                 .unwrap_or(Ok(())),
             content_type.map(|content_type| write!(self.inner, "\r\nContent-Type: {}", content_type))
                 .unwrap_or(Ok(())),
+            headers.iter()
+                .map(|&(ref name, ref value)| write!(self.inner, "\r\n{}: {}", name, value))
+                .collect::<io::Result<()>>(),
             self.inner.write_all(b"\r\n\r\n")
         }
     }
@@ -258,6 +321,29 @@ impl<'a, W: Write> MultipartWriter<'a, W> {
     }
 }
 
+/// Write the `filename` parameter of a `Content-Disposition` header.
+///
+/// If `filename` contains non-ASCII characters, they are replaced with `_` in `filename`, and the
+/// exact name is supplied in an additional `filename*` parameter, encoded per RFC 5987.
+fn write_filename<W: Write>(out: &mut W, filename: &str) -> io::Result<()> {
+    if filename.is_ascii() {
+        return write!(out, "; filename=\"{}\"", filename);
+    }
+
+    let fallback: String = filename.chars().map(|c| if c.is_ascii() { c } else { '_' }).collect();
+    try!(write!(out, "; filename=\"{}\"; filename*=UTF-8''", fallback));
+
+    for &byte in filename.as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            try!(out.write_all(&[byte]));
+        } else {
+            try!(write!(out, "%{:02X}", byte));
+        }
+    }
+
+    Ok(())
+}
+
 fn mime_filename(path: &Path) -> (Mime, Option<&str>) {
     let content_type = ::mime_guess::guess_mime_type(path);
     let filename = opt_filename(path);
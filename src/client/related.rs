@@ -0,0 +1,310 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Building `multipart/related` bodies (RFC 2387), as used by APIs which take a metadata part
+//! and a payload in one request, such as Google Drive and Gmail uploads or DICOMweb.
+//!
+//! The first part added is the root part, which usually contains JSON or XML referencing the
+//! following parts by their `Content-ID`. Unlike `multipart/form-data`, the parts have no
+//! names, so the `Content-Type` header of the request must state the type of the root part;
+//! [`RelatedBody::content_type()`](struct.RelatedBody.html#method.content_type) supplies it.
+//!
+//! ```no_run
+//! # extern crate multipart;
+//! use multipart::client::related::Related;
+//! use std::io::Read;
+//!
+//! # fn main() {
+//! let mut body = Related::new()
+//!     .add_part("application/json; charset=UTF-8".parse().unwrap(), &br#"{"name": "photo.jpg"}"#[..])
+//!     .add_part("image/jpeg".parse().unwrap(), vec![0xFF, 0xD8, 0xFF])
+//!     .prepare();
+//!
+//! // Set as the `Content-Type` header of the request.
+//! let content_type = body.content_type().to_owned();
+//!
+//! let mut bytes = Vec::new();
+//! body.read_to_end(&mut bytes).unwrap();
+//! # }
+//! ```
+use mime::Mime;
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io::prelude::*;
+use std::io;
+
+/// A builder for a `multipart/related` request body.
+///
+/// Lifetime `'d` is that of the part data and headers, which only escapes this struct in
+/// `RelatedBody<'d>`.
+#[derive(Debug, Default)]
+pub struct Related<'d> {
+    parts: Vec<Part<'d>>,
+    boundary: Option<String>,
+}
+
+impl<'d> Related<'d> {
+    /// Create a new, empty `multipart/related` body.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Use the given boundary for this body instead of a randomly generated one.
+    ///
+    /// The boundary must not occur in any of the parts.
+    ///
+    /// ## Panics
+    /// If `boundary` is not a valid boundary per RFC 2046: 1 to 70 characters, consisting of
+    /// ASCII letters and digits, or any of `'()+_,-./:=?` and space (not at the end).
+    pub fn set_boundary<B: Into<String>>(&mut self, boundary: B) -> &mut Self {
+        let boundary = boundary.into();
+        super::assert_valid_boundary(&boundary);
+        self.boundary = Some(boundary);
+        self
+    }
+
+    /// Add a part with the given `Content-Type` whose contents are `data`, which can be a
+    /// `Vec<u8>` or `&[u8]`. The first part added is the root part.
+    pub fn add_part<D: Into<Cow<'d, [u8]>>>(&mut self, content_type: Mime, data: D) -> &mut Self {
+        self.parts.push(Part {
+            content_type: content_type,
+            headers: Vec::new(),
+            data: PartData::Bytes(data.into()),
+        });
+
+        self
+    }
+
+    /// Add a part with the given `Content-Type` whose contents are read from `stream`. The
+    /// first part added is the root part.
+    ///
+    /// The body will not have a known length if any streams are added.
+    ///
+    /// ##Warning
+    /// The given `Read` **must** be able to read to EOF (end of file/no more data), meaning
+    /// `Read::read()` returns `Ok(0)`. If it never returns EOF it will be read to infinity
+    /// and the body will never be completed.
+    pub fn add_stream<R: Read + 'd>(&mut self, content_type: Mime, stream: R) -> &mut Self {
+        self.parts.push(Part {
+            content_type: content_type,
+            headers: Vec::new(),
+            data: PartData::Stream(Box::new(stream)),
+        });
+
+        self
+    }
+
+    /// Set the `Content-ID` of the part which was added last, by which other parts refer to it.
+    ///
+    /// `id` is given without the enclosing angle brackets. If it is set on the root part, it is
+    /// also given as the `start` parameter of the body's `Content-Type`.
+    ///
+    /// ## Panics
+    /// If no part has been added yet, or if `id` contains a line break.
+    pub fn set_content_id<I: Into<String>>(&mut self, id: I) -> &mut Self {
+        self.add_part_header("Content-ID", format!("<{}>", id.into()))
+    }
+
+    /// Add a header to the part which was added last, such as `Content-Transfer-Encoding`.
+    ///
+    /// `Content-Type` is always written by this builder, and should not be added again.
+    ///
+    /// ## Panics
+    /// If no part has been added yet, if `name` is not a valid header name, or if `value`
+    /// contains a line break.
+    pub fn add_part_header<N, V>(&mut self, name: N, value: V) -> &mut Self where N: Into<Cow<'d, str>>, V: Into<Cow<'d, str>> {
+        let (name, value) = (name.into(), value.into());
+        super::assert_valid_header(&name, &value);
+
+        self.parts.last_mut().expect("`add_part_header()` called before adding a part")
+            .headers.push((name, value));
+
+        self
+    }
+
+    /// Export the parts added to this builder as a `RelatedBody`, which implements `Read`,
+    /// leaving this builder empty.
+    pub fn prepare(&mut self) -> RelatedBody<'d> {
+        let boundary = self.boundary.clone().unwrap_or_else(super::gen_boundary);
+
+        let content_type = {
+            let mut content_type = format!("multipart/related; boundary={}", boundary);
+
+            if let Some(root) = self.parts.first() {
+                // The `type` parameter is the media type of the root part without parameters.
+                let root_type = root.content_type.to_string();
+                content_type.push_str(&format!("; type=\"{}\"", root_type.split(';').next().unwrap_or("").trim()));
+
+                let content_id = root.headers.iter()
+                    .find(|&&(ref name, _)| name.eq_ignore_ascii_case("Content-ID"));
+
+                if let Some(&(_, ref id)) = content_id {
+                    content_type.push_str(&format!("; start=\"{}\"", id));
+                }
+            }
+
+            content_type
+        };
+
+        let mut body: Box<Read + 'd> = Box::new(io::empty());
+        let mut content_len = Some(0);
+
+        for part in self.parts.drain(..) {
+            let mut headers = format!("\r\n--{}\r\nContent-Type: {}", boundary, part.content_type);
+
+            for &(ref name, ref value) in &part.headers {
+                headers.push_str(&format!("\r\n{}: {}", name, value));
+            }
+
+            headers.push_str("\r\n\r\n");
+
+            let headers_len = headers.len() as u64;
+            let headers = io::Cursor::new(headers.into_bytes());
+
+            match part.data {
+                PartData::Bytes(data) => {
+                    content_len = content_len.map(|len| len + headers_len + data.len() as u64);
+                    body = Box::new(body.chain(headers).chain(io::Cursor::new(data)));
+                },
+                PartData::Stream(stream) => {
+                    content_len = None;
+                    body = Box::new(body.chain(headers).chain(stream));
+                },
+            }
+        }
+
+        let end = format!("\r\n--{}--", boundary).into_bytes();
+        content_len = content_len.map(|len| len + end.len() as u64);
+
+        RelatedBody {
+            inner: Box::new(body.chain(io::Cursor::new(end))),
+            content_type: content_type,
+            content_len: content_len,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Part<'d> {
+    content_type: Mime,
+    headers: Vec<(Cow<'d, str>, Cow<'d, str>)>,
+    data: PartData<'d>,
+}
+
+enum PartData<'d> {
+    Bytes(Cow<'d, [u8]>),
+    Stream(Box<Read + 'd>),
+}
+
+impl<'d> fmt::Debug for PartData<'d> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PartData::Bytes(ref data) => write!(f, "PartData::Bytes({} bytes)", data.len()),
+            PartData::Stream(_) => f.write_str("PartData::Stream(Box<Read>)"),
+        }
+    }
+}
+
+/// The result of [`Related::prepare()`](struct.Related.html#method.prepare). Implements `Read`,
+/// contains the entire body.
+pub struct RelatedBody<'d> {
+    inner: Box<Read + 'd>,
+    content_type: String,
+    content_len: Option<u64>,
+}
+
+impl<'d> RelatedBody<'d> {
+    /// Get the value of the `Content-Type` header for this body, including the boundary and the
+    /// type of the root part.
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    /// Get the length of this body, if known (no streams were added).
+    pub fn content_len(&self) -> Option<u64> {
+        self.content_len
+    }
+}
+
+impl<'d> Read for RelatedBody<'d> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod test {
+    use server::Multipart;
+
+    use std::io::Read;
+
+    use super::Related;
+
+    fn read_body<'d>(related: &mut Related<'d>) -> (String, Option<u64>, Vec<u8>) {
+        let mut body = related.prepare();
+        let mut buf = Vec::new();
+        body.read_to_end(&mut buf).unwrap();
+
+        (body.content_type().to_owned(), body.content_len(), buf)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut related = Related::new();
+        related.set_boundary("test-boundary")
+            .add_part("application/json; charset=UTF-8".parse().unwrap(), &br#"{"image": "cid:image"}"#[..])
+            .set_content_id("meta")
+            .add_part("image/png".parse().unwrap(), vec![0xFF; 100])
+            .set_content_id("image")
+            .add_part_header("Content-Transfer-Encoding", "binary");
+
+        let (content_type, content_len, body) = read_body(&mut related);
+        assert_eq!(content_len, Some(body.len() as u64));
+
+        let parsed = Multipart::parse_related(&body, &content_type).unwrap();
+        assert_eq!(parsed.root_type, Some("application/json"));
+        assert_eq!(parsed.start, Some("meta"));
+        assert_eq!(parsed.parts.len(), 2);
+
+        let root = parsed.root().unwrap();
+        assert_eq!(root.content_type, Some("application/json; charset=utf-8"));
+        assert_eq!(root.as_text(), Some(r#"{"image": "cid:image"}"#));
+
+        let image = parsed.get("cid:image").unwrap();
+        assert_eq!(image.content_type, Some("image/png"));
+        assert_eq!(image.header("Content-Transfer-Encoding"), Some("binary"));
+        assert_eq!(image.data, &[0xFF; 100][..]);
+    }
+
+    #[test]
+    fn test_round_trip_stream() {
+        let mut related = Related::new();
+        related.add_part("application/xml".parse().unwrap(), &b"<root/>"[..])
+            .add_stream("application/octet-stream".parse().unwrap(), &b"streamed data"[..]);
+
+        let (content_type, content_len, body) = read_body(&mut related);
+        assert_eq!(content_len, None);
+
+        let parsed = Multipart::parse_related(&body, &content_type).unwrap();
+        assert_eq!(parsed.root_type, Some("application/xml"));
+        assert_eq!(parsed.start, None);
+
+        let datas: Vec<_> = parsed.parts.iter().map(|part| part.data).collect();
+        assert_eq!(datas, [&b"<root/>"[..], &b"streamed data"[..]]);
+        assert_eq!(parsed.parts[1].content_id, None);
+    }
+
+    #[test]
+    fn test_empty() {
+        let (content_type, content_len, body) = read_body(&mut Related::new());
+        assert_eq!(content_len, Some(body.len() as u64));
+
+        let parsed = Multipart::parse_related(&body, &content_type).unwrap();
+        assert_eq!(parsed.root_type, None);
+        assert!(parsed.parts.is_empty());
+    }
+}
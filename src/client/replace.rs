@@ -0,0 +1,102 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Writing `multipart/x-mixed-replace` streams, in which each part replaces the previous one,
+//! for servers which push MJPEG video or events over a long-lived response.
+//!
+//! Each part is written with a `Content-Length` header and followed immediately by the next
+//! boundary, then flushed, so clients can show it without waiting for the next part.
+//!
+//! ```rust
+//! # extern crate multipart;
+//! use multipart::client::replace::ReplaceWriter;
+//!
+//! # fn main() {
+//! let mut stream = ReplaceWriter::new(Vec::new());
+//!
+//! // Set as the `Content-Type` header of the response.
+//! let content_type = stream.content_type();
+//!
+//! for frame in &[&b"\xFF\xD8\xFF\xD9"[..], &b"\xFF\xD8\xFF\xD9"[..]] {
+//!     stream.write_part(&"image/jpeg".parse().unwrap(), frame).unwrap();
+//! }
+//!
+//! let body = stream.finish().unwrap();
+//! # }
+//! ```
+use mime::Mime;
+
+use std::io::prelude::*;
+use std::io;
+
+/// A writer of `multipart/x-mixed-replace` streams.
+#[derive(Debug)]
+pub struct ReplaceWriter<W: Write> {
+    inner: W,
+    boundary: String,
+    started: bool,
+}
+
+impl<W: Write> ReplaceWriter<W> {
+    /// Write a stream with a randomly generated boundary to `inner`.
+    pub fn new(inner: W) -> Self {
+        Self::with_boundary(inner, super::gen_boundary())
+    }
+
+    /// Write a stream with the given boundary to `inner`.
+    ///
+    /// The boundary must not occur in any of the parts.
+    ///
+    /// ## Panics
+    /// If `boundary` is not a valid boundary per RFC 2046: 1 to 70 characters, consisting of
+    /// ASCII letters and digits, or any of `'()+_,-./:=?` and space (not at the end).
+    pub fn with_boundary<B: Into<String>>(inner: W, boundary: B) -> Self {
+        let boundary = boundary.into();
+        super::assert_valid_boundary(&boundary);
+
+        ReplaceWriter {
+            inner: inner,
+            boundary: boundary,
+            started: false,
+        }
+    }
+
+    /// Get the value of the `Content-Type` header for this stream, including the boundary.
+    pub fn content_type(&self) -> String {
+        format!("multipart/x-mixed-replace; boundary=\"{}\"", self.boundary)
+    }
+
+    /// Write a part with the given `Content-Type` and contents, followed by the boundary, and
+    /// flush the writer.
+    pub fn write_part(&mut self, content_type: &Mime, data: &[u8]) -> io::Result<()> {
+        try!(self.start());
+
+        try!(write!(self.inner, "\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+                    content_type, data.len()));
+        try!(self.inner.write_all(data));
+        try!(write!(self.inner, "\r\n--{}", self.boundary));
+
+        self.inner.flush()
+    }
+
+    /// Write the closing boundary, and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        try!(self.start());
+        try!(self.inner.write_all(b"--\r\n"));
+        try!(self.inner.flush());
+        Ok(self.inner)
+    }
+
+    /// Write the first boundary if it hasn't been written yet.
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            try!(write!(self.inner, "--{}", self.boundary));
+            self.started = true;
+        }
+
+        Ok(())
+    }
+}
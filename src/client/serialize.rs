@@ -0,0 +1,652 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Building client requests from `serde::Serialize` values, enabled with the `serde` feature
+//! (optional).
+//!
+//! [`Multipart::add_serialized()`](../lazy/struct.Multipart.html#method.add_serialized) adds a
+//! field for each field of a struct or entry of a map:
+//!
+//! * strings, numbers, booleans, chars and unit enum variants are added as text fields;
+//! * byte arrays (with `serde_bytes`) are added as file fields without a filename;
+//! * fields wrapped in [`FilePart`](struct.FilePart.html), or serialized with
+//! [`file_part()`](fn.file_part.html), are added as file fields read from the given path;
+//! * sequences add a field with the same name for each element;
+//! * `None` and unit values are skipped.
+//!
+//! Nested structs and maps, and enum variants with data, are not supported.
+//!
+//! ```no_run
+//! # extern crate multipart;
+//! # extern crate serde;
+//! use multipart::client::lazy::Multipart;
+//! use multipart::client::serialize::FilePart;
+//! use serde::ser::{Serialize, Serializer, SerializeStruct};
+//!
+//! // Usually implemented with `#[derive(Serialize)]`.
+//! struct Upload {
+//!     title: String,
+//!     tags: Vec<String>,
+//!     image: FilePart<&'static str>,
+//! }
+//!
+//! impl Serialize for Upload {
+//!     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+//!         let mut upload = serializer.serialize_struct("Upload", 3)?;
+//!         upload.serialize_field("title", &self.title)?;
+//!         upload.serialize_field("tags", &self.tags)?;
+//!         upload.serialize_field("image", &self.image)?;
+//!         upload.end()
+//!     }
+//! }
+//!
+//! # fn main() {
+//! let upload = Upload {
+//!     title: "Holiday".into(),
+//!     tags: vec!["beach".into(), "sunset".into()],
+//!     image: FilePart("holiday.jpg"),
+//! };
+//!
+//! let mut fields = Multipart::new()
+//!     .add_serialized(&upload).unwrap()
+//!     .prepare().unwrap();
+//! # }
+//! ```
+use serde::ser::{self, Impossible, Serialize, Serializer};
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::lazy::Multipart;
+
+/// The name of the newtype struct which `FilePart` serializes as, to be recognized by the
+/// field serializer.
+const FILE_PART: &'static str = "$multipart::FilePart";
+
+/// Wraps a path to a file which should be added to the request as a file field, instead of
+/// as a text field containing the path.
+///
+/// Only works with the serializer used by `add_serialized()`; other serializers see a newtype
+/// struct containing the path as a string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilePart<P>(pub P);
+
+impl<P: AsRef<Path>> Serialize for FilePart<P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        file_part(&self.0, serializer)
+    }
+}
+
+/// Serialize `path` as a file field, for use with `#[serde(serialize_with = "...")]` on fields
+/// which hold a path.
+///
+/// ## Errors
+/// If `path` is not valid UTF-8.
+pub fn file_part<P: AsRef<Path>, S: Serializer>(path: &P, serializer: S) -> Result<S::Ok, S::Error> {
+    let path = try!(path.as_ref().to_str().ok_or_else(|| <S::Error as ser::Error>::custom("path is not valid UTF-8")));
+    serializer.serialize_newtype_struct(FILE_PART, path)
+}
+
+/// An error returned by [`Multipart::add_serialized()`](../lazy/struct.Multipart.html#method.add_serialized).
+#[derive(Debug)]
+pub enum SerializeError {
+    /// The value, or one of its fields, is of a type which cannot be added to a request, such as a
+    /// nested struct. The string describes the type.
+    UnsupportedType(&'static str),
+    /// An error returned by the `Serialize` implementation of the value.
+    Custom(String),
+}
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SerializeError::UnsupportedType(ty) => write!(f, "cannot add {} to a multipart request", ty),
+            SerializeError::Custom(ref msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl Error for SerializeError {
+    fn description(&self) -> &str {
+        match *self {
+            SerializeError::UnsupportedType(_) => "unsupported type for a multipart request",
+            SerializeError::Custom(ref msg) => msg,
+        }
+    }
+}
+
+impl ser::Error for SerializeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerializeError::Custom(msg.to_string())
+    }
+}
+
+impl Into<io::Error> for SerializeError {
+    fn into(self) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, self)
+    }
+}
+
+impl<'n, 'd> Multipart<'n, 'd> {
+    /// #### Feature: `serde`
+    /// Add a field to this request for each field of `value`, which must serialize as a struct
+    /// or a map with string keys. See the [`serialize`](../serialize/index.html) module for how
+    /// fields are added.
+    ///
+    /// ##Errors
+    /// If `value` or one of its fields is of an unsupported type. Fields added before the error
+    /// was encountered remain in the request.
+    pub fn add_serialized<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<&mut Self, SerializeError> {
+        try!(value.serialize(FormSerializer(self)));
+        Ok(self)
+    }
+}
+
+/// Return an `UnsupportedType` error from each of the given methods of `Serializer`.
+macro_rules! unsupported (
+    ($($method:ident($($arg:ident: $ty:ty),*) -> $ret:ty = $desc:expr;)*) => (
+        $(
+            fn $method(self, $(_: $ty),*) -> Result<$ret, SerializeError> {
+                Err(SerializeError::UnsupportedType($desc))
+            }
+        )*
+    )
+);
+
+/// Serializes the top-level struct or map.
+struct FormSerializer<'a, 'n: 'a, 'd: 'a>(&'a mut Multipart<'n, 'd>);
+
+impl<'a, 'n, 'd> Serializer for FormSerializer<'a, 'n, 'd> {
+    type Ok = ();
+    type Error = SerializeError;
+    type SerializeSeq = Impossible<(), SerializeError>;
+    type SerializeTuple = Impossible<(), SerializeError>;
+    type SerializeTupleStruct = Impossible<(), SerializeError>;
+    type SerializeTupleVariant = Impossible<(), SerializeError>;
+    type SerializeMap = MapSerializer<'a, 'n, 'd>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), SerializeError>;
+
+    unsupported! {
+        serialize_bool(v: bool) -> () = "a boolean";
+        serialize_i8(v: i8) -> () = "an integer";
+        serialize_i16(v: i16) -> () = "an integer";
+        serialize_i32(v: i32) -> () = "an integer";
+        serialize_i64(v: i64) -> () = "an integer";
+        serialize_u8(v: u8) -> () = "an integer";
+        serialize_u16(v: u16) -> () = "an integer";
+        serialize_u32(v: u32) -> () = "an integer";
+        serialize_u64(v: u64) -> () = "an integer";
+        serialize_f32(v: f32) -> () = "a float";
+        serialize_f64(v: f64) -> () = "a float";
+        serialize_char(v: char) -> () = "a char";
+        serialize_str(v: &str) -> () = "a string";
+        serialize_bytes(v: &[u8]) -> () = "a byte array";
+        serialize_none() -> () = "`None`";
+        serialize_unit() -> () = "a unit value";
+        serialize_unit_struct(name: &'static str) -> () = "a unit struct";
+        serialize_unit_variant(name: &'static str, index: u32, variant: &'static str) -> () = "an enum";
+        serialize_seq(len: Option<usize>) -> Self::SerializeSeq = "a sequence";
+        serialize_tuple(len: usize) -> Self::SerializeTuple = "a tuple";
+        serialize_tuple_struct(name: &'static str, len: usize) -> Self::SerializeTupleStruct = "a tuple struct";
+        serialize_tuple_variant(name: &'static str, index: u32, variant: &'static str, len: usize)
+            -> Self::SerializeTupleVariant = "an enum";
+        serialize_struct_variant(name: &'static str, index: u32, variant: &'static str, len: usize)
+            -> Self::SerializeStructVariant = "an enum";
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), SerializeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _: &'static str, value: &T) -> Result<(), SerializeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _: &'static str, _: u32, _: &'static str, _: &T)
+    -> Result<(), SerializeError> {
+        Err(SerializeError::UnsupportedType("an enum"))
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, SerializeError> {
+        Ok(MapSerializer { multipart: self.0, key: None })
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self, SerializeError> {
+        Ok(self)
+    }
+}
+
+impl<'a, 'n, 'd> ser::SerializeStruct for FormSerializer<'a, 'n, 'd> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), SerializeError> {
+        value.serialize(FieldSerializer { multipart: &mut *self.0, name: key.into() })
+    }
+
+    fn end(self) -> Result<(), SerializeError> {
+        Ok(())
+    }
+}
+
+struct MapSerializer<'a, 'n: 'a, 'd: 'a> {
+    multipart: &'a mut Multipart<'n, 'd>,
+    key: Option<String>,
+}
+
+impl<'a, 'n, 'd> ser::SerializeMap for MapSerializer<'a, 'n, 'd> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerializeError> {
+        self.key = Some(try!(key.serialize(KeySerializer)));
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        let name = self.key.take().expect("`serialize_value()` called before `serialize_key()`");
+        value.serialize(FieldSerializer { multipart: &mut *self.multipart, name: name })
+    }
+
+    fn end(self) -> Result<(), SerializeError> {
+        Ok(())
+    }
+}
+
+/// Serializes the value of a single field, which may add any number of fields named `name`.
+struct FieldSerializer<'a, 'n: 'a, 'd: 'a> {
+    multipart: &'a mut Multipart<'n, 'd>,
+    name: String,
+}
+
+impl<'a, 'n, 'd> FieldSerializer<'a, 'n, 'd> {
+    fn add_text<T: ToString>(self, text: T) -> Result<(), SerializeError> {
+        self.multipart.add_text(self.name, text.to_string());
+        Ok(())
+    }
+}
+
+impl<'a, 'n, 'd> Serializer for FieldSerializer<'a, 'n, 'd> {
+    type Ok = ();
+    type Error = SerializeError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Impossible<(), SerializeError>;
+    type SerializeMap = Impossible<(), SerializeError>;
+    type SerializeStruct = Impossible<(), SerializeError>;
+    type SerializeStructVariant = Impossible<(), SerializeError>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), SerializeError> { self.add_text(v) }
+    fn serialize_i8(self, v: i8) -> Result<(), SerializeError> { self.add_text(v) }
+    fn serialize_i16(self, v: i16) -> Result<(), SerializeError> { self.add_text(v) }
+    fn serialize_i32(self, v: i32) -> Result<(), SerializeError> { self.add_text(v) }
+    fn serialize_i64(self, v: i64) -> Result<(), SerializeError> { self.add_text(v) }
+    fn serialize_u8(self, v: u8) -> Result<(), SerializeError> { self.add_text(v) }
+    fn serialize_u16(self, v: u16) -> Result<(), SerializeError> { self.add_text(v) }
+    fn serialize_u32(self, v: u32) -> Result<(), SerializeError> { self.add_text(v) }
+    fn serialize_u64(self, v: u64) -> Result<(), SerializeError> { self.add_text(v) }
+    fn serialize_f32(self, v: f32) -> Result<(), SerializeError> { self.add_text(v) }
+    fn serialize_f64(self, v: f64) -> Result<(), SerializeError> { self.add_text(v) }
+    fn serialize_char(self, v: char) -> Result<(), SerializeError> { self.add_text(v) }
+    fn serialize_str(self, v: &str) -> Result<(), SerializeError> { self.add_text(v) }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), SerializeError> {
+        self.multipart.add_bytes(self.name, None::<String>, None, v.to_owned());
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), SerializeError> { Ok(()) }
+    fn serialize_unit(self) -> Result<(), SerializeError> { Ok(()) }
+    fn serialize_unit_struct(self, _: &'static str) -> Result<(), SerializeError> { Ok(()) }
+
+    fn serialize_unit_variant(self, _: &'static str, _: u32, variant: &'static str) -> Result<(), SerializeError> {
+        self.add_text(variant)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), SerializeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, name: &'static str, value: &T) -> Result<(), SerializeError> {
+        if name == FILE_PART {
+            let path = try!(value.serialize(KeySerializer));
+            self.multipart.add_file(self.name, PathBuf::from(path));
+            Ok(())
+        } else {
+            value.serialize(self)
+        }
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _: &'static str, _: u32, _: &'static str, _: &T)
+    -> Result<(), SerializeError> {
+        Err(SerializeError::UnsupportedType("an enum variant with data"))
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self, SerializeError> { Ok(self) }
+    fn serialize_tuple(self, _: usize) -> Result<Self, SerializeError> { Ok(self) }
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Self, SerializeError> { Ok(self) }
+
+    unsupported! {
+        serialize_tuple_variant(name: &'static str, index: u32, variant: &'static str, len: usize)
+            -> Self::SerializeTupleVariant = "an enum variant with data";
+        serialize_map(len: Option<usize>) -> Self::SerializeMap = "a nested map";
+        serialize_struct(name: &'static str, len: usize) -> Self::SerializeStruct = "a nested struct";
+        serialize_struct_variant(name: &'static str, index: u32, variant: &'static str, len: usize)
+            -> Self::SerializeStructVariant = "an enum variant with data";
+    }
+}
+
+impl<'a, 'n, 'd> FieldSerializer<'a, 'n, 'd> {
+    fn serialize_repeated<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        value.serialize(FieldSerializer { multipart: &mut *self.multipart, name: self.name.clone() })
+    }
+}
+
+impl<'a, 'n, 'd> ser::SerializeSeq for FieldSerializer<'a, 'n, 'd> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        self.serialize_repeated(value)
+    }
+
+    fn end(self) -> Result<(), SerializeError> { Ok(()) }
+}
+
+impl<'a, 'n, 'd> ser::SerializeTuple for FieldSerializer<'a, 'n, 'd> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        self.serialize_repeated(value)
+    }
+
+    fn end(self) -> Result<(), SerializeError> { Ok(()) }
+}
+
+impl<'a, 'n, 'd> ser::SerializeTupleStruct for FieldSerializer<'a, 'n, 'd> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        self.serialize_repeated(value)
+    }
+
+    fn end(self) -> Result<(), SerializeError> { Ok(()) }
+}
+
+/// Serializes map keys and `FilePart` paths, which must be strings or scalars, to `String`.
+struct KeySerializer;
+
+impl Serializer for KeySerializer {
+    type Ok = String;
+    type Error = SerializeError;
+    type SerializeSeq = Impossible<String, SerializeError>;
+    type SerializeTuple = Impossible<String, SerializeError>;
+    type SerializeTupleStruct = Impossible<String, SerializeError>;
+    type SerializeTupleVariant = Impossible<String, SerializeError>;
+    type SerializeMap = Impossible<String, SerializeError>;
+    type SerializeStruct = Impossible<String, SerializeError>;
+    type SerializeStructVariant = Impossible<String, SerializeError>;
+
+    fn serialize_bool(self, v: bool) -> Result<String, SerializeError> { Ok(v.to_string()) }
+    fn serialize_i8(self, v: i8) -> Result<String, SerializeError> { Ok(v.to_string()) }
+    fn serialize_i16(self, v: i16) -> Result<String, SerializeError> { Ok(v.to_string()) }
+    fn serialize_i32(self, v: i32) -> Result<String, SerializeError> { Ok(v.to_string()) }
+    fn serialize_i64(self, v: i64) -> Result<String, SerializeError> { Ok(v.to_string()) }
+    fn serialize_u8(self, v: u8) -> Result<String, SerializeError> { Ok(v.to_string()) }
+    fn serialize_u16(self, v: u16) -> Result<String, SerializeError> { Ok(v.to_string()) }
+    fn serialize_u32(self, v: u32) -> Result<String, SerializeError> { Ok(v.to_string()) }
+    fn serialize_u64(self, v: u64) -> Result<String, SerializeError> { Ok(v.to_string()) }
+    fn serialize_f32(self, v: f32) -> Result<String, SerializeError> { Ok(v.to_string()) }
+    fn serialize_f64(self, v: f64) -> Result<String, SerializeError> { Ok(v.to_string()) }
+    fn serialize_char(self, v: char) -> Result<String, SerializeError> { Ok(v.to_string()) }
+    fn serialize_str(self, v: &str) -> Result<String, SerializeError> { Ok(v.to_owned()) }
+
+    fn serialize_unit_variant(self, _: &'static str, _: u32, variant: &'static str) -> Result<String, SerializeError> {
+        Ok(variant.to_owned())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _: &'static str, value: &T) -> Result<String, SerializeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _: &T) -> Result<String, SerializeError> {
+        Err(SerializeError::UnsupportedType("an optional key"))
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _: &'static str, _: u32, _: &'static str, _: &T)
+    -> Result<String, SerializeError> {
+        Err(SerializeError::UnsupportedType("an enum variant with data as a key"))
+    }
+
+    unsupported! {
+        serialize_bytes(v: &[u8]) -> String = "a byte array as a key";
+        serialize_none() -> String = "an optional key";
+        serialize_unit() -> String = "a unit value as a key";
+        serialize_unit_struct(name: &'static str) -> String = "a unit struct as a key";
+        serialize_seq(len: Option<usize>) -> Self::SerializeSeq = "a sequence as a key";
+        serialize_tuple(len: usize) -> Self::SerializeTuple = "a tuple as a key";
+        serialize_tuple_struct(name: &'static str, len: usize) -> Self::SerializeTupleStruct = "a tuple struct as a key";
+        serialize_tuple_variant(name: &'static str, index: u32, variant: &'static str, len: usize)
+            -> Self::SerializeTupleVariant = "an enum variant with data as a key";
+        serialize_map(len: Option<usize>) -> Self::SerializeMap = "a map as a key";
+        serialize_struct(name: &'static str, len: usize) -> Self::SerializeStruct = "a struct as a key";
+        serialize_struct_variant(name: &'static str, index: u32, variant: &'static str, len: usize)
+            -> Self::SerializeStructVariant = "an enum variant with data as a key";
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod test {
+    use mock::ClientRequest;
+    use server::Multipart as Server;
+
+    use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+    use tempdir::TempDir;
+
+    use std::collections::BTreeMap;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+
+    use client::lazy::Multipart;
+
+    use super::{FilePart, SerializeError};
+
+    /// A field read back by the server: its name, filename if it is a file, and data.
+    type Field = (String, Option<String>, Vec<u8>);
+
+    /// Serialize `value` into a request and read its fields back with the server parser.
+    fn round_trip<T: Serialize + ?Sized>(value: &T) -> Vec<Field> {
+        let buf = Multipart::new()
+            .add_serialized(value).unwrap()
+            .send(ClientRequest::default()).unwrap();
+
+        let mut server = Server::from_request(buf.for_server()).ok().unwrap();
+        let mut fields = Vec::new();
+
+        while let Some(mut field) = server.read_entry().unwrap() {
+            let field = match field.data.as_file() {
+                Some(file) => {
+                    let mut data = Vec::new();
+                    file.read_to_end(&mut data).unwrap();
+                    (field.name.clone(), file.filename().map(String::from), data)
+                },
+                None => (field.name.clone(), None, field.data.as_bytes().unwrap().to_vec()),
+            };
+
+            fields.push(field);
+        }
+
+        fields
+    }
+
+    fn text(name: &str, value: &str) -> Field {
+        (name.to_string(), None, value.as_bytes().to_vec())
+    }
+
+    fn serialize_err<T: Serialize + ?Sized>(value: &T) -> SerializeError {
+        Multipart::new().add_serialized(value).err().unwrap()
+    }
+
+    /// Implements `Serialize` as a struct with the given fields, as `#[derive(Serialize)]` would.
+    macro_rules! serialize_struct (
+        ($ty:ident { $($field:ident),* }) => (
+            impl Serialize for $ty {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    let mut state = try!(serializer.serialize_struct(stringify!($ty), 0));
+                    $(try!(state.serialize_field(stringify!($field), &self.$field));)*
+                    state.end()
+                }
+            }
+        )
+    );
+
+    struct Scalars {
+        text: &'static str,
+        int: i32,
+        float: f64,
+        flag: bool,
+        letter: char,
+    }
+
+    serialize_struct!(Scalars { text, int, float, flag, letter });
+
+    struct Sequences {
+        tags: Vec<&'static str>,
+        pair: (u8, bool),
+        empty: Vec<u32>,
+    }
+
+    serialize_struct!(Sequences { tags, pair, empty });
+
+    struct Optional {
+        present: Option<&'static str>,
+        absent: Option<&'static str>,
+        unit: (),
+    }
+
+    serialize_struct!(Optional { present, absent, unit });
+
+    struct Upload {
+        title: &'static str,
+        file: FilePart<PathBuf>,
+    }
+
+    serialize_struct!(Upload { title, file });
+
+    struct Nested {
+        outer: &'static str,
+        inner: Scalars,
+    }
+
+    serialize_struct!(Nested { outer, inner });
+
+    struct Bytes(&'static [u8]);
+
+    impl Serialize for Bytes {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+
+    struct Binary {
+        data: Bytes,
+    }
+
+    serialize_struct!(Binary { data });
+
+    #[test]
+    fn test_text_fields() {
+        let scalars = Scalars { text: "välue", int: -42, float: 1.5, flag: true, letter: 'x' };
+
+        assert_eq!(round_trip(&scalars), [
+            text("text", "välue"),
+            text("int", "-42"),
+            text("float", "1.5"),
+            text("flag", "true"),
+            text("letter", "x"),
+        ]);
+    }
+
+    #[test]
+    fn test_map() {
+        let mut map = BTreeMap::new();
+        map.insert("a", vec![1, 2]);
+        map.insert("b", vec![3]);
+
+        assert_eq!(round_trip(&map), [text("a", "1"), text("a", "2"), text("b", "3")]);
+    }
+
+    #[test]
+    fn test_sequences() {
+        let sequences = Sequences { tags: vec!["one", "two"], pair: (7, false), empty: vec![] };
+
+        assert_eq!(round_trip(&sequences), [
+            text("tags", "one"),
+            text("tags", "two"),
+            text("pair", "7"),
+            text("pair", "false"),
+        ]);
+    }
+
+    #[test]
+    fn test_skipped_fields() {
+        let optional = Optional { present: Some("here"), absent: None, unit: () };
+        assert_eq!(round_trip(&optional), [text("present", "here")]);
+    }
+
+    #[test]
+    fn test_file_part() {
+        let dir = TempDir::new("multipart-serialize").unwrap();
+        let path = dir.path().join("upload.txt");
+        File::create(&path).unwrap().write_all(b"file contents").unwrap();
+
+        let upload = Upload { title: "Holiday", file: FilePart(path) };
+
+        assert_eq!(round_trip(&upload), [
+            text("title", "Holiday"),
+            ("file".to_string(), Some("upload.txt".to_string()), b"file contents".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn test_bytes() {
+        let fields = round_trip(&Binary { data: Bytes(b"\x00\xFFbytes") });
+        assert_eq!(fields, [("data".to_string(), None, b"\x00\xFFbytes".to_vec())]);
+    }
+
+    #[test]
+    fn test_unsupported_types() {
+        fn assert_unsupported(err: SerializeError, expected: &str) {
+            match err {
+                SerializeError::UnsupportedType(ty) => assert_eq!(ty, expected),
+                err => panic!("expected an unsupported type error, got {:?}", err),
+            }
+        }
+
+        let scalars = Scalars { text: "", int: 0, float: 0.0, flag: false, letter: ' ' };
+        assert_unsupported(serialize_err(&Nested { outer: "outer", inner: scalars }), "a nested struct");
+
+        assert_unsupported(serialize_err(&42), "an integer");
+        assert_unsupported(serialize_err("text"), "a string");
+        assert_unsupported(serialize_err(&vec![1, 2]), "a sequence");
+
+        let mut nested = BTreeMap::new();
+        nested.insert("map", BTreeMap::<&str, &str>::new());
+        assert_unsupported(serialize_err(&nested), "a nested map");
+
+        let mut keys = BTreeMap::new();
+        keys.insert(vec![1], "value");
+        assert_unsupported(serialize_err(&keys), "a sequence as a key");
+    }
+}
@@ -0,0 +1,469 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Asynchronous multipart request bodies, enabled with the `async` feature (optional).
+//!
+//! [`Multipart`](struct.Multipart.html) collects fields like
+//! [`lazy::Multipart`](../lazy/struct.Multipart.html), but accepts asynchronous sources for field
+//! contents. The resulting [`MultipartStream`](struct.MultipartStream.html) is both a
+//! `futures::Stream` of `Bytes` chunks and a `futures::io::AsyncRead`, so it can be handed to any
+//! async HTTP client (e.g. `surf::Body::from_reader()` or `isahc::AsyncBody::from_reader()`).
+//! Field contents are passed through as they become available, without buffering the whole
+//! body or blocking a thread.
+//!
+//! ```no_run
+//! # extern crate futures;
+//! # extern crate multipart;
+//! use futures::io::Cursor;
+//! use multipart::client::stream::Multipart;
+//!
+//! # fn main() {
+//! let mut multipart = Multipart::new();
+//! multipart.add_text("hello", "world")
+//!     .add_reader("file", Cursor::new(b"file contents".to_vec()), Some("file.txt"), None);
+//!
+//! let body = multipart.prepare();
+//! let content_type = format!("multipart/form-data; boundary={}", body.boundary());
+//! // Send `body` with `content_type`...
+//! # }
+//! ```
+use bytes::{Buf, Bytes, BytesMut};
+
+use futures::io::{AllowStdIo, AsyncRead};
+use futures::stream::Stream;
+
+use mime::Mime;
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::{cmp, io, mem};
+
+use super::MultipartWriter;
+
+/// The size of the chunks read from `AsyncRead` fields.
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// The stream type of a single field's contents.
+type PartStream = Pin<Box<Stream<Item = io::Result<Bytes>> + Send>>;
+
+/// A multipart request body builder whose fields may be read asynchronously.
+///
+/// Reusable; fields are moved out by [`prepare()`](#method.prepare).
+#[derive(Default)]
+pub struct Multipart {
+    fields: Vec<Field>,
+    boundary: Option<String>,
+}
+
+impl Multipart {
+    /// Initialize a new asynchronous request body.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Use the given boundary for this request instead of a randomly generated one.
+    ///
+    /// The boundary must not occur in any of the field data.
+    ///
+    /// ## Panics
+    /// If `boundary` is not a valid boundary per RFC 2046: 1 to 70 characters, consisting of
+    /// ASCII letters and digits, or any of `'()+_,-./:=?` and space (not at the end).
+    pub fn set_boundary<B: Into<String>>(&mut self, boundary: B) -> &mut Self {
+        let boundary = boundary.into();
+        super::assert_valid_boundary(&boundary);
+        self.boundary = Some(boundary);
+        self
+    }
+
+    /// Add a text field to this request.
+    pub fn add_text<N, T>(&mut self, name: N, text: T) -> &mut Self where N: Into<String>, T: Into<String> {
+        self.fields.push(Field {
+            name: name.into(),
+            data: Data::Text(text.into()),
+            headers: Vec::new(),
+        });
+
+        self
+    }
+
+    /// Add a file field whose contents are read from a `Stream` of `Bytes` chunks, supplying
+    /// `filename` if given, and `content_type` if given or `application/octet-stream` if not.
+    ///
+    /// The stream must end, or the request will never be completed.
+    pub fn add_stream<N, S, F>(&mut self, name: N, stream: S, filename: Option<F>, content_type: Option<Mime>) -> &mut Self
+    where N: Into<String>, S: Stream<Item = io::Result<Bytes>> + Send + 'static, F: Into<String> {
+        self.fields.push(Field {
+            name: name.into(),
+            data: Data::Stream {
+                filename: filename.map(Into::into),
+                content_type: content_type,
+                stream: Box::pin(stream),
+            },
+            headers: Vec::new(),
+        });
+
+        self
+    }
+
+    /// Add a file field whose contents are read from an `AsyncRead`, supplying `filename` if
+    /// given, and `content_type` if given or `application/octet-stream` if not.
+    ///
+    /// The reader must reach EOF, or the request will never be completed.
+    pub fn add_reader<N, R, F>(&mut self, name: N, reader: R, filename: Option<F>, content_type: Option<Mime>) -> &mut Self
+    where N: Into<String>, R: AsyncRead + Send + 'static, F: Into<String> {
+        let stream = ReaderStream {
+            reader: Box::pin(reader),
+            buf: BytesMut::new(),
+        };
+
+        self.add_stream(name, stream, filename, content_type)
+    }
+
+    /// Add a header to the part of the field which was added last, such as `Content-ID`, which
+    /// is required by some upload APIs.
+    ///
+    /// `Content-Disposition` and `Content-Type` are always written by this builder, and should
+    /// not be added again.
+    ///
+    /// ## Panics
+    /// If no field has been added yet, if `name` is not a valid header name, or if `value`
+    /// contains a line break.
+    pub fn add_part_header<N, V>(&mut self, name: N, value: V) -> &mut Self where N: Into<String>, V: Into<String> {
+        let (name, value) = (name.into(), value.into());
+        super::assert_valid_header(&name, &value);
+
+        self.fields.last_mut().expect("`add_part_header()` called before adding a field")
+            .headers.push((name.into(), value.into()));
+
+        self
+    }
+
+    /// Open the file at `path` and add it as a file field to this request, supplying its filename
+    /// and guessing its `Content-Type` from its extension.
+    ///
+    /// If you want to set these values manually, use `.add_reader()`.
+    ///
+    /// ##Note
+    /// The file is read with blocking I/O, as there is no runtime-agnostic asynchronous file API.
+    /// Local files are usually read quickly enough that this is not an issue; otherwise, open the
+    /// file with the API of your runtime and use `.add_reader()`.
+    ///
+    /// ##Errors
+    /// If the file could not be opened.
+    pub fn add_file<N, P>(&mut self, name: N, path: P) -> io::Result<&mut Self>
+    where N: Into<String>, P: AsRef<Path> {
+        let path = path.as_ref();
+        let (content_type, filename) = super::mime_filename(path);
+        let file = try!(File::open(path));
+
+        Ok(self.add_reader(name, AllowStdIo::new(file), filename, Some(content_type)))
+    }
+
+    /// Move the fields of this request into a `MultipartStream`, with a new random boundary
+    /// unless one was set.
+    pub fn prepare(&mut self) -> MultipartStream {
+        let boundary = self.boundary.clone().unwrap_or_else(super::gen_boundary);
+
+        let mut parts = VecDeque::new();
+        let mut contiguous = Vec::new();
+        let mut sized = true;
+
+        for field in self.fields.drain(..) {
+            let mut writer = MultipartWriter::new(&mut contiguous, &*boundary);
+
+            match field.data {
+                Data::Text(text) => expect_written(writer.write_text(&field.name, &text, &field.headers)),
+                Data::Stream { filename, content_type, stream } => {
+                    // This is necessary to make sure it is interpreted as a file on the server end.
+                    let content_type = Some(content_type.unwrap_or_else(::mime_guess::octet_stream));
+                    let filename = filename.as_ref().map(|f| &**f);
+
+                    expect_written(writer.write_field_headers(&field.name, filename, content_type, &field.headers));
+
+                    let headers = mem::replace(&mut contiguous, Vec::new());
+                    parts.push_back(Part::Bytes(headers.into()));
+                    parts.push_back(Part::Stream(stream));
+                    sized = false;
+                },
+            }
+        }
+
+        contiguous.extend_from_slice(format!("\r\n--{}--", boundary).as_bytes());
+        parts.push_back(Part::Bytes(contiguous.into()));
+
+        let content_len = if sized {
+            Some(parts.iter().map(Part::len).sum())
+        } else {
+            None
+        };
+
+        MultipartStream {
+            boundary: boundary,
+            parts: parts,
+            content_len: content_len,
+            read_buf: Bytes::new(),
+        }
+    }
+}
+
+struct Field {
+    name: String,
+    data: Data,
+    headers: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+}
+
+enum Data {
+    Text(String),
+    Stream {
+        filename: Option<String>,
+        content_type: Option<Mime>,
+        stream: PartStream,
+    },
+}
+
+fn expect_written(res: io::Result<()>) {
+    res.expect("writing to a Vec<u8> cannot fail")
+}
+
+/// The body of an asynchronous multipart request, returned by
+/// [`Multipart::prepare()`](struct.Multipart.html#method.prepare).
+///
+/// Implements both `Stream<Item = io::Result<Bytes>>` and `AsyncRead`; only one of them should be
+/// used for a given body.
+pub struct MultipartStream {
+    boundary: String,
+    parts: VecDeque<Part>,
+    content_len: Option<u64>,
+    /// The unread remainder of the current chunk, when used as `AsyncRead`.
+    read_buf: Bytes,
+}
+
+enum Part {
+    Bytes(Bytes),
+    Stream(PartStream),
+}
+
+impl Part {
+    fn len(&self) -> u64 {
+        match *self {
+            Part::Bytes(ref bytes) => bytes.len() as u64,
+            Part::Stream(_) => 0,
+        }
+    }
+}
+
+impl MultipartStream {
+    /// Get the boundary string of this request body, which must be supplied in the
+    /// `Content-Type` header as `multipart/form-data; boundary={boundary}`.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Get the content-length value for this request body, if applicable (all fields are text,
+    /// i.e. not streams).
+    pub fn content_len(&self) -> Option<u64> {
+        self.content_len
+    }
+}
+
+impl Stream for MultipartStream {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<io::Result<Bytes>>> {
+        let this = self.get_mut();
+
+        loop {
+            let chunk = match this.parts.front_mut() {
+                Some(&mut Part::Bytes(ref mut bytes)) => Some(mem::replace(bytes, Bytes::new())),
+                Some(&mut Part::Stream(ref mut stream)) => match stream.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(Ok(chunk))) => if chunk.is_empty() {
+                        continue;
+                    } else {
+                        return Poll::Ready(Some(Ok(chunk)));
+                    },
+                    Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                    Poll::Ready(None) => None,
+                    Poll::Pending => return Poll::Pending,
+                },
+                None => return Poll::Ready(None),
+            };
+
+            this.parts.pop_front();
+
+            if let Some(chunk) = chunk {
+                return Poll::Ready(Some(Ok(chunk)));
+            }
+        }
+    }
+}
+
+impl AsyncRead for MultipartStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        while this.read_buf.is_empty() {
+            match Pin::new(&mut *this).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => this.read_buf = chunk,
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Err(err)),
+                Poll::Ready(None) => return Poll::Ready(Ok(0)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        let len = cmp::min(buf.len(), this.read_buf.len());
+        buf[..len].copy_from_slice(&this.read_buf[..len]);
+        this.read_buf.advance(len);
+
+        Poll::Ready(Ok(len))
+    }
+}
+
+/// Adapts an `AsyncRead` to a `Stream` of `Bytes` chunks.
+struct ReaderStream<R> {
+    reader: Pin<Box<R>>,
+    buf: BytesMut,
+}
+
+impl<R: AsyncRead> Stream for ReaderStream<R> {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<io::Result<Bytes>>> {
+        let this = self.get_mut();
+        this.buf.resize(READ_CHUNK_SIZE, 0);
+
+        loop {
+            match this.reader.as_mut().poll_read(cx, &mut this.buf) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(None),
+                Poll::Ready(Ok(read)) => return Poll::Ready(Some(Ok(this.buf.split_to(read).freeze()))),
+                Poll::Ready(Err(ref err)) if err.kind() == io::ErrorKind::Interrupted => (),
+                Poll::Ready(Err(err)) => return Poll::Ready(Some(Err(err))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod test {
+    use bytes::Bytes;
+
+    use futures::executor::block_on;
+    use futures::io::{AsyncReadExt, Cursor};
+    use futures::stream::{self, TryStreamExt};
+
+    use server::Multipart as Server;
+
+    use std::io::{self, Read};
+
+    use super::Multipart;
+
+    const BOUNDARY: &'static str = "test-boundary";
+
+    fn test_multipart(chunks: &[&'static [u8]]) -> Multipart {
+        let chunks: Vec<_> = chunks.iter().map(|&chunk| Ok(Bytes::from_static(chunk))).collect();
+
+        let mut multipart = Multipart::new();
+        multipart.set_boundary(BOUNDARY)
+            .add_text("text", "välue")
+            .add_part_header("Content-ID", "<text>")
+            .add_stream("stream", stream::iter(chunks), Some("stream.bin"), None)
+            .add_reader("reader", Cursor::new(vec![0xFF; 20 * 1024]), None::<String>, Some("text/plain".parse().unwrap()));
+
+        multipart
+    }
+
+    fn check_body(body: Vec<u8>) {
+
+        {
+            let mut server = Server::with_body(&body[..], BOUNDARY);
+            let part = server.read_part().unwrap().unwrap();
+            assert_eq!(part.header("Content-ID"), Some("<text>"));
+        }
+
+        let mut server = Server::with_body(&body[..], BOUNDARY);
+
+        {
+            let field = server.read_entry().unwrap().unwrap();
+            assert_eq!(field.name, "text");
+            assert_eq!(field.data.as_text(), Some("välue"));
+        }
+
+        {
+            let mut field = server.read_entry().unwrap().unwrap();
+            assert_eq!(field.name, "stream");
+
+            let file = field.data.as_file().unwrap();
+            assert_eq!(file.filename(), Some("stream.bin"));
+            assert_eq!(file.content_type().to_string(), "application/octet-stream");
+
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).unwrap();
+            assert_eq!(data, b"first chunk, second chunk");
+        }
+
+        {
+            let mut field = server.read_entry().unwrap().unwrap();
+            assert_eq!(field.name, "reader");
+
+            let file = field.data.as_file().unwrap();
+            assert_eq!(file.filename(), None);
+            assert_eq!(file.content_type().to_string(), "text/plain");
+
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).unwrap();
+            assert_eq!(data, vec![0xFF; 20 * 1024]);
+        }
+
+        assert!(server.read_entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_round_trip_stream() {
+        let body = test_multipart(&[b"first chunk", b"", b", second chunk"]).prepare();
+        assert_eq!(body.content_len(), None);
+
+        let chunks: Vec<Bytes> = block_on(body.try_collect()).unwrap();
+        assert!(chunks.iter().all(|chunk| !chunk.is_empty()));
+
+        check_body(chunks.concat());
+    }
+
+    #[test]
+    fn test_round_trip_read() {
+        let mut body = test_multipart(&[b"first chunk, ", b"second chunk"]).prepare();
+
+        let mut buf = Vec::new();
+        block_on(body.read_to_end(&mut buf)).unwrap();
+
+        check_body(buf);
+    }
+
+    #[test]
+    fn test_content_len() {
+        let mut multipart = Multipart::new();
+        multipart.add_text("a", "1").add_text("b", "2");
+
+        let body = multipart.prepare();
+        let content_len = body.content_len();
+
+        let chunks: Vec<Bytes> = block_on(body.try_collect()).unwrap();
+        assert_eq!(content_len, Some(chunks.concat().len() as u64));
+    }
+
+    #[test]
+    fn test_stream_error() {
+        let mut multipart = Multipart::new();
+        let chunks = vec![Ok(Bytes::from_static(b"data")), Err(io::Error::new(io::ErrorKind::Other, "failed"))];
+        multipart.add_stream("stream", stream::iter(chunks), None::<String>, None);
+
+        let err = block_on(multipart.prepare().try_collect::<Vec<_>>()).err().unwrap();
+        assert_eq!(err.to_string(), "failed");
+    }
+}
@@ -14,7 +14,22 @@
 //! * `server` (default): Enable the server-side abstractions for multipart requests. If the
 //! `hyper` feature is also set, enables integration with the Hyper HTTP server API.
 //!
-//! * `hyper` (default): Enable integration with the [Hyper](https://github.com/hyperium/hyper) HTTP library 
+//! `client` and `server` are independent of each other; a service which only parses requests
+//! can set `default-features = false, features = ["server"]` to leave out the client.
+//!
+//! * `std` (default, required by `client` and `server`): Link the standard library. Without it,
+//! the crate is `no_std` and only needs `alloc`.
+//!
+//! * `push`: Enable the [`push`](push/index.html) module, a sans-I/O parser which works on byte
+//! slices and only needs `alloc`, so it can be used without `std`, e.g. by embedded HTTP stacks,
+//! with `default-features = false, features = ["push"]`. Implied by `server`.
+//!
+//! * `simd` (default): Detect at runtime whether the CPU supports AVX2 when searching for
+//! boundaries on x86-64, instead of only using SSE2. SIMD search is always used on AArch64
+//! (NEON). Requires `std` support in `memchr`; disable it for targets without CPU feature
+//! detection.
+//!
+//! * `hyper`: Enable integration with the [Hyper](https://github.com/hyperium/hyper) HTTP library
 //! for client and/or server depending on which other feature flags are set.
 //!
 //! * `iron`: Enable integration with the [Iron](http://ironframework.io) web application
@@ -26,30 +41,296 @@
 //! * `nickel_`: Enable integration with the [Nickel](http://nickel.rs) web application framework.
 //! See the [`server::nickel`](server/nickel/index.html) module for more information. Enables the `hyper`
 //! feature.
+//!
+//! * `gotham_`: Enable integration with the [Gotham](https://gotham.rs) web framework.
+//! See the [`server::gotham`](server/gotham/index.html) module for more information. Enables the
+//! `futures` dependency.
+//!
+//! * `hyper1_`: Enable server-side integration with Hyper 1.x, whose request bodies are
+//! asynchronous. See the [`server::hyper1`](server/hyper1/index.html) module for more information.
+//! Enables the `http` feature.
+//!
+//! * `h2_`: Enable server-side integration with the [h2](https://github.com/hyperium/h2) HTTP/2
+//! implementation, releasing flow control capacity as request bodies are parsed. See the
+//! [`server::h2`](server/h2/index.html) module for more information. Enables the `http` and
+//! `async` features.
+//!
+//! * `http`: Enable server-side integration with the request types of the
+//! [`http`](https://github.com/hyperium/http) crate. See the [`server::http`](server/http/index.html)
+//! module for more information.
+//!
+//! * `tower_`: Enable a [Tower](https://github.com/tower-rs/tower) `Layer` and `Service` for
+//! intercepting multipart requests. See the [`server::tower`](server/tower/index.html) module for
+//! more information. Enables the `http` and `tokio_` features.
+//!
+//! * `reqwest`: Enable client-side integration with the [reqwest](https://github.com/seanmonstar/reqwest)
+//! HTTP client, both blocking and asynchronous. See
+//! [`client::lazy::Multipart::reqwest_request()`](client/lazy/struct.Multipart.html#method.reqwest_request)
+//! for more information.
+//!
+//! * `curl`: Enable client-side integration with [libcurl](https://github.com/alexcrichton/curl-rust)
+//! via the `curl` crate. See
+//! [`client::lazy::PreparedFields::curl_setup()`](client/lazy/struct.PreparedFields.html#method.curl_setup)
+//! for more information.
+//!
+//! * `ureq`: Enable client-side integration with the [ureq](https://github.com/algesten/ureq)
+//! blocking HTTP client. See
+//! [`client::lazy::Multipart::ureq_request()`](client/lazy/struct.Multipart.html#method.ureq_request)
+//! for more information.
+//!
+//! * `wasm-bindgen_`: Enable converting client requests into a `Uint8Array` or `Blob` for the
+//! browser's `fetch()`, via [`wasm-bindgen`](https://github.com/rustwasm/wasm-bindgen). See
+//! [`client::lazy::Multipart::to_blob()`](client/lazy/struct.Multipart.html#method.to_blob)
+//! for more information.
+//!
+//! The client compiles for `wasm32-unknown-unknown` with `default-features = false` and
+//! `features = ["client"]`. There is no filesystem in the browser, so use `add_text()`,
+//! `add_bytes()` or `add_stream()` rather than the methods taking file paths, which fail at runtime.
+//!
+//! * `serde`: Enable building client requests from any `serde::Serialize` value. See the
+//! [`client::serialize`](client/serialize/index.html) module for more information.
+//!
+//! * `flate2`: Enable gzip compression of individual parts of client requests, and decoding of
+//! parts sent with `Content-Encoding: gzip` by the blocking server-side parser. See
+//! [`client::lazy::Multipart::gzip_part()`](client/lazy/struct.Multipart.html#method.gzip_part)
+//! and [`server::MultipartFile::content_encoding()`](server/struct.MultipartFile.html#method.content_encoding)
+//! for more information.
+//!
+//! * `async`: Enable the asynchronous server-side parser, which reads from a
+//! `futures::Stream` of `bytes::Bytes` chunks, and asynchronous client-side request bodies for
+//! async HTTP clients. See the [`server::stream`](server/stream/index.html) and
+//! [`client::stream`](client/stream/index.html) modules for more information.
+//!
+//! * `async-std_`: Enable saving the fields of asynchronously parsed requests with
+//! [async-std](https://async.rs), for async-std and Tide servers which don't use tokio. See the
+//! [`server::async_std`](server/async_std/index.html) module for more information. Enables the
+//! `async` feature.
+//!
+//! * `tokio_`: Enable saving the fields of asynchronously parsed requests with
+//! [tokio](https://tokio.rs)'s file system API, so spooling uploads to disk doesn't block the
+//! reactor, and for running the blocking server-side parser on tokio's blocking thread pool.
+//! See the [`server::tokio`](server/tokio/index.html) module for more information. Enables the
+//! `async` feature.
+//!
+//! * `tokio-uring_`: Enable saving the fields of asynchronously parsed requests with io_uring via
+//! [tokio-uring](https://github.com/tokio-rs/tokio-uring), on Linux only. See the
+//! [`server::tokio_uring`](server/tokio_uring/index.html) module for more information. Enables
+//! the `async` feature. Not included in `all`.
+//!
+//! * `mmap`: Enable saving very large files through memory maps, when their size is given
+//! in their part's `Content-Length` header. See
+//! [`server::MultipartFile::save_as_mmap()`](server/struct.MultipartFile.html#method.save_as_mmap)
+//! for more information.
+//!
+//! * `splice`: Enable moving file fields with a known size from a socket to a file with
+//! `splice()`, on Linux only. See
+//! [`server::MultipartFile::save_as_splice()`](server/struct.MultipartFile.html#method.save_as_splice)
+//! for more information. Not included in `all`.
+//!
+//! * `diskspace`: Enable checking the free space of the filesystem before and while files are
+//! stored, on Unix only. See
+//! [`server::storage::LocalStorage::check_free_space()`](server/storage/struct.LocalStorage.html#method.check_free_space)
+//! for more information. Not included in `all`.
+//!
+//! * `sha2`: Compute the SHA-256 digests of files as they are saved by the server-side parsers,
+//! in [`server::SavedFile::sha256`](server/struct.SavedFile.html#structfield.sha256).
+//!
+//! * `checksum`: Verify file fields against the digests in their `Content-MD5` or `Digest`
+//! headers as they are read by the blocking server-side parser, failing with an error of kind
+//! `io::ErrorKind::InvalidData` if they don't match. See
+//! [`server::Checksum`](server/enum.Checksum.html) for more information.
+//!
+//! * `encrypt`: Enable encrypting the files saved by the server-side parsers with an ephemeral
+//! key, so they are never written to disk in plaintext. See
+//! [`server::Multipart::encrypt()`](server/struct.Multipart.html#method.encrypt)
+//! for more information.
+//!
+//! * `imagesize`: Enable checking the real formats and pixel dimensions of uploaded images as
+//! they are saved, to guard against decompression bombs. See
+//! [`server::ImageValidator`](server/struct.ImageValidator.html) for more information.
+//!
+//! * `infer`: Detect the types of file fields from their magic bytes with the
+//! [`infer`](https://github.com/bojand/infer) crate, to find files whose contents contradict
+//! their declared `Content-Type`. See
+//! [`server::MultipartFile::detected_type()`](server/struct.MultipartFile.html#method.detected_type)
+//! for more information.
+//!
+//! * `s3`: Enable storing the file fields of requests in Amazon S3 with multipart uploads,
+//! without writing them to local disk. See the [`server::s3`](server/s3/index.html) module for
+//! more information.
+//!
+//! * `lambda`: Enable parsing the bodies of AWS Lambda events from API Gateway, which may be
+//! base64-encoded. See the [`server::lambda`](server/lambda/index.html) module for more
+//! information.
+//!
+//! * `zip`: Enable extracting saved ZIP archives with limits against zip bombs. See
+//! [`server::SavedFile::extract_zip()`](server/struct.SavedFile.html#method.extract_zip) for
+//! more information.
+//!
+//! * `zstd`: Enable compressing the files saved by the server-side parsers with zstd, to
+//! reduce temporary disk usage. See
+//! [`server::Multipart::compress()`](server/struct.Multipart.html#method.compress)
+//! for more information.
+//!
+//! * `bench`: Enable the benchmarks of the server-side parsers, run with
+//! `cargo bench --features bench`.
+//! Requires a nightly compiler.
+#![cfg_attr(feature = "bench", feature(test))]
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![warn(missing_docs)]
+#![deny(unsafe_code)]
+#[cfg(feature = "std")]
 #[macro_use] extern crate log;
+#[cfg(test)]
 extern crate env_logger;
+#[cfg(feature = "std")]
+extern crate getrandom;
 
+#[cfg(feature = "std")]
 extern crate mime;
+#[cfg(feature = "std")]
 extern crate mime_guess;
+#[cfg(feature = "std")]
 extern crate rand;
 
+#[cfg(test)]
 extern crate tempdir;
 
+#[cfg(feature = "push")]
+extern crate alloc;
+
+// Injected by `no_std` otherwise.
+#[cfg(all(feature = "push", any(feature = "std", test)))]
+extern crate core;
+
+#[cfg(feature = "async-std")]
+extern crate async_std;
+
+#[cfg(feature = "aws-sdk-s3")]
+extern crate aws_sdk_s3;
+#[cfg(all(test, feature = "aws-sdk-s3"))]
+extern crate aws_smithy_runtime_api;
+
+#[cfg(feature = "base64")]
+extern crate base64;
+
+#[cfg(feature = "bytes")]
+extern crate bytes;
+
+#[cfg(feature = "chacha20")]
+extern crate chacha20;
+
+#[cfg(feature = "curl")]
+extern crate curl;
+
+#[cfg(feature = "flate2")]
+extern crate flate2;
+
+#[cfg(feature = "futures")]
+extern crate futures;
+
+#[cfg(feature = "gotham")]
+extern crate gotham;
+
+#[cfg(feature = "h2")]
+extern crate h2;
+
 #[cfg(feature = "hyper")]
 extern crate hyper;
 
+#[cfg(feature = "hyper1")]
+extern crate hyper1;
+
+#[cfg(feature = "http")]
+extern crate http;
+
+#[cfg(feature = "http-body")]
+extern crate http_body;
+
+#[cfg(feature = "http-body-util")]
+extern crate http_body_util;
+
+#[cfg(feature = "imagesize")]
+extern crate imagesize;
+
+#[cfg(feature = "infer")]
+extern crate infer;
+
 #[cfg(feature = "iron")]
 extern crate iron;
 
+#[cfg(feature = "js-sys")]
+extern crate js_sys;
+
+#[cfg(all(feature = "libc", target_os = "linux"))]
+extern crate libc;
+
+#[cfg(feature = "md-5")]
+extern crate md5;
+
+#[cfg(feature = "memchr")]
+extern crate memchr;
+
+#[cfg(feature = "memmap2")]
+extern crate memmap2;
+
 #[cfg(feature = "nickel")]
 extern crate nickel;
 
+#[cfg(feature = "reqwest")]
+extern crate reqwest;
+
+#[cfg(all(feature = "rustix", unix))]
+extern crate rustix;
+
+#[cfg(all(feature = "self_cell", target_os = "linux"))]
+#[macro_use] extern crate self_cell;
+
+#[cfg(feature = "serde")]
+extern crate serde;
+
+#[cfg(feature = "sha2")]
+extern crate sha2;
+
 #[cfg(feature = "tiny_http")]
 extern crate tiny_http;
 
+#[cfg(feature = "tokio")]
+extern crate tokio;
+
+#[cfg(all(feature = "tokio-uring", target_os = "linux"))]
+extern crate tokio_uring;
+
+#[cfg(feature = "tower-layer")]
+extern crate tower_layer;
+
+#[cfg(feature = "tower-service")]
+extern crate tower_service;
+
+#[cfg(feature = "ureq")]
+extern crate ureq;
+
+#[cfg(feature = "wasm-bindgen")]
+extern crate wasm_bindgen;
+
+#[cfg(feature = "web-sys")]
+extern crate web_sys;
+
+#[cfg(feature = "zeroize")]
+extern crate zeroize;
+
+#[cfg(feature = "zip")]
+extern crate zip;
+
+#[cfg(feature = "zstd")]
+extern crate zstd;
+
+#[cfg(all(feature = "std", not(all(target_arch = "wasm32", target_os = "unknown"))))]
 use rand::Rng;
 
+#[cfg(feature = "std")]
+use std::io;
+
 /// Chain a series of results together, with or without previous results.
 ///
 /// ```
@@ -87,13 +368,81 @@ macro_rules! chain_result {
 
 #[cfg(feature = "client")]
 pub mod client;
+#[cfg(any(feature = "client", feature = "server"))]
+pub mod mock;
+#[cfg(feature = "push")]
+pub mod push;
 #[cfg(feature = "server")]
 pub mod server;
 
 #[cfg(all(test, feature = "client", feature = "server"))]
 mod local_test;
 
+/// The maximum length of a boundary, as specified by RFC 2046.
+#[cfg(any(feature = "client", feature = "server"))]
+const MAX_BOUNDARY_LEN: usize = 70;
+
+#[cfg(any(feature = "client", feature = "server"))]
+const ALPHANUMERIC: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+#[cfg(all(feature = "std", not(all(target_arch = "wasm32", target_os = "unknown"))))]
 fn random_alphanumeric(len: usize) -> String {
     rand::thread_rng().gen_ascii_chars().take(len).collect()
 }
 
+/// `rand` has no source of randomness in the browser, so `getrandom`, which uses the Web Crypto
+/// API there, is used instead.
+#[cfg(all(feature = "std", target_arch = "wasm32", target_os = "unknown"))]
+fn random_alphanumeric(len: usize) -> String {
+    secure_random_alphanumeric(len).expect("no source of randomness available")
+}
+
+/// Generate a random alphanumeric string with the operating system's random number generator.
+#[cfg(feature = "std")]
+fn secure_random_alphanumeric(len: usize) -> io::Result<String> {
+    let mut string = String::with_capacity(len);
+    let mut buf = [0; 32];
+
+    while string.len() < len {
+        try!(getrandom::getrandom(&mut buf)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string())));
+
+        // Bytes from 248 up are discarded, so every character is equally likely.
+        for &byte in buf.iter().filter(|&&byte| byte < 248) {
+            if string.len() == len {
+                break;
+            }
+
+            string.push(ALPHANUMERIC[(byte % 62) as usize] as char);
+        }
+    }
+
+    Ok(string)
+}
+
+/// Panic if `boundary` is not a valid boundary per RFC 2046.
+#[cfg(any(feature = "client", feature = "server"))]
+fn assert_valid_boundary(boundary: &str) {
+    fn is_bchar(c: char) -> bool {
+        c.is_ascii_alphanumeric() || "'()+_,-./:=? ".contains(c)
+    }
+
+    assert!(
+        !boundary.is_empty() && boundary.len() <= MAX_BOUNDARY_LEN
+            && boundary.chars().all(is_bchar) && !boundary.ends_with(' '),
+        "invalid multipart boundary: {:?}", boundary
+    );
+}
+
+/// Panic if `name` is not a valid header name, or if `value` contains a line break, which would
+/// allow injecting arbitrary headers or data into the body.
+#[cfg(any(feature = "client", feature = "server"))]
+fn assert_valid_header(name: &str, value: &str) {
+    fn is_tchar(c: char) -> bool {
+        c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+    }
+
+    assert!(!name.is_empty() && name.chars().all(is_tchar), "invalid header name: {:?}", name);
+    assert!(!value.contains(|c| c == '\r' || c == '\n'), "invalid value for header {:?}: {:?}", name, value);
+}
+
@@ -4,16 +4,12 @@
 // http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
-use client::HttpRequest as ClientRequest;
-use client::HttpStream as ClientStream;
-
-use server::HttpRequest as ServerRequest;
+use mock::{ClientRequest, HttpBuffer};
 
 use rand::Rng;
 use rand::distributions::{Range, Sample};
 
 use std::collections::HashMap;
-use std::io;
 use std::io::prelude::*;
 
 #[derive(Debug)]
@@ -79,7 +75,7 @@ fn gen_bytes() -> Vec<u8> {
 fn test_client(test_fields: &TestFields) -> HttpBuffer {
     use client::Multipart;
 
-    let request = MockClientRequest::default();
+    let request = ClientRequest::default();
 
     let mut test_files = test_fields.files.iter();
 
@@ -131,6 +127,7 @@ fn test_server(buf: HttpBuffer, mut fields: TestFields) {
                 );
 
             },
+            MultipartData::Bytes(bytes) => panic!("Unexpected raw text field {:?}: {:?}", field.name, bytes),
             MultipartData::File(ref mut file) => {
                 let test_bytes = fields.files.remove(&field.name).unwrap();
 
@@ -147,92 +144,3 @@ fn test_server(buf: HttpBuffer, mut fields: TestFields) {
     assert!(fields.texts.is_empty(), "Text fields were not exhausted! Text fields: {:?}", fields.texts);
     assert!(fields.files.is_empty(), "File fields were not exhausted! File fields: {:?}", fields.files);
 }
-
-#[derive(Default, Debug)]
-pub struct MockClientRequest {
-    boundary: Option<String>,
-    content_len: Option<u64>,
-}
-
-impl ClientRequest for MockClientRequest {
-    type Stream = HttpBuffer;
-    type Error = io::Error;
-    
-    fn apply_headers(&mut self, boundary: &str, content_len: Option<u64>) -> bool {
-        self.boundary = Some(boundary.into());
-        self.content_len = content_len;
-        true
-    }
-
-    fn open_stream(self) -> Result<HttpBuffer, io::Error> {
-        debug!("MockClientRequest::open_stream called! {:?}", self);
-        let boundary = self.boundary.expect("HttpRequest::set_headers() was not called!");
-        
-        Ok(HttpBuffer { buf: Vec::new(), boundary: boundary, content_len: self.content_len })
-    }
-}
-
-#[derive(Debug)]
-pub struct HttpBuffer {
-    buf: Vec<u8>,
-    boundary: String,
-    content_len: Option<u64>,
-}
-
-impl HttpBuffer {
-    pub fn for_server(&self) -> ServerBuffer {
-        ServerBuffer {
-            data: &self.buf,
-            boundary: &self.boundary,
-            content_len: self.content_len,
-        }
-    }
-}
-
-impl Write for HttpBuffer {
-    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
-        self.buf.write(data)
-    }
-
-    fn flush(&mut self) -> io::Result<()> {
-        self.buf.flush()
-    }
-}
-
-impl Read for HttpBuffer {
-    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
-        unimplemented!()
-    }
-}
-
-impl ClientStream for HttpBuffer {
-    type Request = MockClientRequest;
-    type Response = HttpBuffer;
-    type Error = io::Error;
-
-    fn finish(self) -> Result<Self, io::Error> { Ok(self) }
-}
-
-#[derive(Debug)]
-pub struct ServerBuffer<'a> {
-    data: &'a [u8],
-    boundary: &'a str,
-    content_len: Option<u64>,
-}
-
-impl<'a> Read for ServerBuffer<'a> {
-    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
-        self.data.read(out)
-    }
-}
-
-impl<'a> ServerRequest for ServerBuffer<'a> {
-    type Body = Self;
-
-    fn multipart_boundary(&self) -> Option<&str> { Some(&self.boundary) }
-
-    fn body(self) -> Self::Body {
-        self
-    }
-}
-
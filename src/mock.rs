@@ -0,0 +1,266 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Mock requests for testing code which builds or handles multipart requests, without opening
+//! sockets.
+//!
+//! [`ClientRequest`](struct.ClientRequest.html) can be passed to the client-side `Multipart`,
+//! which writes the request into an [`HttpBuffer`](struct.HttpBuffer.html).
+//! [`MockHttpRequest`](struct.MockHttpRequest.html) holds the headers and body of a request
+//! in memory, and is accepted by the server-side `Multipart`.
+//!
+//! ```rust
+//! # #[cfg(all(feature = "client", feature = "server"))]
+//! # fn main() {
+//! use multipart::client::Multipart as Client;
+//! use multipart::mock::ClientRequest;
+//! use multipart::server::Multipart as Server;
+//!
+//! let mut client = Client::from_request(ClientRequest::default()).unwrap();
+//! client.write_text("text", "value").unwrap();
+//! let buffer = client.send().unwrap();
+//!
+//! let mut server = Server::from_request(buffer.for_server()).ok().expect("not multipart");
+//! let field = server.read_entry().unwrap().unwrap();
+//! assert_eq!(field.name, "text");
+//! assert_eq!(field.data.as_text(), Some("value"));
+//! # }
+//! # #[cfg(not(all(feature = "client", feature = "server")))]
+//! # fn main() {}
+//! ```
+#[cfg(feature = "server")]
+use mime::{Mime, TopLevel};
+
+use std::io::{self, Write};
+
+#[cfg(feature = "server")]
+use std::io::Cursor;
+
+#[cfg(feature = "client")]
+use client;
+
+#[cfg(feature = "server")]
+use server::{self, accepts_subtype, parse_boundary, DEFAULT_METHODS, DEFAULT_SUBTYPES};
+
+/// A client request which opens an [`HttpBuffer`](struct.HttpBuffer.html) instead of a
+/// connection.
+///
+/// #### Feature: `client`
+#[cfg(feature = "client")]
+#[derive(Clone, Debug, Default)]
+pub struct ClientRequest {
+    boundary: Option<String>,
+    content_len: Option<u64>,
+}
+
+#[cfg(feature = "client")]
+impl client::HttpRequest for ClientRequest {
+    type Stream = HttpBuffer;
+    type Error = io::Error;
+
+    fn apply_headers(&mut self, boundary: &str, content_len: Option<u64>) -> bool {
+        self.boundary = Some(boundary.into());
+        self.content_len = content_len;
+        true
+    }
+
+    /// ## Panics
+    /// If `apply_headers()` was not called.
+    fn open_stream(self) -> Result<HttpBuffer, io::Error> {
+        let boundary = self.boundary.expect("HttpRequest::apply_headers() was not called!");
+
+        Ok(HttpBuffer {
+            buf: Vec::new(),
+            boundary: boundary,
+            content_len: self.content_len,
+        })
+    }
+}
+
+/// The body of a request written by the client-side `Multipart`, along with the headers it set.
+#[derive(Clone, Debug)]
+pub struct HttpBuffer {
+    /// The request body written so far.
+    pub buf: Vec<u8>,
+    /// The boundary of the request.
+    pub boundary: String,
+    /// The `Content-Length` of the request, if it was set.
+    pub content_len: Option<u64>,
+}
+
+impl HttpBuffer {
+    /// Create an empty buffer for a request with the given boundary.
+    pub fn new<B: Into<String>>(boundary: B) -> Self {
+        HttpBuffer {
+            buf: Vec::new(),
+            boundary: boundary.into(),
+            content_len: None,
+        }
+    }
+
+    /// The `Content-Type` header of the request.
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// Create a POST request with the headers and a copy of the body of this request, to be
+    /// read by the server-side `Multipart`.
+    ///
+    /// #### Feature: `server`
+    #[cfg(feature = "server")]
+    pub fn for_server(&self) -> MockHttpRequest {
+        let mut req = MockHttpRequest::new("POST", self.buf.clone());
+        req.header("Content-Type", self.content_type());
+
+        if let Some(content_len) = self.content_len {
+            req.header("Content-Length", content_len.to_string());
+        }
+
+        req
+    }
+}
+
+impl Write for HttpBuffer {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// #### Feature: `client`
+#[cfg(feature = "client")]
+impl client::HttpStream for HttpBuffer {
+    type Request = ClientRequest;
+    type Response = HttpBuffer;
+    type Error = io::Error;
+
+    fn finish(self) -> Result<HttpBuffer, io::Error> {
+        Ok(self)
+    }
+}
+
+/// A server-side request with its headers and body in memory.
+///
+/// Header names are matched case-insensitively.
+///
+/// ```rust
+/// use multipart::mock::MockHttpRequest;
+/// use multipart::server::Multipart;
+///
+/// let body = "--boundary\r\n\
+///     Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+///     Content-Type: text/plain\r\n\r\n\
+///     contents\r\n--boundary--\r\n";
+///
+/// let mut req = MockHttpRequest::new("POST", body);
+/// req.header("Content-Type", "multipart/form-data; boundary=boundary");
+///
+/// let entries = Multipart::from_request(req).ok().expect("not multipart").save_all()
+///     .to_result().unwrap();
+/// assert_eq!(entries.files["file"].filename.as_ref().unwrap(), "a.txt");
+/// ```
+///
+/// #### Feature: `server`
+#[cfg(feature = "server")]
+#[derive(Clone, Debug)]
+pub struct MockHttpRequest {
+    method: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[cfg(feature = "server")]
+impl MockHttpRequest {
+    /// Create a request with the given method and body, and no headers.
+    pub fn new<M: Into<String>, B: Into<Vec<u8>>>(method: M, body: B) -> Self {
+        MockHttpRequest {
+            method: method.into(),
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    /// Add a header to this request.
+    pub fn header<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) -> &mut Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// The method of this request.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Get the value of the first header `name` of this request, matched case-insensitively.
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|&&(ref header, _)| header.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| &**value)
+    }
+
+    /// The body of this request.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+/// #### Feature: `server`
+#[cfg(feature = "server")]
+impl server::HttpRequest for MockHttpRequest {
+    type Body = Cursor<Vec<u8>>;
+
+    fn multipart_boundary(&self) -> Option<&str> {
+        self.multipart_boundary_accepting(DEFAULT_METHODS, DEFAULT_SUBTYPES)
+    }
+
+    fn multipart_boundary_for(&self, methods: &[&str]) -> Option<&str> {
+        self.multipart_boundary_accepting(methods, DEFAULT_SUBTYPES)
+    }
+
+    fn multipart_boundary_accepting(&self, methods: &[&str], subtypes: &[&str]) -> Option<&str> {
+        if !methods.contains(&&*self.method) {
+            return None;
+        }
+
+        let content_type = match self.get_header("Content-Type") {
+            Some(content_type) => content_type,
+            None => return None,
+        };
+
+        match content_type.parse() {
+            Ok(Mime(TopLevel::Multipart, ref sub, _)) if accepts_subtype(subtypes, sub.as_str()) => (),
+            _ => return None,
+        }
+
+        parse_boundary(content_type)
+    }
+
+    fn body(self) -> Cursor<Vec<u8>> {
+        Cursor::new(self.body)
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod test {
+    use super::MockHttpRequest;
+
+    use server::{HttpRequest, Multipart};
+
+    #[test]
+    fn test_mock_http_request() {
+        let mut req = MockHttpRequest::new("PUT", "--boundary--\r\n");
+        req.header("content-type", "multipart/mixed; boundary=boundary");
+
+        assert_eq!(req.multipart_boundary(), None);
+        assert_eq!(req.multipart_boundary_for(&["PUT"]), None);
+        assert_eq!(req.multipart_boundary_accepting(&["PUT"], &["mixed"]), Some("boundary"));
+
+        let mut multipart = Multipart::from_request_accepting(req, &["PUT"], &["*"]).ok().unwrap();
+        assert!(multipart.read_entry().unwrap().is_none());
+    }
+}
@@ -0,0 +1,482 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! A push-based multipart parser which performs no I/O of its own.
+//!
+//! [`PushParser`](struct.PushParser.html) is handed the bytes received so far and reports how
+//! many of them it consumed, along with the next parsing event, if any. This makes it usable with
+//! any source of bytes, blocking or not, and lets callers decide how input is buffered.
+//!
+//! This module only needs `alloc`, so it is available without the `std` feature.
+use alloc::vec::Vec;
+
+use memchr::memmem::{self, Finder};
+
+use core::{cmp, fmt};
+
+#[cfg(feature = "std")]
+use std::{error, io};
+
+/// The maximum size of the header block of a single part, in bytes.
+///
+/// This counts each header line with its CRLF, but not the empty line ending the block.
+pub const MAX_HEADERS_SIZE: usize = 8 * 1024;
+
+/// The maximum amount of transport padding (whitespace) accepted after a boundary, in bytes.
+const MAX_PADDING_SIZE: usize = 1024;
+
+/// A sans-I/O multipart parser.
+///
+/// Call [`parse()`](#method.parse) with the unconsumed input until it returns `(0, None)`, at
+/// which point more input is required (or, if [`is_done()`](#method.is_done), the closing
+/// boundary has been read).
+#[derive(Debug)]
+pub struct PushParser {
+    /// `\r\n--{boundary}`
+    delimiter: Vec<u8>,
+    /// Searches for `delimiter`, with SIMD where the CPU supports it.
+    finder: Finder<'static>,
+    state: State,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Start,
+    Boundary,
+    Headers,
+    Body,
+    Done,
+}
+
+/// An event returned by [`PushParser::parse()`](struct.PushParser.html#method.parse), borrowing
+/// from the input.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// The header block of a new part, without the terminating empty line.
+    Headers(&'a [u8]),
+    /// A chunk of the current part's body.
+    ///
+    /// This is always a prefix of the input, and exactly the bytes consumed.
+    Data(&'a [u8]),
+    /// The body of the current part is complete.
+    PartEnd,
+    /// The closing boundary was read; any following bytes are the epilogue and may be ignored.
+    End,
+}
+
+impl PushParser {
+    /// Create a new parser for the given boundary, as it appears in the `Content-Type` header
+    /// (without the leading `--`).
+    pub fn new(boundary: &str) -> Self {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.as_bytes());
+
+        PushParser {
+            finder: Finder::new(&delimiter).into_owned(),
+            delimiter: delimiter,
+            state: State::Start,
+        }
+    }
+
+    /// Returns `true` if the closing boundary has been read.
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Parse the next event from `input`, which must start with the first byte that has not
+    /// yet been consumed. Set `eof` if no more input will follow.
+    ///
+    /// Returns the number of bytes consumed from the start of `input`, and the event parsed,
+    /// if any. If no bytes were consumed and there was no event, more input is required.
+    pub fn parse<'a>(&mut self, input: &'a [u8], eof: bool)
+    -> Result<(usize, Option<Event<'a>>), ParseError> {
+        match self.state {
+            State::Start => self.parse_start(input, eof),
+            State::Boundary => self.parse_boundary(input, eof),
+            State::Headers => self.parse_headers(input, eof),
+            State::Body => self.parse_body(input, eof),
+            State::Done => Ok((0, None)),
+        }
+    }
+
+    fn parse_start<'a>(&mut self, input: &'a [u8], eof: bool)
+    -> Result<(usize, Option<Event<'a>>), ParseError> {
+        // The first boundary may not be preceded by a CRLF.
+        let dash_boundary_len = self.delimiter.len() - 2;
+
+        if input.len() < dash_boundary_len {
+            return need_more(eof);
+        }
+
+        if input.starts_with(&self.delimiter[2..]) {
+            return self.parse_after_delimiter(input, dash_boundary_len, eof);
+        }
+
+        self.state = State::Boundary;
+        self.parse_boundary(input, eof)
+    }
+
+    fn parse_boundary<'a>(&mut self, input: &'a [u8], eof: bool)
+    -> Result<(usize, Option<Event<'a>>), ParseError> {
+        match self.finder.find(input) {
+            // Discard the preamble or the unread remainder of the previous part.
+            Some(0) => {
+                let delimiter_len = self.delimiter.len();
+                self.parse_after_delimiter(input, delimiter_len, eof)
+            },
+            Some(idx) => Ok((idx, None)),
+            None => {
+                let discard = input.len() - self.partial_delimiter_len(input);
+
+                if discard == 0 {
+                    need_more(eof)
+                } else {
+                    Ok((discard, None))
+                }
+            },
+        }
+    }
+
+    fn parse_after_delimiter<'a>(&mut self, input: &'a [u8], delimiter_len: usize, eof: bool)
+    -> Result<(usize, Option<Event<'a>>), ParseError> {
+        let after = &input[delimiter_len..];
+
+        if after.len() < 2 {
+            return need_more(eof);
+        }
+
+        if after.starts_with(b"--") {
+            self.state = State::Done;
+            return Ok((delimiter_len + 2, Some(Event::End)));
+        }
+
+        match find(after, b"\r\n") {
+            Some(idx) => {
+                if after[..idx].iter().any(|&b| b != b' ' && b != b'\t') {
+                    return Err(ParseError::InvalidBoundary);
+                }
+
+                self.state = State::Headers;
+                Ok((delimiter_len + idx + 2, None))
+            },
+            None if after.len() > MAX_PADDING_SIZE => Err(ParseError::InvalidBoundary),
+            None => need_more(eof),
+        }
+    }
+
+    fn parse_headers<'a>(&mut self, input: &'a [u8], eof: bool)
+    -> Result<(usize, Option<Event<'a>>), ParseError> {
+        if input.starts_with(b"\r\n") {
+            self.state = State::Body;
+            return Ok((2, Some(Event::Headers(&input[..0]))));
+        }
+
+        match find(input, b"\r\n\r\n") {
+            // The header lines count with their CRLFs, but the empty line ending them doesn't.
+            Some(idx) if idx + 2 > MAX_HEADERS_SIZE => Err(ParseError::HeadersTooLarge),
+            Some(idx) => {
+                self.state = State::Body;
+                Ok((idx + 4, Some(Event::Headers(&input[..idx]))))
+            },
+            // Up to the last byte of the empty line, a block which fits may still be incomplete.
+            None if input.len() > MAX_HEADERS_SIZE + 1 => Err(ParseError::HeadersTooLarge),
+            None => need_more(eof),
+        }
+    }
+
+    fn parse_body<'a>(&mut self, input: &'a [u8], eof: bool)
+    -> Result<(usize, Option<Event<'a>>), ParseError> {
+        match self.finder.find(input) {
+            Some(0) => {
+                self.state = State::Boundary;
+                Ok((0, Some(Event::PartEnd)))
+            },
+            Some(idx) => Ok((idx, Some(Event::Data(&input[..idx])))),
+            None => {
+                let len = input.len() - self.partial_delimiter_len(input);
+
+                if len == 0 {
+                    need_more(eof)
+                } else {
+                    Ok((len, Some(Event::Data(&input[..len]))))
+                }
+            },
+        }
+    }
+
+    /// The number of bytes at the end of `input` which could be the start of a delimiter.
+    fn partial_delimiter_len(&self, input: &[u8]) -> usize {
+        let max_len = cmp::min(input.len(), self.delimiter.len() - 1);
+
+        (1 .. max_len + 1).rev()
+            .find(|&len| self.delimiter.starts_with(&input[input.len() - len..]))
+            .unwrap_or(0)
+    }
+}
+
+fn need_more<'a>(eof: bool) -> Result<(usize, Option<Event<'a>>), ParseError> {
+    if eof {
+        Err(ParseError::UnexpectedEof)
+    } else {
+        Ok((0, None))
+    }
+}
+
+/// Find the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    memmem::find(haystack, needle)
+}
+
+/// An error returned by [`PushParser`](struct.PushParser.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The input ended before the closing boundary was read.
+    UnexpectedEof,
+    /// A boundary was followed by something other than whitespace and a CRLF, or `--`.
+    InvalidBoundary,
+    /// The header block of a part exceeded [`MAX_HEADERS_SIZE`](constant.MAX_HEADERS_SIZE.html).
+    HeadersTooLarge,
+    /// The header block of a part was not valid UTF-8, or did not contain a
+    /// `Content-Disposition: form-data` header with a field name.
+    ///
+    /// Not returned by `PushParser` itself, which does not interpret headers.
+    InvalidHeaders,
+}
+
+#[cfg(feature = "std")]
+impl error::Error for ParseError {}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            ParseError::UnexpectedEof => "unexpected end of multipart body",
+            ParseError::InvalidBoundary => "invalid multipart boundary line",
+            ParseError::HeadersTooLarge => "multipart part headers too large",
+            ParseError::InvalidHeaders => "invalid multipart part headers",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<ParseError> for io::Error {
+    fn from(err: ParseError) -> io::Error {
+        let kind = match err {
+            ParseError::UnexpectedEof => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+
+        io::Error::new(kind, err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Event, ParseError, PushParser, MAX_HEADERS_SIZE};
+
+    const BODY: &'static [u8] = b"preamble\r\n--boundary\r\n\
+        Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+        text value\r\n--boundary  \r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        file\r\ncontents\r\n--boundary--\r\nepilogue";
+
+    fn parse_all(chunks: &[&[u8]]) -> Vec<String> {
+        let mut parser = PushParser::new("boundary");
+        let mut buf = Vec::new();
+        let mut events = Vec::new();
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            buf.extend_from_slice(chunk);
+            let eof = i == chunks.len() - 1;
+
+            loop {
+                let (consumed, event) = parser.parse(&buf, eof).unwrap();
+
+                match event {
+                    Some(Event::Headers(headers)) =>
+                        events.push(format!("H:{}", String::from_utf8_lossy(headers))),
+                    // Merge adjacent data events so the result doesn't depend on chunking.
+                    Some(Event::Data(data)) => {
+                        let data = String::from_utf8_lossy(data).into_owned();
+
+                        if events.last().map_or(false, |e: &String| e.starts_with("D:")) {
+                            events.last_mut().unwrap().push_str(&data);
+                        } else {
+                            events.push(format!("D:{}", data));
+                        }
+                    },
+                    Some(Event::PartEnd) => events.push("E".into()),
+                    Some(Event::End) => events.push("END".into()),
+                    None if consumed == 0 => break,
+                    None => (),
+                }
+
+                buf.drain(..consumed);
+            }
+        }
+
+        assert!(parser.is_done());
+        events
+    }
+
+    #[test]
+    fn test_push_parser() {
+        let expected = vec![
+            "H:Content-Disposition: form-data; name=\"text\"",
+            "D:text value",
+            "E",
+            "H:Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\nContent-Type: text/plain",
+            "D:file\r\ncontents",
+            "E",
+            "END",
+        ];
+
+        assert_eq!(parse_all(&[BODY]), expected);
+
+        for split_at in 0 .. BODY.len() {
+            let (left, right) = BODY.split_at(split_at);
+            assert_eq!(parse_all(&[left, right]), expected, "split at {}", split_at);
+        }
+    }
+
+    #[test]
+    fn test_push_parser_eof() {
+        let mut parser = PushParser::new("boundary");
+        let body = b"--boundary\r\nContent-Disposition: form-data; name=\"text\"\r\n\r\ntext";
+
+        let (consumed, event) = parser.parse(body, true).unwrap();
+        assert_eq!((consumed, event), (12, None));
+
+        let body = &body[consumed..];
+        let (consumed, _) = parser.parse(body, true).unwrap();
+
+        let body = &body[consumed..];
+        assert_eq!(parser.parse(body, true).unwrap(), (4, Some(Event::Data(b"text"))));
+        assert!(parser.parse(&body[4..], true).is_err());
+    }
+
+    /// Parse the headers of the first part of `body`, split into two chunks at `split_at`.
+    fn parse_headers_split(body: &[u8], split_at: usize) -> Result<usize, ParseError> {
+        let mut parser = PushParser::new("boundary");
+        // `--boundary\r\n`
+        let (consumed, _) = parser.parse(body, false).unwrap();
+        let headers = &body[consumed..];
+
+        match try!(parser.parse(&headers[..split_at], false)) {
+            (0, None) => (),
+            (_, Some(Event::Headers(headers))) => return Ok(headers.len()),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match try!(parser.parse(headers, false)) {
+            (_, Some(Event::Headers(headers))) => Ok(headers.len()),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_headers_size() {
+        let disposition = "Content-Disposition: form-data; name=\"field\"\r\n";
+
+        for &(size, ok) in &[(MAX_HEADERS_SIZE, true), (MAX_HEADERS_SIZE + 1, false)] {
+            let mut body = b"--boundary\r\n".to_vec();
+            body.extend_from_slice(disposition.as_bytes());
+            // Pad the block to `size` bytes with a second header line.
+            let padding = size - disposition.len() - "X-Padding: \r\n".len();
+            body.extend_from_slice(format!("X-Padding: {}\r\n\r\n", "a".repeat(padding)).as_bytes());
+            body.extend_from_slice(b"value\r\n--boundary--");
+
+            // Split at every byte of the CRLFs which end the block.
+            let block_end = "--boundary\r\n".len() + size + 2;
+
+            for split_at in block_end - 4 .. block_end + 1 {
+                let res = parse_headers_split(&body, split_at - "--boundary\r\n".len());
+
+                if ok {
+                    assert_eq!(res, Ok(size - 2), "split at {}", split_at);
+                } else {
+                    assert_eq!(res, Err(ParseError::HeadersTooLarge), "split at {}", split_at);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "bench")]
+mod bench {
+    extern crate test;
+
+    use self::test::Bencher;
+
+    use memchr::memmem::Finder;
+
+    use super::{Event, PushParser};
+
+    const PARTS: usize = 16;
+    const PART_SIZE: usize = 256 * 1024;
+
+    /// Parts full of CRLFs and dashes, which look like the start of a delimiter, separated by
+    /// real delimiters.
+    fn body() -> Vec<u8> {
+        let filler = b"field data\r\n-- -\r\n--bound\r\n";
+        let mut body = Vec::new();
+
+        for _ in 0 .. PARTS {
+            body.extend_from_slice(b"--boundary\r\nContent-Disposition: form-data; name=\"field\"\r\n\r\n");
+            body.extend(filler.iter().cycle().take(PART_SIZE));
+            body.extend_from_slice(b"\r\n");
+        }
+
+        body.extend_from_slice(b"--boundary--\r\n");
+        body
+    }
+
+    #[bench]
+    fn bench_push_parser(b: &mut Bencher) {
+        let body = body();
+        b.bytes = body.len() as u64;
+
+        b.iter(|| {
+            let mut parser = PushParser::new("boundary");
+            let mut input = &body[..];
+            let mut data_len = 0;
+
+            while !parser.is_done() {
+                let (consumed, event) = parser.parse(input, true).unwrap();
+
+                if let Some(Event::Data(data)) = event {
+                    data_len += data.len();
+                }
+
+                input = &input[consumed..];
+            }
+
+            assert_eq!(data_len, PARTS * PART_SIZE);
+        });
+    }
+
+    #[bench]
+    fn bench_finder_search(b: &mut Bencher) {
+        let body = body();
+        let finder = Finder::new(b"\r\n--boundary--");
+        b.bytes = body.len() as u64;
+
+        b.iter(|| assert_eq!(finder.find(&body), Some(body.len() - finder.needle().len() - 2)));
+    }
+
+    /// The byte-at-a-time search used before boundaries were found with `memchr`, for comparison.
+    #[bench]
+    fn bench_naive_search(b: &mut Bencher) {
+        let body = body();
+        let delimiter = b"\r\n--boundary--";
+        b.bytes = body.len() as u64;
+
+        b.iter(|| {
+            let found = body.windows(delimiter.len()).position(|window| window == &delimiter[..]);
+            assert_eq!(found, Some(body.len() - delimiter.len() - 2));
+        });
+    }
+}
@@ -0,0 +1,63 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Server-side integration with [async-std](https://async.rs) and frameworks built on it, such
+//! as Tide. Enabled with the `async-std_` feature (optional).
+//!
+//! Request bodies implementing `async_std::io::Read`, such as `tide::Request`, are parsed with
+//! [`StreamMultipart::from_reader()`](../stream/struct.StreamMultipart.html#method.from_reader),
+//! and the functions here save all of their fields with `async_std::fs`, without depending
+//! on tokio.
+//!
+//! ```no_run
+//! # extern crate async_std;
+//! # extern crate multipart;
+//! use async_std::fs::File;
+//! use async_std::task;
+//!
+//! use multipart::server::async_std::save_all;
+//! use multipart::server::stream::StreamMultipart;
+//!
+//! # fn main() {
+//! let body = task::block_on(File::open("request_body")).unwrap();
+//! let multipart = StreamMultipart::from_reader(body, "boundary");
+//!
+//! let entries = task::block_on(save_all(multipart)).to_result().unwrap();
+//! println!("Saved files: {:?}", entries.files);
+//! # }
+//! ```
+use async_std::fs::File;
+
+use bytes::Bytes;
+
+use futures::future::Future;
+use futures::stream::Stream;
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use super::SaveResult;
+use super::stream::StreamMultipart;
+
+/// Read the request fully, saving text fields in memory and files in a new temporary directory
+/// under the [temporary root](../fn.temp_root.html), using `async_std::fs`.
+///
+/// See [`StreamMultipart::save_all_with()`](../stream/struct.StreamMultipart.html#method.save_all_with)
+/// for more information.
+pub fn save_all<S, E>(multipart: StreamMultipart<S>) -> impl Future<Output = SaveResult>
+where S: Stream<Item = Result<Bytes, E>> + Unpin, E: Into<Box<Error + Send + Sync>> {
+    multipart.save_all_with(File::create::<PathBuf>)
+}
+
+/// Read the request fully, saving text fields in memory and files in a new temporary directory
+/// under `dir`, using `async_std::fs`.
+///
+/// See [`StreamMultipart::save_all_with()`](../stream/struct.StreamMultipart.html#method.save_all_with)
+/// for more information.
+pub fn save_all_under<S, E, P>(multipart: StreamMultipart<S>, dir: P) -> impl Future<Output = SaveResult>
+where S: Stream<Item = Result<Bytes, E>> + Unpin, E: Into<Box<Error + Send + Sync>>, P: AsRef<Path> {
+    multipart.save_all_under_with(dir, File::create::<PathBuf>)
+}
@@ -0,0 +1,147 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Zero-copy parsing of multipart bodies which are already entirely in memory.
+//!
+//! [`Multipart::parse_bytes()`](../struct.Multipart.html#method.parse_bytes) splits the body
+//! into [`BorrowedField`](struct.BorrowedField.html)s, whose names, headers and contents are
+//! slices of the original buffer. This is useful when the request body has been buffered anyway,
+//! such as in serverless functions, tests and benchmarks.
+use std::str;
+
+use super::push::{Event, ParseError, PushParser};
+use super::{find_header, read_disposition, Multipart};
+
+/// A single field of a multipart body, borrowed from the buffer it was parsed from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BorrowedField<'a> {
+    /// The field's name from the form.
+    pub name: &'a str,
+    /// The filename of this field, if supplied.
+    ///
+    /// ##Warning
+    /// You should treat this value as untrustworthy because it is an arbitrary string provided by
+    /// the client.
+    pub filename: Option<&'a str>,
+    /// The value of the `Content-Type` header of this field, if supplied, including any parameters.
+    pub content_type: Option<&'a str>,
+    /// The entire header block of this field, without the terminating empty line.
+    pub headers: &'a str,
+    /// The contents of this field.
+    pub data: &'a [u8],
+}
+
+impl<'a> BorrowedField<'a> {
+    /// Get the contents of this field as a string, if it is valid UTF-8.
+    pub fn as_text(&self) -> Option<&'a str> {
+        str::from_utf8(self.data).ok()
+    }
+
+    fn from_headers(headers: &'a [u8]) -> Result<BorrowedField<'a>, ParseError> {
+        let headers = try!(str::from_utf8(headers).map_err(|_| ParseError::InvalidHeaders));
+
+        let mut cont_disp = None;
+        let mut content_type = None;
+
+        for line in headers.split("\r\n") {
+            if cont_disp.is_none() {
+                cont_disp = read_disposition(line);
+
+                if cont_disp.is_some() { continue; }
+            }
+
+            if content_type.is_none() {
+                content_type = find_header(line, "Content-Type");
+            }
+        }
+
+        let (name, filename) = try!(cont_disp.ok_or(ParseError::InvalidHeaders));
+
+        Ok(BorrowedField {
+            name: name,
+            filename: filename,
+            content_type: content_type,
+            headers: headers,
+            data: &[],
+        })
+    }
+}
+
+impl<'a> Multipart<&'a [u8]> {
+    /// Parse all fields of `body`, which must contain the entire multipart body, without copying.
+    ///
+    /// The boundary is as it appears in the `Content-Type` header, without the leading `"--"`.
+    ///
+    /// Returns an error if the closing boundary is missing or a field has invalid headers.
+    pub fn parse_bytes(body: &'a [u8], boundary: &str) -> Result<Vec<BorrowedField<'a>>, ParseError> {
+        let mut parser = PushParser::new(boundary);
+        let mut fields = Vec::new();
+        let mut field = None;
+        let mut pos = 0;
+
+        while !parser.is_done() {
+            let (consumed, event) = try!(parser.parse(&body[pos..], true));
+            pos += consumed;
+
+            match event {
+                Some(Event::Headers(headers)) => {
+                    let mut new_field = try!(BorrowedField::from_headers(headers));
+                    new_field.data = &body[pos..pos];
+                    field = Some((new_field, pos));
+                },
+                Some(Event::PartEnd) => if let Some((mut field, data_start)) = field.take() {
+                    field.data = &body[data_start..pos];
+                    fields.push(field);
+                },
+                // Field contents are contiguous in `body`, so they are sliced out at `PartEnd`.
+                Some(Event::Data(_)) | Some(Event::End) | None => (),
+            }
+        }
+
+        Ok(fields)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use server::Multipart;
+
+    #[test]
+    fn test_parse_bytes() {
+        let body = b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+            text value\r\n--boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\r\n\
+            file\r\ncontents\r\n--boundary--\r\n";
+
+        let fields = Multipart::parse_bytes(body, "boundary").unwrap();
+        assert_eq!(fields.len(), 2);
+
+        assert_eq!(fields[0].name, "text");
+        assert_eq!(fields[0].filename, None);
+        assert_eq!(fields[0].as_text(), Some("text value"));
+
+        assert_eq!(fields[1].name, "file");
+        assert_eq!(fields[1].filename, Some("file.txt"));
+        assert_eq!(fields[1].content_type, Some("text/plain; charset=utf-8"));
+        assert_eq!(fields[1].data, b"file\r\ncontents");
+
+        assert!(Multipart::parse_bytes(&body[..body.len() - 6], "boundary").is_err());
+    }
+
+    #[test]
+    fn test_content_type_name() {
+        let body = b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+            X-Content-Type: application/x-evil\r\n\
+            content-type: text/plain\r\n\r\n\
+            contents\r\n--boundary--\r\n";
+
+        let fields = Multipart::parse_bytes(body, "boundary").unwrap();
+        assert_eq!(fields[0].content_type, Some("text/plain"));
+    }
+}
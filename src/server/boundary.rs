@@ -4,11 +4,7 @@
 // http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
-extern crate buf_redux;
-extern crate memchr;
-
-use self::buf_redux::BufReader;
-use self::memchr::memchr;
+use memchr::memmem::Finder;
 
 use std::cmp;
 use std::borrow::Borrow;
@@ -16,86 +12,187 @@ use std::borrow::Borrow;
 use std::io;
 use std::io::prelude::*;
 
+use super::pool::{BufferPool, PooledBuf};
+
+#[cfg(feature = "async")]
+pub use self::async_reader::AsyncBoundaryReader;
+
+/// The default size of the buffer through which [`Multipart`](struct.Multipart.html) reads
+/// the request body, in bytes.
+pub const DEFAULT_BUF_SIZE: usize = 64 * 1024;
+
+/// Get the `boundary` parameter of a `Content-Type` header value, such as
+/// `multipart/form-data; boundary=boundary`.
+///
+/// Parameter names are matched case-insensitively, and quoted values are unquoted, skipping
+/// over any other parameters with quoted values. The media type itself isn't checked. Returns
+/// `None` if there is no boundary, or it is empty.
+///
+/// Boundaries can't contain quotes or backslashes (RFC 2046), so a quoted boundary is returned
+/// as it appears between the quotes.
+///
+/// ```rust
+/// use multipart::server::parse_boundary;
+///
+/// assert_eq!(parse_boundary("multipart/form-data; boundary=abc"), Some("abc"));
+/// assert_eq!(parse_boundary("multipart/form-data; charset=utf-8; BOUNDARY=\"a b;c\""), Some("a b;c"));
+/// assert_eq!(parse_boundary("multipart/form-data"), None);
+/// ```
+pub fn parse_boundary(content_type: &str) -> Option<&str> {
+    parse_param(content_type, "boundary").filter(|boundary| !boundary.is_empty())
+}
+
+/// Get the parameter `param` of a header value like `Content-Type`, as in `parse_boundary()`.
+pub(crate) fn parse_param<'a>(content_type: &'a str, param: &str) -> Option<&'a str> {
+    let mut rest = try_opt!(content_type.find(';').map(|idx| &content_type[idx + 1..]));
+
+    loop {
+        let eq = try_opt!(rest.find('='));
+        let name = rest[..eq].trim();
+        let val_start = rest[eq + 1..].trim_start();
+
+        let (val, after) = if val_start.starts_with('"') {
+            let end = try_opt!(closing_quote(&val_start[1..])) + 1;
+            (&val_start[1..end], &val_start[end + 1..])
+        } else {
+            let end = val_start.find(';').unwrap_or(val_start.len());
+            (val_start[..end].trim_end(), &val_start[end..])
+        };
+
+        if name.eq_ignore_ascii_case(param) {
+            return Some(val);
+        }
+
+        rest = try_opt!(after.find(';').map(|idx| &after[idx + 1..]));
+    }
+}
+
+/// The index of the first unescaped `"` in `s`.
+fn closing_quote(s: &str) -> Option<usize> {
+    let mut escaped = false;
+
+    for (idx, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(idx),
+            _ => (),
+        }
+    }
+
+    None
+}
+
 /// A struct implementing `Read` and `BufRead` that will yield bytes until it sees a given sequence.
 #[derive(Debug)]
 pub struct BoundaryReader<R> {
-    buf: BufReader<R>,
+    buf: Buffer<R>,
     boundary: Vec<u8>,
+    finder: Finder<'static>,
     search_idx: usize,
     boundary_read: bool,
     at_end: bool,
+    crlf_before: bool,
 }
 
 impl<R> BoundaryReader<R> where R: Read {
+    // Used by the tests and benchmarks.
+    #[allow(unused)]
     #[doc(hidden)]
     pub fn from_reader<B: Into<Vec<u8>>>(reader: R, boundary: B) -> BoundaryReader<R> {
+        Self::with_capacity(reader, boundary, DEFAULT_BUF_SIZE)
+    }
+
+    /// The capacity is raised to twice the length of the boundary if it is smaller.
+    #[doc(hidden)]
+    pub fn with_capacity<B: Into<Vec<u8>>>(reader: R, boundary: B, capacity: usize) -> BoundaryReader<R> {
+        let boundary = boundary.into();
+        let capacity = cmp::max(capacity, boundary.len() * 2);
+
+        Self::with_buf(reader, boundary, PooledBuf::unpooled(capacity))
+    }
+
+    /// The buffers of `pool` are grown to twice the length of the boundary if they are smaller.
+    #[doc(hidden)]
+    pub fn with_pool<B: Into<Vec<u8>>>(reader: R, boundary: B, pool: &BufferPool) -> BoundaryReader<R> {
+        let boundary = boundary.into();
+        let buf = pool.take(boundary.len() * 2);
+
+        Self::with_buf(reader, boundary, buf)
+    }
+
+    fn with_buf(reader: R, boundary: Vec<u8>, buf: PooledBuf) -> BoundaryReader<R> {
         BoundaryReader {
-            buf: BufReader::new(reader),
-            boundary: boundary.into(),
+            buf: Buffer::new(reader, buf),
+            finder: Finder::new(&boundary).into_owned(),
+            boundary: boundary,
             search_idx: 0,
             boundary_read: false,
             at_end: false,
+            crlf_before: false,
         }
     }
 
     fn read_to_boundary(&mut self) -> io::Result<&[u8]> {
         use log::LogLevel;
 
-        let buf = try!(fill_buf_min(&mut self.buf, self.boundary.len()));
-        
-        if log_enabled!(LogLevel::Trace) {
-            trace!("Buf: {:?}", String::from_utf8_lossy(buf));
-        }
+        let mut min = self.boundary.len();
 
-        debug!(
-            "Before-loop Buf len: {} Search idx: {} Boundary read: {:?}", 
-            buf.len(), self.search_idx, self.boundary_read
-        );
+        let buf_end = loop {
+            try!(self.buf.fill_min(min));
+            let buf = &self.buf.buf[self.buf.pos .. self.buf.end];
 
-        while !(self.boundary_read || self.at_end) && self.search_idx < buf.len() {
-            let lookahead = &buf[self.search_idx..];
-
-            let maybe_boundary = memchr(self.boundary[0], lookahead);
+            if log_enabled!(LogLevel::Trace) {
+                trace!("Buf: {:?}", String::from_utf8_lossy(buf));
+            }
 
-            debug!("maybe_boundary: {:?}", maybe_boundary);
+            debug!(
+                "Before-loop Buf len: {} Search idx: {} Boundary read: {:?}",
+                buf.len(), self.search_idx, self.boundary_read
+            );
 
-            self.search_idx = match maybe_boundary {
-                Some(boundary_start) => self.search_idx + boundary_start,
-                None => buf.len(),
+            let buf_end = if self.at_end {
+                self.search_idx
+            } else {
+                search_boundary(buf, &self.finder, &mut self.search_idx, &mut self.boundary_read)
             };
 
-            if self.search_idx + self.boundary.len() <= buf.len() {
-                let test = &buf[self.search_idx .. self.search_idx + self.boundary.len()];
-
-                match first_nonmatching_idx(test, &self.boundary) {
-                    Some(idx) => self.search_idx += idx,
-                    None => self.boundary_read = true,
-                } 
-            } else {
-                break;
-            }            
-        }        
-        
-        debug!(
-            "After-loop Buf len: {} Search idx: {} Boundary read: {:?}", 
-            buf.len(), self.search_idx, self.boundary_read
-        );
+            debug!(
+                "After-search Buf len: {} Search idx: {} Boundary read: {:?}",
+                buf.len(), self.search_idx, self.boundary_read
+            );
 
+            // A partial boundary at the end of the body can't be completed, so everything left
+            // is data.
+            if self.buf.eof && !self.boundary_read && !self.at_end {
+                self.at_end = true;
+                self.search_idx = buf.len();
+                break buf.len();
+            }
 
-        let mut buf_end = self.search_idx;
-        
-        if self.boundary_read && self.search_idx >= 2 {
-            let two_bytes_before = &buf[self.search_idx - 2 .. self.search_idx];
+            // The CRLF before a boundary which isn't in the buffer yet is held back until it is
+            // known whether the boundary follows, as it isn't part of the data if it does.
+            let held_back = if self.boundary_read || self.at_end {
+                0
+            } else if buf[..buf_end].ends_with(b"\r\n") {
+                2
+            } else if buf[..buf_end].ends_with(b"\r") {
+                1
+            } else {
+                0
+            };
 
-            debug!("Two bytes before: {:?} (\"\\r\\n\": {:?})", two_bytes_before, b"\r\n");
+            // Returning no data would signal the end of the part, so keep reading until the
+            // start of a boundary is either completed or ruled out, however short the reads of
+            // `reader` are.
+            if buf_end > held_back || self.boundary_read || self.at_end {
+                break buf_end - held_back;
+            }
 
-            if two_bytes_before == &*b"\r\n" {
-                debug!("Subtract two!");
-                buf_end -= 2;
-            } 
-        }
+            min = buf.len() + 1;
+        };
 
-        let ret_buf = &buf[..buf_end];
+        let ret_buf = &self.buf.buf[self.buf.pos .. self.buf.pos + buf_end];
 
         if log_enabled!(LogLevel::Trace) {
             trace!("Returning buf: {:?}", String::from_utf8_lossy(ret_buf));
@@ -104,10 +201,12 @@ impl<R> BoundaryReader<R> where R: Read {
         Ok(ret_buf)
     }
 
+    /// Skip the rest of the current part and the boundary after it. Returns `false` if the end
+    /// of the body was reached without finding a boundary.
     #[doc(hidden)]
-    pub fn consume_boundary(&mut self) -> io::Result<()> {
+    pub fn consume_boundary(&mut self) -> io::Result<bool> {
         if self.at_end {
-            return Ok(());
+            return Ok(false);
         }
 
         while !self.boundary_read {
@@ -120,12 +219,73 @@ impl<R> BoundaryReader<R> where R: Read {
             self.consume(buf_len);
         }
 
+        let found = self.boundary_read;
+
+        // `consume()` leaves the CRLF before the boundary in the buffer.
+        self.crlf_before = found &&
+            self.buf.buf[self.buf.pos .. self.buf.pos + self.search_idx].ends_with(b"\r\n");
+
         self.buf.consume(self.search_idx + self.boundary.len());
 
         self.search_idx = 0;
         self.boundary_read = false;
  
-        Ok(())
+        Ok(found)
+    }
+
+    /// Returns `true` if the boundary last skipped by `consume_boundary()` was preceded by a
+    /// CRLF.
+    #[doc(hidden)]
+    pub fn crlf_before_boundary(&self) -> bool {
+        self.crlf_before
+    }
+
+    /// Discard the buffered data and read `reader` to the end, discarding at most `limit`
+    /// bytes. Returns `true` if the end was reached.
+    #[doc(hidden)]
+    pub fn discard_rest(&mut self, limit: u64) -> io::Result<bool> {
+        let buffered = (self.buf.end - self.buf.pos) as u64;
+        self.search_idx = 0;
+        self.boundary_read = false;
+
+        if buffered > limit {
+            self.buf.consume(limit as usize);
+            return Ok(false);
+        }
+
+        self.buf.consume(buffered as usize);
+
+        let remaining = limit - buffered;
+        let read = try!(io::copy(&mut self.buf.reader.by_ref().take(remaining), &mut io::sink()));
+
+        if read < remaining {
+            return Ok(true);
+        }
+
+        // The limit was reached exactly, so check whether anything is left.
+        let mut byte = [0];
+
+        loop {
+            match self.buf.reader.read(&mut byte) {
+                Ok(read) => return Ok(read == 0),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// The boundary, including the leading `--`.
+    #[doc(hidden)]
+    pub fn boundary(&self) -> &[u8] {
+        &self.boundary
+    }
+
+    /// Unwrap the reader, along with the data in the buffer which has not been consumed.
+    #[doc(hidden)]
+    pub fn into_inner(self) -> (R, Vec<u8>) {
+        let Buffer { reader, buf, pos, end, .. } = self.buf;
+        let buffered = buf[pos .. end].to_vec();
+        (reader, buffered)
     }
 
     // Keeping this around to support nested boundaries later.
@@ -133,12 +293,63 @@ impl<R> BoundaryReader<R> where R: Read {
     #[doc(hidden)]
     pub fn set_boundary<B: Into<Vec<u8>>>(&mut self, boundary: B) {
         self.boundary = boundary.into();
+        self.finder = Finder::new(&self.boundary).into_owned();
+    }
+
+    /// The data before the boundary which was last returned by `fill_buf()` and has not been
+    /// consumed.
+    #[cfg(feature = "checksum")]
+    #[doc(hidden)]
+    pub fn to_boundary(&self) -> &[u8] {
+        let data = &self.buf.buf[self.buf.pos .. self.buf.pos + self.search_idx];
+
+        // As in `search_boundary()`, the CRLF before the boundary isn't part of the data.
+        if self.boundary_read && !self.at_end && data.ends_with(b"\r\n") {
+            &data[..data.len() - 2]
+        } else {
+            data
+        }
+    }
+
+    /// The data in the buffer which has not been consumed, including any boundaries in it.
+    #[cfg(all(feature = "splice", target_os = "linux"))]
+    #[doc(hidden)]
+    pub fn buffered(&self) -> &[u8] {
+        &self.buf.buf[self.buf.pos .. self.buf.end]
+    }
+
+    /// Consume `amt` bytes of the buffer, whether or not they precede the boundary, for data
+    /// which is known to belong to the current part.
+    #[cfg(all(feature = "splice", target_os = "linux"))]
+    #[doc(hidden)]
+    pub fn consume_unchecked(&mut self, amt: usize) {
+        self.buf.consume(amt);
+        self.search_idx = 0;
+        self.boundary_read = false;
+    }
+
+    /// Read data which is known to belong to the current part, such as when its length is
+    /// known, without searching it for the boundary or waiting for the data after it.
+    #[doc(hidden)]
+    pub fn read_unchecked(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.buf.pos == self.buf.end {
+            return self.buf.reader.read(out);
+        }
+
+        let read = cmp::min(out.len(), self.buf.end - self.buf.pos);
+        out[..read].copy_from_slice(&self.buf.buf[self.buf.pos .. self.buf.pos + read]);
+
+        self.buf.consume(read);
+        self.search_idx = 0;
+        self.boundary_read = false;
+
+        Ok(read)
     }
 }
 
 impl<R> Borrow<R> for BoundaryReader<R> {
     fn borrow(&self) -> &R {
-        self.buf.get_ref() 
+        &self.buf.reader
     }
 }
 
@@ -153,6 +364,18 @@ impl<R> Read for BoundaryReader<R> where R: Read {
         self.consume(read);
         Ok(read)
     }
+
+    /// Fill all of `bufs` from a single read of the request body, if there is enough data
+    /// before the boundary.
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+        let read = {
+            let mut buf = try!(self.read_to_boundary());
+            buf.read_vectored(bufs).unwrap()
+        };
+
+        self.consume(read);
+        Ok(read)
+    }
 }
 
 impl<R> BufRead for BoundaryReader<R> where R: Read {
@@ -170,27 +393,312 @@ impl<R> BufRead for BoundaryReader<R> where R: Read {
     }
 }
 
-fn fill_buf_min<R: Read>(buf: &mut BufReader<R>, min: usize) -> io::Result<&[u8]> {
-    if buf.available() < min {
-        try!(buf.read_into_buf());
+#[cfg(feature = "async")]
+mod async_reader {
+    use futures::future::{self, Future};
+    use futures::io::{AsyncBufRead, AsyncRead};
+
+    use memchr::memmem::Finder;
+
+    use std::cmp;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use super::search_boundary;
+
+    /// The asynchronous counterpart of the blocking boundary reader, implementing
+    /// `futures::io::AsyncRead` and `AsyncBufRead` over any `AsyncBufRead`.
+    ///
+    /// Reads yield the bytes before the next boundary; once they return no more data,
+    /// [`consume_boundary()`](#method.consume_boundary) skips the boundary so the following
+    /// part can be read. This allows field bodies to be consumed with the combinators of
+    /// `futures::io`, such as `copy_buf()`.
+    #[derive(Debug)]
+    pub struct AsyncBoundaryReader<R> {
+        inner: R,
+        /// Lookahead copied from `inner`, so a boundary can be recognized when it straddles
+        /// two of its buffers; data before `pos` has been consumed.
+        buf: Vec<u8>,
+        pos: usize,
+        boundary: Vec<u8>,
+        finder: Finder<'static>,
+        search_idx: usize,
+        boundary_read: bool,
+        /// Whether `inner` has returned EOF.
+        eof: bool,
     }
 
-    Ok(buf.get_buf())
+    impl<R: AsyncBufRead + Unpin> AsyncBoundaryReader<R> {
+        /// Wrap `reader`, splitting it at `boundary`.
+        ///
+        /// The boundary is as it appears in the `Content-Type` header, without the leading `"--"`.
+        pub fn new<B: Into<String>>(reader: R, boundary: B) -> Self {
+            let boundary = format!("--{}", boundary.into());
+
+            debug!("Boundary: {}", boundary);
+
+            AsyncBoundaryReader {
+                inner: reader,
+                buf: Vec::new(),
+                pos: 0,
+                finder: Finder::new(&boundary).into_owned(),
+                boundary: boundary.into_bytes(),
+                search_idx: 0,
+                boundary_read: false,
+                eof: false,
+            }
+        }
+
+        /// Get a reference to the wrapped reader.
+        pub fn get_ref(&self) -> &R {
+            &self.inner
+        }
+
+        /// Skip the remainder of the current part and the boundary which follows it.
+        ///
+        /// The bytes after the boundary are `"\r\n"` if another part follows, or `"--"` if
+        /// this was the closing boundary.
+        pub fn consume_boundary<'a>(&'a mut self) -> impl Future<Output = io::Result<()>> + 'a {
+            future::poll_fn(move |cx| self.poll_consume_boundary(cx))
+        }
+
+        /// Poll skipping the remainder of the current part and the boundary which follows it.
+        ///
+        /// See [`consume_boundary()`](#method.consume_boundary) for more information.
+        pub fn poll_consume_boundary(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+            while !self.boundary_read {
+                let buf_len = match self.poll_read_to_boundary(cx) {
+                    Poll::Ready(Ok(buf)) => buf.len(),
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending => return Poll::Pending,
+                };
+
+                if buf_len == 0 {
+                    break;
+                }
+
+                self.consume_data(buf_len);
+            }
+
+            let available = self.buf.len() - self.pos;
+            self.pos += cmp::min(self.search_idx + self.boundary.len(), available);
+
+            self.search_idx = 0;
+            self.boundary_read = false;
+
+            Poll::Ready(Ok(()))
+        }
+
+        /// Copy data from `inner` until at least `min` bytes are buffered, or it is at EOF.
+        fn poll_fill_min(&mut self, cx: &mut Context, min: usize) -> Poll<io::Result<()>> {
+            while self.buf.len() - self.pos < min {
+                if self.pos != 0 {
+                    self.buf.drain(..self.pos);
+                    self.pos = 0;
+                }
+
+                let read = {
+                    let data = match Pin::new(&mut self.inner).poll_fill_buf(cx) {
+                        Poll::Ready(Ok(data)) => data,
+                        Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                        Poll::Pending => return Poll::Pending,
+                    };
+
+                    self.buf.extend_from_slice(data);
+                    data.len()
+                };
+
+                if read == 0 {
+                    self.eof = true;
+                    break;
+                }
+
+                Pin::new(&mut self.inner).consume(read);
+            }
+
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_read_to_boundary(&mut self, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
+            // Two more than the boundary, for the CRLF preceding it.
+            let mut min = self.boundary.len() + 2;
+
+            let buf_end = loop {
+                match self.poll_fill_min(cx, min) {
+                    Poll::Ready(Ok(())) => (),
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending => return Poll::Pending,
+                }
+
+                let buf = &self.buf[self.pos..];
+                let buf_end = search_boundary(buf, &self.finder, &mut self.search_idx, &mut self.boundary_read);
+
+                if self.boundary_read {
+                    break buf_end;
+                }
+
+                // A partial boundary at EOF can't be completed, so everything left is data.
+                if self.eof {
+                    self.search_idx = buf.len();
+                    break buf.len();
+                }
+
+                // Unlike the blocking reader, the buffers of `inner` may be arbitrarily small, so
+                // the bytes which may be the CRLF before a boundary are held back until it is
+                // known whether one follows.
+                let buf_end = cmp::min(buf_end, self.search_idx.saturating_sub(2));
+
+                if buf_end != 0 {
+                    break buf_end;
+                }
+
+                min = buf.len() + 1;
+            };
+
+            Poll::Ready(Ok(&self.buf[self.pos .. self.pos + buf_end]))
+        }
+
+        fn consume_data(&mut self, amt: usize) {
+            let true_amt = cmp::min(amt, self.search_idx);
+            self.pos += true_amt;
+            self.search_idx -= true_amt;
+        }
+    }
+
+    impl<R: AsyncBufRead + Unpin> AsyncRead for AsyncBoundaryReader<R> {
+        fn poll_read(self: Pin<&mut Self>, cx: &mut Context, out: &mut [u8]) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+
+            let read = match this.poll_read_to_boundary(cx) {
+                Poll::Ready(Ok(buf)) => {
+                    let read = cmp::min(buf.len(), out.len());
+                    out[..read].copy_from_slice(&buf[..read]);
+                    read
+                },
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            this.consume_data(read);
+            Poll::Ready(Ok(read))
+        }
+    }
+
+    impl<R: AsyncBufRead + Unpin> AsyncBufRead for AsyncBoundaryReader<R> {
+        fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
+            self.get_mut().poll_read_to_boundary(cx)
+        }
+
+        fn consume(self: Pin<&mut Self>, amt: usize) {
+            self.get_mut().consume_data(amt)
+        }
+    }
+}
+
+/// The buffer of `BoundaryReader`.
+///
+/// Consuming data only advances `pos`; the unconsumed data is moved to the start of the buffer
+/// when more is read, which only happens when less than a boundary's length of it is left, so
+/// the cost of parsing is linear in the size of the request.
+#[derive(Debug)]
+struct Buffer<R> {
+    reader: R,
+    buf: PooledBuf,
+    /// The unconsumed data is `buf[pos .. end]`.
+    pos: usize,
+    end: usize,
+    /// Whether `reader` has returned EOF.
+    eof: bool,
 }
 
-fn first_nonmatching_idx(left: &[u8], right: &[u8]) -> Option<usize> {
-    for (idx, (lb, rb)) in left.iter().zip(right).enumerate() {
-        if lb != rb {
-            return Some(idx);
+impl<R: Read> Buffer<R> {
+    fn new(reader: R, buf: PooledBuf) -> Self {
+        Buffer {
+            reader: reader,
+            buf: buf,
+            pos: 0,
+            end: 0,
+            eof: false,
         }
     }
 
-    None
+    /// Read from `reader` once if there are fewer than `min` bytes of unconsumed data.
+    fn fill_min(&mut self, min: usize) -> io::Result<()> {
+        if self.end - self.pos < min {
+            if self.pos != 0 {
+                self.buf.copy_within(self.pos .. self.end, 0);
+                self.end -= self.pos;
+                self.pos = 0;
+            }
+
+            if self.end < self.buf.len() {
+                let read = try!(self.reader.read(&mut self.buf[self.end..]));
+                self.eof = read == 0;
+                self.end += read;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.end);
+    }
+}
+
+/// Advance `search_idx` through `buf` to the next occurrence of `boundary`, setting
+/// `boundary_read` if it was found in full, and return the length of the data before it
+/// (excluding the CRLF preceding the boundary).
+///
+/// If `buf` ends with the start of a boundary, `search_idx` stops there.
+///
+/// `finder` is built once per boundary, as it selects the fastest search routine for the
+/// boundary and the CPU (using SSE2, AVX2 or NEON where available), which is what dominates
+/// parsing time for large uploads.
+fn search_boundary(buf: &[u8], finder: &Finder, search_idx: &mut usize, boundary_read: &mut bool) -> usize {
+    if !*boundary_read && *search_idx < buf.len() {
+        let lookahead = &buf[*search_idx..];
+
+        *search_idx = match finder.find(lookahead) {
+            Some(boundary_start) => {
+                *boundary_read = true;
+                *search_idx + boundary_start
+            },
+            None => buf.len() - partial_boundary_len(lookahead, finder.needle()),
+        };
+
+        debug!("Search idx: {} Boundary read: {:?}", search_idx, boundary_read);
+    }
+
+    let mut buf_end = *search_idx;
+
+    if *boundary_read && *search_idx >= 2 {
+        let two_bytes_before = &buf[*search_idx - 2 .. *search_idx];
+
+        debug!("Two bytes before: {:?} (\"\\r\\n\": {:?})", two_bytes_before, b"\r\n");
+
+        if two_bytes_before == &*b"\r\n" {
+            debug!("Subtract two!");
+            buf_end -= 2;
+        } 
+    }
+
+    buf_end
+}
+
+/// The number of bytes at the end of `buf` which could be the start of `boundary`.
+fn partial_boundary_len(buf: &[u8], boundary: &[u8]) -> usize {
+    let max_len = cmp::min(buf.len(), boundary.len() - 1);
+
+    (1 .. max_len + 1).rev()
+        .find(|&len| boundary.starts_with(&buf[buf.len() - len..]))
+        .unwrap_or(0)
 }
 
 #[cfg(test)]
 mod test {
-    use super::BoundaryReader;
+    use super::{parse_boundary, BoundaryReader};
 
     use std::io;
     use std::io::prelude::*;
@@ -261,6 +769,68 @@ dashed-value-2\r
 
     }
 
+    #[test]
+    fn test_read_vectored() {
+        let _ = ::env_logger::init();
+
+        let src = &mut &b"first\r\nsecond\r\n--boundary"[..];
+        let mut reader = BoundaryReader::from_reader(src, "--boundary");
+        let (mut first, mut second) = ([0; 7], [0; 16]);
+
+        let read = {
+            let mut bufs = [io::IoSliceMut::new(&mut first), io::IoSliceMut::new(&mut second)];
+            reader.read_vectored(&mut bufs).unwrap()
+        };
+
+        assert_eq!(read, 13);
+        assert_eq!(&first, b"first\r\n");
+        assert_eq!(&second[..6], b"second");
+        assert_eq!(reader.read(&mut second).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_small_capacity() {
+        let _ = ::env_logger::init();
+
+        for capacity in 0 .. TEST_VAL.len() {
+            debug!("Testing capacity: {}", capacity);
+
+            let reader = BoundaryReader::with_capacity(TEST_VAL.as_bytes(), BOUNDARY, capacity);
+            test_boundary_reader(reader);
+        }
+    }
+
+    #[test]
+    fn test_crlf_before_split_boundary() {
+        let _ = ::env_logger::init();
+
+        // The CRLF before the boundary may be read into the buffer without the boundary.
+        for len in 0 .. 64 {
+            let data = "x".repeat(len);
+            let body = format!("{}\r\n--boundary", data);
+
+            let mut reader = BoundaryReader::with_capacity(body.as_bytes(), "--boundary", 24);
+            let mut buf = String::new();
+            reader.read_to_string(&mut buf).unwrap();
+            assert_eq!(buf, data);
+        }
+    }
+
+    #[test]
+    fn test_pooled_buffer() {
+        use super::BufferPool;
+
+        let _ = ::env_logger::init();
+
+        let pool = BufferPool::new(16, 1);
+
+        for _ in 0 .. 3 {
+            let reader = BoundaryReader::with_pool(TEST_VAL.as_bytes(), BOUNDARY, &pool);
+            test_boundary_reader(reader);
+            assert_eq!(pool.idle(), 1);
+        }
+    }
+
     fn test_boundary_reader<R: Read>(mut reader: BoundaryReader<R>) {
         let ref mut buf = String::new();    
 
@@ -292,4 +862,164 @@ dashed-value-2\r
         let _ = reader.read_to_string(buf).unwrap();
         assert_eq!(buf, "--");
     }
+
+    #[test]
+    fn test_near_boundary() {
+        let _ = ::env_logger::init();
+
+        let src = &mut &b"---boundarx\r\n--boundar\r\n---boundary"[..];
+        let mut reader = BoundaryReader::from_reader(src, "--boundary");
+        let ref mut buf = String::new();
+
+        let _ = reader.read_to_string(buf).unwrap();
+        assert_eq!(buf, "---boundarx\r\n--boundar\r\n-");
+    }
+
+    /// Returns at most `chunk` bytes from each read.
+    struct ChunkedReader<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl<'a> Read for ChunkedReader<'a> {
+        fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+            let len = ::std::cmp::min(dst.len(), self.chunk);
+            self.data.read(&mut dst[..len])
+        }
+    }
+
+    #[test]
+    fn test_boundary_across_refills() {
+        let _ = ::env_logger::init();
+
+        // The boundary starts at every offset relative to the reads and refills of the buffer,
+        // which is compacted each time.
+        for len in 0 .. 64 {
+            let data = "x".repeat(len);
+            let body = format!("{}\r\n--boundary\r\nrest", data);
+            let src = ChunkedReader { data: body.as_bytes(), chunk: 7 };
+
+            let mut reader = BoundaryReader::with_capacity(src, "--boundary", 20);
+            let mut buf = String::new();
+            reader.read_to_string(&mut buf).unwrap();
+            assert_eq!(buf, data);
+
+            assert!(reader.consume_boundary().unwrap());
+            buf.clear();
+            reader.read_to_string(&mut buf).unwrap();
+            assert_eq!(buf, "\r\nrest");
+        }
+    }
+
+    #[test]
+    fn test_full_buffer_without_boundary() {
+        let _ = ::env_logger::init();
+
+        // Data which looks like the start of a boundary at the end of every fill of the buffer.
+        let data = "x\r\n--bound".repeat(100);
+        let mut reader = BoundaryReader::with_capacity(data.as_bytes(), "--boundary", 24);
+        let mut buf = String::new();
+
+        reader.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, data);
+        assert!(!reader.consume_boundary().unwrap());
+    }
+
+    #[test]
+    fn test_one_byte_reader() {
+        let _ = ::env_logger::init();
+
+        let src = ChunkedReader { data: TEST_VAL.as_bytes(), chunk: 1 };
+        test_boundary_reader(BoundaryReader::from_reader(src, BOUNDARY));
+
+        let src = ChunkedReader { data: TEST_VAL.as_bytes(), chunk: 1 };
+        test_boundary_reader(BoundaryReader::with_capacity(src, BOUNDARY, 0));
+    }
+
+    #[test]
+    fn test_parse_boundary() {
+        assert_eq!(parse_boundary("multipart/form-data; boundary=abc; charset=utf-8"), Some("abc"));
+        assert_eq!(parse_boundary("multipart/form-data;Boundary = abc "), Some("abc"));
+        assert_eq!(parse_boundary("multipart/form-data; boundary=\"abc\""), Some("abc"));
+        assert_eq!(parse_boundary("multipart/mixed; name=\"x\\\"; boundary=y\"; boundary=z"), Some("z"));
+        assert_eq!(parse_boundary("multipart/form-data; xboundary=abc"), None);
+        assert_eq!(parse_boundary("multipart/form-data; boundary="), None);
+        assert_eq!(parse_boundary("multipart/form-data; boundary=\"abc"), None);
+        assert_eq!(parse_boundary("boundary=abc"), None);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_boundary() {
+        use futures::executor::block_on;
+        use futures::io::{AsyncReadExt, BufReader, Cursor};
+
+        use super::AsyncBoundaryReader;
+
+        let _ = ::env_logger::init();
+
+        for capacity in 1 .. TEST_VAL.len() {
+            debug!("Testing async boundary with capacity: {}", capacity);
+
+            let src = BufReader::with_capacity(capacity, Cursor::new(TEST_VAL.as_bytes()));
+            let mut reader = AsyncBoundaryReader::new(src, "boundary");
+            let ref mut buf = String::new();
+
+            block_on(reader.read_to_string(buf)).unwrap();
+            assert!(buf.is_empty(), "Buffer not empty: {:?}", buf);
+
+            for expected in &["\r\ndashed-value-1", "\r\ndashed-value-2", "--"] {
+                buf.clear();
+                block_on(reader.consume_boundary()).unwrap();
+                block_on(reader.read_to_string(buf)).unwrap();
+                assert_eq!(buf, expected);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "bench")]
+mod bench {
+    extern crate test;
+
+    use self::test::Bencher;
+
+    use super::BoundaryReader;
+
+    use std::io;
+
+    const PARTS: usize = 16;
+    const PART_SIZE: usize = 256 * 1024;
+
+    /// Parts full of CRLFs and dashes, which look like the start of a boundary, separated by
+    /// real boundaries.
+    fn body() -> Vec<u8> {
+        let filler = b"field data\r\n-- -\r\n--bound\r\n";
+        let mut body = Vec::new();
+
+        for _ in 0 .. PARTS {
+            body.extend(filler.iter().cycle().take(PART_SIZE));
+            body.extend_from_slice(b"\r\n--boundary");
+        }
+
+        body
+    }
+
+    #[bench]
+    fn bench_boundary_reader(b: &mut Bencher) {
+        let body = body();
+        b.bytes = body.len() as u64;
+
+        b.iter(|| {
+            let mut reader = BoundaryReader::from_reader(&body[..], "\r\n--boundary");
+            let mut read = 0;
+
+            for _ in 0 .. PARTS + 1 {
+                read += io::copy(&mut reader, &mut io::sink()).unwrap();
+                reader.consume_boundary().unwrap();
+            }
+
+            assert_eq!(read, (PARTS * PART_SIZE) as u64);
+        });
+    }
 }
@@ -0,0 +1,343 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Reading and writing `multipart/byteranges` bodies, as returned by servers answering GET
+//! requests for several ranges of a resource.
+use mime::Mime;
+
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+
+use super::push::ParseError;
+use super::{Multipart, MultipartPart};
+
+impl<B: Read> Multipart<B> {
+    /// Read the next range of a `multipart/byteranges` body (RFC 7233), such as the body of a
+    /// `206 Partial Content` response to a request for several ranges.
+    ///
+    /// The boundary is taken from the `Content-Type` header of the response, with
+    /// [`parse_boundary()`](fn.parse_boundary.html).
+    ///
+    /// ##Errors
+    /// If a part doesn't have a valid `Content-Range` header, with an error wrapping
+    /// `ParseError::InvalidHeaders`.
+    ///
+    /// ```rust
+    /// use multipart::server::Multipart;
+    /// use std::io::Read;
+    ///
+    /// let body: &[u8] = b"--boundary\r\n\
+    ///     Content-Type: text/plain\r\n\
+    ///     Content-Range: bytes 0-4/26\r\n\r\n\
+    ///     abcde\r\n--boundary\r\n\
+    ///     Content-Type: text/plain\r\n\
+    ///     Content-Range: bytes 20-25/26\r\n\r\n\
+    ///     uvwxyz\r\n--boundary--\r\n";
+    ///
+    /// let mut multipart = Multipart::with_body(body, "boundary");
+    /// let mut ranges = Vec::new();
+    ///
+    /// while let Some(mut range) = multipart.read_byte_range().unwrap() {
+    ///     let mut data = String::new();
+    ///     range.read_to_string(&mut data).unwrap();
+    ///     ranges.push((range.range.start, data));
+    /// }
+    ///
+    /// assert_eq!(ranges, [(0, "abcde".to_string()), (20, "uvwxyz".to_string())]);
+    /// ```
+    pub fn read_byte_range(&mut self) -> io::Result<Option<ByteRange<B>>> {
+        let part = match try!(self.read_part()) {
+            Some(part) => part,
+            None => return Ok(None),
+        };
+
+        let range = try!(part.header("Content-Range").and_then(ContentRange::parse)
+            .ok_or(ParseError::InvalidHeaders));
+
+        Ok(Some(ByteRange {
+            range: range,
+            content_type: part.content_type(),
+            part: part,
+        }))
+    }
+}
+
+/// A range of a `multipart/byteranges` body, returned by
+/// [`Multipart::read_byte_range()`](struct.Multipart.html#method.read_byte_range).
+///
+/// Implements `Read` and `BufRead` for the contents of the range.
+#[derive(Debug)]
+pub struct ByteRange<'a, B: 'a> {
+    /// The range of the resource this part contains, from its `Content-Range` header.
+    pub range: ContentRange,
+    /// The `Content-Type` of this part, if supplied and valid; usually the type of the whole
+    /// resource.
+    pub content_type: Option<Mime>,
+    part: MultipartPart<'a, B>,
+}
+
+impl<'a, B: Read> ByteRange<'a, B> {
+    /// Get the value of the header `name` of this part, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.part.header(name)
+    }
+}
+
+impl<'a, B: Read> Read for ByteRange<'a, B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.part.read(buf)
+    }
+}
+
+impl<'a, B: Read> BufRead for ByteRange<'a, B> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.part.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.part.consume(amt)
+    }
+}
+
+/// The value of a `Content-Range: bytes {start}-{end}/{complete_length}` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentRange {
+    /// The offset of the first byte of the range.
+    pub start: u64,
+    /// The offset of the last byte of the range, inclusive.
+    pub end: u64,
+    /// The length of the whole resource, or `None` if the server sent `*`.
+    pub complete_length: Option<u64>,
+}
+
+impl ContentRange {
+    /// Parse the value of a `Content-Range` header for a range of bytes.
+    ///
+    /// Returns `None` if it is invalid, uses a unit other than `bytes`, or is unsatisfied
+    /// (`bytes */{complete_length}`).
+    pub fn parse(value: &str) -> Option<ContentRange> {
+        let value = value.trim();
+
+        if value.len() < 6 || !value[..6].eq_ignore_ascii_case("bytes ") {
+            return None;
+        }
+
+        let value = value[6..].trim_start();
+        let slash = try_opt!(value.find('/'));
+        let dash = try_opt!(value[..slash].find('-'));
+
+        let start = try_opt!(value[..dash].trim().parse().ok());
+        let end = try_opt!(value[dash + 1..slash].trim().parse().ok());
+
+        let complete_length = match value[slash + 1..].trim() {
+            "*" => None,
+            len => Some(try_opt!(len.parse().ok())),
+        };
+
+        if end < start || complete_length.map_or(false, |len| end >= len) {
+            return None;
+        }
+
+        Some(ContentRange {
+            start: start,
+            end: end,
+            complete_length: complete_length,
+        })
+    }
+
+    /// The number of bytes in this range.
+    pub fn size(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// The length of the boundaries generated by [`ByteRangesWriter`](struct.ByteRangesWriter.html).
+const BOUNDARY_LEN: usize = 32;
+
+/// A writer of `multipart/byteranges` response bodies, for answering requests for several
+/// ranges of a resource with `206 Partial Content` (RFC 7233).
+///
+/// Each part has the `Content-Type` of the resource and a `Content-Range` header, and the length
+/// of the whole body is known before writing it, for the `Content-Length` header of the
+/// response.
+///
+/// ```rust
+/// use multipart::server::{ByteRangesWriter, Multipart};
+/// use std::io::Cursor;
+///
+/// let resource = b"abcdefghijklmnopqrstuvwxyz";
+///
+/// let mut writer = ByteRangesWriter::new("text/plain".parse().unwrap(), resource.len() as u64);
+/// writer.add_range(0, 4).add_range(20, 25);
+///
+/// // Set as the `Content-Type` and `Content-Length` headers of the response.
+/// let content_type = writer.content_type();
+/// let content_length = writer.content_length();
+///
+/// let mut body = Vec::new();
+/// writer.write_to(Cursor::new(&resource[..]), &mut body).unwrap();
+/// assert_eq!(body.len() as u64, content_length);
+///
+/// let mut multipart = Multipart::with_body(&body[..], writer.boundary());
+/// let range = multipart.read_byte_range().unwrap().unwrap();
+/// assert_eq!((range.range.start, range.range.end), (0, 4));
+/// ```
+#[derive(Clone, Debug)]
+pub struct ByteRangesWriter {
+    content_type: Mime,
+    complete_length: u64,
+    ranges: Vec<ContentRange>,
+    boundary: String,
+}
+
+impl ByteRangesWriter {
+    /// Write ranges of a resource with the given `Content-Type` and length, with a randomly
+    /// generated boundary.
+    pub fn new(content_type: Mime, complete_length: u64) -> Self {
+        ByteRangesWriter {
+            content_type: content_type,
+            complete_length: complete_length,
+            ranges: Vec::new(),
+            boundary: ::random_alphanumeric(BOUNDARY_LEN),
+        }
+    }
+
+    /// Add the range of bytes from `start` to `end`, inclusive, as in the `Range` header.
+    ///
+    /// ##Panics
+    /// If `end` is less than `start`, or not less than the length of the resource.
+    pub fn add_range(&mut self, start: u64, end: u64) -> &mut Self {
+        assert!(start <= end && end < self.complete_length,
+                "invalid range {}-{} of {} bytes", start, end, self.complete_length);
+
+        self.ranges.push(ContentRange {
+            start: start,
+            end: end,
+            complete_length: Some(self.complete_length),
+        });
+
+        self
+    }
+
+    /// The boundary of the body.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Get the value of the `Content-Type` header for the body, including the boundary.
+    pub fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    /// Get the length of the body, for the `Content-Length` header.
+    pub fn content_length(&self) -> u64 {
+        let headers_len: u64 = self.ranges.iter().map(|range| self.part_headers(range).len() as u64).sum();
+        let data_len: u64 = self.ranges.iter().map(ContentRange::size).sum();
+
+        headers_len + data_len + self.closing_boundary().len() as u64
+    }
+
+    /// Write the body to `out`, reading the ranges from `source`, which contains the whole
+    /// resource. Returns the number of bytes written.
+    ///
+    /// ##Errors
+    /// If `source` ends before a range does, with an error of kind `UnexpectedEof`.
+    pub fn write_to<R: Read + Seek, W: Write>(&self, mut source: R, mut out: W) -> io::Result<u64> {
+        let mut written = 0;
+
+        for range in &self.ranges {
+            let headers = self.part_headers(range);
+            try!(out.write_all(headers.as_bytes()));
+
+            try!(source.seek(SeekFrom::Start(range.start)));
+            let copied = try!(io::copy(&mut source.by_ref().take(range.size()), &mut out));
+
+            if copied < range.size() {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "resource ended before range"));
+            }
+
+            written += headers.len() as u64 + copied;
+        }
+
+        let closing = self.closing_boundary();
+        try!(out.write_all(closing.as_bytes()));
+
+        Ok(written + closing.len() as u64)
+    }
+
+    fn part_headers(&self, range: &ContentRange) -> String {
+        format!("\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                self.boundary, self.content_type, range.start, range.end, self.complete_length)
+    }
+
+    fn closing_boundary(&self) -> String {
+        format!("\r\n--{}--\r\n", self.boundary)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ByteRangesWriter, ContentRange};
+
+    use std::io::{self, Cursor, Read};
+
+    use server::Multipart;
+    use server::push::ParseError;
+
+    #[test]
+    fn test_content_range() {
+        let range = ContentRange::parse("bytes 0-499/1234").unwrap();
+        assert_eq!(range, ContentRange { start: 0, end: 499, complete_length: Some(1234) });
+        assert_eq!(range.size(), 500);
+
+        assert_eq!(ContentRange::parse("Bytes 10-10/*"),
+                   Some(ContentRange { start: 10, end: 10, complete_length: None }));
+
+        assert_eq!(ContentRange::parse("bytes */1234"), None);
+        assert_eq!(ContentRange::parse("bytes 5-4/10"), None);
+        assert_eq!(ContentRange::parse("bytes 0-10/10"), None);
+        assert_eq!(ContentRange::parse("items 0-4/10"), None);
+    }
+
+    #[test]
+    fn test_missing_content_range() {
+        let body = b"--boundary\r\nContent-Type: text/plain\r\n\r\nabc\r\n--boundary--\r\n";
+        let mut multipart = Multipart::with_body(&body[..], "boundary");
+
+        let err = multipart.read_byte_range().unwrap_err();
+        assert_eq!(err.get_ref().and_then(|err| err.downcast_ref::<ParseError>()),
+                   Some(&ParseError::InvalidHeaders));
+    }
+
+    #[test]
+    fn test_byte_ranges_writer() {
+        let resource = b"abcdefghijklmnopqrstuvwxyz";
+
+        let mut writer = ByteRangesWriter::new("text/plain".parse().unwrap(), 26);
+        writer.add_range(0, 4).add_range(25, 25);
+
+        let mut body = Vec::new();
+        let written = writer.write_to(Cursor::new(&resource[..]), &mut body).unwrap();
+        assert_eq!(written, body.len() as u64);
+        assert_eq!(writer.content_length(), written);
+
+        let mut multipart = Multipart::with_body(&body[..], writer.boundary());
+        let mut ranges = Vec::new();
+
+        while let Some(mut range) = multipart.read_byte_range().unwrap() {
+            let mut data = String::new();
+            range.read_to_string(&mut data).unwrap();
+            ranges.push((range.range, data));
+        }
+
+        assert_eq!(ranges, [
+            (ContentRange { start: 0, end: 4, complete_length: Some(26) }, "abcde".to_string()),
+            (ContentRange { start: 25, end: 25, complete_length: Some(26) }, "z".to_string()),
+        ]);
+
+        let err = writer.write_to(Cursor::new(&resource[..10]), Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,379 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Reading multipart requests in CGI and FastCGI programs.
+//!
+//! A CGI program is given the request's headers as environment variables and its body on
+//! stdin, which [`Multipart::from_cgi_stdin()`](../struct.Multipart.html#method.from_cgi_stdin)
+//! reads directly:
+//!
+//! ```rust,no_run
+//! use multipart::server::Multipart;
+//!
+//! let mut multipart = Multipart::from_cgi_stdin().expect("not a multipart request");
+//!
+//! multipart.foreach_entry(|field| println!("field: {}", field.name)).unwrap();
+//! ```
+//!
+//! A FastCGI application instead receives records on a socket, which
+//! [`FastCgiStdin`](struct.FastCgiStdin.html) unwraps into the variables and body of a request.
+use mime::{Mime, TopLevel};
+
+use std::io::{self, Read, Stdin, Take};
+use std::{cmp, env};
+
+use super::{accepts_subtype, parse_boundary, Multipart, DEFAULT_SUBTYPES};
+
+const FCGI_VERSION_1: u8 = 1;
+
+const FCGI_ABORT_REQUEST: u8 = 2;
+const FCGI_PARAMS: u8 = 4;
+const FCGI_STDIN: u8 = 5;
+
+impl<B: Read> Multipart<Take<B>> {
+    /// Wrap `body` in the multipart reader if the CGI meta-variables `vars` contain a
+    /// `CONTENT_TYPE` of `multipart/form-data` with a boundary. Otherwise, returns `body`.
+    ///
+    /// `vars` may be the environment, as from `std::env::vars()`, or any other collection of
+    /// name-value pairs, such as the params of a FastCGI request.
+    ///
+    /// Only `CONTENT_LENGTH` bytes of `body` are read, as the web server may not close it at the
+    /// end of the request. If `CONTENT_LENGTH` is unset, `body` is read until EOF; if it is not
+    /// a number, `body` is returned.
+    pub fn from_cgi<I, K, V>(vars: I, body: B) -> Result<Self, B>
+    where I: IntoIterator<Item = (K, V)>, K: AsRef<str>, V: AsRef<str> {
+        let mut boundary = None;
+        let mut content_len = Some(u64::max_value());
+
+        for (name, value) in vars {
+            match name.as_ref() {
+                "CONTENT_TYPE" => boundary = form_data_boundary(value.as_ref()).map(String::from),
+                "CONTENT_LENGTH" => content_len = value.as_ref().trim().parse().ok(),
+                _ => (),
+            }
+        }
+
+        match (boundary, content_len) {
+            (Some(boundary), Some(content_len)) =>
+                Ok(Multipart::with_body(body.take(content_len), boundary)),
+            _ => Err(body),
+        }
+    }
+}
+
+impl Multipart<Take<Stdin>> {
+    /// Read the request of a CGI program: its meta-variables from the environment and its body
+    /// from stdin. See [`from_cgi()`](#method.from_cgi) for more information.
+    pub fn from_cgi_stdin() -> Result<Self, Stdin> {
+        Multipart::from_cgi(env::vars(), io::stdin())
+    }
+}
+
+fn form_data_boundary(content_type: &str) -> Option<&str> {
+    match content_type.parse() {
+        Ok(Mime(TopLevel::Multipart, ref sub, _)) if accepts_subtype(DEFAULT_SUBTYPES, sub.as_str()) => (),
+        _ => return None,
+    }
+
+    parse_boundary(content_type)
+}
+
+/// Reads the params and the `FCGI_STDIN` stream of a request from a FastCGI connection.
+///
+/// Call [`read_params()`](#method.read_params) first, then pass the params and this reader to
+/// [`Multipart::from_cgi()`](../struct.Multipart.html#method.from_cgi), which reads the body.
+/// Only the first request on the connection is read; records of other requests and management
+/// records are skipped. Responding to the request is left to the application.
+///
+/// ```rust
+/// use multipart::server::Multipart;
+/// use multipart::server::cgi::FastCgiStdin;
+///
+/// fn record(kind: u8, content: &[u8]) -> Vec<u8> {
+///     let mut record = vec![1, kind, 0, 1, 0, content.len() as u8, 0, 0];
+///     record.extend_from_slice(content);
+///     record
+/// }
+///
+/// let mut conn = record(1, &[0, 1, 0, 0, 0, 0, 0, 0]); // FCGI_BEGIN_REQUEST
+/// conn.extend(record(4, b"\x0c\x26CONTENT_TYPEmultipart/form-data; boundary=boundary"));
+/// conn.extend(record(4, b"")); // FCGI_PARAMS
+/// conn.extend(record(5, b"--boundary\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nb\r\n"));
+/// conn.extend(record(5, b"--boundary--\r\n"));
+/// conn.extend(record(5, b"")); // FCGI_STDIN
+///
+/// let mut stdin = FastCgiStdin::new(&conn[..]);
+/// let params = stdin.read_params().unwrap();
+///
+/// let mut multipart = Multipart::from_cgi(params, stdin).ok().expect("not a multipart request");
+/// let field = multipart.read_entry().unwrap().unwrap();
+/// assert_eq!(field.name, "a");
+/// assert_eq!(field.data.as_text(), Some("b"));
+/// ```
+#[derive(Debug)]
+pub struct FastCgiStdin<R> {
+    inner: R,
+    request_id: Option<u16>,
+    /// The unread content of the current `FCGI_STDIN` record.
+    remaining: usize,
+    /// The padding after the current `FCGI_STDIN` record.
+    padding: usize,
+    ended: bool,
+}
+
+struct RecordHeader {
+    kind: u8,
+    content_len: usize,
+    padding: usize,
+}
+
+impl<R: Read> FastCgiStdin<R> {
+    /// Read the records of a request from `inner`, such as a connection accepted from the web
+    /// server.
+    pub fn new(inner: R) -> Self {
+        FastCgiStdin {
+            inner: inner,
+            request_id: None,
+            remaining: 0,
+            padding: 0,
+            ended: false,
+        }
+    }
+
+    /// Read the `FCGI_PARAMS` stream of the request, which holds its CGI meta-variables.
+    ///
+    /// ##Errors
+    /// Of kind `InvalidData` if the records are malformed or `FCGI_STDIN` starts first, and
+    /// `ConnectionAborted` if the web server aborts the request.
+    pub fn read_params(&mut self) -> io::Result<Vec<(String, String)>> {
+        let mut params = Vec::new();
+
+        loop {
+            let header = try!(self.next_record());
+
+            match header.kind {
+                FCGI_PARAMS if header.content_len == 0 => {
+                    try!(skip(&mut self.inner, header.padding));
+                    break;
+                },
+                FCGI_PARAMS => {
+                    let start = params.len();
+                    params.resize(start + header.content_len, 0);
+                    try!(self.inner.read_exact(&mut params[start..]));
+                    try!(skip(&mut self.inner, header.padding));
+                },
+                FCGI_STDIN => return Err(invalid_data("FastCGI stdin started before the end of the params")),
+                _ => try!(skip(&mut self.inner, header.content_len + header.padding)),
+            }
+        }
+
+        decode_params(&params)
+    }
+
+    /// Get the underlying reader, such as to respond to the request.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Read the header of the next record of the request, skipping the records of other requests.
+    fn next_record(&mut self) -> io::Result<RecordHeader> {
+        loop {
+            let mut header = [0; 8];
+            try!(self.inner.read_exact(&mut header));
+
+            if header[0] != FCGI_VERSION_1 {
+                return Err(invalid_data("unsupported FastCGI protocol version"));
+            }
+
+            let request_id = (header[2] as u16) << 8 | header[3] as u16;
+
+            let record = RecordHeader {
+                kind: header[1],
+                content_len: (header[4] as usize) << 8 | header[5] as usize,
+                padding: header[6] as usize,
+            };
+
+            // Request ID 0 is used for management records.
+            if request_id == 0 || *self.request_id.get_or_insert(request_id) != request_id {
+                try!(skip(&mut self.inner, record.content_len + record.padding));
+                continue;
+            }
+
+            if record.kind == FCGI_ABORT_REQUEST {
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "FastCGI request aborted"));
+            }
+
+            return Ok(record);
+        }
+    }
+}
+
+impl<R: Read> Read for FastCgiStdin<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.remaining == 0 {
+            if self.ended || buf.is_empty() {
+                return Ok(0);
+            }
+
+            try!(skip(&mut self.inner, self.padding));
+            self.padding = 0;
+
+            let header = try!(self.next_record());
+
+            match header.kind {
+                FCGI_STDIN if header.content_len == 0 => {
+                    try!(skip(&mut self.inner, header.padding));
+                    self.ended = true;
+                },
+                FCGI_STDIN => {
+                    self.remaining = header.content_len;
+                    self.padding = header.padding;
+                },
+                _ => try!(skip(&mut self.inner, header.content_len + header.padding)),
+            }
+        }
+
+        let len = cmp::min(buf.len(), self.remaining);
+        let read = try!(self.inner.read(&mut buf[..len]));
+
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
+/// Decode FastCGI name-value pairs, whose lengths are one byte, or four with the high bit set.
+fn decode_params(mut buf: &[u8]) -> io::Result<Vec<(String, String)>> {
+    fn read_len(buf: &mut &[u8]) -> io::Result<usize> {
+        let (len, size) = match *buf {
+            buf if !buf.is_empty() && buf[0] < 0x80 => (buf[0] as usize, 1),
+            buf if buf.len() >= 4 => (
+                ((buf[0] & 0x7F) as usize) << 24 | (buf[1] as usize) << 16
+                    | (buf[2] as usize) << 8 | buf[3] as usize,
+                4,
+            ),
+            _ => return Err(invalid_data("truncated FastCGI param")),
+        };
+
+        *buf = &buf[size..];
+        Ok(len)
+    }
+
+    fn read_str(buf: &mut &[u8], len: usize) -> io::Result<String> {
+        if buf.len() < len {
+            return Err(invalid_data("truncated FastCGI param"));
+        }
+
+        let (string, rest) = buf.split_at(len);
+        *buf = rest;
+        String::from_utf8(string.to_vec()).map_err(|_| invalid_data("FastCGI param is not valid UTF-8"))
+    }
+
+    let mut params = Vec::new();
+
+    while !buf.is_empty() {
+        let name_len = try!(read_len(&mut buf));
+        let value_len = try!(read_len(&mut buf));
+        let name = try!(read_str(&mut buf, name_len));
+        let value = try!(read_str(&mut buf, value_len));
+        params.push((name, value));
+    }
+
+    Ok(params)
+}
+
+fn skip<R: Read>(inner: &mut R, len: usize) -> io::Result<()> {
+    let skipped = try!(io::copy(&mut inner.take(len as u64), &mut io::sink()));
+
+    if skipped < len as u64 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(())
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_params, FastCgiStdin};
+
+    use server::Multipart;
+
+    use std::io::Read;
+
+    fn record(kind: u8, request_id: u8, content: &[u8], padding: u8) -> Vec<u8> {
+        let mut record = vec![1, kind, 0, request_id, 0, content.len() as u8, padding, 0];
+        record.extend_from_slice(content);
+        record.extend((0 .. padding).map(|_| 0xFF));
+        record
+    }
+
+    #[test]
+    fn test_from_cgi() {
+        let body: &[u8] = b"--boundary\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nb\r\n\
+            --boundary--\r\nnext request";
+
+        let vars = vec![
+            ("CONTENT_TYPE", "multipart/form-data; boundary=boundary"),
+            ("CONTENT_LENGTH", "73"),
+        ];
+
+        let mut multipart = Multipart::from_cgi(vars, body).ok().unwrap();
+        assert_eq!(multipart.read_entry().unwrap().unwrap().name, "a");
+        assert!(multipart.read_entry().unwrap().is_none());
+
+        assert!(Multipart::from_cgi(vec![("CONTENT_TYPE", "text/plain")], body).is_err());
+
+        let vars = vec![
+            ("CONTENT_TYPE", "multipart/form-data; boundary=boundary"),
+            ("CONTENT_LENGTH", "many"),
+        ];
+        assert!(Multipart::from_cgi(vars, body).is_err());
+    }
+
+    #[test]
+    fn test_fastcgi_stdin() {
+        let long_value = "x".repeat(200);
+        let mut params = b"\x0c\x01CONTENT_TYPEa\x0a\x80\x00\x00\xC8LONG_PARAM".to_vec();
+        params.extend_from_slice(long_value.as_bytes());
+
+        let mut conn = record(1, 1, &[0, 1, 0, 0, 0, 0, 0, 0], 0);
+        conn.extend(record(4, 1, &params[..100], 4));
+        // Records of other requests and management records are skipped.
+        conn.extend(record(4, 2, b"\x01\x01ab", 0));
+        conn.extend(record(9, 0, b"", 0));
+        conn.extend(record(4, 1, &params[100..], 0));
+        conn.extend(record(4, 1, b"", 0));
+        conn.extend(record(5, 1, b"hello, ", 3));
+        conn.extend(record(5, 2, b"other", 0));
+        conn.extend(record(5, 1, b"world", 1));
+        conn.extend(record(5, 1, b"", 0));
+
+        let mut stdin = FastCgiStdin::new(&conn[..]);
+
+        assert_eq!(stdin.read_params().unwrap(), vec![
+            ("CONTENT_TYPE".to_string(), "a".to_string()),
+            ("LONG_PARAM".to_string(), long_value),
+        ]);
+
+        let mut body = String::new();
+        stdin.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello, world");
+
+        // FCGI_ABORT_REQUEST
+        let mut conn = record(5, 1, b"partial", 0);
+        conn.extend(record(2, 1, b"", 0));
+        assert!(FastCgiStdin::new(&conn[..]).read_to_end(&mut Vec::new()).is_err());
+
+        assert!(decode_params(b"\x05\x01abc").is_err());
+    }
+}
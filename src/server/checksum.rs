@@ -0,0 +1,232 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Verification of the `Content-MD5` and `Digest` headers of parts.
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use std::{error, fmt, io};
+
+/// A digest of the contents of a part, sent by the client in its `Content-MD5` header
+/// ([RFC 1864](https://tools.ietf.org/html/rfc1864)) or `Digest` header
+/// ([RFC 3230](https://tools.ietf.org/html/rfc3230)).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Checksum {
+    /// An MD5 digest, from `Content-MD5` or `Digest: MD5=...`.
+    Md5([u8; 16]),
+    /// A SHA-256 digest, from `Digest: SHA-256=...`.
+    Sha256([u8; 32]),
+}
+
+impl Checksum {
+    /// Read a checksum from a header line, if it is a `Content-MD5` header or a `Digest`
+    /// header with a supported algorithm.
+    pub fn read_from(line: &str) -> Option<Checksum> {
+        if let Some(value) = header_value("Content-MD5", line) {
+            return decode(value).and_then(|digest| md5_checksum(&digest));
+        }
+
+        let value = try_opt!(header_value("Digest", line));
+
+        // The first supported algorithm in the list is used.
+        value.split(',').filter_map(|digest| {
+            let mut split = digest.trim().splitn(2, '=');
+            let algorithm = try_opt!(split.next());
+            let digest = try_opt!(split.next().and_then(decode));
+
+            if algorithm.eq_ignore_ascii_case("md5") {
+                md5_checksum(&digest)
+            } else if algorithm.eq_ignore_ascii_case("sha-256") && digest.len() == 32 {
+                let mut sha256 = [0; 32];
+                sha256.copy_from_slice(&digest);
+                Some(Checksum::Sha256(sha256))
+            } else {
+                None
+            }
+        }).next()
+    }
+}
+
+fn header_value<'a>(name: &str, line: &'a str) -> Option<&'a str> {
+    let name_len = name.len();
+
+    if line.len() > name_len && line.as_bytes()[..name_len].eq_ignore_ascii_case(name.as_bytes())
+        && line.as_bytes()[name_len] == b':' {
+        Some(line[name_len + 1..].trim())
+    } else {
+        None
+    }
+}
+
+fn decode(value: &str) -> Option<Vec<u8>> {
+    STANDARD.decode(value.trim()).ok()
+}
+
+fn md5_checksum(digest: &[u8]) -> Option<Checksum> {
+    if digest.len() != 16 {
+        return None;
+    }
+
+    let mut md5 = [0; 16];
+    md5.copy_from_slice(digest);
+    Some(Checksum::Md5(md5))
+}
+
+/// The error of a part whose contents don't match the digest in its `Content-MD5` or `Digest`
+/// header.
+///
+/// Returned wrapped in an `io::Error` of kind `io::ErrorKind::InvalidData`, from which it can be
+/// recovered with `get_ref()` and `downcast_ref()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    /// The checksum sent by the client.
+    pub expected: Checksum,
+}
+
+impl error::Error for ChecksumMismatch {}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let algorithm = match self.expected {
+            Checksum::Md5(_) => "MD5",
+            Checksum::Sha256(_) => "SHA-256",
+        };
+
+        write!(f, "multipart part does not match its {} checksum", algorithm)
+    }
+}
+
+impl From<ChecksumMismatch> for io::Error {
+    fn from(err: ChecksumMismatch) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Hashes the contents of a part to compare them to its `Checksum`.
+#[derive(Debug)]
+pub struct Verifier {
+    expected: Checksum,
+    hasher: Hasher,
+}
+
+#[derive(Debug)]
+enum Hasher {
+    Md5(Md5),
+    Sha256(Sha256),
+}
+
+impl Verifier {
+    pub fn new(expected: Checksum) -> Self {
+        let hasher = match expected {
+            Checksum::Md5(_) => Hasher::Md5(Md5::new()),
+            Checksum::Sha256(_) => Hasher::Sha256(Sha256::new()),
+        };
+
+        Verifier {
+            expected: expected,
+            hasher: hasher,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self.hasher {
+            Hasher::Md5(ref mut md5) => md5.update(data),
+            Hasher::Sha256(ref mut sha256) => sha256.update(data),
+        }
+    }
+
+    /// Compare the digest of the data passed so far to the expected one.
+    pub fn verify(self) -> io::Result<()> {
+        let matches = match (self.hasher, &self.expected) {
+            (Hasher::Md5(md5), &Checksum::Md5(ref expected)) => md5.finalize()[..] == expected[..],
+            (Hasher::Sha256(sha256), &Checksum::Sha256(ref expected)) => sha256.finalize()[..] == expected[..],
+            _ => false,
+        };
+
+        if matches {
+            Ok(())
+        } else {
+            Err(ChecksumMismatch { expected: self.expected }.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Checksum, ChecksumMismatch, Verifier};
+
+    use server::Multipart;
+
+    use std::io;
+
+    #[test]
+    fn test_read_checksum() {
+        // MD5 and SHA-256 of "contents".
+        let md5 = [
+            0x98, 0xbf, 0x7d, 0x8c, 0x15, 0x78, 0x4f, 0x0a,
+            0x3d, 0x63, 0x20, 0x44, 0x41, 0xe1, 0xe2, 0xaa,
+        ];
+
+        assert_eq!(Checksum::read_from("Content-MD5: mL99jBV4Two9YyBEQeHiqg=="), Some(Checksum::Md5(md5)));
+        assert_eq!(Checksum::read_from("digest: unixsum=30637, md5=mL99jBV4Two9YyBEQeHiqg=="), Some(Checksum::Md5(md5)));
+
+        match Checksum::read_from("Digest: SHA-256=0bKln76n4gB3r5+Rsn6V6GUGGycL4D/1Oas7c1h4gug=") {
+            Some(Checksum::Sha256(_)) => (),
+            other => panic!("unexpected checksum: {:?}", other),
+        }
+
+        assert_eq!(Checksum::read_from("Content-MD5: not base64"), None);
+        assert_eq!(Checksum::read_from("Content-Digest: md5=mL99jBV4Two9YyBEQeHiqg=="), None);
+    }
+
+    #[test]
+    fn test_verify() {
+        for header in &["Content-MD5: mL99jBV4Two9YyBEQeHiqg==",
+                        "Digest: SHA-256=0bKln76n4gB3r5+Rsn6V6GUGGycL4D/1Oas7c1h4gug="] {
+            let checksum = Checksum::read_from(header).unwrap();
+
+            let mut verifier = Verifier::new(checksum.clone());
+            verifier.update(b"con");
+            verifier.update(b"tents");
+            verifier.verify().unwrap();
+
+            let mut verifier = Verifier::new(checksum);
+            verifier.update(b"tampered");
+            assert!(verifier.verify().is_err());
+        }
+    }
+
+    #[test]
+    fn test_verify_file() {
+        fn read_file(md5: &str) -> io::Result<Vec<u8>> {
+            let body = format!("--boundary\r\n\
+                Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+                Content-Type: text/plain\r\n\
+                Content-MD5: {}\r\n\r\n\
+                contents\r\n--boundary--\r\n", md5);
+
+            let mut multipart = Multipart::with_body(body.as_bytes(), "boundary");
+            let mut field = multipart.read_entry().unwrap().unwrap();
+            let mut data = Vec::new();
+            try!(field.data.as_file().unwrap().save_to(&mut data));
+            Ok(data)
+        }
+
+        assert_eq!(read_file("mL99jBV4Two9YyBEQeHiqg==").unwrap(), b"contents");
+
+        let err = read_file("1B2M2Y8AsgTpgAmY7PhCfg==").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mismatch = err.get_ref().and_then(|err| err.downcast_ref::<ChecksumMismatch>()).unwrap();
+        assert_eq!(mismatch.expected, Checksum::Md5([
+            0xd4, 0x1d, 0x8c, 0xd9, 0x8f, 0x00, 0xb2, 0x04, 0xe9, 0x80, 0x09, 0x98, 0xec, 0xf8, 0x42, 0x7e
+        ]));
+        assert_eq!(mismatch.to_string(), "multipart part does not match its MD5 checksum");
+    }
+}
@@ -0,0 +1,128 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! zstd compression of saved files, for `LocalStorage::zstd()`.
+use zstd::Encoder;
+
+use std::fmt;
+use std::io::{self, Write};
+
+/// Compresses the data written to it with zstd before passing it to the inner writer, or passes
+/// it through as-is without a compression level.
+pub enum Compress<W: Write> {
+    Plain(W),
+    Zstd(Encoder<'static, W>),
+}
+
+impl<W: Write> Compress<W> {
+    pub fn new(inner: W, level: Option<i32>) -> io::Result<Self> {
+        match level {
+            Some(level) => Encoder::new(inner, level).map(Compress::Zstd),
+            None => Ok(Compress::Plain(inner)),
+        }
+    }
+
+    /// Write the end of the compressed data, returning the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Compress::Plain(inner) => Ok(inner),
+            Compress::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Compress<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Compress::Plain(ref mut inner) => inner.write(buf),
+            Compress::Zstd(ref mut encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Compress::Plain(ref mut inner) => inner.flush(),
+            Compress::Zstd(ref mut encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl<W: Write + fmt::Debug> fmt::Debug for Compress<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Compress::Plain(ref inner) => write!(f, "Compress::Plain({:?})", inner),
+            Compress::Zstd(ref encoder) => write!(f, "Compress::Zstd({:?})", encoder.get_ref()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use server::Multipart;
+    use server::storage::LocalStorage;
+
+    use tempdir::TempDir;
+
+    use std::fs;
+    use std::io::Read;
+
+    fn file_body(contents: &str) -> String {
+        format!("--boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            {}\r\n--boundary--\r\n", contents)
+    }
+
+    #[test]
+    fn test_save_all_compressed() {
+        let contents = "compressible ".repeat(1000);
+        let body = file_body(&contents);
+
+        let entries = Multipart::with_body(body.as_bytes(), "boundary").compress(3).save_all()
+            .to_result().unwrap();
+
+        let file = &entries.files["file"];
+        assert_eq!(file.size, contents.len() as u64);
+        assert!(fs::metadata(&file.path).unwrap().len() < contents.len() as u64 / 10);
+
+        let mut read = String::new();
+        entries.open_file(file).unwrap().read_to_string(&mut read).unwrap();
+        assert_eq!(read, contents);
+
+        let dir = TempDir::new("multipart-zstd").unwrap();
+        let mut storage = LocalStorage::new(dir.path());
+        storage.zstd(3);
+
+        let entries = Multipart::with_body(body.as_bytes(), "boundary").save_all_to(&mut storage).unwrap();
+        let saved = fs::read(&entries.files["file"].stored.path).unwrap();
+        assert_eq!(::zstd::decode_all(&saved[..]).unwrap(), contents.as_bytes());
+
+        let entries = Multipart::with_body(body.as_bytes(), "boundary").compress(3).save_all_under(dir.path())
+            .to_result().unwrap();
+        let saved = fs::read(&entries.files["file"].path).unwrap();
+        assert_eq!(::zstd::decode_all(&saved[..]).unwrap(), contents.as_bytes());
+    }
+
+    #[cfg(feature = "encrypt")]
+    #[test]
+    fn test_save_all_compressed_encrypted() {
+        let contents = "compressible ".repeat(1000);
+        let body = file_body(&contents);
+
+        let entries = Multipart::with_body(body.as_bytes(), "boundary").compress(3).encrypt(true).save_all()
+            .to_result().unwrap();
+        assert!(entries.is_encrypted());
+
+        let file = &entries.files["file"];
+        let saved = fs::read(&file.path).unwrap();
+        assert!(saved.len() < contents.len() / 10);
+        assert!(::zstd::decode_all(&saved[..]).is_err());
+
+        let mut read = String::new();
+        entries.open_file(file).unwrap().read_to_string(&mut read).unwrap();
+        assert_eq!(read, contents);
+    }
+}
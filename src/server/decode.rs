@@ -0,0 +1,130 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Decoding of parts sent with `Content-Encoding: gzip`.
+use flate2::write::GzDecoder;
+
+use std::cmp;
+use std::io::{self, Write};
+
+/// Returns `true` if parts with this `Content-Encoding` are decoded.
+pub fn is_supported(encoding: &str) -> bool {
+    encoding.eq_ignore_ascii_case("gzip") || encoding.eq_ignore_ascii_case("x-gzip")
+}
+
+/// Decodes a gzip stream which is written to it in chunks, keeping the decoded data until it's
+/// consumed.
+///
+/// The decoded data of each chunk is bounded by the decompressor's internal buffer, so a
+/// highly compressed part can't make this allocate more than that at once.
+#[derive(Debug)]
+pub struct Decoder {
+    gz: GzDecoder<Vec<u8>>,
+    pos: usize,
+    done: bool,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder {
+            gz: GzDecoder::new(Vec::new()),
+            pos: 0,
+            done: false,
+        }
+    }
+
+    /// The decoded data which has not been consumed.
+    pub fn decoded(&self) -> &[u8] {
+        &self.gz.get_ref()[self.pos..]
+    }
+
+    pub fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.gz.get_ref().len());
+    }
+
+    /// Returns `true` if the end of the input has been decoded.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Decode a chunk of `input`, returning the number of bytes of it which were used.
+    pub fn decode(&mut self, input: &[u8]) -> io::Result<usize> {
+        self.clear_consumed();
+
+        match try!(self.gz.write(input)) {
+            0 if !input.is_empty() => Err(io::Error::new(
+                io::ErrorKind::InvalidData, "data after the end of a gzip-encoded part"
+            )),
+            used => Ok(used),
+        }
+    }
+
+    /// Decode the remainder of the input, after its end was reached, checking that the gzip
+    /// stream was complete.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.clear_consumed();
+        try!(self.gz.try_finish());
+        self.done = true;
+        Ok(())
+    }
+
+    fn clear_consumed(&mut self) {
+        if self.pos == self.gz.get_ref().len() {
+            self.gz.get_mut().clear();
+            self.pos = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    use server::Multipart;
+
+    use std::io::{self, Read, Write};
+
+    fn body(encoded: &[u8]) -> Vec<u8> {
+        let mut body = b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+            Content-Type: text/plain\r\n\
+            Content-Encoding: gzip\r\n\r\n".to_vec();
+        body.extend_from_slice(encoded);
+        body.extend_from_slice(b"\r\n--boundary--\r\n");
+        body
+    }
+
+    fn read_file(body: &[u8]) -> io::Result<Vec<u8>> {
+        // A small buffer, so the part is decoded in several chunks.
+        let mut multipart = Multipart::with_capacity(body, "boundary", 64);
+        let mut field = multipart.read_entry().unwrap().unwrap();
+        let file = field.data.as_file().unwrap();
+        assert_eq!(file.content_encoding(), Some("gzip"));
+
+        let mut data = Vec::new();
+        try!(file.read_to_end(&mut data));
+        Ok(data)
+    }
+
+    #[test]
+    fn test_gzip_part() {
+        let contents: Vec<u8> = (0..10000u32).flat_map(|i| i.to_string().into_bytes()).collect();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&contents).unwrap();
+        let encoded = encoder.finish().unwrap();
+
+        assert_eq!(read_file(&body(&encoded)).unwrap(), contents);
+
+        let truncated = &encoded[..encoded.len() - 4];
+        assert_eq!(read_file(&body(truncated)).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        let mut trailing = encoded.clone();
+        trailing.extend_from_slice(b"garbage");
+        assert_eq!(read_file(&body(&trailing)).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! The `Content-Disposition` parameters for file metadata which RFC 2388 allowed on
+//! `form-data` parts, and RFC 7578 deprecated.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::boundary::parse_param;
+
+/// The `creation-date`, `modification-date` and `size` parameters of the `Content-Disposition`
+/// header of a part (RFC 2183), which some legacy clients still send with files.
+///
+/// Parameters which are missing or invalid are `None`.
+///
+/// ##Warning
+/// Like the filename, these are provided by the client, so they should be treated as
+/// untrustworthy; in particular, `size` is not checked against the contents of the part.
+///
+/// ```rust
+/// use multipart::server::FieldHeaders;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let headers = FieldHeaders::parse("Content-Disposition: form-data; name=\"file\"; \
+///     filename=\"report.txt\"; modification-date=\"Wed, 12 Feb 1997 16:29:51 -0500\"; \
+///     size=1024").unwrap();
+///
+/// let params = headers.disposition_params;
+/// assert_eq!(params.modification_date, Some(UNIX_EPOCH + Duration::from_secs(855782991)));
+/// assert_eq!(params.creation_date, None);
+/// assert_eq!(params.size, Some(1024));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DispositionParams {
+    /// The `creation-date` of the file.
+    pub creation_date: Option<SystemTime>,
+    /// The `modification-date` of the file.
+    pub modification_date: Option<SystemTime>,
+    /// The `size` of the file in bytes.
+    pub size: Option<u64>,
+}
+
+impl DispositionParams {
+    /// Read the parameters from a `Content-Disposition` header line.
+    pub(crate) fn read_from(line: &str) -> DispositionParams {
+        DispositionParams {
+            creation_date: parse_param(line, "creation-date").and_then(parse_date),
+            modification_date: parse_param(line, "modification-date").and_then(parse_date),
+            size: parse_param(line, "size").and_then(|size| size.parse().ok()),
+        }
+    }
+}
+
+/// Parse an RFC 822 date-time, such as `Wed, 12 Feb 1997 16:29:51 -0500`.
+///
+/// Two-digit years are read as in RFC 5322, and unknown time zones as UTC.
+fn parse_date(date: &str) -> Option<SystemTime> {
+    const MONTHS: [&'static str; 12] = ["jan", "feb", "mar", "apr", "may", "jun",
+                                        "jul", "aug", "sep", "oct", "nov", "dec"];
+
+    // The day of the week is redundant.
+    let date = date.find(',').map_or(date, |comma| &date[comma + 1..]);
+    let mut fields = date.split_whitespace();
+
+    let day: i64 = try_opt!(try_opt!(fields.next()).parse().ok());
+    let month = try_opt!(fields.next());
+    let month = try_opt!(MONTHS.iter().position(|name| month.eq_ignore_ascii_case(name))) as i64 + 1;
+
+    let year = try_opt!(fields.next());
+    let year: i64 = match (year.len(), try_opt!(year.parse().ok())) {
+        (2, year) if year < 50 => 2000 + year,
+        (2, year) | (3, year) => 1900 + year,
+        (_, year) => year,
+    };
+
+    let mut time = try_opt!(fields.next()).split(':');
+    let hour: i64 = try_opt!(try_opt!(time.next()).parse().ok());
+    let minute: i64 = try_opt!(try_opt!(time.next()).parse().ok());
+    let second: i64 = match time.next() {
+        Some(second) => try_opt!(second.parse().ok()),
+        None => 0,
+    };
+
+    if day < 1 || day > 31 || hour > 23 || minute > 59 || second > 60 || time.next().is_some() {
+        return None;
+    }
+
+    let offset = fields.next().map_or(0, zone_offset);
+
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+
+    if secs >= 0 {
+        Some(UNIX_EPOCH + Duration::from_secs(secs as u64))
+    } else {
+        Some(UNIX_EPOCH - Duration::from_secs(-secs as u64))
+    }
+}
+
+/// The offset of a time zone from UTC in seconds, such as `-0500` or `EST`.
+fn zone_offset(zone: &str) -> i64 {
+    let hours = match zone {
+        "EDT" => -4,
+        "EST" | "CDT" => -5,
+        "CST" | "MDT" => -6,
+        "MST" | "PDT" => -7,
+        "PST" => -8,
+        _ => 0,
+    };
+
+    if hours != 0 || zone.len() != 5 {
+        return hours * 3600;
+    }
+
+    let sign = match &zone[..1] {
+        "+" => 1,
+        "-" => -1,
+        _ => return 0,
+    };
+
+    match (zone[1..3].parse::<i64>(), zone[3..].parse::<i64>()) {
+        (Ok(hours), Ok(minutes)) => sign * (hours * 3600 + minutes * 60),
+        _ => 0,
+    }
+}
+
+/// The number of days from the Unix epoch to the given date in the proleptic Gregorian
+/// calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_date;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_parse_date() {
+        let secs = |secs| Some(UNIX_EPOCH + Duration::from_secs(secs));
+
+        assert_eq!(parse_date("Thu, 01 Jan 1970 00:00:00 GMT"), secs(0));
+        assert_eq!(parse_date("1 jan 70 00:00 +0100"), Some(UNIX_EPOCH - Duration::from_secs(3600)));
+        assert_eq!(parse_date("29 Feb 2000 12:30:15 PST"), secs(951856215));
+        assert_eq!(parse_date("Sun, 6 Nov 1994 08:49:37 +0000"), secs(784111777));
+        assert_eq!(parse_date("06 Nov 94 08:49:37 Z"), secs(784111777));
+
+        assert_eq!(parse_date("6 Nov 1994"), None);
+        assert_eq!(parse_date("6 Foo 1994 08:49:37"), None);
+        assert_eq!(parse_date("6 Nov 1994 24:00:00"), None);
+    }
+}
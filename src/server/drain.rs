@@ -0,0 +1,156 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Discarding the unread part of a request body, so the connection can be reused.
+use std::io::{self, Read};
+use std::ops::{Deref, DerefMut};
+
+use super::Multipart;
+
+impl<B: Read> Multipart<B> {
+    /// Discard the rest of the request body: the remaining fields, the final boundary and any
+    /// data after it.
+    ///
+    /// Call this if a handler stops reading entries before `read_entry()` returns `Ok(None)`,
+    /// so the connection can be reused; on a keep-alive connection, unread body bytes would
+    /// otherwise be taken as the start of the next request. The remaining fields are skipped
+    /// without being parsed.
+    ///
+    /// The body is read until it returns EOF, as the request bodies of the server integrations
+    /// in this crate do at the end of the request. To bound the work done for a client which
+    /// sends a huge body, use [`drain_limited()`](#method.drain_limited) instead.
+    pub fn drain(&mut self) -> io::Result<()> {
+        self.drain_limited(u64::max_value()).map(|_| ())
+    }
+
+    /// Discard the rest of the request body like [`drain()`](#method.drain), but at most
+    /// `limit` bytes of it.
+    ///
+    /// Returns `true` if the body was read to the end, or `false` if more than `limit` bytes
+    /// were left, in which case the connection can't be reused and the server should close it.
+    pub fn drain_limited(&mut self, limit: u64) -> io::Result<bool> {
+        self.drained = try!(self.source.discard_rest(limit));
+        Ok(self.drained)
+    }
+
+    /// Returns `true` if the request body was read to the end by `drain()` or
+    /// `drain_limited()`.
+    ///
+    /// If this returns `false` once the request has been handled, the connection may still
+    /// hold part of the body, so the server should close it rather than reading another request
+    /// from it.
+    pub fn is_drained(&self) -> bool {
+        self.drained
+    }
+
+    /// Wrap this in a [`DrainOnDrop`](struct.DrainOnDrop.html), which discards the rest of the
+    /// request body, up to `limit` bytes of it, when dropped.
+    pub fn drain_on_drop(self, limit: u64) -> DrainOnDrop<B> {
+        DrainOnDrop {
+            multipart: self,
+            limit: limit,
+        }
+    }
+}
+
+/// A [`Multipart`](struct.Multipart.html) which discards the rest of the request body when
+/// dropped, so handlers which return early don't leave unread bytes on a keep-alive
+/// connection; created with
+/// [`Multipart::drain_on_drop()`](struct.Multipart.html#method.drain_on_drop).
+///
+/// At most `limit` bytes are discarded; if more are left, or reading the body fails, a warning
+/// is logged, and the server should close the connection. Servers which need to make that
+/// decision themselves should call
+/// [`Multipart::drain_limited()`](struct.Multipart.html#method.drain_limited) instead.
+///
+/// Dereferences to the wrapped `Multipart`.
+pub struct DrainOnDrop<B: Read> {
+    multipart: Multipart<B>,
+    limit: u64,
+}
+
+impl<B: Read> Deref for DrainOnDrop<B> {
+    type Target = Multipart<B>;
+
+    fn deref(&self) -> &Multipart<B> {
+        &self.multipart
+    }
+}
+
+impl<B: Read> DerefMut for DrainOnDrop<B> {
+    fn deref_mut(&mut self) -> &mut Multipart<B> {
+        &mut self.multipart
+    }
+}
+
+impl<B: Read> Drop for DrainOnDrop<B> {
+    fn drop(&mut self) {
+        if self.multipart.drained {
+            return;
+        }
+
+        match self.multipart.drain_limited(self.limit) {
+            Ok(true) => (),
+            Ok(false) => warn!("More than {} bytes of the request body were left unread; \
+                                the connection should be closed", self.limit),
+            Err(err) => warn!("Failed to drain the request body: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use server::Multipart;
+
+    use std::borrow::Borrow;
+
+    const BODY: &[u8] = b"--boundary\r\n\
+        Content-Disposition: form-data; name=\"first\"\r\n\r\n\
+        first\r\n--boundary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        file contents\r\n--boundary--\r\nepilogue";
+
+    #[test]
+    fn test_drain() {
+        let mut multipart = Multipart::with_body(BODY, "boundary");
+
+        assert_eq!(multipart.read_entry().unwrap().unwrap().name, "first");
+        multipart.drain().unwrap();
+        assert!(remaining(&multipart).is_empty());
+
+        let mut multipart = Multipart::with_body(BODY, "boundary");
+        while multipart.read_entry().unwrap().is_some() {}
+        multipart.drain().unwrap();
+        assert!(remaining(&multipart).is_empty());
+    }
+
+    #[test]
+    fn test_drain_limited() {
+        let mut multipart = Multipart::with_body(BODY, "boundary");
+        assert!(!multipart.drain_limited(10).unwrap());
+        assert!(!multipart.is_drained());
+        // One more byte is read to check for the end of the body.
+        assert_eq!(remaining(&multipart).len(), BODY.len() - 11);
+
+        let mut multipart = Multipart::with_body(BODY, "boundary");
+        assert!(multipart.drain_limited(BODY.len() as u64).unwrap());
+        assert!(multipart.is_drained());
+
+        let mut body = BODY;
+
+        {
+            let mut multipart = Multipart::with_body(&mut body, "boundary").drain_on_drop(BODY.len() as u64);
+            assert_eq!(multipart.read_entry().unwrap().unwrap().name, "first");
+        }
+
+        assert!(body.is_empty());
+    }
+
+    fn remaining<'a>(multipart: &Multipart<&'a [u8]>) -> &'a [u8] {
+        *multipart.borrow()
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Encryption of saved files with an ephemeral key, so they are never written to disk in
+//! plaintext.
+//!
+//! Files are encrypted with ChaCha20 under a random nonce, which is written at the start of the
+//! file.
+use chacha20::ChaCha20;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+
+use rand::{OsRng, Rng};
+
+use zeroize::Zeroizing;
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// A random key, generated for one request, which the files saved from it are encrypted with.
+///
+/// The key is kept only in memory, and is zeroed when the last clone of it is dropped, after
+/// which the files it encrypted can't be read.
+///
+/// #### Feature: `encrypt`
+#[derive(Clone)]
+pub struct EncryptionKey(Arc<Zeroizing<[u8; KEY_LEN]>>);
+
+impl EncryptionKey {
+    /// Generate a new key with the operating system's random number generator.
+    pub fn generate() -> io::Result<Self> {
+        let mut rng = try!(OsRng::new().map_err(|err| io::Error::new(io::ErrorKind::Other, err)));
+        let mut key = Zeroizing::new([0; KEY_LEN]);
+        rng.fill_bytes(&mut *key);
+        Ok(EncryptionKey(Arc::new(key)))
+    }
+
+    /// Open a file encrypted with this key for reading its plaintext.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<Decrypt<File>> {
+        Decrypt::new(try!(File::open(path)), Some(self))
+    }
+
+    fn cipher(&self, nonce: &[u8; NONCE_LEN]) -> ChaCha20 {
+        ChaCha20::new((&**self.0).into(), nonce.into())
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never print the key itself.
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// Encrypts the data written to it before passing it to the inner writer, or passes it through
+/// as-is without a key.
+pub struct Encrypt<W> {
+    inner: W,
+    cipher: Option<ChaCha20>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Encrypt<W> {
+    /// Start encrypting to `inner` with `key`, writing a random nonce to it first.
+    pub fn new(mut inner: W, key: Option<&EncryptionKey>) -> io::Result<Self> {
+        let cipher = match key {
+            Some(key) => {
+                let mut rng = try!(OsRng::new().map_err(|err| io::Error::new(io::ErrorKind::Other, err)));
+                let mut nonce = [0; NONCE_LEN];
+                rng.fill_bytes(&mut nonce);
+                try!(inner.write_all(&nonce));
+                Some(key.cipher(&nonce))
+            },
+            None => None,
+        };
+
+        Ok(Encrypt {
+            inner: inner,
+            cipher: cipher,
+            buf: Vec::new(),
+        })
+    }
+}
+
+impl<W: Write> Write for Encrypt<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let cipher = match self.cipher {
+            Some(ref mut cipher) => cipher,
+            None => return self.inner.write(buf),
+        };
+
+        // The keystream has to advance by exactly the data written, so the whole chunk is
+        // written or the file is left unreadable anyway.
+        self.buf.clear();
+        self.buf.extend_from_slice(buf);
+        cipher.apply_keystream(&mut self.buf);
+        try!(self.inner.write_all(&self.buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: fmt::Debug> fmt::Debug for Encrypt<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Encrypt")
+            .field("inner", &self.inner)
+            .field("encrypted", &self.cipher.is_some())
+            .finish()
+    }
+}
+
+/// Decrypts the data read from the inner reader, or passes it through as-is without a key.
+///
+/// #### Feature: `encrypt`
+pub struct Decrypt<R> {
+    inner: R,
+    cipher: Option<ChaCha20>,
+}
+
+impl<R: Read> Decrypt<R> {
+    /// Start decrypting `inner` with `key`, reading its nonce first.
+    pub fn new(mut inner: R, key: Option<&EncryptionKey>) -> io::Result<Self> {
+        let cipher = match key {
+            Some(key) => {
+                let mut nonce = [0; NONCE_LEN];
+                try!(inner.read_exact(&mut nonce));
+                Some(key.cipher(&nonce))
+            },
+            None => None,
+        };
+
+        Ok(Decrypt {
+            inner: inner,
+            cipher: cipher,
+        })
+    }
+
+    /// Unwrap the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for Decrypt<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = try!(self.inner.read(buf));
+
+        if let Some(ref mut cipher) = self.cipher {
+            cipher.apply_keystream(&mut buf[..read]);
+        }
+
+        Ok(read)
+    }
+}
+
+impl<R: fmt::Debug> fmt::Debug for Decrypt<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Decrypt")
+            .field("inner", &self.inner)
+            .field("encrypted", &self.cipher.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Decrypt, Encrypt, EncryptionKey, NONCE_LEN};
+
+    use server::Multipart;
+
+    use tempdir::TempDir;
+
+    use std::fs;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_encrypt_roundtrip() {
+        let key = EncryptionKey::generate().unwrap();
+
+        let mut encrypt = Encrypt::new(Vec::new(), Some(&key)).unwrap();
+        encrypt.write_all(b"sensitive ").unwrap();
+        encrypt.write_all(b"contents").unwrap();
+        let encrypted = encrypt.inner;
+
+        assert_eq!(encrypted.len(), NONCE_LEN + 18);
+        assert!(!encrypted.windows(9).any(|w| w == b"sensitive"));
+
+        let mut decrypted = Vec::new();
+        Decrypt::new(&encrypted[..], Some(&key)).unwrap().read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, b"sensitive contents");
+
+        let other = EncryptionKey::generate().unwrap();
+        let mut decrypted = Vec::new();
+        Decrypt::new(&encrypted[..], Some(&other)).unwrap().read_to_end(&mut decrypted).unwrap();
+        assert!(decrypted != b"sensitive contents");
+    }
+
+    #[test]
+    fn test_save_all_encrypted() {
+        let body: &[u8] = b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"id.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            sensitive contents\r\n--boundary--\r\n";
+
+        let entries = Multipart::with_body(body, "boundary").encrypt(true).save_all().to_result().unwrap();
+        assert!(entries.is_encrypted());
+
+        let file = &entries.files["file"];
+        assert_eq!(file.size, 18);
+        assert!(fs::read(&file.path).unwrap() != b"sensitive contents");
+
+        let mut contents = String::new();
+        entries.open_file(file).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "sensitive contents");
+
+        let dir = TempDir::new("multipart-encrypt").unwrap();
+        let entries = Multipart::with_body(body, "boundary").encrypt(true).save_all_under(dir.path())
+            .to_result().unwrap();
+        assert!(entries.is_encrypted());
+        assert!(entries.files["file"].path.starts_with(dir.path()));
+        assert!(fs::read(&entries.files["file"].path).unwrap() != b"sensitive contents");
+
+        let entries = Multipart::with_body(body, "boundary").save_all().to_result().unwrap();
+        assert!(!entries.is_encrypted());
+        assert_eq!(fs::read(&entries.files["file"].path).unwrap(), b"sensitive contents");
+    }
+}
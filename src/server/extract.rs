@@ -0,0 +1,202 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Extraction of uploaded ZIP archives, for `SavedFile::extract_zip()`.
+use zip::ZipArchive;
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use super::{create_full_path, SavedFile};
+
+/// Limits on the archives extracted by
+/// [`SavedFile::extract_zip()`](struct.SavedFile.html#method.extract_zip), which guard against
+/// zip bombs.
+#[derive(Clone, Copy, Debug)]
+pub struct ZipLimits {
+    /// The maximum number of entries in the archive, including directories.
+    pub max_entries: usize,
+    /// The maximum total size of the extracted files, in bytes.
+    ///
+    /// This is checked against the data actually extracted, not the sizes the archive claims.
+    pub max_size: u64,
+}
+
+impl Default for ZipLimits {
+    /// At most 1000 entries and 100 MiB.
+    fn default() -> Self {
+        ZipLimits {
+            max_entries: 1000,
+            max_size: 100 * 1024 * 1024,
+        }
+    }
+}
+
+/// A file extracted from an archive by
+/// [`SavedFile::extract_zip()`](struct.SavedFile.html#method.extract_zip).
+#[derive(Clone, Debug)]
+pub struct ExtractedFile {
+    /// The path the file was extracted to.
+    pub path: PathBuf,
+    /// The path of the file within the archive.
+    ///
+    /// ##Warning
+    /// This is taken from the archive, so it should be treated as untrustworthy, even though it
+    /// is checked to be relative and not to contain any `..` components.
+    pub name: PathBuf,
+    /// The size of the extracted file.
+    pub size: u64,
+}
+
+impl SavedFile {
+    /// Extract this file, which should be a ZIP archive, into the directory `dest`, which is
+    /// created if it does not exist.
+    ///
+    /// The paths of the entries are checked before anything is written: entries with absolute
+    /// paths or paths which would escape `dest` are rejected. Symbolic links are skipped rather
+    /// than created.
+    ///
+    /// ##Errors
+    /// If the archive is invalid, has an unsafe path or exceeds `limits`, returns an error of
+    /// kind `io::ErrorKind::InvalidData`. The files extracted before the error are left in
+    /// `dest`.
+    ///
+    /// #### Feature: `zip`
+    pub fn extract_zip<P: AsRef<Path>>(&self, dest: P, limits: ZipLimits) -> io::Result<Vec<ExtractedFile>> {
+        let dest = dest.as_ref();
+        let mut archive = try!(ZipArchive::new(try!(File::open(&self.path))).map_err(invalid_data));
+
+        if archive.len() > limits.max_entries {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "ZIP archive has too many entries"
+            ));
+        }
+
+        // Check all paths first, so an unsafe archive isn't partially extracted.
+        for i in 0 .. archive.len() {
+            let entry = try!(archive.by_index_raw(i).map_err(invalid_data));
+
+            if entry.enclosed_name().is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData, "ZIP archive has an entry with an unsafe path"
+                ));
+            }
+        }
+
+        let mut extracted = Vec::new();
+        let mut total_size = 0;
+
+        for i in 0 .. archive.len() {
+            let mut entry = try!(archive.by_index(i).map_err(invalid_data));
+            let name = entry.enclosed_name().expect("entry paths were checked");
+            let path = dest.join(&name);
+
+            if entry.is_dir() {
+                try!(fs::create_dir_all(&path));
+                continue;
+            }
+
+            if entry.is_symlink() {
+                warn!("Skipping symbolic link in ZIP archive: {:?}", name);
+                continue;
+            }
+
+            let mut file = try!(create_full_path(&path));
+            // One byte over the limit is read to detect that it was exceeded.
+            let remaining = limits.max_size - total_size;
+            let size = try!(io::copy(&mut (&mut entry).take(remaining + 1), &mut file));
+
+            if size > remaining {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData, "ZIP archive is larger than the maximum size"
+                ));
+            }
+
+            total_size += size;
+
+            extracted.push(ExtractedFile {
+                path: path,
+                name: name,
+                size: size,
+            });
+        }
+
+        Ok(extracted)
+    }
+}
+
+fn invalid_data<E: Into<io::Error>>(err: E) -> io::Error {
+    let err = err.into();
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod test {
+    use tempdir::TempDir;
+
+    use zip::ZipWriter;
+    use zip::write::SimpleFileOptions;
+
+    use super::ZipLimits;
+    use server::SavedFile;
+
+    use std::fs::{self, File};
+    use std::io::{self, Write};
+
+    fn zip(dir: &TempDir, entries: &[(&str, &[u8])]) -> SavedFile {
+        let path = dir.path().join("upload.zip");
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+
+        for &(name, data) in entries {
+            writer.start_file(name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+
+        writer.finish().unwrap();
+
+        SavedFile {
+            size: fs::metadata(&path).unwrap().len(),
+            path: path,
+            filename: Some("upload.zip".into()),
+            sha256: None,
+            compressed_size: None,
+            extension: None,
+        }
+    }
+
+    #[test]
+    fn test_extract_zip() {
+        let dir = TempDir::new("multipart-zip").unwrap();
+        let saved = zip(&dir, &[("a.txt", b"first"), ("sub/b.txt", &[b'b'; 10000])]);
+        let dest = dir.path().join("out");
+
+        let extracted = saved.extract_zip(&dest, ZipLimits::default()).unwrap();
+
+        assert_eq!(extracted.len(), 2);
+        assert_eq!(extracted[1].size, 10000);
+        assert_eq!(fs::read(dest.join("a.txt")).unwrap(), b"first");
+        assert_eq!(fs::read(dest.join("sub").join("b.txt")).unwrap().len(), 10000);
+    }
+
+    #[test]
+    fn test_zip_limits() {
+        let dir = TempDir::new("multipart-zip").unwrap();
+        let dest = dir.path().join("out");
+
+        let check_err = |saved: SavedFile, limits: ZipLimits| {
+            let err = saved.extract_zip(&dest, limits).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        };
+
+        check_err(zip(&dir, &[("../escape.txt", b"data")]), ZipLimits::default());
+        check_err(zip(&dir, &[("/abs.txt", b"data")]), ZipLimits::default());
+        check_err(zip(&dir, &[("a", b""), ("b", b"")]), ZipLimits { max_entries: 1, .. ZipLimits::default() });
+        check_err(zip(&dir, &[("a", &[0; 1000]), ("b", &[0; 1000])]), ZipLimits { max_size: 1999, .. ZipLimits::default() });
+
+        assert!(!dir.path().join("escape.txt").exists());
+    }
+}
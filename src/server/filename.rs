@@ -0,0 +1,430 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Normalization of the filenames sent by clients, for saving files under them.
+use mime::Mime;
+
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::{cmp, error, fmt, io};
+
+/// The device names reserved by Windows, which can't be used as filenames even with an
+/// extension.
+const RESERVED_NAMES: &'static [&'static str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM0", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT0", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The characters which are invalid in filenames on Windows, besides control characters.
+const WINDOWS_INVALID: &'static [char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// How many numbered names are tried for a file before giving up.
+const MAX_NUMBERED: u32 = 1000;
+
+/// The default maximum length of filenames, in bytes, which is the limit of most filesystems.
+pub const DEFAULT_MAX_FILENAME_LEN: usize = 255;
+
+/// What to do with filenames longer than the maximum length of a
+/// [`FilenamePolicy`](struct.FilenamePolicy.html).
+///
+/// The extension of a filename is kept unless it takes more than half of the maximum length.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LengthPolicy {
+    /// Cut the end of the name before its extension.
+    Truncate,
+    /// Replace the end of the name before its extension with a hash of the whole name, so that
+    /// long names which only differ at their end stay distinct.
+    Hash,
+    /// Reject the file with a [`FilenameTooLong`](struct.FilenameTooLong.html) error.
+    Reject,
+}
+
+/// How the filenames sent by clients are normalized so files can be saved under them.
+///
+/// Only the last component of a filename is kept, after either `/` or `\`, as some browsers
+/// send full paths. Control characters and characters which are invalid in filenames are
+/// replaced. By default, the rules of Windows are applied on every platform so the saved files
+/// are portable: its invalid characters are replaced, trailing dots and spaces are removed, and
+/// reserved device names such as `CON` or `com1.txt` are prefixed with the replacement
+/// character. Filenames longer than [`DEFAULT_MAX_FILENAME_LEN`](constant.DEFAULT_MAX_FILENAME_LEN.html)
+/// bytes are truncated.
+///
+/// ```rust
+/// use multipart::server::FilenamePolicy;
+///
+/// let policy = FilenamePolicy::new();
+/// assert_eq!(policy.normalize("C:\\Users\\me\\report.pdf").unwrap().unwrap(), "report.pdf");
+/// assert_eq!(policy.normalize("what?.txt. ").unwrap().unwrap(), "what_.txt");
+/// assert_eq!(policy.normalize("nul.tar.gz").unwrap().unwrap(), "_nul.tar.gz");
+/// assert_eq!(policy.normalize("../..").unwrap(), None);
+/// ```
+#[derive(Clone, Debug)]
+pub struct FilenamePolicy {
+    replacement: char,
+    portable: bool,
+    max_length: usize,
+    overflow: LengthPolicy,
+}
+
+impl FilenamePolicy {
+    /// The default policy, which makes filenames portable, replaces invalid characters with
+    /// `_` and truncates long filenames.
+    pub fn new() -> Self {
+        FilenamePolicy {
+            replacement: '_',
+            portable: true,
+            max_length: DEFAULT_MAX_FILENAME_LEN,
+            overflow: LengthPolicy::Truncate,
+        }
+    }
+
+    /// Set the character which replaces invalid characters in filenames.
+    ///
+    /// ##Panics
+    /// If `replacement` is itself invalid in filenames.
+    pub fn replacement(&mut self, replacement: char) -> &mut Self {
+        assert!(!is_invalid(replacement, true), "invalid replacement character: {:?}", replacement);
+        self.replacement = replacement;
+        self
+    }
+
+    /// If `portable` is `true`, the default, apply the rules of Windows on every platform.
+    /// Otherwise, only apply the rules of the current platform.
+    pub fn portable(&mut self, portable: bool) -> &mut Self {
+        self.portable = portable;
+        self
+    }
+
+    /// Set the maximum length of filenames in bytes, and what to do with longer ones.
+    ///
+    /// Default value: [`DEFAULT_MAX_FILENAME_LEN`](constant.DEFAULT_MAX_FILENAME_LEN.html),
+    /// `LengthPolicy::Truncate`
+    ///
+    /// ##Panics
+    /// If `max_length` is less than 16, which leaves no room for a hash.
+    pub fn max_length(&mut self, max_length: usize, overflow: LengthPolicy) -> &mut Self {
+        assert!(max_length >= 16, "maximum filename length too small: {}", max_length);
+        self.max_length = max_length;
+        self.overflow = overflow;
+        self
+    }
+
+    /// Normalize `filename`, returning `None` if no usable name remains.
+    ///
+    /// ##Errors
+    /// If the normalized filename is too long and the policy is `LengthPolicy::Reject`.
+    pub fn normalize(&self, filename: &str) -> Result<Option<String>, FilenameTooLong> {
+        let windows = self.portable || cfg!(windows);
+
+        let name = filename.rsplit(|c| c == '/' || c == '\\').next().unwrap_or("");
+
+        let mut name: String = name.chars()
+            .map(|c| if is_invalid(c, windows) { self.replacement } else { c })
+            .collect();
+
+        if windows {
+            self.fix_windows(&mut name);
+        }
+
+        let mut name = try!(self.limit(name));
+
+        if windows {
+            // Cutting a name may leave it with a trailing dot or a reserved stem.
+            self.fix_windows(&mut name);
+            name = try!(self.limit(name));
+        }
+
+        if name.is_empty() || name == "." || name == ".." {
+            return Ok(None);
+        }
+
+        Ok(Some(name))
+    }
+
+    fn fix_windows(&self, name: &mut String) {
+        let len = name.trim_end_matches(|c| c == '.' || c == ' ').len();
+        name.truncate(len);
+
+        if is_reserved(name) {
+            name.insert(0, self.replacement);
+        }
+    }
+
+    fn limit(&self, name: String) -> Result<String, FilenameTooLong> {
+        if name.len() <= self.max_length {
+            return Ok(name);
+        }
+
+        let (stem, ext) = split_extension(&name, self.max_length / 2);
+
+        match self.overflow {
+            LengthPolicy::Truncate => {
+                Ok(format!("{}{}", truncate(stem, self.max_length - ext.len()), ext))
+            },
+            LengthPolicy::Hash => {
+                let hash = format!("-{:08x}", fnv1a(name.as_bytes()) as u32);
+                let stem = truncate(stem, self.max_length - ext.len() - hash.len());
+                Ok(format!("{}{}{}", stem, hash, ext))
+            },
+            LengthPolicy::Reject => Err(FilenameTooLong {
+                length: name.len(),
+                max_length: self.max_length,
+            }),
+        }
+    }
+}
+
+impl Default for FilenamePolicy {
+    fn default() -> Self {
+        FilenamePolicy::new()
+    }
+}
+
+/// The error of a file whose filename is longer than the maximum length of a
+/// [`FilenamePolicy`](struct.FilenamePolicy.html) with `LengthPolicy::Reject`.
+///
+/// Returned wrapped in an `io::Error` of kind `io::ErrorKind::InvalidData` when saving, from
+/// which it can be recovered with `get_ref()` and `downcast_ref()`.
+#[derive(Clone, Debug)]
+pub struct FilenameTooLong {
+    /// The length of the normalized filename, in bytes.
+    pub length: usize,
+    /// The maximum length, in bytes.
+    pub max_length: usize,
+}
+
+impl error::Error for FilenameTooLong {
+    fn description(&self) -> &str {
+        "filename is too long"
+    }
+}
+
+impl fmt::Display for FilenameTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "filename is {} bytes long, more than the maximum of {}", self.length, self.max_length)
+    }
+}
+
+impl From<FilenameTooLong> for io::Error {
+    fn from(err: FilenameTooLong) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+fn is_invalid(c: char, windows: bool) -> bool {
+    c == '/' || c.is_control() || (windows && WINDOWS_INVALID.contains(&c))
+}
+
+fn is_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or("").trim_end();
+    RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+/// Split a name before its extension, if it has one of at most `max_ext` bytes.
+fn split_extension(name: &str, max_ext: usize) -> (&str, &str) {
+    // A leading dot starts a hidden file's name, not its extension.
+    match name.rfind('.').filter(|&idx| idx > 0 && name.len() - idx <= max_ext) {
+        Some(idx) => name.split_at(idx),
+        None => (name, ""),
+    }
+}
+
+/// Cut `s` to at most `max` bytes, on a character boundary.
+fn truncate(s: &str, max: usize) -> &str {
+    let mut end = cmp::min(max, s.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// The 64-bit FNV-1a hash, which is stable across platforms and releases.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Create a new file named `name` in `dir`, numbering it like `name (1).txt` if a file with
+/// that name already exists.
+pub fn create_unique(dir: &Path, name: &str) -> io::Result<(PathBuf, File)> {
+    try!(fs::create_dir_all(dir));
+
+    for i in 0..MAX_NUMBERED {
+        let path = if i == 0 { dir.join(name) } else { dir.join(numbered(name, i)) };
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "too many files with the same name"))
+}
+
+fn numbered(name: &str, i: u32) -> String {
+    let (stem, ext) = split_extension(name, name.len());
+    format!("{} ({}){}", stem, i, ext)
+}
+
+/// The usual extensions of common types which have several, where the first known one in
+/// alphabetical order would be surprising.
+const PREFERRED_EXTENSIONS: &'static [(&'static str, &'static str)] = &[
+    ("application/javascript", "js"),
+    ("application/msword", "doc"),
+    ("application/octet-stream", ""),
+    ("application/xml", "xml"),
+    ("audio/mpeg", "mp3"),
+    ("image/jpeg", "jpg"),
+    ("image/svg+xml", "svg"),
+    ("image/tiff", "tif"),
+    ("text/html", "html"),
+    ("text/plain", "txt"),
+    ("video/mp4", "mp4"),
+    ("video/mpeg", "mpg"),
+    ("video/quicktime", "mov"),
+];
+
+/// Get the file extension for `content_type`, without the dot, if it is a known type other
+/// than `application/octet-stream`.
+pub fn extension_for(content_type: &Mime) -> Option<&'static str> {
+    let (top, sub) = (content_type.0.as_str(), content_type.1.as_str());
+
+    let preferred = PREFERRED_EXTENSIONS.iter().find(|&&(ty, _)| {
+        ty.len() == top.len() + 1 + sub.len() && ty[..top.len()].eq_ignore_ascii_case(top)
+            && ty[top.len() + 1..].eq_ignore_ascii_case(sub)
+    });
+
+    if let Some(&(_, ext)) = preferred {
+        return if ext.is_empty() { None } else { Some(ext) };
+    }
+
+    if top == "*" || sub == "*" {
+        return None;
+    }
+
+    let exts = try_opt!(::mime_guess::get_extensions(top, sub));
+
+    // Such as `png` for `image/png`, or the only extension of the type.
+    exts.iter().find(|ext| ext.eq_ignore_ascii_case(sub)).cloned()
+        .or_else(|| if exts.len() == 1 { Some(exts[0]) } else { None })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{extension_for, FilenamePolicy, FilenameTooLong, LengthPolicy};
+
+    use server::Multipart;
+    use server::storage::LocalStorage;
+
+    use tempdir::TempDir;
+
+    use std::fs;
+
+    #[test]
+    fn test_normalize() {
+        let policy = FilenamePolicy::new();
+
+        assert_eq!(policy.normalize("file.txt").unwrap().unwrap(), "file.txt");
+        assert_eq!(policy.normalize("/etc/passwd").unwrap().unwrap(), "passwd");
+        assert_eq!(policy.normalize("a<b>c:d\"e|f?g*h").unwrap().unwrap(), "a_b_c_d_e_f_g_h");
+        assert_eq!(policy.normalize("tab\there\u{7f}").unwrap().unwrap(), "tab_here_");
+        assert_eq!(policy.normalize("trailing. . ").unwrap().unwrap(), "trailing");
+        assert_eq!(policy.normalize("CON").unwrap().unwrap(), "_CON");
+        assert_eq!(policy.normalize("Com1.txt").unwrap().unwrap(), "_Com1.txt");
+        assert_eq!(policy.normalize("aux .log").unwrap().unwrap(), "_aux .log");
+        assert_eq!(policy.normalize("console.txt").unwrap().unwrap(), "console.txt");
+        assert_eq!(policy.normalize("...").unwrap(), None);
+        assert_eq!(policy.normalize("dir/").unwrap(), None);
+        assert_eq!(policy.normalize("").unwrap(), None);
+
+        let mut policy = FilenamePolicy::new();
+        policy.replacement('-');
+        assert_eq!(policy.normalize("a:b").unwrap().unwrap(), "a-b");
+
+        #[cfg(not(windows))]
+        {
+            policy.portable(false);
+            assert_eq!(policy.normalize("a:b?.").unwrap().unwrap(), "a:b?.");
+            assert_eq!(policy.normalize("CON").unwrap().unwrap(), "CON");
+        }
+    }
+
+    #[test]
+    fn test_extension_for() {
+        let ext = |content_type: &str| extension_for(&content_type.parse().unwrap());
+
+        assert_eq!(ext("image/png"), Some("png"));
+        assert_eq!(ext("image/JPEG"), Some("jpg"));
+        assert_eq!(ext("text/plain; charset=utf-8"), Some("txt"));
+        assert_eq!(ext("application/pdf"), Some("pdf"));
+        assert_eq!(ext("application/octet-stream"), None);
+        assert_eq!(ext("image/*"), None);
+        assert_eq!(ext("application/x-unknown"), None);
+    }
+
+    #[test]
+    fn test_max_length() {
+        let long = format!("{}.txt", "a".repeat(10000));
+
+        let mut policy = FilenamePolicy::new();
+        let name = policy.normalize(&long).unwrap().unwrap();
+        assert_eq!(name.len(), 255);
+        assert!(name.ends_with("aaa.txt"));
+
+        // Multibyte characters aren't split.
+        let name = policy.normalize(&"é".repeat(200)).unwrap().unwrap();
+        assert_eq!(name, "é".repeat(127));
+
+        policy.max_length(32, LengthPolicy::Hash);
+        let first = policy.normalize(&format!("{}1.txt", "a".repeat(100))).unwrap().unwrap();
+        let second = policy.normalize(&format!("{}2.txt", "a".repeat(100))).unwrap().unwrap();
+        assert_eq!(first.len(), 32);
+        assert!(first.starts_with("aaaa") && first.ends_with(".txt"));
+        assert!(first != second);
+        assert_eq!(policy.normalize("short.txt").unwrap().unwrap(), "short.txt");
+
+        // Extensions taking most of the name aren't kept.
+        let name = policy.normalize(&format!("a.{}", "b".repeat(100))).unwrap().unwrap();
+        assert_eq!(name.len(), 32);
+        assert!(name.starts_with("a.bbb"));
+
+        policy.max_length(32, LengthPolicy::Reject);
+        let FilenameTooLong { length, max_length } = policy.normalize(&long).unwrap_err();
+        assert_eq!((length, max_length), (10004, 32));
+    }
+
+    #[test]
+    fn test_original_filenames() {
+        let body: &[u8] = b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"first\"; filename=\"report.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            first\r\n--boundary\r\n\
+            Content-Disposition: form-data; name=\"second\"; filename=\"report.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            second\r\n--boundary\r\n\
+            Content-Disposition: form-data; name=\"third\"; filename=\"..\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            third\r\n--boundary--\r\n";
+
+        let dir = TempDir::new("multipart-filenames").unwrap();
+        let mut storage = LocalStorage::new(dir.path());
+        storage.original_filenames(FilenamePolicy::new());
+
+        let entries = Multipart::with_body(body, "boundary").save_all_to(&mut storage).unwrap();
+
+        assert_eq!(entries.files["first"].stored.path, dir.path().join("report.txt"));
+        assert_eq!(entries.files["second"].stored.path, dir.path().join("report (1).txt"));
+        assert_eq!(fs::read(dir.path().join("report (1).txt")).unwrap(), b"second");
+
+        // Unusable names fall back to random ones.
+        let third = &entries.files["third"].stored.path;
+        assert_eq!(third.parent().unwrap(), dir.path());
+        assert_eq!(fs::read(third).unwrap(), b"third");
+    }
+}
@@ -0,0 +1,309 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Integration with the [Gotham](https://gotham.rs) web framework, enabled with the `gotham_`
+//! feature (optional). Includes a `Middleware` implementation.
+//!
+//! Successful reads will be placed in the Gotham `State` as an
+//! [`Entries`](../struct.Entries.html) instance:
+//!
+//! ```no_run
+//! extern crate gotham;
+//! extern crate multipart;
+//!
+//! use gotham::pipeline::{new_pipeline, single::single_pipeline};
+//! use gotham::router::builder::*;
+//! use gotham::state::{FromState, State};
+//!
+//! use multipart::server::Entries;
+//! use multipart::server::gotham::Intercept;
+//!
+//! fn handler(state: State) -> (State, String) {
+//!     let body = match Entries::try_borrow_from(&state) {
+//!         Some(entries) => format!("{:?}", entries),
+//!         None => "Not a multipart request".into(),
+//!     };
+//!
+//!     (state, body)
+//! }
+//!
+//! fn main() {
+//!     let (chain, pipelines) = single_pipeline(new_pipeline().add(Intercept::default()).build());
+//!
+//!     let router = build_router(chain, pipelines, |route| {
+//!         route.post("/").to(handler);
+//!     });
+//!
+//!     gotham::start("localhost:80", router);
+//! }
+//! ```
+use futures::future::{self, FutureExt};
+
+use gotham::anyhow;
+use gotham::handler::{HandlerError, HandlerFuture};
+use gotham::hyper::body::{Bytes, HttpBody};
+use gotham::hyper::{Body, HeaderMap, Method, StatusCode};
+use gotham::hyper::header::CONTENT_TYPE;
+use gotham::middleware::{Middleware, NewMiddleware};
+use gotham::state::{FromState, State, StateData};
+
+use mime::{Mime, TopLevel};
+
+use std::io;
+use std::mem;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::Poll;
+
+use super::{accepts_subtype, parse_boundary, Entries, Multipart, SaveResult, DEFAULT_METHODS, DEFAULT_SUBTYPES};
+
+/// The default file size limit for [`Intercept`](struct.Intercept.html), in bytes.
+pub const DEFAULT_FILE_SIZE_LIMIT: u64 = 2 * 1024 * 1024;
+
+/// The default request body size limit for [`Intercept`](struct.Intercept.html), in bytes.
+pub const DEFAULT_BODY_SIZE_LIMIT: usize = 16 * 1024 * 1024;
+
+/// A Gotham `Middleware` which will read out multipart requests and place the result in the
+/// request `State` as [`Entries`](../struct.Entries.html).
+///
+/// The request body is collected in memory before it is parsed, and the files are then saved to
+/// a temporary directory. Non-multipart requests are passed through untouched.
+///
+/// Only requests whose method is one of `methods` and whose `Content-Type` is one of the
+/// `subtypes` of `multipart/*` are intercepted. If the body is longer than `body_size_limit`, a
+/// `HandlerError` with the status `413 Payload Too Large` is returned; any other errors which
+/// occur during reading will be returned as a `HandlerError` with the status `400 Bad Request`.
+#[derive(Clone, Debug)]
+pub struct Intercept {
+    /// The parent directory for all temporary directories created by this middleware.
+    /// Will be created if it doesn't exist (lazy).
+    ///
+    /// If omitted, uses the [temporary root](../fn.temp_root.html).
+    ///
+    /// Default value: `None`.
+    pub temp_dir_path: Option<PathBuf>,
+    /// The size limit of uploaded files, in bytes. Files which exceed this size will be truncated.
+    ///
+    /// Default value: [`DEFAULT_FILE_SIZE_LIMIT`](constant.DEFAULT_FILE_SIZE_LIMIT.html)
+    pub file_size_limit: u64,
+    /// The size limit of the whole request body, in bytes, which is collected in memory before
+    /// it is parsed.
+    ///
+    /// Default value: [`DEFAULT_BODY_SIZE_LIMIT`](constant.DEFAULT_BODY_SIZE_LIMIT.html)
+    pub body_size_limit: usize,
+    /// The HTTP methods of the requests which are read.
+    ///
+    /// Default value: [`DEFAULT_METHODS`](../constant.DEFAULT_METHODS.html), only `POST`
+    pub methods: &'static [&'static str],
+    /// The subtypes of `multipart/*` of the requests which are read, or `"*"` for any.
+    ///
+    /// Default value: [`DEFAULT_SUBTYPES`](../constant.DEFAULT_SUBTYPES.html), only `form-data`
+    pub subtypes: &'static [&'static str],
+}
+
+impl Intercept {
+    /// Set the `temp_dir_path` for this middleware.
+    pub fn temp_dir_path<P: Into<PathBuf>>(self, path: P) -> Self {
+        Intercept { temp_dir_path: Some(path.into()), .. self }
+    }
+
+    /// Set the `file_size_limit` for this middleware.
+    pub fn file_size_limit(self, limit: u64) -> Self {
+        Intercept { file_size_limit: limit, .. self }
+    }
+
+    /// Set the `body_size_limit` for this middleware.
+    pub fn body_size_limit(self, limit: usize) -> Self {
+        Intercept { body_size_limit: limit, .. self }
+    }
+
+    /// Set the `methods` for this middleware, such as `&["POST", "PUT", "PATCH"]`.
+    pub fn methods(self, methods: &'static [&'static str]) -> Self {
+        Intercept { methods: methods, .. self }
+    }
+
+    /// Set the `subtypes` for this middleware, such as `&["form-data", "mixed"]`.
+    pub fn subtypes(self, subtypes: &'static [&'static str]) -> Self {
+        Intercept { subtypes: subtypes, .. self }
+    }
+
+    fn read_entries(&self, body: Bytes, boundary: String) -> io::Result<Entries> {
+        let mut multipart = Multipart::with_body(io::Cursor::new(body), boundary);
+
+        let result = match self.temp_dir_path {
+            Some(ref dir) => multipart.save_all_under_limited(dir, self.file_size_limit),
+            None => multipart.save_all_limited(self.file_size_limit),
+        };
+
+        match result {
+            SaveResult::Full(entries) => Ok(entries),
+            SaveResult::Partial(_, err) | SaveResult::Error(err) => Err(err),
+        }
+    }
+}
+
+impl Default for Intercept {
+    fn default() -> Self {
+        Intercept {
+            temp_dir_path: None,
+            file_size_limit: DEFAULT_FILE_SIZE_LIMIT,
+            body_size_limit: DEFAULT_BODY_SIZE_LIMIT,
+            methods: DEFAULT_METHODS,
+            subtypes: DEFAULT_SUBTYPES,
+        }
+    }
+}
+
+impl NewMiddleware for Intercept {
+    type Instance = Self;
+
+    fn new_middleware(&self) -> anyhow::Result<Self> {
+        Ok(self.clone())
+    }
+}
+
+impl Middleware for Intercept {
+    fn call<Chain>(self, mut state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static {
+        let method = Method::borrow_from(&state);
+
+        if !self.methods.iter().any(|name| name.eq_ignore_ascii_case(method.as_str())) {
+            return chain(state);
+        }
+
+        let boundary = match multipart_boundary(HeaderMap::borrow_from(&state), self.subtypes) {
+            Some(boundary) => boundary,
+            None => return chain(state),
+        };
+
+        let mut body = Body::take_from(&mut state);
+        let mut buf = Vec::new();
+        let limit = self.body_size_limit;
+
+        let read_body = future::poll_fn(move |cx| loop {
+            match Pin::new(&mut body).poll_data(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    if chunk.len() > limit - buf.len() {
+                        let err = io::Error::new(io::ErrorKind::InvalidData,
+                                                 "request body exceeds the size limit");
+                        return Poll::Ready(Err((err, StatusCode::PAYLOAD_TOO_LARGE)));
+                    }
+
+                    buf.extend_from_slice(&chunk);
+                },
+                Poll::Ready(Some(Err(err))) => {
+                    let err = io::Error::new(io::ErrorKind::Other, err);
+                    return Poll::Ready(Err((err, StatusCode::BAD_REQUEST)));
+                },
+                Poll::Ready(None) => return Poll::Ready(Ok(Bytes::from(mem::replace(&mut buf, Vec::new())))),
+                Poll::Pending => return Poll::Pending,
+            }
+        });
+
+        read_body.then(move |res| {
+            let entries = res.and_then(|bytes| {
+                self.read_entries(bytes, boundary).map_err(|err| (err, StatusCode::BAD_REQUEST))
+            });
+
+            match entries {
+                Ok(entries) => {
+                    state.put(entries);
+                    chain(state)
+                },
+                Err((err, status)) => {
+                    let err = HandlerError::from(err).with_status(status);
+                    future::err((state, err)).boxed()
+                },
+            }
+        }).boxed()
+    }
+}
+
+impl StateData for Entries {}
+
+fn multipart_boundary(headers: &HeaderMap, subtypes: &[&str]) -> Option<String> {
+    let content_type = try_opt!(headers.get(CONTENT_TYPE).and_then(|val| val.to_str().ok()));
+
+    match content_type.parse() {
+        Ok(Mime(TopLevel::Multipart, ref sub, _)) if accepts_subtype(subtypes, sub.as_str()) => (),
+        _ => return None,
+    }
+
+    parse_boundary(content_type).map(Into::into)
+}
+
+#[cfg(test)]
+mod test {
+    use gotham::hyper::header::{HeaderValue, CONTENT_TYPE};
+    use gotham::hyper::{Body, Method, StatusCode};
+    use gotham::pipeline::{new_pipeline, single::single_pipeline};
+    use gotham::router::builder::*;
+    use gotham::router::Router;
+    use gotham::state::{FromState, State};
+    use gotham::test::TestServer;
+
+    use server::Entries;
+
+    use super::Intercept;
+
+    const BODY: &'static str = "--boundary\r\n\
+        Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+        value\r\n--boundary--\r\n";
+
+    fn handler(state: State) -> (State, String) {
+        let body = match Entries::try_borrow_from(&state) {
+            Some(entries) => format!("text: {}", entries.fields["text"]),
+            None => "not multipart".to_string(),
+        };
+
+        (state, body)
+    }
+
+    fn router(intercept: Intercept) -> Router {
+        let (chain, pipelines) = single_pipeline(new_pipeline().add(intercept).build());
+
+        build_router(chain, pipelines, |route| {
+            route.request(vec![Method::POST, Method::PUT], "/").to(handler);
+        })
+    }
+
+    /// Send `BODY` to the router with the given method and `Content-Type` subtype, returning
+    /// the status and body of the response.
+    fn send(intercept: Intercept, method: Method, subtype: &str) -> (StatusCode, String) {
+        let server = TestServer::new(router(intercept)).unwrap();
+
+        let mut req = server.client().build_request(method, "http://localhost/");
+        let content_type = format!("multipart/{}; boundary=boundary", subtype);
+        req.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_str(&content_type).unwrap());
+        *req.body_mut() = Body::from(BODY);
+
+        let res = req.perform().unwrap();
+        (res.status(), res.read_utf8_body().unwrap())
+    }
+
+    #[test]
+    fn test_intercept() {
+        let ok = (StatusCode::OK, "text: value".to_string());
+        let skipped = (StatusCode::OK, "not multipart".to_string());
+
+        assert_eq!(send(Intercept::default(), Method::POST, "form-data"), ok);
+        assert_eq!(send(Intercept::default(), Method::PUT, "form-data"), skipped);
+        assert_eq!(send(Intercept::default(), Method::POST, "mixed"), skipped);
+
+        let intercept = Intercept::default().methods(&["POST", "PUT"]).subtypes(&["form-data", "mixed"]);
+        assert_eq!(send(intercept.clone(), Method::PUT, "form-data"), ok);
+        assert_eq!(send(intercept, Method::POST, "mixed"), ok);
+    }
+
+    #[test]
+    fn test_body_size_limit() {
+        let intercept = Intercept::default().body_size_limit(BODY.len());
+        assert_eq!(send(intercept, Method::POST, "form-data").0, StatusCode::OK);
+
+        let intercept = Intercept::default().body_size_limit(BODY.len() - 1);
+        assert_eq!(send(intercept, Method::POST, "form-data").0, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Decompression of gzip files as they are saved, for `LocalStorage::gunzip()`.
+use flate2::write::GzDecoder;
+
+use std::cmp;
+use std::io::{self, Write};
+
+/// The first bytes of every gzip stream.
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Passes the data written to it to the inner writer, decompressing it if it is gzip.
+#[derive(Debug)]
+pub struct Gunzip<W: Write> {
+    /// Set until the data is found to be gzip.
+    plain: Option<W>,
+    decoder: Option<GzDecoder<Limit<W>>>,
+    /// The first bytes of the data, kept until there are enough to detect gzip.
+    head: Vec<u8>,
+    decided: bool,
+    max_size: u64,
+}
+
+impl<W: Write> Gunzip<W> {
+    /// Pass the data through as-is.
+    pub fn plain(inner: W) -> Self {
+        Self::new(inner, true, 0)
+    }
+
+    /// Decompress the data if it starts with the gzip magic bytes, failing if it decompresses
+    /// to more than `max_size` bytes.
+    pub fn detect(inner: W, max_size: u64) -> Self {
+        Self::new(inner, false, max_size)
+    }
+
+    /// Decompress the data, which must be gzip, failing if it decompresses to more than
+    /// `max_size` bytes.
+    pub fn force(inner: W, max_size: u64) -> Self {
+        let mut gunzip = Self::new(inner, true, max_size);
+        gunzip.start_decoder();
+        gunzip
+    }
+
+    fn new(inner: W, decided: bool, max_size: u64) -> Self {
+        Gunzip {
+            plain: Some(inner),
+            decoder: None,
+            head: Vec::with_capacity(MAGIC.len()),
+            decided: decided,
+            max_size: max_size,
+        }
+    }
+
+    fn start_decoder(&mut self) {
+        let inner = self.plain.take().expect("decoder already started");
+        self.decoder = Some(GzDecoder::new(Limit { inner: inner, written: 0, max: self.max_size }));
+    }
+
+    /// Pass on the head of the data once it's known whether it's gzip.
+    fn decide(&mut self) -> io::Result<()> {
+        self.decided = true;
+
+        if self.head == MAGIC {
+            self.start_decoder();
+        }
+
+        let head = ::std::mem::replace(&mut self.head, Vec::new());
+        self.write_all(&head)
+    }
+
+    /// Finish writing, returning the inner writer and the decompressed size of the data, if it
+    /// was decompressed.
+    pub fn finish(mut self) -> io::Result<(W, Option<u64>)> {
+        if !self.decided {
+            try!(self.decide());
+        }
+
+        match self.decoder.take() {
+            Some(decoder) => {
+                let limit = try!(decoder.finish());
+                Ok((limit.inner, Some(limit.written)))
+            },
+            None => Ok((self.plain.take().expect("writer already taken"), None)),
+        }
+    }
+}
+
+impl<W: Write> Write for Gunzip<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.decided {
+            let len = cmp::min(buf.len(), MAGIC.len() - self.head.len());
+            self.head.extend_from_slice(&buf[..len]);
+
+            if self.head.len() == MAGIC.len() {
+                try!(self.decide());
+            }
+
+            return Ok(len);
+        }
+
+        match self.decoder {
+            Some(ref mut decoder) => decoder.write(buf),
+            None => self.plain.as_mut().expect("writer already taken").write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.decoder {
+            Some(ref mut decoder) => decoder.flush(),
+            None => self.plain.as_mut().expect("writer already taken").flush(),
+        }
+    }
+}
+
+/// Counts the decompressed data, failing if there is too much of it.
+#[derive(Debug)]
+struct Limit<W> {
+    inner: W,
+    written: u64,
+    max: u64,
+}
+
+impl<W: Write> Write for Limit<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written + buf.len() as u64 > self.max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "decompressed file is larger than the maximum size"
+            ));
+        }
+
+        let written = try!(self.inner.write(buf));
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    use super::Gunzip;
+
+    use std::io::{self, Write};
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn write_all(mut gunzip: Gunzip<Vec<u8>>, data: &[u8]) -> io::Result<(Vec<u8>, Option<u64>)> {
+        // One byte at a time, to test detecting the magic bytes across writes.
+        for byte in data.chunks(1) {
+            try!(gunzip.write_all(byte));
+        }
+
+        gunzip.finish()
+    }
+
+    #[test]
+    fn test_gunzip() {
+        let data = vec![b'a'; 10000];
+        let compressed = gzip(&data);
+
+        assert_eq!(write_all(Gunzip::detect(Vec::new(), 10000), &compressed).unwrap(),
+                   (data.clone(), Some(10000)));
+        assert_eq!(write_all(Gunzip::force(Vec::new(), 10000), &compressed).unwrap(),
+                   (data.clone(), Some(10000)));
+        assert_eq!(write_all(Gunzip::plain(Vec::new()), &compressed).unwrap(),
+                   (compressed.clone(), None));
+
+        assert_eq!(write_all(Gunzip::detect(Vec::new(), 10000), b"plain").unwrap(),
+                   (b"plain".to_vec(), None));
+        assert_eq!(write_all(Gunzip::detect(Vec::new(), 10000), b"a").unwrap(),
+                   (b"a".to_vec(), None));
+
+        assert!(write_all(Gunzip::detect(Vec::new(), 9999), &compressed).is_err());
+        assert!(write_all(Gunzip::force(Vec::new(), 10000), b"plain").is_err());
+    }
+}
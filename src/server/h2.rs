@@ -0,0 +1,62 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Server-side integration with the [h2](https://github.com/hyperium/h2) HTTP/2 implementation.
+//! Enabled with the `h2_` feature (optional).
+//!
+//! Unlike Hyper, h2 leaves it to the application to release flow control capacity for the
+//! received data, otherwise the client stops sending once the window is exhausted.
+//! [`stream_request()`](fn.stream_request.html) releases the capacity of the request body as the
+//! parser consumes it, via
+//! [`StreamMultipart::on_consumed()`](../stream/struct.StreamMultipart.html#method.on_consumed),
+//! so a slow consumer, such as one saving a large file, holds back only the data it hasn't
+//! processed yet.
+use bytes::Bytes;
+
+use futures::stream::Stream;
+
+use h2::RecvStream;
+
+use http::Request;
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::stream::StreamMultipart;
+
+/// If `req` is a `multipart/form-data` POST request, return a `StreamMultipart` which parses its
+/// body and releases its flow control capacity as it is consumed. Otherwise, returns the
+/// original request.
+pub fn stream_request(req: Request<RecvStream>) -> Result<StreamMultipart<H2Body>, Request<RecvStream>> {
+    let boundary = match super::http::request_boundary(&req) {
+        Some(boundary) => boundary.to_owned(),
+        None => return Err(req),
+    };
+
+    let mut body = req.into_body();
+    let mut flow_control = body.flow_control().clone();
+
+    let mut multipart = StreamMultipart::with_body(H2Body(body), boundary);
+
+    multipart.on_consumed(move |consumed| {
+        // Only fails if more is released than was received, which the parser can't do.
+        let _ = flow_control.release_capacity(consumed);
+    });
+
+    Ok(multipart)
+}
+
+/// Adapts `h2::RecvStream` to a `Stream` of `Bytes` chunks.
+#[derive(Debug)]
+pub struct H2Body(pub RecvStream);
+
+impl Stream for H2Body {
+    type Item = Result<Bytes, ::h2::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.get_mut().0.poll_data(cx)
+    }
+}
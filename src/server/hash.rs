@@ -0,0 +1,110 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Digests of saved files, computed while they are written, for `SavedFile::sha256`.
+//!
+//! Without the `sha2` feature, `Hasher` does nothing and its digests are always `None`.
+#[cfg(feature = "sha2")]
+use sha2::{Digest, Sha256};
+
+use std::io::{self, Write};
+
+/// Computes the SHA-256 digest of the data passed to it.
+#[cfg(feature = "sha2")]
+#[derive(Clone, Debug, Default)]
+pub struct Hasher(Sha256);
+
+#[cfg(feature = "sha2")]
+impl Hasher {
+    pub fn new() -> Self {
+        Hasher(Sha256::new())
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    /// Get the lowercase hex digest of the data passed so far, and reset this hasher.
+    pub fn finish(&mut self) -> Option<String> {
+        Some(self.0.finalize_reset().iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+}
+
+/// Computes nothing, as the `sha2` feature is disabled.
+#[cfg(not(feature = "sha2"))]
+#[derive(Clone, Debug, Default)]
+pub struct Hasher;
+
+#[cfg(not(feature = "sha2"))]
+impl Hasher {
+    pub fn new() -> Self {
+        Hasher
+    }
+
+    pub fn update(&mut self, _: &[u8]) {}
+
+    pub fn finish(&mut self) -> Option<String> {
+        None
+    }
+}
+
+/// Passes the data written to it to a `Hasher`, then to the inner writer.
+#[derive(Debug)]
+pub struct HashWriter<W> {
+    inner: W,
+    hasher: Hasher,
+}
+
+impl<W> HashWriter<W> {
+    pub fn new(inner: W) -> Self {
+        HashWriter {
+            inner: inner,
+            hasher: Hasher::new(),
+        }
+    }
+
+    /// Get the digest of the data written so far.
+    pub fn finish(&mut self) -> Option<String> {
+        self.hasher.finish()
+    }
+
+    #[cfg_attr(not(feature = "zstd"), allow(dead_code))]
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = try!(self.inner.write(buf));
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(all(test, feature = "sha2"))]
+mod test {
+    use super::HashWriter;
+
+    use std::io::Write;
+
+    #[test]
+    fn test_hash_writer() {
+        let mut writer = HashWriter::new(Vec::new());
+        writer.write_all(b"hello ").unwrap();
+        writer.write_all(b"world").unwrap();
+
+        assert_eq!(writer.inner, b"hello world");
+        assert_eq!(
+            writer.finish().unwrap(),
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Integration with the types from the [`http`](https://github.com/hyperium/http) crate,
+//! enabled with the `http` feature (optional).
+//!
+//! Contains `impl `[`HttpRequest`](../trait.HttpRequest.html)` for http::Request<B>` and
+//! `&mut http::Request<B>` where `B: Read`, as well as
+//! [`Multipart::from_headers()`](../struct.Multipart.html#method.from_headers) for frameworks
+//! which keep the headers and body apart.
+use http::header::CONTENT_TYPE;
+use http::{HeaderMap, Request};
+
+use mime::{Mime, TopLevel};
+
+use std::io::Read;
+
+use super::{accepts_subtype, parse_boundary, HttpRequest, Multipart, DEFAULT_METHODS, DEFAULT_SUBTYPES};
+
+impl<B: Read> Multipart<B> {
+    /// #### Feature: `http`
+    /// If `headers` contains a `Content-Type: multipart/form-data` header with a boundary,
+    /// return `body` wrapped in the multipart reader. Otherwise, returns `body`.
+    ///
+    /// Unlike `from_request()`, the request method is not checked.
+    pub fn from_headers(headers: &HeaderMap, body: B) -> Result<Self, B> {
+        match multipart_boundary(headers) {
+            Some(boundary) => Ok(Multipart::with_body(body, boundary)),
+            None => Err(body),
+        }
+    }
+}
+
+/// ####Feature: `http`
+impl<B: Read> HttpRequest for Request<B> {
+    type Body = B;
+
+    fn multipart_boundary(&self) -> Option<&str> {
+        request_boundary(self)
+    }
+
+    fn multipart_boundary_for(&self, methods: &[&str]) -> Option<&str> {
+        request_boundary_for(self, methods)
+    }
+
+    fn multipart_boundary_accepting(&self, methods: &[&str], subtypes: &[&str]) -> Option<&str> {
+        request_boundary_accepting(self, methods, subtypes)
+    }
+
+    fn body(self) -> B {
+        self.into_body()
+    }
+}
+
+/// ####Feature: `http`
+impl<'r, B: Read> HttpRequest for &'r mut Request<B> {
+    type Body = &'r mut B;
+
+    fn multipart_boundary(&self) -> Option<&str> {
+        request_boundary(self)
+    }
+
+    fn multipart_boundary_for(&self, methods: &[&str]) -> Option<&str> {
+        request_boundary_for(self, methods)
+    }
+
+    fn multipart_boundary_accepting(&self, methods: &[&str], subtypes: &[&str]) -> Option<&str> {
+        request_boundary_accepting(self, methods, subtypes)
+    }
+
+    fn body(self) -> &'r mut B {
+        self.body_mut()
+    }
+}
+
+/// Get the boundary string of `req` if it is a POST request with the `Content-Type` header set
+/// to `multipart/form-data`.
+pub fn request_boundary<B>(req: &Request<B>) -> Option<&str> {
+    request_boundary_for(req, DEFAULT_METHODS)
+}
+
+/// Get the boundary string of `req` if its method is one of `methods` and it has the
+/// `Content-Type` header set to `multipart/form-data`.
+pub fn request_boundary_for<'a, B>(req: &'a Request<B>, methods: &[&str]) -> Option<&'a str> {
+    request_boundary_accepting(req, methods, DEFAULT_SUBTYPES)
+}
+
+/// Get the boundary string of `req` if its method is one of `methods` and it has the
+/// `Content-Type` header set to `multipart/{subtype}`, where `subtype` is one of `subtypes`.
+pub fn request_boundary_accepting<'a, B>(req: &'a Request<B>, methods: &[&str], subtypes: &[&str])
+                                         -> Option<&'a str> {
+    if !methods.contains(&req.method().as_str()) {
+        return None;
+    }
+
+    subtype_boundary(req.headers(), subtypes)
+}
+
+/// Get the boundary string from `headers` if the `Content-Type` header is set to
+/// `multipart/form-data`.
+pub fn multipart_boundary(headers: &HeaderMap) -> Option<&str> {
+    subtype_boundary(headers, DEFAULT_SUBTYPES)
+}
+
+/// Get the boundary string from `headers` if the `Content-Type` header is set to
+/// `multipart/{subtype}`, where `subtype` is one of `subtypes`.
+pub fn subtype_boundary<'a>(headers: &'a HeaderMap, subtypes: &[&str]) -> Option<&'a str> {
+    let content_type = try_opt!(headers.get(CONTENT_TYPE).and_then(|val| val.to_str().ok()));
+
+    match content_type.parse() {
+        Ok(Mime(TopLevel::Multipart, ref sub, _)) if accepts_subtype(subtypes, sub.as_str()) => (),
+        _ => return None,
+    }
+
+    parse_boundary(content_type)
+}
+
+#[cfg(test)]
+mod test {
+    use http::Request;
+
+    use server::Multipart;
+
+    #[test]
+    fn test_from_request_methods() {
+        let req = || Request::put("/")
+            .header("Content-Type", "multipart/form-data; boundary=boundary")
+            .body(&b"--boundary--\r\n"[..])
+            .unwrap();
+
+        assert!(Multipart::from_request(req()).is_err());
+        assert!(Multipart::from_request_methods(req(), &["POST", "PUT"]).is_ok());
+        assert!(Multipart::from_request_methods(req(), &["PATCH"]).is_err());
+    }
+
+    #[test]
+    fn test_from_request_accepting() {
+        let req = |content_type| Request::post("/")
+            .header("Content-Type", content_type)
+            .body(&b"--boundary--\r\n"[..])
+            .unwrap();
+
+        let mixed = "multipart/mixed; boundary=boundary";
+        assert!(Multipart::from_request(req(mixed)).is_err());
+        assert!(Multipart::from_request_accepting(req(mixed), &["POST"], &["form-data", "Mixed"]).is_ok());
+        assert!(Multipart::from_request_accepting(req(mixed), &["POST"], &["*"]).is_ok());
+        assert!(Multipart::from_request_accepting(req(mixed), &["POST"], &["related"]).is_err());
+        assert!(Multipart::from_request_accepting(req("text/plain; boundary=boundary"), &["POST"], &["*"])
+            .is_err());
+    }
+}
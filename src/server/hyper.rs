@@ -10,15 +10,21 @@
 //! Also contains an implementation of [`HttpRequest`](../trait.HttpRequest.html)`
 //! for `hyper::server::Request` and `&mut hyper::server::Request`.
 use hyper::net::Fresh;
-use hyper::header::ContentType;
+use hyper::header::{ContentLength, ContentType, Headers};
 use hyper::method::Method;
 use hyper::server::{Handler, Request, Response};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
 
 pub use hyper::server::Request as HyperRequest;
 
 use mime::{Mime, TopLevel, SubLevel, Attr, Value};
 
-use super::{Multipart, HttpRequest};
+use std::borrow::Borrow;
+use std::fs;
+use std::io::{self, Read};
+
+use super::{accepts_subtype, Entries, Multipart, MultipartData, HttpRequest, DEFAULT_METHODS, DEFAULT_SUBTYPES};
 
 /// A container that implements `hyper::server::Handler` which will switch
 /// the handler implementation depending on if the incoming request is multipart or not.
@@ -30,6 +36,7 @@ use super::{Multipart, HttpRequest};
 pub struct Switch<H, M> {
     normal: H,
     multipart: M,
+    methods: &'static [&'static str],
 }
 
 impl<H, M> Switch<H, M> where H: Handler, M: MultipartHandler {
@@ -39,17 +46,107 @@ impl<H, M> Switch<H, M> where H: Handler, M: MultipartHandler {
         Switch {
             normal: normal,
             multipart: multipart,
+            methods: DEFAULT_METHODS,
         }
     }
 }
 
+impl<H, M> Switch<H, M> {
+    /// Check for requests with the `Content-Type` `top/sub` before falling back to the normal
+    /// handler, sending them to `handler` instead.
+    ///
+    /// This can be chained to build a fallback chain; the content type added last is checked
+    /// first, and multipart requests are always checked before any of them.
+    pub fn with_content_type<C>(self, top: TopLevel, sub: SubLevel, handler: C)
+    -> Switch<ContentTypeSwitch<C, H>, M> {
+        Switch {
+            normal: ContentTypeSwitch::new(top, sub, handler, self.normal),
+            multipart: self.multipart,
+            methods: self.methods,
+        }
+    }
+
+    /// Only send multipart requests whose method is one of `methods`, such as
+    /// `&["POST", "PUT", "PATCH"]`, to the multipart handler, rather than only POST requests.
+    pub fn with_methods(self, methods: &'static [&'static str]) -> Self {
+        Switch { methods: methods, .. self }
+    }
+
+    /// Send `application/x-www-form-urlencoded` requests to `handler` before falling back
+    /// to the normal handler.
+    ///
+    /// See `with_content_type()` for more information.
+    pub fn with_urlencoded<U>(self, handler: U) -> Switch<ContentTypeSwitch<U, H>, M> {
+        self.with_content_type(TopLevel::Application, SubLevel::WwwFormUrlEncoded, handler)
+    }
+}
+
 impl<H, M> Handler for Switch<H, M> where H: Handler, M: MultipartHandler {
     fn handle<'a, 'k>(&'a self, req: Request<'a, 'k>, res: Response<'a, Fresh>) {
-        match Multipart::from_request(req) {
+        match Multipart::from_request_methods(req, self.methods) {
             Ok(multi) => self.multipart.handle_multipart(multi, res),
             Err(req) => self.normal.handle(req, res),
         }
     }
+
+    /// Handle `Expect: 100-continue` by asking the handler which would receive the request.
+    fn check_continue(&self, (method, uri, headers): (&Method, &RequestUri, &Headers)) -> StatusCode {
+        if multipart_boundary(method, headers, self.methods, DEFAULT_SUBTYPES).is_some() {
+            self.multipart.check_continue(headers)
+        } else {
+            self.normal.check_continue((method, uri, headers))
+        }
+    }
+}
+
+/// A `Handler` which sends requests whose `Content-Type` is `top/sub` (ignoring parameters) to
+/// `matched`, and all other requests to `fallback`.
+///
+/// Usually created with [`Switch::with_content_type()`](struct.Switch.html#method.with_content_type),
+/// but can be nested with other handlers as well.
+pub struct ContentTypeSwitch<C, F> {
+    top: TopLevel,
+    sub: SubLevel,
+    matched: C,
+    fallback: F,
+}
+
+impl<C, F> ContentTypeSwitch<C, F> {
+    /// Create a new `ContentTypeSwitch` sending requests with `Content-Type: {top}/{sub}`
+    /// to `matched`, and all others to `fallback`.
+    pub fn new(top: TopLevel, sub: SubLevel, matched: C, fallback: F) -> Self {
+        ContentTypeSwitch {
+            top: top,
+            sub: sub,
+            matched: matched,
+            fallback: fallback,
+        }
+    }
+
+    fn matches(&self, headers: &Headers) -> bool {
+        match headers.get::<ContentType>() {
+            Some(&ContentType(Mime(ref top, ref sub, _))) => *top == self.top && *sub == self.sub,
+            None => false,
+        }
+    }
+}
+
+impl<C, F> Handler for ContentTypeSwitch<C, F> where C: Handler, F: Handler {
+    fn handle<'a, 'k>(&'a self, req: Request<'a, 'k>, res: Response<'a, Fresh>) {
+        if self.matches(&req.headers) {
+            self.matched.handle(req, res)
+        } else {
+            self.fallback.handle(req, res)
+        }
+    }
+
+    fn check_continue(&self, (method, uri, headers): (&Method, &RequestUri, &Headers)) -> StatusCode {
+        if self.matches(headers) {
+            self.matched.check_continue((method, uri, headers))
+        } else {
+            self.fallback.check_continue((method, uri, headers))
+        }
+    }
 }
 
 /// A trait defining a type that can handle an incoming multipart request.
@@ -61,6 +158,18 @@ pub trait MultipartHandler: Send + Sync {
     fn handle_multipart<'a, 'k>(&self, 
                                 multipart: Multipart<Request<'a, 'k>>, 
                                 response: Response<'a, Fresh>);
+
+    /// Decide whether a multipart request with `Expect: 100-continue` should have its body sent,
+    /// based on its headers.
+    ///
+    /// Return `StatusCode::Continue` to accept the body, or an error status such as
+    /// `StatusCode::PayloadTooLarge` or `StatusCode::ExpectationFailed` to reject the request
+    /// before the client uploads it.
+    ///
+    /// The default implementation always returns `StatusCode::Continue`.
+    fn check_continue(&self, _headers: &Headers) -> StatusCode {
+        StatusCode::Continue
+    }
 }
 
 impl<F> MultipartHandler for F 
@@ -72,33 +181,222 @@ where F: Fn(Multipart<Request>, Response<Fresh>), F: Send + Sync {
     }
 }
 
+/// The default file size limit for [`SaveEntries`](struct.SaveEntries.html), in bytes.
+pub const DEFAULT_FILE_SIZE_LIMIT: u64 = 2 * 1024 * 1024;
+
+/// The default file count limit for [`SaveEntries`](struct.SaveEntries.html).
+pub const DEFAULT_FILE_COUNT_LIMIT: u32 = 16;
+
+/// A `MultipartHandler` which reads out the request fully, saving its files to a temporary
+/// directory, before passing the resulting `Entries` to the wrapped `EntriesHandler`.
+///
+/// If the request could not be read, a `400 Bad Request` response is written instead;
+/// if the file size or count limit was exceeded, a `413 Payload Too Large` response is written.
+/// The bodies of these responses can be configured.
+///
+/// Use it as the `multipart` handler of a `Switch`:
+///
+/// ```no_run
+/// # extern crate hyper;
+/// # extern crate multipart;
+/// use hyper::server::{Server, Request, Response};
+/// use multipart::server::Entries;
+/// use multipart::server::hyper::{SaveEntries, Switch};
+///
+/// fn normal(_: Request, res: Response) {
+///     res.send(b"Not a multipart request").unwrap();
+/// }
+///
+/// fn multipart(entries: Entries, res: Response) {
+///     res.send(format!("{:?}", entries).as_bytes()).unwrap();
+/// }
+///
+/// # fn main() {
+/// let handler = SaveEntries::new(multipart).file_size_limit(8 * 1024 * 1024);
+/// Server::http("0.0.0.0:0").unwrap().handle(Switch::new(normal, handler)).unwrap();
+/// # }
+/// ```
+pub struct SaveEntries<E> {
+    handler: E,
+    file_size_limit: u64,
+    file_count_limit: u32,
+    request_size_limit: Option<u64>,
+    bad_request_body: String,
+    too_large_body: String,
+}
+
+impl<E> SaveEntries<E> where E: EntriesHandler {
+    /// Wrap `handler` with the default limits and response bodies.
+    pub fn new(handler: E) -> Self {
+        SaveEntries {
+            handler: handler,
+            file_size_limit: DEFAULT_FILE_SIZE_LIMIT,
+            file_count_limit: DEFAULT_FILE_COUNT_LIMIT,
+            request_size_limit: None,
+            bad_request_body: "Invalid multipart request".into(),
+            too_large_body: "Multipart request exceeded limits".into(),
+        }
+    }
+
+    /// Set the size limit of uploaded files, in bytes.
+    ///
+    /// Default value: [`DEFAULT_FILE_SIZE_LIMIT`](constant.DEFAULT_FILE_SIZE_LIMIT.html)
+    pub fn file_size_limit(self, limit: u64) -> Self {
+        SaveEntries { file_size_limit: limit, .. self }
+    }
+
+    /// Set the limit on the number of files in a request.
+    ///
+    /// Default value: [`DEFAULT_FILE_COUNT_LIMIT`](constant.DEFAULT_FILE_COUNT_LIMIT.html)
+    pub fn file_count_limit(self, limit: u32) -> Self {
+        SaveEntries { file_count_limit: limit, .. self }
+    }
+
+    /// Set the limit on the `Content-Length` of requests which will be accepted.
+    ///
+    /// Requests with `Expect: 100-continue` which exceed this limit will be rejected with
+    /// `417 Expectation Failed` before their body is sent; other requests which exceed it
+    /// are answered with `413 Payload Too Large` without being read.
+    ///
+    /// Default value: `None` (no limit).
+    pub fn request_size_limit(self, limit: u64) -> Self {
+        SaveEntries { request_size_limit: Some(limit), .. self }
+    }
+
+    fn exceeds_size_limit(&self, headers: &Headers) -> bool {
+        match (self.request_size_limit, headers.get::<ContentLength>()) {
+            (Some(limit), Some(&ContentLength(len))) => len > limit,
+            _ => false,
+        }
+    }
+
+    /// Set the body of the `400 Bad Request` response written when the request could not be read.
+    pub fn bad_request_body<B: Into<String>>(self, body: B) -> Self {
+        SaveEntries { bad_request_body: body.into(), .. self }
+    }
+
+    /// Set the body of the `413 Payload Too Large` response written when a limit was exceeded.
+    pub fn too_large_body<B: Into<String>>(self, body: B) -> Self {
+        SaveEntries { too_large_body: body.into(), .. self }
+    }
+
+    fn read_entries<R: Read>(&self, multipart: &mut Multipart<R>) -> Result<Entries, SaveError> {
+        let mut entries = try!(Entries::new_tempdir());
+        let mut file_count = 0;
+
+        while let Some(field) = try!(multipart.read_entry()) {
+            match field.data {
+                MultipartData::File(mut file) => {
+                    if file_count >= self.file_count_limit {
+                        return Err(SaveError::LimitExceeded);
+                    }
+
+                    // Read one byte past the limit, to tell a file which is exactly as long as
+                    // the limit from one which was truncated.
+                    let limit = self.file_size_limit.saturating_add(1);
+                    let file = try!(file.save_in_limited(&entries.dir, limit));
+
+                    if file.size > self.file_size_limit {
+                        try!(fs::remove_file(&file.path));
+                        return Err(SaveError::LimitExceeded);
+                    }
+
+                    entries.files.insert(field.name, file);
+                    file_count += 1;
+                },
+                MultipartData::Text(text) => {
+                    entries.fields.insert(field.name, text.into());
+                },
+                MultipartData::Bytes(bytes) => {
+                    entries.fields.insert(field.name, String::from_utf8_lossy(bytes).into_owned());
+                },
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+impl<E> MultipartHandler for SaveEntries<E> where E: EntriesHandler {
+    fn handle_multipart<'a, 'k>(&self,
+                                mut multipart: Multipart<Request<'a, 'k>>,
+                                mut response: Response<'a, Fresh>) {
+        let too_large = {
+            let req: &Request = multipart.borrow();
+            self.exceeds_size_limit(&req.headers)
+        };
+
+        let res = if too_large {
+            Err(SaveError::LimitExceeded)
+        } else {
+            self.read_entries(&mut multipart)
+        };
+
+        let (status, body) = match res {
+            Ok(entries) => return self.handler.handle_entries(entries, response),
+            Err(SaveError::Io(err)) => {
+                warn!("Error reading multipart request: {}", err);
+                (StatusCode::BadRequest, &self.bad_request_body)
+            },
+            Err(SaveError::LimitExceeded) => (StatusCode::PayloadTooLarge, &self.too_large_body),
+        };
+
+        *response.status_mut() = status;
+
+        if let Err(err) = response.send(body.as_bytes()) {
+            error!("Error writing error response: {}", err);
+        }
+    }
+
+    fn check_continue(&self, headers: &Headers) -> StatusCode {
+        if self.exceeds_size_limit(headers) {
+            StatusCode::ExpectationFailed
+        } else {
+            StatusCode::Continue
+        }
+    }
+}
+
+enum SaveError {
+    Io(io::Error),
+    LimitExceeded,
+}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+
+/// A trait defining a type that can handle the saved entries of a multipart request.
+///
+/// Extends to closures of the type `Fn(Entries, Response<Fresh>)`,
+/// and subsequently static functions.
+pub trait EntriesHandler: Send + Sync {
+    /// Generate a response from the entries of this request.
+    fn handle_entries<'a>(&self, entries: Entries, response: Response<'a, Fresh>);
+}
+
+impl<F> EntriesHandler for F
+where F: Fn(Entries, Response<Fresh>), F: Send + Sync {
+    fn handle_entries<'a>(&self, entries: Entries, response: Response<'a, Fresh>) {
+        (*self)(entries, response);
+    }
+}
+
 impl<'a, 'b> HttpRequest for HyperRequest<'a, 'b> {
     type Body = Self;
 
     fn multipart_boundary(&self) -> Option<&str> {
-        if self.method != Method::Post {
-            return None;
-        }
+        multipart_boundary(&self.method, &self.headers, DEFAULT_METHODS, DEFAULT_SUBTYPES)
+    }
+
+    fn multipart_boundary_for(&self, methods: &[&str]) -> Option<&str> {
+        multipart_boundary(&self.method, &self.headers, methods, DEFAULT_SUBTYPES)
+    }
 
-        self.headers.get::<ContentType>().and_then(|ct| {
-            let ContentType(ref mime) = *ct;
-            let params = match *mime {
-                Mime(TopLevel::Multipart, SubLevel::FormData, ref params) => params,
-                _ => return None,
-            };
-
-            params.iter().find(|&&(ref name, _)|
-                match *name {
-                    Attr::Boundary => true,
-                    _ => false,
-                }
-            ).and_then(|&(_, ref val)|
-                match *val {
-                    Value::Ext(ref val) => Some(&**val),
-                    _ => None,
-                }
-            )
-        })
+    fn multipart_boundary_accepting(&self, methods: &[&str], subtypes: &[&str]) -> Option<&str> {
+        multipart_boundary(&self.method, &self.headers, methods, subtypes)
     }
 
     fn body(self) -> Self {
@@ -110,29 +408,15 @@ impl<'r, 'a, 'b> HttpRequest for &'r mut HyperRequest<'a, 'b> {
     type Body = Self;
 
     fn multipart_boundary(&self) -> Option<&str> {
-        if self.method != Method::Post {
-            return None;
-        }
+        multipart_boundary(&self.method, &self.headers, DEFAULT_METHODS, DEFAULT_SUBTYPES)
+    }
 
-        self.headers.get::<ContentType>().and_then(|ct| {
-            let ContentType(ref mime) = *ct;
-            let params = match *mime {
-                Mime(TopLevel::Multipart, SubLevel::FormData, ref params) => params,
-                _ => return None,
-            };
-
-            params.iter().find(|&&(ref name, _)|
-                match *name {
-                    Attr::Boundary => true,
-                    _ => false,
-                }
-            ).and_then(|&(_, ref val)|
-                match *val {
-                    Value::Ext(ref val) => Some(&**val),
-                    _ => None,
-                }
-            )
-        })
+    fn multipart_boundary_for(&self, methods: &[&str]) -> Option<&str> {
+        multipart_boundary(&self.method, &self.headers, methods, DEFAULT_SUBTYPES)
+    }
+
+    fn multipart_boundary_accepting(&self, methods: &[&str], subtypes: &[&str]) -> Option<&str> {
+        multipart_boundary(&self.method, &self.headers, methods, subtypes)
     }
 
     fn body(self) -> Self::Body {
@@ -140,3 +424,71 @@ impl<'r, 'a, 'b> HttpRequest for &'r mut HyperRequest<'a, 'b> {
     }
 }
 
+
+fn multipart_boundary<'a>(method: &Method, headers: &'a Headers, methods: &[&str], subtypes: &[&str])
+                          -> Option<&'a str> {
+    if !is_method(method, methods) {
+        return None;
+    }
+
+    headers.get::<ContentType>().and_then(|ct| {
+        let ContentType(ref mime) = *ct;
+        let params = match *mime {
+            Mime(TopLevel::Multipart, ref sub, ref params) if accepts_subtype(subtypes, sub.as_str()) => params,
+            _ => return None,
+        };
+
+        params.iter().find(|&&(ref name, _)|
+            match *name {
+                Attr::Boundary => true,
+                _ => false,
+            }
+        ).and_then(|&(_, ref val)|
+            match *val {
+                Value::Ext(ref val) => Some(&**val),
+                _ => None,
+            }
+        )
+    })
+}
+
+/// Returns `true` if `method` is one of `methods`.
+fn is_method(method: &Method, methods: &[&str]) -> bool {
+    methods.iter().any(|name| name.parse::<Method>().ok().as_ref() == Some(method))
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::net::Fresh;
+    use hyper::server::Response;
+
+    use server::{Entries, Multipart};
+
+    use std::io::Cursor;
+
+    use super::{SaveEntries, SaveError};
+
+    fn handler(_: Entries, _: Response<Fresh>) {}
+
+    fn file_body(contents: &str) -> Multipart<Cursor<String>> {
+        let body = format!("--boundary\r\n\
+                            Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+                            Content-Type: text/plain\r\n\r\n\
+                            {}\r\n--boundary--\r\n", contents);
+
+        Multipart::with_body(Cursor::new(body), "boundary")
+    }
+
+    #[test]
+    fn test_file_size_limit() {
+        let save = SaveEntries::new(handler).file_size_limit(4);
+
+        let entries = save.read_entries(&mut file_body("four")).ok().unwrap();
+        assert_eq!(entries.files["file"].size, 4);
+
+        match save.read_entries(&mut file_body("five!")) {
+            Err(SaveError::LimitExceeded) => (),
+            _ => panic!("expected the file size limit to be exceeded"),
+        }
+    }
+}
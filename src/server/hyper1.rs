@@ -0,0 +1,290 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Server-side integration with [Hyper](https://github.com/hyperium/hyper) 1.x.
+//! Enabled with the `hyper1_` feature (optional).
+//!
+//! Modern Hyper bodies are asynchronous streams of byte chunks rather than blocking readers.
+//! [`stream_request()`](fn.stream_request.html) feeds the chunks to a
+//! [`StreamMultipart`](../stream/struct.StreamMultipart.html) as they arrive, and should be
+//! preferred for large uploads.
+//!
+//! For code written against the blocking [`Multipart`](../struct.Multipart.html),
+//! [`read_request()`](fn.read_request.html) returns a future which collects the body in memory
+//! first, up to [`DEFAULT_BODY_SIZE_LIMIT`](constant.DEFAULT_BODY_SIZE_LIMIT.html) bytes
+//! (see [`read_request_limited()`](fn.read_request_limited.html)).
+//!
+//! ```no_run
+//! # extern crate futures;
+//! # extern crate hyper1 as hyper;
+//! # extern crate multipart;
+//! use futures::future::{self, Either, Future, FutureExt};
+//! use hyper::{Request, Response};
+//! use hyper::body::Incoming;
+//!
+//! use multipart::server::hyper1::read_request;
+//!
+//! fn handle(req: Request<Incoming>) -> impl Future<Output = Response<String>> {
+//!     match read_request(req) {
+//!         Ok(multipart) => Either::Left(multipart.map(|res| match res {
+//!             Ok(mut multipart) => Response::new(format!("{:?}", multipart.save_all())),
+//!             Err(err) => Response::new(format!("Error reading body: {}", err)),
+//!         })),
+//!         Err(_) => Either::Right(future::ready(Response::new("Not multipart".into()))),
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! [`Switch`](struct.Switch.html) provides the same convenience as the Hyper 0.x `Switch`, for
+//! Hyper 1.x `Service`s, passing multipart requests on as a `StreamMultipart`.
+use bytes::Bytes;
+
+use futures::future::{Future, FutureExt, TryFutureExt};
+
+use http_body_util::{BodyDataStream, BodyExt, Limited};
+
+use hyper1::body::Body;
+use hyper1::service::Service;
+use hyper1::Request;
+
+use std::error::Error;
+use std::io;
+use std::pin::Pin;
+
+use super::Multipart;
+use super::stream::StreamMultipart;
+
+pub use http_body_util::LengthLimitError;
+
+/// The default size limit of request bodies collected in memory, in bytes.
+pub const DEFAULT_BODY_SIZE_LIMIT: usize = 16 * 1024 * 1024;
+
+/// A `Multipart` reading from a request body which was collected into memory.
+pub type BufferedMultipart = Multipart<io::Cursor<Bytes>>;
+
+/// A `StreamMultipart` parsing a request body as it arrives, up to a size limit, as passed to
+/// the `multipart` service of a [`Switch`](struct.Switch.html).
+pub type LimitedMultipart<B> = StreamMultipart<BodyDataStream<Limited<B>>>;
+
+/// If `req` is a `multipart/form-data` POST request, return a future which collects its body
+/// and resolves to a `Multipart` reading from it. Otherwise, returns the original request.
+///
+/// The body may be at most [`DEFAULT_BODY_SIZE_LIMIT`](constant.DEFAULT_BODY_SIZE_LIMIT.html)
+/// bytes long; see [`read_request_limited()`](fn.read_request_limited.html).
+pub fn read_request<B>(req: Request<B>)
+-> Result<impl Future<Output = Result<BufferedMultipart, Box<Error + Send + Sync>>>, Request<B>>
+where B: Body, B::Error: Into<Box<Error + Send + Sync>> {
+    read_request_limited(req, DEFAULT_BODY_SIZE_LIMIT)
+}
+
+/// If `req` is a `multipart/form-data` POST request, return a future which collects at most
+/// `limit` bytes of its body and resolves to a `Multipart` reading from it. Otherwise, returns
+/// the original request.
+///
+/// If the body is longer than `limit`, the future resolves to a
+/// [`LengthLimitError`](struct.LengthLimitError.html), which can be checked for with
+/// `err.is::<LengthLimitError>()`. Other errors are those of the body.
+pub fn read_request_limited<B>(req: Request<B>, limit: usize)
+-> Result<impl Future<Output = Result<BufferedMultipart, Box<Error + Send + Sync>>>, Request<B>>
+where B: Body, B::Error: Into<Box<Error + Send + Sync>> {
+    let boundary = match multipart_boundary(&req) {
+        Some(boundary) => boundary,
+        None => return Err(req),
+    };
+
+    let read_body = Limited::new(req.into_body(), limit).collect().map_ok(move |collected| {
+        Multipart::with_body(io::Cursor::new(collected.to_bytes()), boundary)
+    });
+
+    Ok(read_body)
+}
+
+/// If `req` is a `multipart/form-data` POST request, return a `StreamMultipart` which parses its
+/// body as the chunks arrive, without collecting it first. Otherwise, returns the original request.
+pub fn stream_request<B>(req: Request<B>) -> Result<StreamMultipart<BodyDataStream<B>>, Request<B>>
+where B: Body<Data = Bytes> + Unpin {
+    let boundary = match multipart_boundary(&req) {
+        Some(boundary) => boundary,
+        None => return Err(req),
+    };
+
+    Ok(StreamMultipart::with_body(BodyDataStream::new(req.into_body()), boundary))
+}
+
+/// Get the boundary string of `req` if it is a POST request with the `Content-Type` header set
+/// to `multipart/form-data`.
+pub fn multipart_boundary<B>(req: &Request<B>) -> Option<String> {
+    super::http::request_boundary(req).map(String::from)
+}
+
+/// A container that implements `hyper::service::Service` which will switch the service
+/// implementation depending on if the incoming request is multipart or not.
+///
+/// Normal requests are passed to the `normal` service, while multipart requests are passed to
+/// the `multipart` service as a [`LimitedMultipart`](type.LimitedMultipart.html), which parses
+/// the body as the chunks arrive, so it isn't collected in memory. Save it with the functions of
+/// [`server::tokio`](../tokio/index.html) or
+/// [`StreamMultipart::save_all_with()`](../stream/struct.StreamMultipart.html#method.save_all_with),
+/// which don't block the executor.
+///
+/// If the body is longer than [`body_size_limit()`](#method.body_size_limit) bytes, reading it
+/// fails with an `io::Error` wrapping a [`LengthLimitError`](struct.LengthLimitError.html),
+/// which can be checked for with
+/// `err.get_ref().map_or(false, |err| err.is::<LengthLimitError>())`.
+pub struct Switch<H, M> {
+    normal: H,
+    multipart: M,
+    body_size_limit: usize,
+}
+
+impl<H, M> Switch<H, M> {
+    /// Create a new `Switch` instance where
+    /// `normal` handles normal Hyper requests and `multipart` handles Multipart requests
+    pub fn new(normal: H, multipart: M) -> Switch<H, M> {
+        Switch {
+            normal: normal,
+            multipart: multipart,
+            body_size_limit: DEFAULT_BODY_SIZE_LIMIT,
+        }
+    }
+
+    /// Set the size limit of the bodies of multipart requests, in bytes.
+    ///
+    /// Default value: [`DEFAULT_BODY_SIZE_LIMIT`](constant.DEFAULT_BODY_SIZE_LIMIT.html)
+    pub fn body_size_limit(self, limit: usize) -> Self {
+        Switch { body_size_limit: limit, .. self }
+    }
+}
+
+impl<H, M, B> Service<Request<B>> for Switch<H, M>
+where H: Service<Request<B>>, H::Future: Send + 'static,
+      M: MultipartService<B, Response = H::Response, Error = H::Error>, M::Future: Send + 'static,
+      B: Body<Data = Bytes> + Unpin, B::Error: Into<Box<Error + Send + Sync>> {
+    type Response = H::Response;
+    type Error = H::Error;
+    type Future = Pin<Box<Future<Output = Result<H::Response, H::Error>> + Send>>;
+
+    fn call(&self, req: Request<B>) -> Self::Future {
+        let boundary = match multipart_boundary(&req) {
+            Some(boundary) => boundary,
+            None => return self.normal.call(req).boxed(),
+        };
+
+        let body = BodyDataStream::new(Limited::new(req.into_body(), self.body_size_limit));
+        self.multipart.call_multipart(StreamMultipart::with_body(body, boundary)).boxed()
+    }
+}
+
+/// A trait defining a type that can asynchronously handle an incoming multipart request with a
+/// body of type `B`.
+///
+/// Extends to closures of the type
+/// `Fn(LimitedMultipart<B>) -> impl Future<Output = Result<_, _>>`, and subsequently static
+/// functions.
+pub trait MultipartService<B> {
+    /// The response type returned by the future.
+    type Response;
+    /// The error type returned by the future.
+    type Error;
+    /// The future which generates the response.
+    type Future: Future<Output = Result<Self::Response, Self::Error>>;
+
+    /// Generate a response from this multipart request.
+    fn call_multipart(&self, multipart: LimitedMultipart<B>) -> Self::Future;
+}
+
+impl<F, Fut, R, E, B> MultipartService<B> for F
+where F: Fn(LimitedMultipart<B>) -> Fut, Fut: Future<Output = Result<R, E>> {
+    type Response = R;
+    type Error = E;
+    type Future = Fut;
+
+    fn call_multipart(&self, multipart: LimitedMultipart<B>) -> Fut {
+        (*self)(multipart)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use futures::executor::block_on;
+    use futures::future::{self, FutureExt};
+    use futures::stream::StreamExt;
+
+    use http_body_util::Full;
+
+    use hyper1::service::{service_fn, Service};
+    use hyper1::{Request, Response};
+
+    use std::error::Error;
+    use std::io;
+
+    use super::{read_request_limited, LengthLimitError, LimitedMultipart, Switch};
+    use server::stream::StreamEvent;
+
+    const BODY: &'static str = "--boundary\r\n\
+        Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+        value\r\n--boundary--\r\n";
+
+    fn request(method: &str, body: &'static str) -> Request<Full<Bytes>> {
+        Request::builder()
+            .method(method)
+            .header("Content-Type", "multipart/form-data; boundary=boundary")
+            .body(Full::new(Bytes::from(body)))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_read_request_limited() {
+        let mut multipart = block_on(read_request_limited(request("POST", BODY), BODY.len()).ok().unwrap())
+            .unwrap();
+        let field = multipart.read_entry().unwrap().unwrap();
+        assert_eq!(field.name, "text");
+
+        let err = block_on(read_request_limited(request("POST", BODY), BODY.len() - 1).ok().unwrap())
+            .err().unwrap();
+        assert!(err.is::<LengthLimitError>());
+
+        assert!(read_request_limited(request("GET", BODY), BODY.len()).is_err());
+    }
+
+    #[test]
+    fn test_switch() {
+        let normal = service_fn(|_: Request<Full<Bytes>>| {
+            future::ok::<_, Box<Error + Send + Sync>>(Response::new("normal".to_string()))
+        });
+
+        // Responds with the events read from the body, up to the first error.
+        let multipart = |multipart: LimitedMultipart<Full<Bytes>>| multipart.scan(false, |failed, event| {
+            let event = if *failed { None } else { Some(event) };
+            *failed = event.as_ref().map_or(true, |event| event.is_err());
+            future::ready(event)
+        }).map(|event| match event {
+            Ok(StreamEvent::Field(headers)) => format!("field {};", headers.name),
+            Ok(StreamEvent::Data(data)) => format!("data {};", String::from_utf8_lossy(&data)),
+            Ok(StreamEvent::FieldEnd) => "end;".to_string(),
+            Err(err) => {
+                assert_eq!(err.kind(), io::ErrorKind::Other);
+                assert!(err.get_ref().unwrap().is::<LengthLimitError>());
+                "too long".to_string()
+            },
+        }).collect::<String>().map(|res| Ok::<_, Box<Error + Send + Sync>>(Response::new(res)));
+
+        let switch = Switch::new(normal, multipart).body_size_limit(BODY.len());
+
+        let res = block_on(switch.call(request("POST", BODY))).unwrap();
+        assert_eq!(res.body(), "field text;data value;end;");
+
+        let res = block_on(switch.call(request("GET", BODY))).unwrap();
+        assert_eq!(res.body(), "normal");
+
+        let switch = switch.body_size_limit(BODY.len() - 1);
+        let res = block_on(switch.call(request("POST", BODY))).unwrap();
+        assert_eq!(res.body(), "too long");
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Validation of the formats and dimensions of uploaded images.
+use imagesize::{self, ImageType};
+
+use mime::{Mime, TopLevel};
+
+use std::io::{self, Read};
+
+use super::inspect::{PartInspector, Verdict};
+use super::policy;
+use super::FieldHeaders;
+
+/// The default maximum width and height of images, in pixels.
+pub const DEFAULT_MAX_IMAGE_DIMENSION: u64 = 16 * 1024;
+
+/// The default maximum number of pixels in an image.
+pub const DEFAULT_MAX_IMAGE_PIXELS: u64 = 64 * 1024 * 1024;
+
+/// The default amount of each file read to find its format and dimensions.
+pub const DEFAULT_IMAGE_HEADER_SIZE: usize = 64 * 1024;
+
+/// A [`PartInspector`](trait.PartInspector.html) which checks the real format and pixel
+/// dimensions of uploaded images, guarding against decompression bombs: small files which
+/// declare huge dimensions and exhaust memory when they are decoded.
+///
+/// The format and dimensions are read from the start of each file, without decoding it. File
+/// parts declared as `image/*` which aren't recognized as an image are rejected, and any file
+/// recognized as an image is checked, whatever its declared type.
+///
+/// ```rust
+/// use multipart::server::{ImageValidator, Multipart};
+/// use multipart::server::storage::MemoryStorage;
+///
+/// let body: &[u8] = b"--boundary\r\n\
+///     Content-Disposition: form-data; name=\"avatar\"; filename=\"avatar.png\"\r\n\
+///     Content-Type: image/png\r\n\r\n\
+///     not really a png\r\n--boundary--\r\n";
+///
+/// let mut validator = ImageValidator::new();
+/// validator.max_dimensions(1024, 1024)
+///     .formats(vec!["image/png".parse().unwrap(), "image/jpeg".parse().unwrap()]);
+///
+/// assert!(Multipart::with_body(body, "boundary").save_all_inspected(MemoryStorage, validator).is_err());
+/// ```
+///
+/// #### Feature: `imagesize`
+#[derive(Clone, Debug)]
+pub struct ImageValidator {
+    max_width: u64,
+    max_height: u64,
+    max_pixels: u64,
+    formats: Option<Vec<Mime>>,
+    header_size: usize,
+}
+
+impl ImageValidator {
+    /// A validator which accepts images in any recognized format up to
+    /// [`DEFAULT_MAX_IMAGE_DIMENSION`](constant.DEFAULT_MAX_IMAGE_DIMENSION.html) pixels wide
+    /// and high, and up to [`DEFAULT_MAX_IMAGE_PIXELS`](constant.DEFAULT_MAX_IMAGE_PIXELS.html)
+    /// pixels in total.
+    pub fn new() -> Self {
+        ImageValidator {
+            max_width: DEFAULT_MAX_IMAGE_DIMENSION,
+            max_height: DEFAULT_MAX_IMAGE_DIMENSION,
+            max_pixels: DEFAULT_MAX_IMAGE_PIXELS,
+            formats: None,
+            header_size: DEFAULT_IMAGE_HEADER_SIZE,
+        }
+    }
+
+    /// Set the maximum width and height of images, in pixels.
+    pub fn max_dimensions(&mut self, width: u64, height: u64) -> &mut Self {
+        self.max_width = width;
+        self.max_height = height;
+        self
+    }
+
+    /// Set the maximum number of pixels in an image.
+    pub fn max_pixels(&mut self, pixels: u64) -> &mut Self {
+        self.max_pixels = pixels;
+        self
+    }
+
+    /// Only accept images in these formats, such as `image/png`, rather than any recognized
+    /// format.
+    pub fn formats<I: IntoIterator<Item = Mime>>(&mut self, formats: I) -> &mut Self {
+        self.formats = Some(formats.into_iter().collect());
+        self
+    }
+
+    /// Set how much of each file is read to find its format and dimensions.
+    ///
+    /// Images whose dimensions aren't found within this size, such as JPEGs with large metadata
+    /// segments, are rejected.
+    ///
+    /// Default value: [`DEFAULT_IMAGE_HEADER_SIZE`](constant.DEFAULT_IMAGE_HEADER_SIZE.html)
+    pub fn header_size(&mut self, size: usize) -> &mut Self {
+        self.header_size = size;
+        self
+    }
+
+    /// Check the start of a file, returning the reason to reject it, if any.
+    fn check(&self, declared_image: bool, header: &[u8]) -> Option<String> {
+        let format = match imagesize::image_type(header) {
+            Ok(format) => format,
+            Err(_) if declared_image => return Some("file is not a recognized image".into()),
+            Err(_) => return None,
+        };
+
+        if let Some(ref formats) = self.formats {
+            let allowed = format_type(format)
+                .map_or(false, |ty| formats.iter().any(|pattern| policy::matches(pattern, &ty)));
+
+            if !allowed {
+                return Some(format!("image format {:?} is not allowed", format));
+            }
+        }
+
+        let size = match imagesize::blob_size(header) {
+            Ok(size) => size,
+            Err(_) => return Some("image dimensions could not be read".into()),
+        };
+
+        let (width, height) = (size.width as u64, size.height as u64);
+
+        if width > self.max_width || height > self.max_height
+            || width.saturating_mul(height) > self.max_pixels {
+            return Some(format!("image dimensions {}x{} exceed the maximum", width, height));
+        }
+
+        None
+    }
+}
+
+impl Default for ImageValidator {
+    fn default() -> Self {
+        ImageValidator::new()
+    }
+}
+
+impl PartInspector for ImageValidator {
+    fn inspect(&mut self, headers: &FieldHeaders, data: &mut Read) -> io::Result<Verdict> {
+        let declared_image = match headers.content_type {
+            Some(ref content_type) => content_type.0 == TopLevel::Image,
+            // Text fields aren't checked.
+            None => return Ok(Verdict::Accept),
+        };
+
+        let mut header = Vec::new();
+        try!(data.take(self.header_size as u64).read_to_end(&mut header));
+
+        Ok(match self.check(declared_image, &header) {
+            Some(reason) => Verdict::Reject(reason),
+            None => Verdict::Accept,
+        })
+    }
+}
+
+/// Get the MIME type of an image format, if it has a well-known one.
+fn format_type(format: ImageType) -> Option<Mime> {
+    let content_type = match format {
+        ImageType::Bmp => "image/bmp",
+        ImageType::Gif => "image/gif",
+        ImageType::Heif(_) => "image/heif",
+        ImageType::Ico => "image/x-icon",
+        ImageType::Jpeg => "image/jpeg",
+        ImageType::Jxl => "image/jxl",
+        ImageType::Png => "image/png",
+        ImageType::Psd => "image/vnd.adobe.photoshop",
+        ImageType::Tiff => "image/tiff",
+        ImageType::Webp => "image/webp",
+        _ => return None,
+    };
+
+    content_type.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::ImageValidator;
+
+    use server::{Multipart, PartRejected};
+    use server::storage::MemoryStorage;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        for dimension in &[width, height] {
+            png.extend_from_slice(&[(dimension >> 24) as u8, (dimension >> 16) as u8,
+                                    (dimension >> 8) as u8, *dimension as u8]);
+        }
+        png.extend_from_slice(b"\x08\x06\0\0\0");
+        png
+    }
+
+    fn validate(validator: &ImageValidator, content_type: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        let mut body = format!("--boundary\r\n\
+            Content-Disposition: form-data; name=\"image\"; filename=\"image\"\r\n\
+            Content-Type: {}\r\n\r\n", content_type).into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n--boundary--\r\n");
+
+        match Multipart::with_body(&body[..], "boundary").save_all_inspected(MemoryStorage, validator.clone()) {
+            Ok(mut entries) => Ok(entries.files.remove("image").unwrap().stored),
+            Err((_, err)) => Err(err.get_ref().unwrap().downcast_ref::<PartRejected>().unwrap().reason.clone()),
+        }
+    }
+
+    #[test]
+    fn test_image_validator() {
+        let mut validator = ImageValidator::new();
+        validator.max_dimensions(1000, 1000).max_pixels(500 * 1000);
+
+        let small = png(800, 600);
+        assert_eq!(validate(&validator, "image/png", &small).unwrap(), small);
+        assert_eq!(validate(&validator, "text/plain", b"not an image").unwrap(), b"not an image");
+
+        assert!(validate(&validator, "image/png", &png(1001, 10)).is_err());
+        assert!(validate(&validator, "image/png", &png(1000, 1000)).is_err());
+        // Images are checked even when they claim not to be.
+        assert!(validate(&validator, "application/octet-stream", &png(100000, 100000)).is_err());
+        assert_eq!(validate(&validator, "image/png", b"not an image").unwrap_err(),
+                   "file is not a recognized image");
+
+        validator.formats(vec!["image/jpeg".parse().unwrap()]);
+        assert_eq!(validate(&validator, "image/png", &small).unwrap_err(),
+                   "image format Png is not allowed");
+
+        validator.formats(vec!["image/*".parse().unwrap()]);
+        assert!(validate(&validator, "image/png", &small).is_ok());
+    }
+}
@@ -0,0 +1,269 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Inspection of the parts of a request as they are saved, for
+//! `Multipart::save_all_inspected()`.
+use std::io::{self, Read, Write};
+use std::{error, fmt};
+
+use super::FieldHeaders;
+
+/// The decision of a [`PartInspector`](trait.PartInspector.html) about a part.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Keep the part.
+    Accept,
+    /// Veto the part, for the given reason. Saving the request stops with a
+    /// [`PartRejected`](struct.PartRejected.html) error.
+    Reject(String),
+}
+
+/// Inspects each part of a request while it is saved by
+/// [`Multipart::save_all_inspected()`](struct.Multipart.html#method.save_all_inspected), such as
+/// for virus scanning, PII detection or custom policies, and may veto it.
+///
+/// Implemented for closures with the same signature as `inspect()`.
+///
+/// ```rust
+/// use multipart::server::{FieldHeaders, Multipart, Verdict};
+/// use multipart::server::storage::MemoryStorage;
+///
+/// use std::io::{self, Read};
+///
+/// let body: &[u8] = b"--boundary\r\n\
+///     Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+///     Content-Type: text/plain\r\n\r\n\
+///     contents\r\n--boundary--\r\n";
+///
+/// let mut sizes = Vec::new();
+///
+/// let inspector = |_: &FieldHeaders, data: &mut Read| -> io::Result<Verdict> {
+///     let mut contents = Vec::new();
+///     try!(data.read_to_end(&mut contents));
+///     sizes.push(contents.len());
+///     Ok(Verdict::Accept)
+/// };
+///
+/// let entries = Multipart::with_body(body, "boundary").save_all_inspected(MemoryStorage, inspector)
+///     .unwrap();
+///
+/// assert_eq!(entries.files["file"].stored, b"contents");
+/// assert_eq!(sizes, [8]);
+/// ```
+pub trait PartInspector {
+    /// Inspect a part with the given headers, reading as much of its contents from `data` as
+    /// needed.
+    ///
+    /// The contents are saved as they are read, and any which aren't read are saved after this
+    /// returns. Text fields have no `content_type` in their headers.
+    fn inspect(&mut self, headers: &FieldHeaders, data: &mut Read) -> io::Result<Verdict>;
+}
+
+impl<F> PartInspector for F where F: FnMut(&FieldHeaders, &mut Read) -> io::Result<Verdict> {
+    fn inspect(&mut self, headers: &FieldHeaders, data: &mut Read) -> io::Result<Verdict> {
+        self(headers, data)
+    }
+}
+
+/// A [`PartInspector`](trait.PartInspector.html) which rejects files whose contents are
+/// executables or scripts, whatever their declared type: Windows (PE), ELF and Mach-O
+/// executables, and scripts starting with `#!`.
+///
+/// Executables are recognized by their magic bytes. Mach-O universal binaries share theirs
+/// with Java class files, which are rejected too. Text fields are not checked.
+///
+/// ```rust
+/// use multipart::server::{Multipart, RejectExecutables};
+/// use multipart::server::storage::MemoryStorage;
+///
+/// let body: &[u8] = b"--boundary\r\n\
+///     Content-Disposition: form-data; name=\"file\"; filename=\"cat.jpg\"\r\n\
+///     Content-Type: image/jpeg\r\n\r\n\
+///     #!/bin/sh\nrm -rf /\r\n--boundary--\r\n";
+///
+/// let res = Multipart::with_body(body, "boundary").save_all_inspected(MemoryStorage, RejectExecutables);
+/// assert!(res.is_err());
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RejectExecutables;
+
+/// The magic bytes of executables, and what they are.
+const EXECUTABLE_MAGIC: &'static [(&'static [u8], &'static str)] = &[
+    (b"MZ", "a Windows executable"),
+    (b"\x7fELF", "an ELF executable"),
+    (b"\xfe\xed\xfa\xce", "a Mach-O executable"),
+    (b"\xfe\xed\xfa\xcf", "a Mach-O executable"),
+    (b"\xce\xfa\xed\xfe", "a Mach-O executable"),
+    (b"\xcf\xfa\xed\xfe", "a Mach-O executable"),
+    (b"\xca\xfe\xba\xbe", "a Mach-O universal binary or Java class"),
+    (b"#!", "a script"),
+];
+
+impl PartInspector for RejectExecutables {
+    fn inspect(&mut self, headers: &FieldHeaders, data: &mut Read) -> io::Result<Verdict> {
+        if headers.content_type.is_none() {
+            return Ok(Verdict::Accept);
+        }
+
+        let mut start = Vec::with_capacity(4);
+        try!(data.take(4).read_to_end(&mut start));
+
+        Ok(match EXECUTABLE_MAGIC.iter().find(|&&(magic, _)| start.starts_with(magic)) {
+            Some(&(_, kind)) => Verdict::Reject(format!("file is {}", kind)),
+            None => Verdict::Accept,
+        })
+    }
+}
+
+/// The error of a part vetoed by a [`PartInspector`](trait.PartInspector.html).
+///
+/// Returned wrapped in an `io::Error` of kind `io::ErrorKind::InvalidData`, from which it can be
+/// recovered with `get_ref()` and `downcast_ref()`.
+#[derive(Clone, Debug)]
+pub struct PartRejected {
+    /// The name of the field.
+    pub field_name: String,
+    /// The reason given by the inspector.
+    pub reason: String,
+}
+
+impl error::Error for PartRejected {
+    fn description(&self) -> &str {
+        "multipart part was rejected by an inspector"
+    }
+}
+
+impl fmt::Display for PartRejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "field {:?} was rejected: {}", self.field_name, self.reason)
+    }
+}
+
+impl From<PartRejected> for io::Error {
+    fn from(err: PartRejected) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Writes the data read from `reader` to `writer` as it is read.
+pub struct Tee<R, W> {
+    reader: R,
+    writer: W,
+    size: u64,
+}
+
+impl<R: Read, W: Write> Tee<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Tee {
+            reader: reader,
+            writer: writer,
+            size: 0,
+        }
+    }
+
+    /// Write the data which hasn't been read yet, returning the total size of the data.
+    pub fn finish(mut self) -> io::Result<u64> {
+        let rest = try!(io::copy(&mut self.reader, &mut self.writer));
+        Ok(self.size + rest)
+    }
+}
+
+impl<R: Read, W: Write> Read for Tee<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = try!(self.reader.read(buf));
+        try!(self.writer.write_all(&buf[..read]));
+        self.size += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PartRejected, RejectExecutables, Verdict};
+
+    use server::{FieldHeaders, Multipart};
+    use server::storage::{LocalStorage, MemoryStorage};
+
+    use tempdir::TempDir;
+
+    use std::fs;
+    use std::io::{self, Read};
+
+    const BODY: &[u8] = b"--boundary\r\n\
+        Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+        text field\r\n--boundary\r\n\
+        Content-Disposition: form-data; name=\"clean\"; filename=\"clean.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        clean file\r\n--boundary\r\n\
+        Content-Disposition: form-data; name=\"infected\"; filename=\"infected.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        infected file\r\n--boundary--\r\n";
+
+    #[test]
+    fn test_save_all_inspected() {
+        let dir = TempDir::new("multipart-inspect").unwrap();
+        let mut inspected = Vec::new();
+
+        let (entries, err) = {
+            let scan = |headers: &FieldHeaders, data: &mut Read| -> io::Result<Verdict> {
+                inspected.push((headers.name.clone(), headers.content_type.is_some()));
+
+                // Only read the start of the data; the rest is still saved.
+                let mut start = [0; 8];
+                let read = try!(data.read(&mut start));
+
+                Ok(if &start[..read] == b"infected" {
+                    Verdict::Reject("virus found".into())
+                } else {
+                    Verdict::Accept
+                })
+            };
+
+            Multipart::with_body(BODY, "boundary")
+                .save_all_inspected(LocalStorage::new(dir.path()), scan).unwrap_err()
+        };
+
+        assert_eq!(inspected, [
+            ("text".to_string(), false), ("clean".to_string(), true), ("infected".to_string(), true)
+        ]);
+
+        assert_eq!(entries.fields["text"], "text field");
+        assert_eq!(fs::read(&entries.files["clean"].stored.path).unwrap(), b"clean file");
+        assert_eq!(entries.files["clean"].size, 10);
+        assert!(!entries.files.contains_key("infected"));
+
+        // The rejected file was deleted.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let err = err.get_ref().and_then(|err| err.downcast_ref::<PartRejected>()).unwrap();
+        assert_eq!(err.field_name, "infected");
+        assert_eq!(err.reason, "virus found");
+    }
+
+    #[test]
+    fn test_reject_executables() {
+        fn check(data: &[u8]) -> Result<(), String> {
+            let mut body = b"--boundary\r\n\
+                Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+                Content-Type: text/plain\r\n\r\n".to_vec();
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n--boundary--\r\n");
+
+            Multipart::with_body(&body[..], "boundary").save_all_inspected(MemoryStorage, RejectExecutables)
+                .map(|_| ())
+                .map_err(|(_, err)| err.get_ref().unwrap().downcast_ref::<PartRejected>().unwrap().reason.clone())
+        }
+
+        assert_eq!(check(b"MZ\x90\0\x03\0").unwrap_err(), "file is a Windows executable");
+        assert_eq!(check(b"\x7fELF\x02\x01").unwrap_err(), "file is an ELF executable");
+        assert_eq!(check(b"\xcf\xfa\xed\xfe\x07\0").unwrap_err(), "file is a Mach-O executable");
+        assert_eq!(check(b"#!/bin/sh\n").unwrap_err(), "file is a script");
+
+        check(b"plain text").unwrap();
+        check(b"M").unwrap();
+        check(b"").unwrap();
+    }
+}
@@ -4,6 +4,7 @@
 //! iron::Request`.
 
 use iron::headers::ContentType;
+use iron::method::Method;
 use iron::mime::{Mime, TopLevel, SubLevel};
 use iron::request::{Body as IronBody, Request as IronRequest};
 use iron::typemap::Key;
@@ -12,7 +13,7 @@ use iron::{BeforeMiddleware, IronError, IronResult};
 use std::path::PathBuf;
 use std::{error, fmt};
 
-use super::{Entries, HttpRequest, Multipart, MultipartData};
+use super::{accepts_subtype, Entries, HttpRequest, Multipart, MultipartData};
 
 impl<'r, 'a, 'b> HttpRequest for &'r mut IronRequest<'a, 'b> {
     type Body = &'r mut IronBody<'a, 'b>;
@@ -26,6 +27,27 @@ impl<'r, 'a, 'b> HttpRequest for &'r mut IronRequest<'a, 'b> {
         }
     }
 
+    fn multipart_boundary_for(&self, methods: &[&str]) -> Option<&str> {
+        if !methods.iter().any(|name| name.parse::<Method>().ok().as_ref() == Some(&self.method)) {
+            return None;
+        }
+
+        self.multipart_boundary()
+    }
+
+    fn multipart_boundary_accepting(&self, methods: &[&str], subtypes: &[&str]) -> Option<&str> {
+        if !methods.iter().any(|name| name.parse::<Method>().ok().as_ref() == Some(&self.method)) {
+            return None;
+        }
+
+        let content_type = try_opt!(self.headers.get::<ContentType>());
+        match **content_type {
+            Mime(TopLevel::Multipart, ref sub, _) if accepts_subtype(subtypes, sub.as_str()) =>
+                content_type.get_param("boundary").map(|b| b.as_str()),
+            _ => None,
+        }
+    }
+
     fn body(self) -> &'r mut IronBody<'a, 'b> {
         &mut self.body
     }
@@ -73,7 +95,7 @@ pub struct Intercept {
     /// The parent directory for all temporary directories created by this middleware.
     /// Will be created if it doesn't exist (lazy).
     /// 
-    /// If omitted, uses the OS temporary directory.
+    /// If omitted, uses the [temporary root](../fn.temp_root.html).
     ///
     /// Default value: `None`.
     pub temp_dir_path: Option<PathBuf>,
@@ -173,6 +195,9 @@ impl Intercept {
                 MultipartData::Text(text) => {
                     entries.fields.insert(field.name, text.into());
                 },
+                MultipartData::Bytes(bytes) => {
+                    entries.fields.insert(field.name, String::from_utf8_lossy(bytes).into_owned());
+                },
             }
         }
 
@@ -0,0 +1,173 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Parsing the bodies of AWS Lambda events from API Gateway or function URLs, enabled with the
+//! `lambda` feature (optional).
+//!
+//! Serverless handlers are given the whole request at once, with the body as a string which is
+//! base64-encoded if it is binary, rather than a stream.
+//! [`LambdaEvent`](struct.LambdaEvent.html) decodes the body and finds the boundary in the
+//! headers, so that the fields can be parsed in memory with
+//! [`Multipart::parse_bytes()`](../struct.Multipart.html#method.parse_bytes).
+//!
+//! ```rust
+//! use multipart::server::lambda::LambdaEvent;
+//!
+//! use std::collections::HashMap;
+//!
+//! let mut headers = HashMap::new();
+//! // API Gateway's HTTP APIs lowercase header names, REST APIs don't; either is accepted.
+//! headers.insert("content-type".to_string(), "multipart/form-data; boundary=boundary".to_string());
+//!
+//! // `--boundary\r\nContent-Disposition: form-data; name="text"\r\n\r\nvalue\r\n--boundary--\r\n`
+//! let body = "LS1ib3VuZGFyeQ0KQ29udGVudC1EaXNwb3NpdGlvbjogZm9ybS1kYXRhOyBuYW1lPSJ0ZXh0Ig0KDQp2\
+//!             YWx1ZQ0KLS1ib3VuZGFyeS0tDQo=";
+//!
+//! let event = LambdaEvent::new(&headers, body, true).unwrap();
+//! let fields = event.fields().unwrap();
+//!
+//! assert_eq!(fields[0].name, "text");
+//! assert_eq!(fields[0].as_text(), Some("value"));
+//! ```
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use mime::{Mime, TopLevel};
+
+use std::{error, fmt, io};
+
+use super::borrowed::BorrowedField;
+use super::push::ParseError;
+use super::{accepts_subtype, parse_boundary, Multipart, DEFAULT_SUBTYPES};
+
+/// An error returned by [`LambdaEvent::new()`](struct.LambdaEvent.html#method.new).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LambdaError {
+    /// There was no `Content-Type: multipart/form-data` header with a `boundary` parameter.
+    MissingBoundary,
+    /// The body was marked as base64-encoded, but was not valid base64.
+    InvalidBase64,
+}
+
+impl error::Error for LambdaError {}
+
+impl fmt::Display for LambdaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            LambdaError::MissingBoundary => "multipart body has no boundary",
+            LambdaError::InvalidBase64 => "multipart body is not valid base64",
+        })
+    }
+}
+
+impl From<LambdaError> for io::Error {
+    fn from(err: LambdaError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// The decoded body of a multipart request received by a Lambda function, and its boundary.
+#[derive(Clone, Debug)]
+pub struct LambdaEvent {
+    boundary: String,
+    body: Vec<u8>,
+}
+
+impl LambdaEvent {
+    /// Decode the body of an event, as given by its `headers`, `body` and `isBase64Encoded`
+    /// fields.
+    ///
+    /// `headers` may be any collection of name-value pairs, such as a `HashMap<String, String>`;
+    /// names are compared case-insensitively.
+    ///
+    /// ##Errors
+    /// * `LambdaError::MissingBoundary` if there is no `Content-Type: multipart/form-data` header
+    /// with a boundary.
+    /// * `LambdaError::InvalidBase64` if `is_base64_encoded` is set and `body` is not valid base64.
+    pub fn new<I, K, V>(headers: I, body: &str, is_base64_encoded: bool) -> Result<Self, LambdaError>
+    where I: IntoIterator<Item = (K, V)>, K: AsRef<str>, V: AsRef<str> {
+        let boundary = try!(headers.into_iter()
+            .find(|&(ref name, _)| name.as_ref().eq_ignore_ascii_case("Content-Type"))
+            .and_then(|(_, content_type)| form_data_boundary(content_type.as_ref()))
+            .ok_or(LambdaError::MissingBoundary));
+
+        let body = if is_base64_encoded {
+            try!(STANDARD.decode(body).map_err(|_| LambdaError::InvalidBase64))
+        } else {
+            body.as_bytes().to_vec()
+        };
+
+        Ok(LambdaEvent {
+            boundary: boundary,
+            body: body,
+        })
+    }
+
+    /// The boundary of the body, as it appeared in the `Content-Type` header.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// The decoded body.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Parse all fields of the body with
+    /// [`Multipart::parse_bytes()`](../struct.Multipart.html#method.parse_bytes), borrowing
+    /// from this event.
+    pub fn fields(&self) -> Result<Vec<BorrowedField>, ParseError> {
+        Multipart::parse_bytes(&self.body, &self.boundary)
+    }
+
+    /// Get the decoded body, to be read with `Multipart::with_body()` instead.
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+}
+
+fn form_data_boundary(content_type: &str) -> Option<String> {
+    match content_type.parse() {
+        Ok(Mime(TopLevel::Multipart, ref sub, _)) if accepts_subtype(DEFAULT_SUBTYPES, sub.as_str()) => (),
+        _ => return None,
+    }
+
+    parse_boundary(content_type).map(Into::into)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LambdaError, LambdaEvent};
+
+    const BODY: &'static str = "--boundary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"file.bin\"\r\n\r\n\
+        \u{0}\u{1}\r\n--boundary--\r\n";
+
+    #[test]
+    fn test_lambda_event() {
+        let headers = [("Content-Type", "multipart/form-data; boundary=boundary")];
+
+        let event = LambdaEvent::new(headers.iter().cloned(), BODY, false).unwrap();
+        assert_eq!(event.boundary(), "boundary");
+
+        let fields = event.fields().unwrap();
+        assert_eq!(fields[0].filename, Some("file.bin"));
+        assert_eq!(fields[0].data, b"\x00\x01");
+
+        let encoded = "LS1ib3VuZGFyeS0tDQo=";
+        let headers = [("accept", "*/*"), ("content-type", "multipart/form-data; boundary=\"boundary\"")];
+        let event = LambdaEvent::new(headers.iter().cloned(), encoded, true).unwrap();
+        assert_eq!(event.body(), b"--boundary--\r\n");
+        assert_eq!(event.fields().unwrap(), vec![]);
+
+        assert_eq!(LambdaEvent::new(headers.iter().cloned(), "not base64!", true).err(),
+                   Some(LambdaError::InvalidBase64));
+
+        let headers = [("Content-Type", "application/json")];
+        assert_eq!(LambdaEvent::new(headers.iter().cloned(), BODY, false).err(),
+                   Some(LambdaError::MissingBoundary));
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// Limits how many files are written to disk at once, across all the requests which share it,
+/// so a spike in uploads doesn't overwhelm slow or networked storage.
+///
+/// Cloning a `SaveLimiter` returns another handle to the same limit. Pass it to
+/// [`Multipart::save_limiter()`](struct.Multipart.html#method.save_limiter) or
+/// [`LocalStorage::limit_concurrency()`](storage/struct.LocalStorage.html#method.limit_concurrency);
+/// saving a file then blocks the current thread until fewer than `max` files are being written.
+///
+/// ```rust
+/// use multipart::server::{Multipart, SaveLimiter};
+///
+/// // Shared by every request handler.
+/// let limiter = SaveLimiter::new(4);
+///
+/// let body: &[u8] = b"--boundary\r\n\
+///     Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+///     Content-Type: text/plain\r\n\r\n\
+///     contents\r\n--boundary--\r\n";
+///
+/// let entries = Multipart::with_body(body, "boundary").save_limiter(limiter.clone()).save_all()
+///     .to_result().unwrap();
+///
+/// assert_eq!(entries.files.len(), 1);
+/// assert_eq!(limiter.active(), 0);
+/// ```
+#[derive(Clone, Debug)]
+pub struct SaveLimiter {
+    inner: Arc<LimiterInner>,
+}
+
+#[derive(Debug)]
+struct LimiterInner {
+    active: Mutex<usize>,
+    released: Condvar,
+    max: usize,
+}
+
+impl SaveLimiter {
+    /// Allow at most `max` files to be written at once.
+    ///
+    /// ##Panics
+    /// If `max` is zero.
+    pub fn new(max: usize) -> Self {
+        assert!(max > 0, "SaveLimiter must allow at least one file");
+
+        SaveLimiter {
+            inner: Arc::new(LimiterInner {
+                active: Mutex::new(0),
+                released: Condvar::new(),
+                max: max,
+            }),
+        }
+    }
+
+    /// The maximum number of files written at once.
+    pub fn max(&self) -> usize {
+        self.inner.max
+    }
+
+    /// The number of files currently being written.
+    pub fn active(&self) -> usize {
+        *self.lock()
+    }
+
+    /// Wait until fewer than `max` files are being written, then take a permit to write one,
+    /// which is released when it is dropped.
+    pub fn acquire(&self) -> SavePermit {
+        let mut active = self.lock();
+
+        while *active >= self.inner.max {
+            active = self.inner.released.wait(active).unwrap_or_else(|err| err.into_inner());
+        }
+
+        *active += 1;
+        SavePermit { limiter: self.clone() }
+    }
+
+    /// Take a permit to write a file if fewer than `max` files are being written, without
+    /// waiting.
+    pub fn try_acquire(&self) -> Option<SavePermit> {
+        let mut active = self.lock();
+
+        if *active >= self.inner.max {
+            return None;
+        }
+
+        *active += 1;
+        Some(SavePermit { limiter: self.clone() })
+    }
+
+    fn lock(&self) -> MutexGuard<usize> {
+        // The count is still valid if another thread panicked while holding the lock.
+        self.inner.active.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// A permit to write a file, taken from a [`SaveLimiter`](struct.SaveLimiter.html) and released
+/// when dropped.
+#[derive(Debug)]
+pub struct SavePermit {
+    limiter: SaveLimiter,
+}
+
+impl Drop for SavePermit {
+    fn drop(&mut self) {
+        *self.limiter.lock() -= 1;
+        self.limiter.inner.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SaveLimiter;
+
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_save_limiter() {
+        let limiter = SaveLimiter::new(2);
+
+        let first = limiter.acquire();
+        let second = limiter.try_acquire().unwrap();
+        assert_eq!(limiter.active(), 2);
+        assert!(limiter.try_acquire().is_none());
+
+        let (tx, rx) = mpsc::channel();
+        let waiting = limiter.clone();
+        let handle = thread::spawn(move || {
+            let _permit = waiting.acquire();
+            tx.send(()).unwrap();
+        });
+
+        // The third writer waits until a permit is released.
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        drop(first);
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        handle.join().unwrap();
+
+        drop(second);
+        assert_eq!(limiter.active(), 0);
+    }
+}
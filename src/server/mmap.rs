@@ -0,0 +1,50 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Saving files through memory maps, for `MultipartFile::save_as_mmap()`.
+use memmap2::MmapMut;
+
+use super::hash::Hasher;
+
+use std::fs::File;
+use std::io::{self, Read};
+
+/// Read from `reader` into `file`, which has been allocated with a size of `len` bytes, until
+/// it is full or `reader` is at EOF, returning the number of bytes read and passing them to
+/// `hasher`.
+pub fn read_into_file<R: Read>(reader: &mut R, file: &File, len: u64, hasher: &mut Hasher) -> io::Result<u64> {
+    if len == 0 {
+        // Empty maps aren't supported on all platforms.
+        return Ok(0);
+    }
+
+    if len > usize::max_value() as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "file is too large to be mapped"));
+    }
+
+    // SAFETY: the file was just created by us and has already been extended to `len`, so the
+    // whole map is backed by it; as with any memory map, it must not be truncated or modified
+    // by other processes while it is mapped, which is why only newly created files are mapped.
+    #[allow(unsafe_code)]
+    let mut map = try!(unsafe { MmapMut::map_mut(file) });
+
+    let mut read = 0;
+
+    while read < map.len() {
+        match reader.read(&mut map[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    hasher.update(&map[..read]);
+
+    try!(map.flush());
+
+    Ok(read as u64)
+}
@@ -11,18 +11,54 @@
 //! to accept, parse, and serve HTTP `multipart/form-data` requests (file uploads).
 //!
 //! See the `Multipart` struct for more info.
-use mime::Mime;
+use mime::{Attr, Mime};
 
-use tempdir::TempDir;
-
-use std::borrow::Borrow;
+use std::borrow::{Borrow, Cow};
 use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
-use std::{fmt, io, mem, ptr};
+use std::{fmt, io, mem, str};
 
 use self::boundary::BoundaryReader;
+#[cfg(feature = "checksum")]
+use self::checksum::Verifier;
+use self::hash::HashWriter;
+use self::nested::Nesting;
+use self::push::{ParseError, MAX_HEADERS_SIZE};
+use self::storage::LocalStorage;
+use self::strict::Strict;
+
+pub use self::boundary::{parse_boundary, DEFAULT_BUF_SIZE};
+pub use self::byteranges::{ByteRange, ByteRangesWriter, ContentRange};
+#[cfg(feature = "checksum")]
+pub use self::checksum::{Checksum, ChecksumMismatch};
+pub use self::disposition::DispositionParams;
+pub use self::drain::DrainOnDrop;
+#[cfg(feature = "encrypt")]
+pub use self::encrypt::{Decrypt, EncryptionKey};
+#[cfg(feature = "zip")]
+pub use self::extract::{ExtractedFile, ZipLimits};
+pub use self::filename::{FilenamePolicy, FilenameTooLong, LengthPolicy, DEFAULT_MAX_FILENAME_LEN};
+#[cfg(feature = "imagesize")]
+pub use self::image::{
+    ImageValidator, DEFAULT_IMAGE_HEADER_SIZE, DEFAULT_MAX_IMAGE_DIMENSION, DEFAULT_MAX_IMAGE_PIXELS,
+};
+pub use self::inspect::{PartInspector, PartRejected, RejectExecutables, Verdict};
+pub use self::limiter::{SaveLimiter, SavePermit};
+pub use self::nested::{NestedBody, NestingLimits};
+pub use self::part::MultipartPart;
+pub use self::policy::{DisallowedType, TypePolicy};
+pub use self::pool::BufferPool;
+pub use self::replace::Replacement;
+pub use self::response::ResponseWriter;
+#[cfg(feature = "infer")]
+pub use self::sniff::TypeMismatch;
+#[cfg(all(feature = "diskspace", unix))]
+pub use self::space::{InsufficientStorage, FREE_SPACE_CHECK_INTERVAL};
+pub use self::strict::{StrictViolation, Violation};
+pub use self::temp::{temp_root, DropPolicy, TempDir, TEMP_DIR_ENV};
+pub use self::text::{InvalidUtf8, Utf8Policy};
 
 macro_rules! try_opt (
     ($expr:expr) => (
@@ -35,26 +71,161 @@ macro_rules! try_opt (
 
 mod boundary;
 
+mod byteranges;
+
+#[cfg(feature = "checksum")]
+mod checksum;
+
+#[cfg(feature = "zstd")]
+mod compress;
+
+#[cfg(feature = "flate2")]
+mod decode;
+
+mod disposition;
+
+mod drain;
+
+#[cfg(feature = "encrypt")]
+mod encrypt;
+
+#[cfg(feature = "zip")]
+mod extract;
+
+mod filename;
+
+#[cfg(feature = "flate2")]
+mod gunzip;
+
+#[cfg(feature = "imagesize")]
+mod image;
+
+mod hash;
+
+mod inspect;
+
+mod limiter;
+
+#[cfg(feature = "mmap")]
+mod mmap;
+
+mod nested;
+
+mod part;
+
+mod policy;
+
+mod pool;
+
+mod replace;
+
+mod response;
+
+#[cfg(feature = "infer")]
+mod sniff;
+
+#[cfg(all(feature = "diskspace", unix))]
+mod space;
+
+#[cfg(all(feature = "splice", target_os = "linux"))]
+mod splice;
+
+mod strict;
+
+mod temp;
+
+mod text;
+
+pub mod borrowed;
+
+pub mod cgi;
+
+pub mod nonblocking;
+
+pub use push;
+
+pub mod related;
+
+pub mod storage;
+
+#[cfg(feature = "async")]
+pub mod stream;
+
+#[cfg(feature = "async-std_")]
+pub mod async_std;
+
+#[cfg(feature = "gotham")]
+pub mod gotham;
+
+#[cfg(feature = "h2_")]
+pub mod h2;
+
+#[cfg(feature = "http")]
+pub mod http;
+
 #[cfg(feature = "hyper")]
 pub mod hyper;
 
+#[cfg(feature = "hyper1")]
+pub mod hyper1;
+
 #[cfg(feature = "iron")]
 pub mod iron;
 
+#[cfg(feature = "lambda")]
+pub mod lambda;
+
 #[cfg(feature = "nickel")]
 pub mod nickel;
 
+#[cfg(feature = "s3")]
+pub mod s3;
+
 #[cfg(feature = "tiny_http")]
 pub mod tiny_http;
 
+#[cfg(feature = "tokio_")]
+pub mod tokio;
+
+#[cfg(all(feature = "tokio-uring_", target_os = "linux"))]
+pub mod tokio_uring;
+
+#[cfg(feature = "tower_")]
+pub mod tower;
+
 const RANDOM_FILENAME_LEN: usize = 12;
 
+/// The HTTP methods of the multipart requests accepted by default: only POST.
+pub const DEFAULT_METHODS: &'static [&'static str] = &["POST"];
+
+/// The `multipart/*` subtypes of the requests accepted by default: only `form-data`.
+///
+/// Use `&["*"]` to accept any subtype.
+pub const DEFAULT_SUBTYPES: &'static [&'static str] = &["form-data"];
+
 /// The server-side implementation of `multipart/form-data` requests.
 ///
 /// Implements `Borrow<R>` to allow access to the request body, if desired.
+///
+/// Errors from the request body can't be recovered from, including `io::ErrorKind::WouldBlock`;
+/// for non-blocking readers, use [`NonBlockingMultipart`](nonblocking/struct.NonBlockingMultipart.html).
+///
+/// `Multipart<B>` is `Send` and `Sync` whenever `B` is, so it can be handed to a worker thread
+/// along with the request body.
 pub struct Multipart<B> {
     source: BoundaryReader<B>,
     line_buf: String, 
+    text_buf: Vec<u8>,
+    utf8: Utf8Policy,
+    temp_root: Option<PathBuf>,
+    limiter: Option<SaveLimiter>,
+    #[cfg(feature = "encrypt")]
+    encrypt: bool,
+    #[cfg(feature = "zstd")]
+    zstd_level: Option<i32>,
+    drained: bool,
+    nesting: Nesting,
+    strict: Strict,
 }
 
 impl Multipart<()> {
@@ -70,28 +241,170 @@ impl Multipart<()> {
 
         Ok(Multipart::with_body(req.body(), boundary))        
     }   
+
+    /// If the given `HttpRequest` is a multipart/form-data request whose method is one of
+    /// `methods`, such as `&["POST", "PUT", "PATCH"]`, return the request body wrapped in the
+    /// multipart reader. Otherwise, returns the original request.
+    ///
+    /// See [`HttpRequest::multipart_boundary_for()`](trait.HttpRequest.html#method.multipart_boundary_for)
+    /// for more information.
+    pub fn from_request_methods<R: HttpRequest>(req: R, methods: &[&str]) -> Result<Multipart<R::Body>, R> {
+        let boundary = match req.multipart_boundary_for(methods).map(String::from) {
+            Some(boundary) => boundary,
+            None => return Err(req),
+        };
+
+        Ok(Multipart::with_body(req.body(), boundary))
+    }
+
+    /// If the given `HttpRequest` is a `multipart/{subtype}` request where `subtype` is one of
+    /// `subtypes`, such as `&["form-data", "mixed"]`, and its method is one of `methods`, return
+    /// the request body wrapped in the multipart reader. Otherwise, returns the original request.
+    ///
+    /// Subtypes are compared case-insensitively, and `&["*"]` accepts any subtype.
+    ///
+    /// See [`HttpRequest::multipart_boundary_accepting()`](trait.HttpRequest.html#method.multipart_boundary_accepting)
+    /// for more information.
+    pub fn from_request_accepting<R: HttpRequest>(req: R, methods: &[&str], subtypes: &[&str])
+                                                  -> Result<Multipart<R::Body>, R> {
+        let boundary = match req.multipart_boundary_accepting(methods, subtypes).map(String::from) {
+            Some(boundary) => boundary,
+            None => return Err(req),
+        };
+
+        Ok(Multipart::with_body(req.body(), boundary))
+    }
 }
 
 impl<B: Read> Multipart<B> {
     /// Construct a new `Multipart` with the given body reader and boundary.
     /// This will prepend the requisite `"--"` to the boundary.
     pub fn with_body<Bnd: Into<String>>(body: B, boundary: Bnd) -> Self {
+        Self::with_capacity(body, boundary, DEFAULT_BUF_SIZE)
+    }
+
+    /// Construct a new `Multipart` with the given body reader and boundary, reading the body
+    /// through a buffer of `capacity` bytes instead of
+    /// [`DEFAULT_BUF_SIZE`](constant.DEFAULT_BUF_SIZE.html).
+    ///
+    /// Smaller buffers use less memory per request, such as on embedded servers, while larger
+    /// ones mean fewer reads from the body for large uploads. The capacity is raised to twice
+    /// the length of the boundary if it is smaller.
+    pub fn with_capacity<Bnd: Into<String>>(body: B, boundary: Bnd, capacity: usize) -> Self {
         let boundary = prepend_str("--", boundary.into());
 
         debug!("Boundary: {}", boundary);
 
         Multipart { 
-            source: BoundaryReader::from_reader(body, boundary),
+            source: BoundaryReader::with_capacity(body, boundary, capacity),
             line_buf: String::new(),
+            text_buf: Vec::new(),
+            utf8: Utf8Policy::Strict,
+            temp_root: None,
+            limiter: None,
+            #[cfg(feature = "encrypt")]
+            encrypt: false,
+            #[cfg(feature = "zstd")]
+            zstd_level: None,
+            drained: false,
+            nesting: Nesting::new(NestingLimits::default()),
+            strict: Strict::default(),
         }
     }
 
+    /// Construct a new `Multipart` with the given body reader and boundary, reading the body
+    /// through a buffer taken from `pool`, which is returned to it when this is dropped.
+    ///
+    /// See [`BufferPool`](struct.BufferPool.html) for more information.
+    pub fn with_pool<Bnd: Into<String>>(body: B, boundary: Bnd, pool: &BufferPool) -> Self {
+        let boundary = prepend_str("--", boundary.into());
+
+        debug!("Boundary: {}", boundary);
+
+        Multipart {
+            source: BoundaryReader::with_pool(body, boundary, pool),
+            line_buf: String::new(),
+            text_buf: Vec::new(),
+            utf8: Utf8Policy::Strict,
+            temp_root: None,
+            limiter: None,
+            #[cfg(feature = "encrypt")]
+            encrypt: false,
+            #[cfg(feature = "zstd")]
+            zstd_level: None,
+            drained: false,
+            nesting: Nesting::new(NestingLimits::default()),
+            strict: Strict::default(),
+        }
+    }
+
+    /// Set how text fields which aren't valid UTF-8 are read.
+    ///
+    /// Default value: `Utf8Policy::Strict`
+    pub fn utf8_policy(&mut self, policy: Utf8Policy) -> &mut Self {
+        self.utf8 = policy;
+        self
+    }
+
+    /// Set the directory the `save_all*()` methods without a `dir` create their temporary
+    /// directory in, instead of the [temporary root](fn.temp_root.html).
+    pub fn temp_root<P: Into<PathBuf>>(&mut self, dir: P) -> &mut Self {
+        self.temp_root = Some(dir.into());
+        self
+    }
+
+    /// Limit how many files the `save_all*()` methods write at once with `limiter`, shared
+    /// with other requests. See [`SaveLimiter`](struct.SaveLimiter.html) for more information.
+    pub fn save_limiter(&mut self, limiter: SaveLimiter) -> &mut Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Encrypt the files saved by the `save_all*()` methods with a new ephemeral key.
+    ///
+    /// Use this for sensitive uploads, so they are never written to the temporary directory in
+    /// plaintext. The key is kept only in the returned `Entries`, so read the files with
+    /// [`Entries::open_file()`](struct.Entries.html#method.open_file); once the `Entries` are
+    /// dropped, the key is zeroed and the files can no longer be read.
+    ///
+    /// Default value: `false`
+    ///
+    /// #### Feature: `encrypt`
+    #[cfg(feature = "encrypt")]
+    pub fn encrypt(&mut self, encrypt: bool) -> &mut Self {
+        self.encrypt = encrypt;
+        self
+    }
+
+    /// Compress the files saved by the `save_all*()` methods with zstd at `level`.
+    ///
+    /// Use this to reduce the temporary disk usage of large, compressible uploads. Read the
+    /// files with [`Entries::open_file()`](struct.Entries.html#method.open_file), which
+    /// decompresses them. Files can be both compressed and [encrypted](#method.encrypt).
+    ///
+    /// Default value: none, files are saved uncompressed.
+    ///
+    /// #### Feature: `zstd`
+    #[cfg(feature = "zstd")]
+    pub fn compress(&mut self, level: i32) -> &mut Self {
+        self.zstd_level = Some(level);
+        self
+    }
+
     /// Read the next entry from this multipart request, returning a struct with the field's name and
     /// data. See `MultipartField` for more info.
     ///
     /// ##Warning: Risk of Data Loss
     /// If the previously returned entry had contents of type `MultipartField::File`,
     /// calling this again will discard any unread contents of that entry.
+    ///
+    /// Returns `Ok(None)` at a part without a `Content-Disposition: form-data` header; to read
+    /// other multipart types, such as `multipart/mixed`, use
+    /// [`read_part()`](#method.read_part) instead.
+    ///
+    /// ##Errors
+    /// If a text field isn't valid UTF-8 and the [`Utf8Policy`](enum.Utf8Policy.html) is
+    /// `Strict`, the default, with an error wrapping an [`InvalidUtf8`](struct.InvalidUtf8.html).
     pub fn read_entry(&mut self) -> io::Result<Option<MultipartField<B>>> {
         if !try!(self.consume_boundary()) {
             return Ok(None);
@@ -100,11 +413,6 @@ impl<B: Read> Multipart<B> {
         MultipartField::read_from(self)
     }
 
-    fn read_content_disposition(&mut self) -> io::Result<Option<ContentDisp>> {
-        let line = try!(self.read_line());
-        Ok(ContentDisp::read_from(line))
-    }
-
     /// Call `f` for each entry in the multipart request.
     /// 
     /// This is a substitute for Rust not supporting streaming iterators (where the return value
@@ -121,19 +429,75 @@ impl<B: Read> Multipart<B> {
         }
     }
 
-    fn read_content_type(&mut self) -> io::Result<Option<ContentType>> {
-        debug!("Read content type!");
-        let line = try!(self.read_line());
-        Ok(ContentType::read_from(line))
+    /// Unwrap the request body, along with the bytes which were read from it into the buffer
+    /// but not parsed yet.
+    ///
+    /// The rest of the body is the buffered bytes followed by what is left in the reader, so
+    /// it can be handed to other code after deciding not to parse it, or read further, e.g. for
+    /// trailers:
+    ///
+    /// ```rust
+    /// use multipart::server::Multipart;
+    /// use std::io::Read;
+    ///
+    /// let body: &[u8] = b"--boundary\r\n\
+    ///     Content-Disposition: form-data; name=\"field\"\r\n\r\n\
+    ///     value\r\n--boundary--\r\n";
+    ///
+    /// let mut multipart = Multipart::with_body(body, "boundary");
+    /// multipart.read_entry().unwrap().unwrap();
+    ///
+    /// let (rest, buffered) = multipart.into_inner();
+    /// let mut unread = Vec::new();
+    /// buffered.chain(rest).read_to_end(&mut unread).unwrap();
+    /// assert_eq!(unread, b"\r\n--boundary--\r\n");
+    /// ```
+    pub fn into_inner(self) -> (B, Vec<u8>) {
+        self.source.into_inner()
+    }
+
+    /// The boundary of this request, as passed to the constructor or parsed from the
+    /// `Content-Type` header, without the leading `--`.
+    pub fn boundary(&self) -> &str {
+        // The boundary was passed in as a `String`, with `--` prepended.
+        str::from_utf8(&self.source.boundary()[2..]).expect("boundary is not UTF-8")
+    }
+
+    /// Rebuild the `Content-Type` header value of this request:
+    /// `multipart/form-data; boundary={boundary}`, with the boundary quoted if necessary.
+    ///
+    /// Use this to pass the raw body through to another service, such as with
+    /// [`into_inner()`](#method.into_inner).
+    ///
+    /// ```rust
+    /// use multipart::server::Multipart;
+    ///
+    /// let multipart = Multipart::with_body(&b""[..], "boundary");
+    /// assert_eq!(multipart.boundary(), "boundary");
+    /// assert_eq!(multipart.content_type(), "multipart/form-data; boundary=boundary");
+    ///
+    /// let multipart = Multipart::with_body(&b""[..], "my boundary");
+    /// assert_eq!(multipart.content_type_with_subtype("mixed"), "multipart/mixed; boundary=\"my boundary\"");
+    /// ```
+    pub fn content_type(&self) -> String {
+        self.content_type_with_subtype("form-data")
+    }
+
+    /// Rebuild the `Content-Type` header value of this request with a subtype other than
+    /// `form-data`, such as for requests accepted by
+    /// [`from_request_accepting()`](#method.from_request_accepting):
+    /// `multipart/{subtype}; boundary={boundary}`.
+    pub fn content_type_with_subtype(&self, subtype: &str) -> String {
+        format!("multipart/{}; boundary={}", subtype, quote_param(self.boundary()))
     }
 
     /// Read the request fully, parsing all fields and saving all files in a new temporary
-    /// directory under the OS temporary directory. 
+    /// directory under the temporary root. 
     ///
     /// If there is an error in reading the request, returns the partial result along with the
     /// error. See [`SaveResult`](enum.saveresult.html) for more information.
     pub fn save_all(&mut self) -> SaveResult {
-        let mut entries = match Entries::new_tempdir() {
+        let mut entries = match self.new_entries(None) {
             Ok(entries) => entries,
             Err(err) => return SaveResult::Error(err),
         };
@@ -150,7 +514,7 @@ impl<B: Read> Multipart<B> {
     /// If there is an error in reading the request, returns the partial result along with the
     /// error. See [`SaveResult`](enum.saveresult.html) for more information.
     pub fn save_all_under<P: AsRef<Path>>(&mut self, dir: P) -> SaveResult {
-        let mut entries = match Entries::new_tempdir_in(dir) {
+        let mut entries = match self.new_entries(Some(dir.as_ref())) {
             Ok(entries) => entries,
             Err(err) => return SaveResult::Error(err),
         };
@@ -162,14 +526,14 @@ impl<B: Read> Multipart<B> {
     }
 
     /// Read the request fully, parsing all fields and saving all fields in a new temporary
-    /// directory under the OS temporary directory.
+    /// directory under the temporary root.
     ///
     /// Files larger than `limit` will be truncated to `limit`.
     ///
     /// If there is an error in reading the request, returns the partial result along with the
     /// error. See [`SaveResult`](enum.saveresult.html) for more information.
     pub fn save_all_limited(&mut self, limit: u64) -> SaveResult {
-        let mut entries = match Entries::new_tempdir() {
+        let mut entries = match self.new_entries(None) {
             Ok(entries) => entries,
             Err(err) => return SaveResult::Error(err),
         };
@@ -188,7 +552,7 @@ impl<B: Read> Multipart<B> {
     /// If there is an error in reading the request, returns the partial result along with the
     /// error. See [`SaveResult`](enum.saveresult.html) for more information.
     pub fn save_all_under_limited<P: AsRef<Path>>(&mut self, dir: P, limit: u64) -> SaveResult {
-        let mut entries = match Entries::new_tempdir_in(dir) {
+        let mut entries = match self.new_entries(Some(dir.as_ref())) {
             Ok(entries) => entries,
             Err(err) => return SaveResult::Error(err),
         };
@@ -199,60 +563,114 @@ impl<B: Read> Multipart<B> {
         }
     }
 
+    /// Create the entries for a `save_all*()` method, in a new temporary directory under `dir`,
+    /// or under the `temp_root()` if `dir` is `None`.
+    fn new_entries(&self, dir: Option<&Path>) -> io::Result<Entries> {
+        let entries = try!(match dir.or(self.temp_root.as_ref().map(|dir| &**dir)) {
+            Some(dir) => Entries::new_tempdir_in(dir),
+            None => Entries::new_tempdir(),
+        });
+
+        #[cfg(feature = "encrypt")]
+        let entries = if self.encrypt { try!(entries.encrypted()) } else { entries };
+
+        #[cfg(feature = "zstd")]
+        let entries = match self.zstd_level {
+            Some(level) => entries.compressed(level),
+            None => entries,
+        };
+
+        Ok(entries)
+    }
+
     fn read_to_entries(&mut self, entries: &mut Entries, limit: Option<u64>) -> io::Result<()> {
-        while let Some(field) = try!(self.read_entry()) {
-            match field.data {
-                MultipartData::File(mut file) => {
-                    let file = if let Some(limit) = limit {
-                        try!(file.save_in_limited(&entries.dir, limit))
-                    } else {
-                        try!(file.save_in(&entries.dir))
-                    };
-
-                    entries.files.insert(field.name, file);
-                },
-                MultipartData::Text(text) => {
-                    entries.fields.insert(field.name, text.into());
-                },
+        let mut storage = LocalStorage::new(entries.dir.as_path());
+
+        if let Some(ref limiter) = self.limiter {
+            storage.limit_concurrency(limiter.clone());
+        }
+
+        #[cfg(feature = "encrypt")]
+        {
+            if let Some(ref key) = entries.key {
+                storage.encrypt(key.clone());
             }
         }
 
-        Ok(())
-    } 
+        #[cfg(feature = "zstd")]
+        {
+            if let Some(level) = entries.zstd_level {
+                storage.zstd(level);
+            }
+        }
 
-    fn read_line(&mut self) -> io::Result<&str> {
-        self.line_buf.clear();
+        let files = &mut entries.files;
 
-        match self.source.read_line(&mut self.line_buf) {
-            Ok(read) => Ok(&self.line_buf[..read]),
-            Err(err) => Err(err),
-        }
+        self.store_entries(&mut storage, limit, None, &mut entries.fields, |name, file| {
+            files.insert(name, file.stored);
+        })
     }
 
-    fn read_to_string(&mut self) -> io::Result<&str> {
+    /// Read the header block of a part, up to and including the empty line which ends it,
+    /// into `line_buf`, which is reused for every part.
+    fn read_headers(&mut self) -> io::Result<&str> {
         self.line_buf.clear();
 
-        match self.source.read_to_string(&mut self.line_buf) {
-            Ok(read) => Ok(&self.line_buf[..read]),
-            Err(err) => Err(err),
+        loop {
+            let line_start = self.line_buf.len();
+            // Don't let a single unterminated line grow the buffer without bound. The empty
+            // line ending the block doesn't count towards the limit.
+            let limit = (MAX_HEADERS_SIZE + 2 - line_start) as u64;
+            let read = try!((&mut self.source).take(limit).read_line(&mut self.line_buf));
+
+            if read == 0 || &self.line_buf[line_start..] == "\r\n" {
+                break;
+            }
+
+            if self.line_buf.len() > MAX_HEADERS_SIZE {
+                return Err(ParseError::HeadersTooLarge.into());
+            }
+        }
+
+        Ok(&self.line_buf)
+    }
+
+    /// Read the rest of a text field, handling invalid UTF-8 according to the `Utf8Policy`.
+    fn read_text(&mut self, field_name: &str) -> io::Result<MultipartData<B>> {
+        self.text_buf.clear();
+        try!(self.source.read_to_end(&mut self.text_buf));
+
+        match str::from_utf8(&self.text_buf) {
+            Ok(text) => Ok(MultipartData::Text(text)),
+            Err(err) => match self.utf8 {
+                Utf8Policy::Strict => Err(InvalidUtf8 {
+                    field_name: field_name.into(),
+                    valid_up_to: err.valid_up_to(),
+                }.into()),
+                Utf8Policy::Lossy => {
+                    self.line_buf = String::from_utf8_lossy(&self.text_buf).into_owned();
+                    Ok(MultipartData::Text(&self.line_buf))
+                },
+                Utf8Policy::Raw => Ok(MultipartData::Bytes(&self.text_buf)),
+            },
         }
     }
 
     fn consume_boundary(&mut self) -> io::Result<bool> {
-        try!(self.source.consume_boundary());
+        if self.strict.is_ended() {
+            return Ok(false);
+        }
 
-        let mut out = [0; 2];
-        let _ = try!(self.source.read(&mut out));
+        let found = try!(self.source.consume_boundary());
 
-        if *b"\r\n" == out {
-            Ok(true)
-        } else {
-            if *b"--" != out {
-                warn!("Unexpected 2-bytes after boundary: {:?}", out);
-            }
+        let mut out = [0; 2];
+        let read = try!(self.source.read(&mut out));
 
-            Ok(false)
+        if *b"\r\n" != out && *b"--" != out {
+            warn!("Unexpected 2-bytes after boundary: {:?}", out);
         }
+
+        self.check_delimiter(found, &out[..read])
     }
 }
 
@@ -317,71 +735,182 @@ struct ContentType {
 
 impl ContentType {
     fn read_from(line: &str) -> Option<ContentType> {
-        const CONTENT_TYPE: &'static str = "Content-Type:";
-        const BOUNDARY: &'static str = "boundary=\"";
-
         debug!("Reading Content-Type header from line: {:?}", line);
 
-        if let Some((cont_type, after_cont_type)) = get_str_after(CONTENT_TYPE, ';', line) {
-            let content_type = read_content_type(cont_type.trim());
-
-            let boundary = get_str_after(BOUNDARY, '"', after_cont_type).map(|tup| tup.0.into());
-
-            Some(ContentType {
-                val: content_type,
-                boundary: boundary,
-            })
-        } else {
-            get_remainder_after(CONTENT_TYPE, line).map(|cont_type| {
-                let content_type = read_content_type(cont_type.trim());
-                ContentType { val: content_type, boundary: None }
-            })
-        }
+        find_header(line, "Content-Type").map(|cont_type| ContentType {
+            val: read_content_type(cont_type),
+            boundary: parse_boundary(cont_type).map(String::from),
+        })
     }
 }
 
+/// Parse a `Content-Type` value with its parameters, or without them if they are malformed.
 fn read_content_type(cont_type: &str) -> Mime {
-    cont_type.parse().ok().unwrap_or_else(::mime_guess::octet_stream)
+    cont_type.parse().ok()
+        .or_else(|| cont_type.split(';').next().and_then(|essence| essence.trim().parse().ok()))
+        .unwrap_or_else(::mime_guess::octet_stream)
+}
+
+/// Get the `charset` parameter of `content_type`, if present.
+fn charset(content_type: &Mime) -> Option<&str> {
+    content_type.get_param(Attr::Charset).map(|charset| charset.as_str())
 }
 
 struct ContentDisp {
     field_name: String,
     filename: Option<String>,
+    params: DispositionParams,
 }
 
 impl ContentDisp {
     fn read_from(line: &str) -> Option<ContentDisp> {
-        debug!("Reading Content-Disposition from line: {:?}", line);
+        read_disposition(line).map(|(field_name, filename)| ContentDisp {
+            field_name: field_name.to_owned(),
+            filename: filename.map(ToOwned::to_owned),
+            params: DispositionParams::read_from(line),
+        })
+    }
+}
+
+/// Read the field name and filename from a `Content-Disposition: form-data` header line.
+fn read_disposition(line: &str) -> Option<(&str, Option<&str>)> {
+    debug!("Reading Content-Disposition from line: {:?}", line);
 
-        if line.is_empty() {
+    if line.is_empty() {
+        return None;
+    }
+
+    const CONT_DISP: &'static str = "Content-Disposition:";
+    const NAME: &'static str = "name=\"";
+    const FILENAME: &'static str = "filename=\"";
+
+    let after_disp_type = {
+        let (disp_type, after_disp_type) = try_opt!(get_str_after(CONT_DISP, ';', line));
+        let disp_type = disp_type.trim();
+
+        if disp_type != "form-data" {
+            error!("Unexpected Content-Disposition value: {:?}", disp_type);
             return None;
         }
 
-        const CONT_DISP: &'static str = "Content-Disposition:";
-        const NAME: &'static str = "name=\"";
-        const FILENAME: &'static str = "filename=\"";
+        after_disp_type
+    };
+
+    let (field_name, after_field_name) = try_opt!(get_str_after(NAME, '"', after_disp_type));
+
+    let filename = get_str_after(FILENAME, '"', after_field_name)
+        .map(|(filename, _)| filename);
+
+    Some((field_name, filename))
+}
+
+/// The headers of a single part (field) of a multipart body.
+#[derive(Clone, Debug)]
+pub struct FieldHeaders {
+    /// The field's name from the form.
+    pub name: String,
+    /// The filename of this field, if supplied.
+    ///
+    /// ##Warning
+    /// You should treat this value as untrustworthy because it is an arbitrary string provided by
+    /// the client.
+    pub filename: Option<String>,
+    /// The `Content-Type` of this field, if supplied, including its parameters.
+    pub content_type: Option<Mime>,
+    /// The `Content-Length` of this field, if supplied; most clients don't send it.
+    pub content_length: Option<u64>,
+    /// The `Content-Encoding` of this field, if supplied, such as `gzip`.
+    pub content_encoding: Option<String>,
+    /// The deprecated file metadata parameters of the `Content-Disposition` header, if supplied.
+    pub disposition_params: DispositionParams,
+    /// The digest of this field from its `Content-MD5` or `Digest` header, if supplied.
+    ///
+    /// #### Feature: `checksum`
+    #[cfg(feature = "checksum")]
+    pub checksum: Option<Checksum>,
+}
+
+impl FieldHeaders {
+    /// Parse the header block of a part, without the terminating empty line.
+    ///
+    /// Returns `None` if it does not contain a `Content-Disposition: form-data` header
+    /// with a field name.
+    pub fn parse(headers: &str) -> Option<FieldHeaders> {
+        let mut cont_disp = None;
+        let mut content_type = None;
+        let mut content_length = None;
+        let mut content_encoding = None;
+        #[cfg(feature = "checksum")]
+        let mut checksum = None;
+
+        for line in headers.split("\r\n") {
+            if cont_disp.is_none() {
+                cont_disp = ContentDisp::read_from(line);
+
+                if cont_disp.is_some() { continue; }
+            }
 
-        let after_disp_type = {
-            let (disp_type, after_disp_type) = try_opt!(get_str_after(CONT_DISP, ';', line));
-            let disp_type = disp_type.trim();
+            if content_type.is_none() {
+                content_type = ContentType::read_from(line).map(|ct| ct.val);
+            }
 
-            if disp_type != "form-data" {
-                error!("Unexpected Content-Disposition value: {:?}", disp_type);
-                return None;
+            if content_length.is_none() {
+                content_length = read_content_length(line);
             }
 
-            after_disp_type
-        };
+            if content_encoding.is_none() {
+                content_encoding = read_content_encoding(line);
+            }
 
-        let (field_name, after_field_name) = try_opt!(get_str_after(NAME, '"', after_disp_type));
+            #[cfg(feature = "checksum")]
+            {
+                if checksum.is_none() {
+                    checksum = Checksum::read_from(line);
+                }
+            }
+        }
 
-        let filename = get_str_after(FILENAME, '"', after_field_name)
-            .map(|(filename, _)| filename.to_owned());
+        let cont_disp = try_opt!(cont_disp);
 
-        Some(ContentDisp { field_name: field_name.to_owned(), filename: filename })
+        Some(FieldHeaders {
+            name: cont_disp.field_name,
+            filename: cont_disp.filename,
+            content_type: content_type,
+            content_length: content_length,
+            content_encoding: content_encoding,
+            disposition_params: cont_disp.params,
+            #[cfg(feature = "checksum")]
+            checksum: checksum,
+        })
+    }
+
+    /// The `charset` parameter of the `Content-Type` of this field, if supplied, such as `utf-8`.
+    pub fn charset(&self) -> Option<&str> {
+        self.content_type.as_ref().and_then(charset)
     }
 }
 
+fn read_content_length(line: &str) -> Option<u64> {
+    find_header(line, "Content-Length").and_then(|len| len.parse().ok())
+}
+
+fn read_content_encoding(line: &str) -> Option<String> {
+    find_header(line, "Content-Encoding").map(String::from)
+}
+
+/// Get the value of the header `name` in a header block, matched case-insensitively.
+fn find_header<'a>(headers: &'a str, name: &str) -> Option<&'a str> {
+    headers.split("\r\n").filter_map(|line| {
+        let colon = try_opt!(line.find(':'));
+
+        if line[..colon].trim().eq_ignore_ascii_case(name) {
+            Some(line[colon + 1..].trim())
+        } else {
+            None
+        }
+    }).next()
+}
+
 /// Get the string after `needle` in `haystack`, stopping before `end_val_delim`
 fn get_str_after<'a>(needle: &str, end_val_delim: char, haystack: &'a str) -> Option<(&'a str, &'a str)> {
     let val_start_idx = try_opt!(haystack.find(needle)) + needle.len();
@@ -389,12 +918,6 @@ fn get_str_after<'a>(needle: &str, end_val_delim: char, haystack: &'a str) -> Op
     Some((&haystack[val_start_idx..val_end_idx], &haystack[val_end_idx..]))
 }
 
-/// Get everything after `needle` in `haystack`
-fn get_remainder_after<'a>(needle: &str, haystack: &'a str) -> Option<(&'a str)> {
-    let val_start_idx = try_opt!(haystack.find(needle)) + needle.len();
-    Some(&haystack[val_start_idx..])
-}
-
 /// A server-side HTTP request that may or may not be multipart.
 ///
 /// May be implemented by mutable references if providing the request or body by-value is
@@ -409,10 +932,68 @@ pub trait HttpRequest {
     /// `Content-Type: multipart/form-data; boundary={boundary}`.
     fn multipart_boundary(&self) -> Option<&str>;
 
+    /// Get the boundary string of this request if its method is one of `methods`, which are
+    /// case-sensitive, and it has the `Content-Type` header set to `multipart/form-data`.
+    ///
+    /// The implementations in this crate check the method against `methods`. The default
+    /// implementation can't, so it returns `multipart_boundary()` if `methods` contains
+    /// `"POST"`, and `None` otherwise.
+    fn multipart_boundary_for(&self, methods: &[&str]) -> Option<&str> {
+        if methods.contains(&"POST") {
+            self.multipart_boundary()
+        } else {
+            None
+        }
+    }
+
+    /// Get the boundary string of this request if its method is one of `methods` and it has the
+    /// `Content-Type` header set to `multipart/{subtype}`, where `subtype` is one of `subtypes`
+    /// (case-insensitive) or `subtypes` contains `"*"`.
+    ///
+    /// The implementations in this crate check the subtype against `subtypes`. The default
+    /// implementation can't, so it returns `multipart_boundary_for(methods)` if `subtypes`
+    /// accepts `form-data`, and `None` otherwise.
+    fn multipart_boundary_accepting(&self, methods: &[&str], subtypes: &[&str]) -> Option<&str> {
+        if accepts_subtype(subtypes, "form-data") {
+            self.multipart_boundary_for(methods)
+        } else {
+            None
+        }
+    }
+
     /// Return the request body for reading.
     fn body(self) -> Self::Body;
 }
 
+/// Quote `val` for use as a header parameter value if it isn't a valid token (RFC 2045).
+fn quote_param(val: &str) -> Cow<str> {
+    let is_token_char = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`{|}~".contains(c);
+
+    if !val.is_empty() && val.chars().all(is_token_char) {
+        return Cow::Borrowed(val);
+    }
+
+    let mut quoted = String::with_capacity(val.len() + 2);
+    quoted.push('"');
+
+    for c in val.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+
+        quoted.push(c);
+    }
+
+    quoted.push('"');
+    Cow::Owned(quoted)
+}
+
+/// Returns `true` if the `multipart/*` subtype `sub` is one of `subtypes`, or `subtypes`
+/// contains `"*"`.
+pub(crate) fn accepts_subtype(subtypes: &[&str], sub: &str) -> bool {
+    subtypes.iter().any(|accepted| *accepted == "*" || accepted.eq_ignore_ascii_case(sub))
+}
+
 /// A field in a multipart request. May be either text or a binary stream (file).
 #[derive(Debug)]
 pub struct MultipartField<'a, B: 'a> {
@@ -420,38 +1001,47 @@ pub struct MultipartField<'a, B: 'a> {
     pub name: String,
     /// The data of the field. Can be text or binary.
     pub data: MultipartData<'a, B>,
+    /// The headers the field was read from, for `PartInspector`s.
+    headers: FieldHeaders,
 }
 
 impl<'a, B: Read + 'a> MultipartField<'a, B> {
     fn read_from(multipart: &'a mut Multipart<B>) -> io::Result<Option<MultipartField<'a, B>>> {
-        let cont_disp = match multipart.read_content_disposition() {
-            Ok(Some(cont_disp)) => cont_disp,
-            Ok(None) => return Ok(None),
-            Err(err) => return Err(err),
-        };        
-
-        let data = match try!(multipart.read_content_type()) {
-            Some(content_type) => {
-                let _ = try!(multipart.read_line()); // Consume empty line
-                MultipartData::File(
-                    MultipartFile::from_stream(
-                        cont_disp.filename, 
-                        content_type.val,
-                        &mut multipart.source,
-                    )
-                 )
-            },
-            None => {
-                // Empty line consumed by read_content_type()
-                let text = try!(multipart.read_to_string()); 
-                MultipartData::Text(&text)
+        try!(multipart.read_headers());
+        try!(multipart.check_headers(&multipart.line_buf));
+
+        let headers = match FieldHeaders::parse(&multipart.line_buf) {
+            Some(headers) => headers,
+            None => return Ok(None),
+        };
+
+        let data = match headers.content_type {
+            Some(ref content_type) => {
+                let mut file = MultipartFile::from_stream(
+                    headers.filename.clone(),
+                    content_type.clone(),
+                    headers.content_length,
+                    headers.content_encoding.clone(),
+                    &mut multipart.source,
+                );
+
+                file.disposition_params = headers.disposition_params;
+
+                #[cfg(feature = "checksum")]
+                {
+                    file.verifier = headers.checksum.clone().map(Verifier::new);
+                }
+
+                MultipartData::File(file)
             },
+            None => try!(multipart.read_text(&headers.name)),
         };
 
         Ok(Some(
             MultipartField {
-                name: cont_disp.field_name,
+                name: headers.name.clone(),
                 data: data,
+                headers: headers,
             }
         ))
     }
@@ -462,6 +1052,8 @@ impl<'a, B: Read + 'a> MultipartField<'a, B> {
 pub enum MultipartData<'a, B: 'a> {
     /// The field's payload is a text string.
     Text(&'a str),
+    /// The field's payload is text which isn't valid UTF-8, read with `Utf8Policy::Raw`.
+    Bytes(&'a [u8]),
     /// The field's payload is a binary stream (file).
     File(MultipartFile<'a, B>),
     // TODO: Support multiple files per field (nested boundaries)
@@ -477,6 +1069,16 @@ impl<'a, B> MultipartData<'a, B> {
         }
     }
 
+    /// Borrow this payload as the raw bytes of a text field, whether or not they are valid
+    /// UTF-8.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match *self {
+            MultipartData::Text(ref s) => Some(s.as_bytes()),
+            MultipartData::Bytes(ref bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
     /// Borrow this payload as a file field, if possible.
     /// Mutably borrows so the contents can be read.
     pub fn as_file(&mut self) -> Option<&mut MultipartFile<'a, B>> {
@@ -499,17 +1101,42 @@ impl<'a, B> MultipartData<'a, B> {
 pub struct MultipartFile<'a, B: 'a> {
     filename: Option<String>,
     content_type: Mime,
+    content_length: Option<u64>,
+    content_encoding: Option<String>,
+    disposition_params: DispositionParams,
     stream: &'a mut BoundaryReader<B>,
+    /// Decodes the file if it has a supported `Content-Encoding`.
+    #[cfg(feature = "flate2")]
+    decoder: Option<decode::Decoder>,
+    /// Taken when the end of the file is read.
+    #[cfg(feature = "checksum")]
+    verifier: Option<Verifier>,
+    /// Set when the type is first detected.
+    #[cfg(feature = "infer")]
+    detected_type: Option<Option<Mime>>,
 }
 
 impl<'a, B: Read> MultipartFile<'a, B> {
     fn from_stream(filename: Option<String>, 
                    content_type: Mime, 
+                   content_length: Option<u64>,
+                   content_encoding: Option<String>,
                    stream: &'a mut BoundaryReader<B>) -> MultipartFile<'a, B> {
         MultipartFile {
             filename: filename,
             content_type: content_type,
+            content_length: content_length,
+            #[cfg(feature = "flate2")]
+            decoder: content_encoding.as_ref()
+                .filter(|encoding| decode::is_supported(encoding))
+                .map(|_| decode::Decoder::new()),
+            content_encoding: content_encoding,
+            disposition_params: DispositionParams::default(),
             stream: stream,
+            #[cfg(feature = "checksum")]
+            verifier: None,
+            #[cfg(feature = "infer")]
+            detected_type: None,
         }    
     }
 
@@ -519,7 +1146,7 @@ impl<'a, B: Read> MultipartFile<'a, B> {
     ///
     /// Retries when `io::Error::kind() == io::ErrorKind::Interrupted`.
     pub fn save_to<W: Write>(&mut self, mut out: W) -> io::Result<u64> {
-        retry_on_interrupt(|| io::copy(self.stream, &mut out))
+        retry_on_interrupt(|| io::copy(self, &mut out))
     }
 
     /// Save this file to the given output stream, **truncated** to `limit` 
@@ -529,7 +1156,7 @@ impl<'a, B: Read> MultipartFile<'a, B> {
     ///
     /// Retries when `io::Error::kind() == io::ErrorKind::Interrupted`.
     pub fn save_to_limited<W: Write>(&mut self, mut out: W, limit: u64) -> io::Result<u64> {
-        retry_on_interrupt(|| io::copy(&mut self.stream.take(limit), &mut out))
+        retry_on_interrupt(|| io::copy(&mut self.take(limit), &mut out))
     }
 
     /// Save this file to `path`.
@@ -539,13 +1166,16 @@ impl<'a, B: Read> MultipartFile<'a, B> {
     /// Retries when `io::Error::kind() == io::ErrorKind::Interrupted`.
     pub fn save_as<P: Into<PathBuf>>(&mut self, path: P) -> io::Result<SavedFile> {
         let path = path.into();
-        let file = try!(create_full_path(&path)); 
-        let size = try!(self.save_to(file));
+        let mut file = HashWriter::new(try!(create_full_path(&path)));
+        let size = try!(self.save_to(&mut file));
 
         Ok(SavedFile {
             path: path,
             filename: self.filename.clone(),
             size: size,
+            sha256: file.finish(),
+            compressed_size: None,
+            extension: None,
         })
     }
 
@@ -571,13 +1201,16 @@ impl<'a, B: Read> MultipartFile<'a, B> {
     /// Retries when `io::Error::kind() == io::ErrorKind::Interrupted`.
     pub fn save_as_limited<P: Into<PathBuf>>(&mut self, path: P, limit: u64) -> io::Result<SavedFile> {
         let path = path.into();
-        let file = try!(create_full_path(&path));
-        let size = try!(self.save_to_limited(file, limit));
+        let mut file = HashWriter::new(try!(create_full_path(&path)));
+        let size = try!(self.save_to_limited(&mut file, limit));
         
         Ok(SavedFile {
             path: path,
             filename: self.filename.clone(),
             size: size,
+            sha256: file.finish(),
+            compressed_size: None,
+            extension: None,
         })
     }
     
@@ -596,6 +1229,89 @@ impl<'a, B: Read> MultipartFile<'a, B> {
         self.save_as_limited(path, limit)
     } 
 
+    /// Save this file to `path` by reading it into a memory map of the file, which is allocated
+    /// up front with the size given by the part's `Content-Length` header.
+    ///
+    /// This avoids a `write()` call per buffer for multi-gigabyte files, on platforms where
+    /// mapping files is faster than buffered writes. If the part has no `Content-Length`,
+    /// this is the same as [`save_as_limited()`](#method.save_as_limited).
+    ///
+    /// Any missing directories in the `path` will be created.
+    ///
+    /// ##Errors
+    /// If the part's `Content-Length` is greater than `max_len`, returns an error of kind
+    /// `io::ErrorKind::InvalidData` without creating the file, as the header is sent by the
+    /// client and the file would be allocated with that size.
+    ///
+    /// If the part is longer than its `Content-Length`, returns an error of kind
+    /// `io::ErrorKind::InvalidData` and leaves the file with the data which fit. If it is
+    /// shorter, the file is truncated to the data that was read.
+    ///
+    /// #### Feature: `mmap`
+    #[cfg(feature = "mmap")]
+    pub fn save_as_mmap<P: Into<PathBuf>>(&mut self, path: P, max_len: u64) -> io::Result<SavedFile> {
+        let len = match self.content_length {
+            Some(len) if !self.is_decoded() => len,
+            _ => return self.save_as_limited(path, max_len),
+        };
+
+        if len > max_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "file field's Content-Length is greater than the limit"
+            ));
+        }
+
+        let path = path.into();
+        // Mapping the file for writing requires it to be opened for reading too.
+        let mut options = fs::OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(true);
+
+        let file = try!(open_full_path(&path, &options));
+        try!(file.set_len(len));
+
+        let mut hasher = hash::Hasher::new();
+        let size = try!(mmap::read_into_file(self, &file, len, &mut hasher));
+
+        if size < len {
+            try!(file.set_len(size));
+        } else if !try!(self.fill_buf()).is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "file field is longer than its Content-Length"
+            ));
+        }
+
+        Ok(SavedFile {
+            path: path,
+            filename: self.filename.clone(),
+            size: size,
+            sha256: hasher.finish(),
+            compressed_size: None,
+            extension: None,
+        })
+    }
+
+    /// Save this file in the directory pointed at by `dir`, using a random alphanumeric string
+    /// as the filename, by reading it into a memory map of the file.
+    ///
+    /// See [`save_as_mmap()`](#method.save_as_mmap) for more information.
+    ///
+    /// #### Feature: `mmap`
+    #[cfg(feature = "mmap")]
+    pub fn save_in_mmap<P: AsRef<Path>>(&mut self, dir: P, max_len: u64) -> io::Result<SavedFile> {
+        let path = dir.as_ref().join(::random_alphanumeric(RANDOM_FILENAME_LEN));
+        self.save_as_mmap(path, max_len)
+    }
+
+    /// Check the data read against the checksum of this file, if it has one and it hasn't been
+    /// checked yet.
+    #[cfg(feature = "checksum")]
+    fn verify(&mut self) -> io::Result<()> {
+        match self.verifier.take() {
+            Some(verifier) => verifier.verify(),
+            None => Ok(()),
+        }
+    }
+
     /// Get the filename of this entry, if supplied.
     ///
     /// ##Warning
@@ -611,25 +1327,352 @@ impl<'a, B: Read> MultipartFile<'a, B> {
     pub fn content_type(&self) -> &Mime {
         &self.content_type    
     }
+
+    /// The `charset` parameter of the `Content-Type` of this file, if supplied, such as `utf-8`.
+    ///
+    /// ```rust
+    /// # extern crate mime;
+    /// # extern crate multipart;
+    /// use mime::{Mime, SubLevel, TopLevel};
+    ///
+    /// use multipart::server::Multipart;
+    ///
+    /// # fn main() {
+    /// let body: &[u8] = b"--boundary\r\n\
+    ///     Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+    ///     Content-Type: text/plain; charset=iso-8859-1\r\n\r\n\
+    ///     contents\r\n--boundary--\r\n";
+    ///
+    /// let mut multipart = Multipart::with_body(body, "boundary");
+    /// let mut field = multipart.read_entry().unwrap().unwrap();
+    /// let file = field.data.as_file().unwrap();
+    ///
+    /// match *file.content_type() {
+    ///     Mime(TopLevel::Text, SubLevel::Plain, _) => assert_eq!(file.charset(), Some("iso-8859-1")),
+    ///     _ => panic!("not text/plain"),
+    /// }
+    /// # }
+    /// ```
+    pub fn charset(&self) -> Option<&str> {
+        charset(&self.content_type)
+    }
+
+    /// Detect the type of this file from its magic bytes, if it is a known binary format, to
+    /// compare to its declared [`content_type()`](#method.content_type).
+    ///
+    /// The start of the file is only peeked at, not consumed. If the file is decoded, the type
+    /// of the decoded data is detected.
+    ///
+    /// ##Warning
+    /// This must be called before any of the file has been read, or the type of the data after
+    /// that point is detected instead. The result is kept for later calls.
+    ///
+    /// #### Feature: `infer`
+    #[cfg(feature = "infer")]
+    pub fn detected_type(&mut self) -> io::Result<Option<&Mime>> {
+        if self.detected_type.is_none() {
+            let detected = sniff::detect(try!(self.fill_buf()));
+            self.detected_type = Some(detected);
+        }
+
+        Ok(self.detected_type.as_ref().and_then(Option::as_ref))
+    }
+
+    /// Returns `true` if the [detected type](#method.detected_type) of this file contradicts
+    /// its declared `Content-Type`, such as an executable claiming to be `image/png`.
+    ///
+    /// Files whose type can't be detected, and files declared as `application/octet-stream`,
+    /// never mismatch.
+    ///
+    /// #### Feature: `infer`
+    #[cfg(feature = "infer")]
+    pub fn type_mismatch(&mut self) -> io::Result<bool> {
+        let detected = try!(self.detected_type()).cloned();
+        Ok(detected.map_or(false, |detected| sniff::is_mismatch(&self.content_type, &detected)))
+    }
+
+    /// Get the size of this file given in its `Content-Length` header, if supplied by the client.
+    ///
+    /// If the file is decoded, this is its size before decoding.
+    ///
+    /// ##Warning
+    /// This is not checked against the file's contents until it is read.
+    pub fn content_length(&self) -> Option<u64> {
+        self.content_length
+    }
+
+    /// Get the deprecated `creation-date`, `modification-date` and `size` parameters of the
+    /// `Content-Disposition` header of this file, if supplied by the client.
+    pub fn disposition_params(&self) -> &DispositionParams {
+        &self.disposition_params
+    }
+
+    /// Get the `Content-Encoding` of this file as sent by the client, if supplied.
+    ///
+    /// With the `flate2` feature, files encoded with `gzip` are decoded transparently as they're
+    /// read and saved; see [`is_decoded()`](#method.is_decoded). Other encodings are passed
+    /// through as sent.
+    pub fn content_encoding(&self) -> Option<&str> {
+        self.content_encoding.as_ref().map(|s| &**s)
+    }
+
+    /// Returns `true` if this file is decoded according to its `Content-Encoding` as it's read.
+    #[cfg(feature = "flate2")]
+    pub fn is_decoded(&self) -> bool {
+        self.decoder.is_some()
+    }
+
+    /// Returns `true` if this file is decoded according to its `Content-Encoding` as it's read;
+    /// always `false` without the `flate2` feature.
+    #[cfg(not(feature = "flate2"))]
+    pub fn is_decoded(&self) -> bool {
+        false
+    }
+
+    /// Fill the buffer of decoded data, if it is empty, by decoding the file's contents.
+    #[cfg(feature = "flate2")]
+    fn fill_decoded(&mut self) -> io::Result<&[u8]> {
+        let decoder = self.decoder.as_mut().expect("file is not decoded");
+
+        while decoder.decoded().is_empty() && !decoder.is_done() {
+            let used = {
+                let input = try!(self.stream.fill_buf());
+
+                if input.is_empty() {
+                    #[cfg(feature = "checksum")]
+                    {
+                        if let Some(verifier) = self.verifier.take() {
+                            try!(verifier.verify());
+                        }
+                    }
+
+                    try!(decoder.finish());
+                    continue;
+                }
+
+                let used = try!(decoder.decode(input));
+
+                #[cfg(feature = "checksum")]
+                {
+                    if let Some(ref mut verifier) = self.verifier {
+                        verifier.update(&input[..used]);
+                    }
+                }
+
+                used
+            };
+
+            self.stream.consume(used);
+        }
+
+        Ok(decoder.decoded())
+    }
 }
 
+#[cfg(all(feature = "splice", target_os = "linux"))]
+impl<'a, B: Read + ::std::os::unix::io::AsRawFd> MultipartFile<'a, B> {
+    /// Save this file to `path`, moving its contents from the request body to the file with
+    /// `splice()` where possible, so the bulk of a large upload isn't copied through userspace
+    /// on its way to the file.
+    ///
+    /// This requires the size of the file, given by its part's `Content-Length` header, and a
+    /// request body which is a socket or pipe, such as a `TcpStream`. If the part has no
+    /// `Content-Length`, this is the same as [`save_as()`](#method.save_as); if the body doesn't
+    /// support `splice()`, the file is copied through userspace as usual.
+    ///
+    /// Any missing directories in the `path` will be created.
+    ///
+    /// ##Warning
+    /// This must be called before any of the file has been read. The kernel moves the data up
+    /// to the `Content-Length` without it being searched for the boundary, so the file is read
+    /// back afterwards to check that a client didn't claim a length running over the boundary.
+    ///
+    /// ##Errors
+    /// If the part is shorter or longer than its `Content-Length`, or the data up to it
+    /// contains the boundary, returns an error of kind `io::ErrorKind::InvalidData`, and the
+    /// file is left with the data that was read. As the data following the boundary has been
+    /// moved to the file, the rest of the request can't be read after the latter error.
+    ///
+    /// #### Feature: `splice` (Linux only)
+    pub fn save_as_splice<P: Into<PathBuf>>(&mut self, path: P) -> io::Result<SavedFile> {
+        let len = match self.content_length {
+            Some(len) if !self.is_decoded() => len,
+            _ => return self.save_as(path),
+        };
+
+        let path = path.into();
+        // Readable, to check it for the boundary.
+        let mut file = try!(open_full_path(&path, fs::OpenOptions::new()
+            .read(true).write(true).create(true).truncate(true)));
+
+        // Write out what has already been read into the buffer first.
+        let buffered = {
+            let buffered = self.stream.buffered();
+            let buffered = &buffered[..::std::cmp::min(buffered.len() as u64, len) as usize];
+            try!(file.write_all(buffered));
+            buffered.len()
+        };
+
+        self.stream.consume_unchecked(buffered);
+
+        let mut size = buffered as u64;
+
+        if size < len {
+            let fd = Borrow::<B>::borrow(&*self.stream).as_raw_fd();
+
+            size += match try!(splice::splice_to_file(fd, &file, len - size)) {
+                Some(moved) => moved,
+                None => try!(self.save_to_limited(&mut file, len - size)),
+            };
+        }
+
+        if size < len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "file field is shorter than its Content-Length"
+            ));
+        }
+
+        if try!(splice::file_contains(&mut file, self.stream.boundary())) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "file field's Content-Length runs over the boundary"
+            ));
+        }
+
+        if !try!(self.stream.fill_buf()).is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "file field is longer than its Content-Length"
+            ));
+        }
+
+        Ok(SavedFile {
+            path: path,
+            filename: self.filename.clone(),
+            size: size,
+            // The data doesn't pass through this process.
+            sha256: None,
+            compressed_size: None,
+            extension: None,
+        })
+    }
+
+    /// Save this file in the directory pointed at by `dir`, using a random alphanumeric string
+    /// as the filename, moving its contents with `splice()` where possible.
+    ///
+    /// See [`save_as_splice()`](#method.save_as_splice) for more information.
+    ///
+    /// #### Feature: `splice` (Linux only)
+    pub fn save_in_splice<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<SavedFile> {
+        let path = dir.as_ref().join(::random_alphanumeric(RANDOM_FILENAME_LEN));
+        self.save_as_splice(path)
+    }
+}
+
+/// With the `checksum` feature, reading the end of a file which has a `Content-MD5` or `Digest`
+/// header returns an error of kind `io::ErrorKind::InvalidData`, wrapping a
+/// [`ChecksumMismatch`](struct.ChecksumMismatch.html), if the data read doesn't match it. Files
+/// which are only read in part, or are saved with `save_as_splice()`, aren't verified.
 impl<'a, B: Read> Read for MultipartFile<'a, B> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>{
-        self.stream.read(buf)
+        if self.is_decoded() {
+            let read = {
+                let mut decoded = try!(self.fill_buf());
+                try!(decoded.read(buf))
+            };
+
+            self.consume(read);
+            return Ok(read);
+        }
+
+        let read = try!(self.stream.read(buf));
+
+        #[cfg(feature = "checksum")]
+        {
+            if let Some(ref mut verifier) = self.verifier {
+                verifier.update(&buf[..read]);
+            }
+
+            if read == 0 && !buf.is_empty() {
+                try!(self.verify());
+            }
+        }
+
+        Ok(read)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+        if self.is_decoded() {
+            return match bufs.iter_mut().find(|buf| !buf.is_empty()) {
+                Some(buf) => self.read(buf),
+                None => Ok(0),
+            };
+        }
+
+        let read = try!(self.stream.read_vectored(bufs));
+
+        #[cfg(feature = "checksum")]
+        {
+            if let Some(ref mut verifier) = self.verifier {
+                let mut left = read;
+
+                for buf in bufs.iter() {
+                    let len = ::std::cmp::min(left, buf.len());
+                    verifier.update(&buf[..len]);
+                    left -= len;
+                }
+            }
+
+            if read == 0 && bufs.iter().any(|buf| !buf.is_empty()) {
+                try!(self.verify());
+            }
+        }
+
+        Ok(read)
     }
 }
 
 impl<'a, B: Read> BufRead for MultipartFile<'a, B> {
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        #[cfg(feature = "flate2")]
+        {
+            if self.decoder.is_some() {
+                return self.fill_decoded();
+            }
+        }
+
+        #[cfg(feature = "checksum")]
+        {
+            if try!(self.stream.fill_buf()).is_empty() {
+                try!(self.verify());
+            }
+        }
+
         self.stream.fill_buf()
     }
 
     fn consume(&mut self, amt: usize) {
+        #[cfg(feature = "flate2")]
+        {
+            if let Some(ref mut decoder) = self.decoder {
+                return decoder.consume(amt);
+            }
+        }
+
+        #[cfg(feature = "checksum")]
+        {
+            if let Some(ref mut verifier) = self.verifier {
+                let data = self.stream.to_boundary();
+                verifier.update(&data[..::std::cmp::min(amt, data.len())]);
+            }
+        }
+
         self.stream.consume(amt)
     }
 }
 
 /// A result of `Multipart::save_all()`.
+///
+/// `Entries` and `SavedFile` are `Send` and `Sync`, so the results of a request can be moved to
+/// another thread, or shared read-only between threads in an `Arc`.
 #[derive(Debug)]
 pub struct Entries {
     /// The text fields of the multipart request, mapped by field name -> value.
@@ -638,6 +1681,12 @@ pub struct Entries {
     pub files: HashMap<String, SavedFile>,
     /// The directory the files in this request were saved under; may be temporary or permanent.
     pub dir: SaveDir,
+    /// The key the files were encrypted with, if any.
+    #[cfg(feature = "encrypt")]
+    key: Option<EncryptionKey>,
+    /// The zstd level the files were compressed at, if any.
+    #[cfg(feature = "zstd")]
+    zstd_level: Option<i32>,
 }
 
 impl Entries {
@@ -654,14 +1703,101 @@ impl Entries {
             fields: HashMap::new(),
             files: HashMap::new(),
             dir: SaveDir::Temp(tempdir),
+            #[cfg(feature = "encrypt")]
+            key: None,
+            #[cfg(feature = "zstd")]
+            zstd_level: None,
         }
     }
+
+    #[cfg(feature = "zstd")]
+    fn compressed(mut self, level: i32) -> Entries {
+        self.zstd_level = Some(level);
+        self
+    }
+
+    #[cfg(feature = "encrypt")]
+    fn encrypted(mut self) -> io::Result<Entries> {
+        self.key = Some(try!(EncryptionKey::generate()));
+        Ok(self)
+    }
+
+    /// Set what happens to the directory of the saved files when these entries are dropped.
+    ///
+    /// By default, a temporary directory created by a `save_all*()` method is deleted and a
+    /// permanent one is kept.
+    pub fn drop_policy(&mut self, policy: DropPolicy) -> &mut Self {
+        let path = mem::replace(&mut self.dir, SaveDir::Perm(PathBuf::new())).into_path();
+
+        self.dir = match policy {
+            DropPolicy::DeleteOnDrop => SaveDir::Temp(TempDir::adopt(path)),
+            DropPolicy::Keep => SaveDir::Perm(path),
+            DropPolicy::MoveTo(dest) => {
+                let mut tempdir = TempDir::adopt(path);
+                tempdir.move_on_drop(dest);
+                SaveDir::Temp(tempdir)
+            },
+        };
+
+        self
+    }
+
+    /// Move the directory of the saved files to `dest` now with a single atomic rename, and keep
+    /// it when these entries are dropped. The paths in `files` are updated.
+    ///
+    /// `dest` must be on the same filesystem and must not exist or be an empty directory.
+    pub fn persist<P: Into<PathBuf>>(&mut self, dest: P) -> io::Result<()> {
+        let dest = dest.into();
+        try!(fs::rename(self.dir.as_path(), &dest));
+
+        for file in self.files.values_mut() {
+            let moved = file.path.strip_prefix(self.dir.as_path()).map(|rel| dest.join(rel));
+
+            if let Ok(moved) = moved {
+                file.path = moved;
+            }
+        }
+
+        // Disarm the old directory, which no longer exists.
+        mem::replace(&mut self.dir, SaveDir::Perm(dest)).into_path();
+        Ok(())
+    }
+
+    /// Returns `true` if the files were encrypted as they were saved, as set by
+    /// [`Multipart::encrypt()`](struct.Multipart.html#method.encrypt).
+    ///
+    /// #### Feature: `encrypt`
+    #[cfg(feature = "encrypt")]
+    pub fn is_encrypted(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Open one of the saved files for reading, decrypting and decompressing it if it was
+    /// encrypted or compressed.
+    ///
+    /// #### Feature: `encrypt` or `zstd`
+    #[cfg(any(feature = "encrypt", feature = "zstd"))]
+    pub fn open_file(&self, file: &SavedFile) -> io::Result<Box<Read + Send>> {
+        let file = try!(File::open(&file.path));
+
+        #[cfg(feature = "encrypt")]
+        let file = try!(Decrypt::new(file, self.key.as_ref()));
+
+        #[cfg(feature = "zstd")]
+        {
+            if self.zstd_level.is_some() {
+                return Ok(Box::new(try!(::zstd::Decoder::new(file))));
+            }
+        }
+
+        Ok(Box::new(file))
+    }
 }
 
 /// The save directory for `Entries`. May be temporary (delete-on-drop) or permanent.
 pub enum SaveDir {
     /// This directory is temporary and will be deleted, along with its contents, when this wrapper
-    /// is dropped.
+    /// is dropped. See [`TempDir`](struct.TempDir.html) for how it is created.
     Temp(TempDir),
     /// This directory is permanent and will be left on the filesystem when this wrapper is dropped.
     Perm(PathBuf),
@@ -738,8 +1874,6 @@ impl AsRef<Path> for SaveDir {
     }
 }
 
-// grrr, no Debug impl for TempDir, can't derive
-// FIXME when tempdir > 0.3.4 is released (Debug PR landed 3/3/2016) 
 impl fmt::Debug for SaveDir {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::SaveDir::*;
@@ -767,6 +1901,24 @@ pub struct SavedFile {
 
     /// The number of bytes written to the disk; may be truncated.
     pub size: u64,
+
+    /// The lowercase hex SHA-256 digest of the saved data, computed while it was written.
+    ///
+    /// Always `None` without the `sha2` feature, and for files saved with `save_as_splice()`,
+    /// whose data is moved without passing through this process.
+    pub sha256: Option<String>,
+
+    /// If this file was decompressed before it was saved, its size as uploaded; `size` is its
+    /// size once decompressed.
+    ///
+    /// See [`LocalStorage::gunzip()`](storage/struct.LocalStorage.html#method.gunzip).
+    pub compressed_size: Option<u64>,
+
+    /// The extension, without the dot, which was appended to the generated name of this file
+    /// for its `Content-Type`, if any.
+    ///
+    /// See [`LocalStorage::extensions()`](storage/struct.LocalStorage.html#method.extensions).
+    pub extension: Option<String>,
 }
 
 fn retry_on_interrupt<F, T>(mut do_fn: F) -> io::Result<T> where F: FnMut() -> io::Result<T> {
@@ -781,25 +1933,15 @@ fn retry_on_interrupt<F, T>(mut do_fn: F) -> io::Result<T> where F: FnMut() -> i
 } 
 
 fn prepend_str(prefix: &str, mut string: String) -> String {
-    string.reserve(prefix.len());
-
-    unsafe {
-        let bytes = string.as_mut_vec();
-
-        // This addition is safe because it was already done in `String::reserve()`
-        // which would have panicked if it overflowed.
-        let old_len = bytes.len();
-        let new_len = bytes.len() + prefix.len();
-        bytes.set_len(new_len);
-
-        ptr::copy(bytes.as_ptr(), bytes[prefix.len()..].as_mut_ptr(), old_len);
-        ptr::copy(prefix.as_ptr(), bytes.as_mut_ptr(), prefix.len());
-    }
-
+    string.insert_str(0, prefix);
     string
 }
 
 fn create_full_path(path: &Path) -> io::Result<File> {
+    open_full_path(path, fs::OpenOptions::new().write(true).create(true).truncate(true))
+}
+
+fn open_full_path(path: &Path, options: &fs::OpenOptions) -> io::Result<File> {
     if let Some(parent) = path.parent() {
         try!(fs::create_dir_all(parent));
     } else {
@@ -807,5 +1949,247 @@ fn create_full_path(path: &Path) -> io::Result<File> {
         warn!("Attempting to save file in what looks like a root directory. File path: {:?}", path);
     }
 
-    File::create(&path)
+    options.open(&path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::fs::File;
+    use std::net::TcpStream;
+
+    fn assert_send<T: Send>() {}
+    fn assert_sync<T: Sync>() {}
+
+    #[test]
+    fn test_send_sync() {
+        assert_send::<Multipart<TcpStream>>();
+        assert_sync::<Multipart<TcpStream>>();
+        assert_send::<Multipart<&'static [u8]>>();
+        assert_send::<MultipartField<'static, TcpStream>>();
+        assert_send::<MultipartFile<'static, TcpStream>>();
+        assert_send::<MultipartPart<'static, TcpStream>>();
+        assert_send::<DrainOnDrop<TcpStream>>();
+        assert_send::<ResponseWriter<File>>();
+        assert_send::<ByteRangesWriter>();
+        assert_send::<TempDir>();
+
+        assert_send::<Entries>();
+        assert_sync::<Entries>();
+        assert_send::<SaveResult>();
+        assert_sync::<SaveResult>();
+        assert_send::<SavedFile>();
+        assert_sync::<SavedFile>();
+        assert_send::<SaveDir>();
+        assert_sync::<SaveDir>();
+        assert_send::<SaveLimiter>();
+        assert_sync::<SaveLimiter>();
+        assert_send::<SavePermit>();
+        assert_send::<BufferPool>();
+        assert_sync::<BufferPool>();
+        assert_send::<storage::StoredEntries<storage::LocalFile>>();
+        assert_sync::<storage::StoredEntries<storage::LocalFile>>();
+        assert_send::<borrowed::BorrowedField<'static>>();
+        assert_sync::<borrowed::BorrowedField<'static>>();
+        assert_send::<push::PushParser>();
+        assert_sync::<push::PushParser>();
+        assert_send::<nonblocking::NonBlockingMultipart<TcpStream>>();
+    }
+
+    #[test]
+    fn test_content_type_params() {
+        let headers = FieldHeaders::parse("Content-Disposition: form-data; name=\"file\"\r\n\
+            Content-Type: text/plain; charset=utf-8").unwrap();
+        assert_eq!(headers.content_type.as_ref().unwrap().to_string(), "text/plain; charset=utf-8");
+        assert_eq!(headers.charset(), Some("utf-8"));
+
+        // Malformed parameters are dropped rather than the whole type.
+        let headers = FieldHeaders::parse("Content-Disposition: form-data; name=\"file\"\r\n\
+            Content-Type: image/png; =").unwrap();
+        assert_eq!(headers.content_type.unwrap().to_string(), "image/png");
+        assert_eq!(FieldHeaders::parse("Content-Disposition: form-data; name=\"a\"").unwrap().charset(), None);
+    }
+
+    #[test]
+    fn test_header_names() {
+        // Only the exact header names count, in any case.
+        let headers = FieldHeaders::parse("Content-Disposition: form-data; name=\"file\"\r\n\
+            X-Content-Length: 999999999\r\n\
+            X-Content-Type: application/x-evil\r\n\
+            X-Content-Encoding: gzip").unwrap();
+        assert_eq!(headers.content_length, None);
+        assert_eq!(headers.content_type, None);
+        assert_eq!(headers.content_encoding, None);
+
+        let headers = FieldHeaders::parse("Content-Disposition: form-data; name=\"file\"\r\n\
+            X-Content-Length: 999999999\r\n\
+            content-length: 5\r\n\
+            CONTENT-TYPE: text/plain\r\n\
+            content-encoding:  gzip ").unwrap();
+        assert_eq!(headers.content_length, Some(5));
+        assert_eq!(headers.content_type.unwrap().to_string(), "text/plain");
+        assert_eq!(headers.content_encoding.as_ref().map(|s| &**s), Some("gzip"));
+    }
+
+    fn read_field_name(headers: &str) -> io::Result<String> {
+        let body = format!(
+            "--boundary\r\nContent-Disposition: form-data; name=\"text\"\r\n{}\r\nvalue\r\n--boundary--",
+            headers
+        );
+
+        let mut multipart = Multipart::with_body(body.as_bytes(), "boundary");
+        let field = try!(multipart.read_entry()).expect("no field");
+        Ok(field.name)
+    }
+
+    #[test]
+    fn test_headers_size() {
+        let _ = ::env_logger::init();
+
+        // The limit covers the `Content-Disposition` line, but not the empty line ending the block.
+        let overhead = "Content-Disposition: form-data; name=\"text\"\r\n".len();
+        let line = |len: usize| format!("X-Pad: {}\r\n", "a".repeat(len - "X-Pad: \r\n".len()));
+
+        assert_eq!(read_field_name(&line(MAX_HEADERS_SIZE - overhead)).unwrap(), "text");
+
+        let err = read_field_name(&line(MAX_HEADERS_SIZE - overhead + 1)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // A single line longer than the limit, which is never read in full.
+        let err = read_field_name(&line(MAX_HEADERS_SIZE * 4)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Many short lines.
+        let err = read_field_name(&line(64).repeat(MAX_HEADERS_SIZE / 64 + 1)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "mmap")]
+    fn save_mmap(content_length: Option<u64>, data: &str, path: &Path, max_len: u64) -> io::Result<SavedFile> {
+        let content_length = content_length.map_or(String::new(), |len| format!("Content-Length: {}\r\n", len));
+        let body = format!(
+            "--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"file.bin\"\r\n\
+             Content-Type: application/octet-stream\r\n{}\r\n{}\r\n--boundary--",
+            content_length, data
+        );
+
+        let mut multipart = Multipart::with_body(body.as_bytes(), "boundary");
+        let mut field = multipart.read_entry().unwrap().expect("no field");
+        let file = field.data.as_file().expect("not a file");
+        file.save_as_mmap(path, max_len)
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_save_as_mmap() {
+        let _ = ::env_logger::init();
+
+        let dir = ::tempdir::TempDir::new("multipart").unwrap();
+        let path = dir.path().join("file.bin");
+
+        let saved = save_mmap(Some(4), "data", &path, 4).unwrap();
+        assert_eq!(saved.size, 4);
+        assert_eq!(fs::read(&path).unwrap(), b"data");
+
+        // Shorter than its `Content-Length`.
+        let saved = save_mmap(Some(8), "data", &path, 8).unwrap();
+        assert_eq!(saved.size, 4);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 4);
+
+        // Longer than its `Content-Length`.
+        let err = save_mmap(Some(4), "datum", &path, 8).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_save_as_mmap_over_limit() {
+        let _ = ::env_logger::init();
+
+        let dir = ::tempdir::TempDir::new("multipart").unwrap();
+        let path = dir.path().join("file.bin");
+
+        // The file isn't allocated with the length the client claims.
+        let err = save_mmap(Some(u64::max_value()), "data", &path, 1024).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!path.exists());
+
+        // Without a `Content-Length`, the file is truncated to the limit.
+        let saved = save_mmap(None, "data", &path, 2).unwrap();
+        assert_eq!(saved.size, 2);
+        assert_eq!(fs::read(&path).unwrap(), b"da");
+    }
+
+    /// Save a file field with the given `Content-Length` and data from a socket with
+    /// `save_as_splice()`.
+    #[cfg(all(feature = "splice", target_os = "linux"))]
+    fn save_splice(content_length: usize, data: &[u8], path: &Path) -> io::Result<SavedFile> {
+        use std::os::unix::net::UnixStream;
+        use std::thread;
+
+        let mut body = format!(
+            "--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"file.bin\"\r\n\
+             Content-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+            content_length
+        ).into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n--boundary--\r\n");
+
+        let (mut client, server) = UnixStream::pair().unwrap();
+        // The socket buffer is smaller than the body.
+        let writer = thread::spawn(move || client.write_all(&body));
+
+        let res = {
+            let mut multipart = Multipart::with_body(server, "boundary");
+            let mut field = multipart.read_entry().unwrap().expect("no field");
+            let res = field.data.as_file().expect("not a file").save_as_splice(path);
+            res
+        };
+
+        // Fails if the body wasn't read to the end.
+        let _ = writer.join().unwrap();
+        res
+    }
+
+    #[cfg(all(feature = "splice", target_os = "linux"))]
+    #[test]
+    fn test_save_as_splice() {
+        let _ = ::env_logger::init();
+
+        let dir = ::tempdir::TempDir::new("multipart").unwrap();
+        let path = dir.path().join("file.bin");
+
+        // Larger than the buffer, so most of it is spliced.
+        let data: Vec<u8> = (0 .. DEFAULT_BUF_SIZE * 4).map(|i| (i % 251) as u8).collect();
+        let saved = save_splice(data.len(), &data, &path).unwrap();
+        assert_eq!(saved.size, data.len() as u64);
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        let err = save_splice(data.len() + 1, &data, &path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err = save_splice(data.len() - 1, &data, &path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(all(feature = "splice", target_os = "linux"))]
+    #[test]
+    fn test_save_as_splice_boundary() {
+        let _ = ::env_logger::init();
+
+        let dir = ::tempdir::TempDir::new("multipart").unwrap();
+        let path = dir.path().join("file.bin");
+
+        // A `Content-Length` covering the boundary and a hidden part after the real data, both
+        // in the buffer and past it.
+        for &len in &[16, DEFAULT_BUF_SIZE * 2] {
+            let mut data = vec![b'a'; len];
+            data.extend_from_slice(b"\r\n--boundary\r\n\
+                Content-Disposition: form-data; name=\"hidden\"\r\n\r\nhidden");
+
+            let err = save_splice(data.len(), &data, &path).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "length {}", len);
+        }
+    }
 }
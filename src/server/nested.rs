@@ -0,0 +1,265 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Descending into parts which are multipart bodies themselves, such as the `multipart/mixed`
+//! parts of RFC 2388 uploads and of email messages.
+use std::fmt;
+use std::io::{self, BufRead, Read};
+
+use super::{parse_boundary, Multipart, MultipartPart};
+
+/// Limits on descending into nested multipart bodies with
+/// [`MultipartPart::into_nested()`](struct.MultipartPart.html#method.into_nested), which guard
+/// against bodies nested deeply enough to exhaust memory.
+///
+/// Set with [`Multipart::nesting_limits()`](struct.Multipart.html#method.nesting_limits), and
+/// inherited by the nested bodies.
+#[derive(Clone, Copy, Debug)]
+pub struct NestingLimits {
+    /// The maximum depth of nesting: 1 allows descending into parts of the request body, but
+    /// not into parts of those, and 0 disallows nesting altogether.
+    pub max_depth: u32,
+    /// The maximum total size of the parts descended into from the request body, in bytes.
+    ///
+    /// Parts at deeper levels are contained in these, so they count towards the same limit.
+    pub max_size: u64,
+}
+
+impl Default for NestingLimits {
+    /// At most 8 levels and 100 MiB.
+    fn default() -> Self {
+        NestingLimits {
+            max_depth: 8,
+            max_size: 100 * 1024 * 1024,
+        }
+    }
+}
+
+/// The nesting state of a `Multipart`.
+#[derive(Debug)]
+pub(crate) struct Nesting {
+    limits: NestingLimits,
+    depth: u32,
+    /// The number of bytes left to read from nested parts before `limits.max_size` is reached.
+    remaining: u64,
+}
+
+impl Nesting {
+    pub(crate) fn new(limits: NestingLimits) -> Nesting {
+        Nesting {
+            limits: limits,
+            depth: 0,
+            remaining: limits.max_size,
+        }
+    }
+}
+
+impl<B: Read> Multipart<B> {
+    /// Set the limits on descending into nested multipart bodies with
+    /// [`MultipartPart::into_nested()`](struct.MultipartPart.html#method.into_nested).
+    ///
+    /// Default value: `NestingLimits::default()`
+    pub fn nesting_limits(&mut self, limits: NestingLimits) -> &mut Self {
+        self.nesting = Nesting {
+            limits: limits,
+            depth: self.nesting.depth,
+            remaining: limits.max_size,
+        };
+        self
+    }
+
+    /// The nesting depth of this body: 0 for a request body, 1 for a body nested in one of its
+    /// parts, and so on.
+    pub fn depth(&self) -> u32 {
+        self.nesting.depth
+    }
+}
+
+impl<'a, B: Read + 'a> MultipartPart<'a, B> {
+    /// Returns `true` if the `Content-Type` of this part is `multipart/*` with a boundary, so it
+    /// can be read with [`into_nested()`](#method.into_nested).
+    pub fn is_multipart(&self) -> bool {
+        self.nested_boundary().is_some()
+    }
+
+    /// Read this part as a multipart body of its own, using the boundary from its
+    /// `Content-Type`.
+    ///
+    /// The nested body has the same type at any depth, so it may be read by a recursive
+    /// function. It is subject to the [`NestingLimits`](struct.NestingLimits.html) of the body
+    /// this part was read from.
+    ///
+    /// Returns an error of kind `InvalidInput` if this part isn't `multipart/*` with a boundary,
+    /// or `InvalidData` if it would exceed the maximum depth. Reading the nested body returns an
+    /// error of kind `InvalidData` once the maximum size is exceeded.
+    ///
+    /// ```rust
+    /// use multipart::server::Multipart;
+    /// use std::io::{self, Read};
+    ///
+    /// // Collect the contents of all the leaf parts, at any depth.
+    /// fn leaves<B: Read>(multipart: &mut Multipart<B>, out: &mut Vec<String>) -> io::Result<()> {
+    ///     while let Some(mut part) = try!(multipart.read_part()) {
+    ///         if part.is_multipart() {
+    ///             try!(leaves(&mut try!(part.into_nested()), out));
+    ///         } else {
+    ///             let mut text = String::new();
+    ///             try!(part.read_to_string(&mut text));
+    ///             out.push(text);
+    ///         }
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    ///
+    /// let body: &[u8] = b"--outer\r\n\
+    ///     Content-Disposition: form-data; name=\"files\"\r\n\
+    ///     Content-Type: multipart/mixed; boundary=inner\r\n\r\n\
+    ///     --inner\r\n\
+    ///     Content-Disposition: file; filename=\"a.txt\"\r\n\r\n\
+    ///     first file\r\n--inner\r\n\
+    ///     Content-Disposition: file; filename=\"b.txt\"\r\n\r\n\
+    ///     second file\r\n--inner--\r\n\
+    ///     \r\n--outer--\r\n";
+    ///
+    /// let mut out = Vec::new();
+    /// leaves(&mut Multipart::with_body(body, "outer"), &mut out).unwrap();
+    /// assert_eq!(out, ["first file", "second file"]);
+    /// ```
+    pub fn into_nested(self) -> io::Result<Multipart<NestedBody<'a>>> {
+        let boundary = try!(self.nested_boundary().map(String::from).ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput, "part is not a multipart body with a boundary")));
+
+        let MultipartPart { stream, nesting, .. } = self;
+
+        if nesting.depth >= nesting.limits.max_depth {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("multipart bodies nested more than {} levels deep", nesting.limits.max_depth)));
+        }
+
+        let nested = Nesting {
+            limits: nesting.limits,
+            depth: nesting.depth + 1,
+            remaining: nesting.remaining,
+        };
+
+        let mut multipart = Multipart::with_body(NestedBody {
+            inner: stream,
+            remaining: &mut nesting.remaining,
+            max_size: nesting.limits.max_size,
+        }, boundary);
+
+        multipart.nesting = nested;
+        Ok(multipart)
+    }
+
+    fn nested_boundary(&self) -> Option<&str> {
+        let content_type = try_opt!(self.header("Content-Type"));
+
+        if content_type.len() < 10 || !content_type[..10].eq_ignore_ascii_case("multipart/") {
+            return None;
+        }
+
+        parse_boundary(content_type)
+    }
+}
+
+/// The body of a multipart body nested in a part of another, returned by
+/// [`MultipartPart::into_nested()`](struct.MultipartPart.html#method.into_nested).
+///
+/// This is not `Send`, as it borrows the parser of the enclosing body, whose type is erased so
+/// that bodies can be nested to any depth.
+pub struct NestedBody<'a> {
+    inner: &'a mut BufRead,
+    remaining: &'a mut u64,
+    max_size: u64,
+}
+
+impl<'a> fmt::Debug for NestedBody<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NestedBody")
+            .field("remaining", &self.remaining)
+            .field("max_size", &self.max_size)
+            .finish()
+    }
+}
+
+impl<'a> Read for NestedBody<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = try!(self.inner.read(buf));
+
+        if read as u64 > *self.remaining {
+            *self.remaining = 0;
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("nested multipart bodies larger than {} bytes", self.max_size)));
+        }
+
+        *self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::NestingLimits;
+
+    use server::Multipart;
+
+    use std::io::{ErrorKind, Read};
+
+    const BODY: &[u8] = b"--outer\r\n\
+        Content-Type: multipart/mixed; boundary=middle\r\n\r\n\
+        --middle\r\n\
+        Content-Type: multipart/mixed; boundary=inner\r\n\r\n\
+        --inner\r\n\r\n\
+        innermost\r\n--inner--\r\n\
+        \r\n--middle--\r\n\
+        \r\n--outer\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        not nested\r\n--outer--\r\n";
+
+    #[test]
+    fn test_into_nested() {
+        let mut outer = Multipart::with_body(BODY, "outer");
+
+        {
+            let part = outer.read_part().unwrap().unwrap();
+            assert!(part.is_multipart());
+
+            let mut middle = part.into_nested().unwrap();
+            assert_eq!(middle.depth(), 1);
+
+            let part = middle.read_part().unwrap().unwrap();
+            let mut inner = part.into_nested().unwrap();
+            assert_eq!(inner.depth(), 2);
+
+            let mut text = String::new();
+            inner.read_part().unwrap().unwrap().read_to_string(&mut text).unwrap();
+            assert_eq!(text, "innermost");
+            assert!(inner.read_part().unwrap().is_none());
+        }
+
+        let part = outer.read_part().unwrap().unwrap();
+        assert!(!part.is_multipart());
+        assert_eq!(part.into_nested().err().unwrap().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_nesting_limits() {
+        let mut outer = Multipart::with_body(BODY, "outer");
+        outer.nesting_limits(NestingLimits { max_depth: 1, .. NestingLimits::default() });
+
+        let mut middle = outer.read_part().unwrap().unwrap().into_nested().unwrap();
+        let part = middle.read_part().unwrap().unwrap();
+        assert_eq!(part.into_nested().err().unwrap().kind(), ErrorKind::InvalidData);
+
+        let mut outer = Multipart::with_body(BODY, "outer");
+        outer.nesting_limits(NestingLimits { max_depth: 2, max_size: 32 });
+
+        let mut middle = outer.read_part().unwrap().unwrap().into_nested().unwrap();
+        assert_eq!(middle.read_part().unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
@@ -16,6 +16,14 @@ impl<'r, 'mw, 'server, D: 'mw> HttpRequest for &'r mut NickelRequest<'mw, 'serve
         self.origin.multipart_boundary()
     }
 
+    fn multipart_boundary_for(&self, methods: &[&str]) -> Option<&str> {
+        self.origin.multipart_boundary_for(methods)
+    }
+
+    fn multipart_boundary_accepting(&self, methods: &[&str], subtypes: &[&str]) -> Option<&str> {
+        self.origin.multipart_boundary_accepting(methods, subtypes)
+    }
+
     fn body(self) -> Self::Body {
         &mut self.origin
     }
@@ -0,0 +1,246 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Multipart parsing from non-blocking readers, such as sockets driven by a `mio` or `poll()`
+//! event loop.
+//!
+//! The blocking [`Multipart`](../struct.Multipart.html) treats every error from the request
+//! body as fatal, as it may have consumed part of a boundary or header line when the error
+//! occurred. [`NonBlockingMultipart`](struct.NonBlockingMultipart.html) keeps all unparsed input
+//! in its own buffer instead, so when the reader returns `io::ErrorKind::WouldBlock`, the error
+//! is passed on and parsing resumes exactly where it stopped on the next call, once the reader
+//! is ready again.
+use std::io::{self, Read};
+use std::str;
+
+use super::FieldHeaders;
+use super::push::{Event, ParseError, PushParser};
+
+/// The number of bytes read from the reader at once.
+const READ_SIZE: usize = 8 * 1024;
+
+/// An event read by [`NonBlockingMultipart`](struct.NonBlockingMultipart.html).
+#[derive(Debug)]
+pub enum ReadEvent<'a> {
+    /// A new field has started.
+    Field(FieldHeaders),
+    /// A chunk of the current field's contents, borrowed from the parser's buffer.
+    Data(&'a [u8]),
+    /// The current field is complete.
+    FieldEnd,
+}
+
+/// A multipart parser reading from a `Read` which may return `io::ErrorKind::WouldBlock`.
+///
+/// ```no_run
+/// # extern crate multipart;
+/// use multipart::server::nonblocking::{NonBlockingMultipart, ReadEvent};
+///
+/// use std::io;
+/// use std::net::TcpStream;
+///
+/// # fn main() {
+/// let stream = TcpStream::connect("127.0.0.1:8080").unwrap();
+/// stream.set_nonblocking(true).unwrap();
+///
+/// let mut multipart = NonBlockingMultipart::with_body(stream, "boundary");
+///
+/// loop {
+///     match multipart.read_event() {
+///         Ok(Some(ReadEvent::Field(headers))) => println!("Field: {:?}", headers),
+///         Ok(Some(ReadEvent::Data(data))) => println!("{} bytes", data.len()),
+///         Ok(Some(ReadEvent::FieldEnd)) => (),
+///         Ok(None) => break,
+///         // Wait until the socket is readable, then call `read_event()` again.
+///         Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+///         Err(err) => panic!("Error reading request: {}", err),
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct NonBlockingMultipart<R> {
+    reader: R,
+    parser: PushParser,
+    /// Input read but not yet parsed starts at `pos`.
+    buf: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+enum Parsed {
+    Field(FieldHeaders),
+    Data(usize, usize),
+    FieldEnd,
+    None,
+}
+
+impl<R: Read> NonBlockingMultipart<R> {
+    /// Construct a new `NonBlockingMultipart` with the given body reader and boundary.
+    ///
+    /// The boundary is as it appears in the `Content-Type` header, without the leading `"--"`.
+    pub fn with_body<Bnd: Into<String>>(reader: R, boundary: Bnd) -> Self {
+        let boundary = boundary.into();
+
+        debug!("Boundary: {}", boundary);
+
+        NonBlockingMultipart {
+            reader: reader,
+            parser: PushParser::new(&boundary),
+            buf: Vec::new(),
+            pos: 0,
+            eof: false,
+        }
+    }
+
+    /// Read the next event from the request, or `None` after the closing boundary.
+    ///
+    /// ##Errors
+    /// Errors from the reader are returned as-is. `WouldBlock` and `Interrupted` leave this
+    /// parser as it was, so this can be called again when the reader is ready; after any other
+    /// error, the request should be abandoned.
+    pub fn read_event(&mut self) -> io::Result<Option<ReadEvent>> {
+        loop {
+            let (consumed, parsed) = {
+                let input = &self.buf[self.pos..];
+                let (consumed, event) = try!(self.parser.parse(input, self.eof));
+
+                let parsed = match event {
+                    Some(Event::Headers(headers)) => Parsed::Field(try!(parse_headers(headers))),
+                    Some(Event::Data(data)) => Parsed::Data(self.pos, self.pos + data.len()),
+                    Some(Event::PartEnd) => Parsed::FieldEnd,
+                    Some(Event::End) | None => Parsed::None,
+                };
+
+                (consumed, parsed)
+            };
+
+            self.pos += consumed;
+
+            match parsed {
+                Parsed::Field(headers) => return Ok(Some(ReadEvent::Field(headers))),
+                Parsed::Data(start, end) => return Ok(Some(ReadEvent::Data(&self.buf[start..end]))),
+                Parsed::FieldEnd => return Ok(Some(ReadEvent::FieldEnd)),
+                Parsed::None => (),
+            }
+
+            if self.parser.is_done() {
+                return Ok(None);
+            }
+
+            if consumed == 0 {
+                try!(self.read_more());
+            }
+        }
+    }
+
+    /// Read more input into the buffer, keeping the unparsed input.
+    fn read_more(&mut self) -> io::Result<()> {
+        if self.pos != 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+
+        let len = self.buf.len();
+        self.buf.resize(len + READ_SIZE, 0);
+
+        match self.reader.read(&mut self.buf[len..]) {
+            Ok(read) => {
+                self.buf.truncate(len + read);
+                self.eof = read == 0;
+                Ok(())
+            },
+            Err(err) => {
+                self.buf.truncate(len);
+
+                if err.kind() == io::ErrorKind::Interrupted {
+                    Ok(())
+                } else {
+                    Err(err)
+                }
+            },
+        }
+    }
+
+    /// Returns `true` if the closing boundary has been read.
+    pub fn is_done(&self) -> bool {
+        self.parser.is_done()
+    }
+
+    /// Get a reference to the body reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Get a mutable reference to the body reader, such as to register it with an event loop.
+    ///
+    /// Reading from it directly will corrupt the request.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+}
+
+fn parse_headers(headers: &[u8]) -> io::Result<FieldHeaders> {
+    str::from_utf8(headers).ok()
+        .and_then(FieldHeaders::parse)
+        .ok_or_else(|| ParseError::InvalidHeaders.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{NonBlockingMultipart, ReadEvent};
+
+    use std::io::{self, Read};
+
+    const BODY: &'static [u8] = b"--boundary\r\n\
+        Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+        text value\r\n--boundary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        file\r\ncontents\r\n--boundary--\r\n";
+
+    /// Returns `WouldBlock` before every byte.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        blocked: bool,
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.blocked = !self.blocked;
+
+            if self.blocked {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+
+            (&mut self.data).take(1).read(buf)
+        }
+    }
+
+    #[test]
+    fn test_would_block() {
+        let mut multipart = NonBlockingMultipart::with_body(Trickle { data: BODY, blocked: false }, "boundary");
+        let mut events = Vec::new();
+        let mut blocked = 0;
+
+        loop {
+            match multipart.read_event() {
+                Ok(Some(ReadEvent::Field(headers))) => events.push(format!("F:{}", headers.name)),
+                Ok(Some(ReadEvent::Data(data))) => match events.last_mut() {
+                    Some(last) if last.starts_with("D:") => last.push_str(&String::from_utf8_lossy(data)),
+                    _ => events.push(format!("D:{}", String::from_utf8_lossy(data))),
+                },
+                Ok(Some(ReadEvent::FieldEnd)) => events.push("E".into()),
+                Ok(None) => break,
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => blocked += 1,
+                Err(err) => panic!("unexpected error: {}", err),
+            }
+        }
+
+        assert!(blocked > 0);
+        assert_eq!(events, ["F:text", "D:text value", "E", "F:file", "D:file\r\ncontents", "E"]);
+    }
+}
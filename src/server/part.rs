@@ -0,0 +1,231 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Reading parts with their full headers, for multipart types other than `form-data`.
+use mime::{Attr, Mime, SubLevel, TopLevel, Value};
+
+use std::io::{self, BufRead, Read};
+
+use super::boundary::BoundaryReader;
+use super::nested::Nesting;
+use super::{find_header, read_disposition, Multipart};
+
+impl<B: Read> Multipart<B> {
+    /// Read the next part of this multipart body with its full headers, whether or not it has a
+    /// `Content-Disposition: form-data` header.
+    ///
+    /// Use this for bodies which aren't `multipart/form-data`, such as the `multipart/mixed`
+    /// bodies of batch APIs and email gateways, accepted with
+    /// [`from_request_accepting()`](#method.from_request_accepting). `read_entry()` instead
+    /// stops at the first part without a form field name.
+    ///
+    /// Like `read_entry()`, this discards any unread contents of the previous part.
+    ///
+    /// ```rust
+    /// use multipart::server::Multipart;
+    /// use std::io::Read;
+    ///
+    /// let body: &[u8] = b"--boundary\r\n\
+    ///     Content-Type: application/http\r\n\
+    ///     Content-ID: <1>\r\n\r\n\
+    ///     GET /items/1 HTTP/1.1\r\n--boundary--\r\n";
+    ///
+    /// let mut multipart = Multipart::with_body(body, "boundary");
+    /// let mut part = multipart.read_part().unwrap().unwrap();
+    /// assert_eq!(part.header("content-id"), Some("<1>"));
+    ///
+    /// let mut request = String::new();
+    /// part.read_to_string(&mut request).unwrap();
+    /// assert_eq!(request, "GET /items/1 HTTP/1.1");
+    /// ```
+    pub fn read_part(&mut self) -> io::Result<Option<MultipartPart<B>>> {
+        self.read_part_with_default(false)
+    }
+
+    /// Read the next part of a `multipart/digest` body, like [`read_part()`](#method.read_part).
+    ///
+    /// The parts of a digest are usually email messages, so those without a `Content-Type`
+    /// header are `message/rfc822` rather than `text/plain` (RFC 2046), as returned by
+    /// [`MultipartPart::effective_content_type()`](struct.MultipartPart.html#method.effective_content_type).
+    ///
+    /// ```rust
+    /// use multipart::server::Multipart;
+    ///
+    /// let body: &[u8] = b"--boundary\r\n\r\n\
+    ///     From: someone@example.com\r\n\
+    ///     Subject: first\r\n\r\n\
+    ///     Message body\r\n--boundary--\r\n";
+    ///
+    /// let mut multipart = Multipart::with_body(body, "boundary");
+    /// let part = multipart.read_digest_part().unwrap().unwrap();
+    /// assert_eq!(part.content_type(), None);
+    /// assert_eq!(part.effective_content_type().to_string(), "message/rfc822");
+    /// ```
+    pub fn read_digest_part(&mut self) -> io::Result<Option<MultipartPart<B>>> {
+        self.read_part_with_default(true)
+    }
+
+    fn read_part_with_default(&mut self, digest: bool) -> io::Result<Option<MultipartPart<B>>> {
+        if !try!(self.consume_boundary()) {
+            return Ok(None);
+        }
+
+        let headers = try!(self.read_headers()).trim_end_matches("\r\n").to_owned();
+
+        Ok(Some(MultipartPart {
+            headers: headers,
+            digest: digest,
+            stream: &mut self.source,
+            nesting: &mut self.nesting,
+        }))
+    }
+}
+
+/// A part of a multipart body with its full headers, returned by
+/// [`Multipart::read_part()`](struct.Multipart.html#method.read_part).
+///
+/// Implements `Read` and `BufRead` for the contents of the part.
+#[derive(Debug)]
+pub struct MultipartPart<'a, B: 'a> {
+    headers: String,
+    /// Set for parts of a `multipart/digest` body, which are `message/rfc822` by default.
+    digest: bool,
+    pub(crate) stream: &'a mut BoundaryReader<B>,
+    pub(crate) nesting: &'a mut Nesting,
+}
+
+impl<'a, B: Read> MultipartPart<'a, B> {
+    /// The entire header block of this part, without the terminating empty line.
+    pub fn headers(&self) -> &str {
+        &self.headers
+    }
+
+    /// Get the value of the header `name` of this part, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// The `Content-Type` of this part, if supplied and valid.
+    pub fn content_type(&self) -> Option<Mime> {
+        self.header("Content-Type").and_then(|content_type| content_type.parse().ok())
+    }
+
+    /// The `Content-Type` of this part, or the default for the type of the body if it wasn't
+    /// supplied or is invalid: `message/rfc822` for parts read with
+    /// [`Multipart::read_digest_part()`](struct.Multipart.html#method.read_digest_part), or
+    /// `text/plain; charset=us-ascii` otherwise (RFC 2046).
+    pub fn effective_content_type(&self) -> Mime {
+        self.content_type().unwrap_or_else(|| if self.digest {
+            Mime(TopLevel::Message, SubLevel::Ext("rfc822".into()), vec![])
+        } else {
+            Mime(TopLevel::Text, SubLevel::Plain, vec![(Attr::Charset, Value::Ext("us-ascii".into()))])
+        })
+    }
+
+    /// Returns `true` if this part is an email message (`message/rfc822`), such as the parts of
+    /// a `multipart/digest` body.
+    pub fn is_message(&self) -> bool {
+        match self.effective_content_type() {
+            Mime(TopLevel::Message, SubLevel::Ext(ref sub), _) => sub.eq_ignore_ascii_case("rfc822"),
+            _ => false,
+        }
+    }
+
+    /// The field name from the `Content-Disposition: form-data` header of this part, if it has
+    /// one.
+    pub fn field_name(&self) -> Option<&str> {
+        self.headers.split("\r\n").filter_map(read_disposition).next().map(|(name, _)| name)
+    }
+}
+
+impl<'a, B: Read> Read for MultipartPart<'a, B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl<'a, B: Read> BufRead for MultipartPart<'a, B> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.stream.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.stream.consume(amt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use server::Multipart;
+
+    use std::io::Read;
+
+    #[test]
+    fn test_read_part() {
+        let body = b"--boundary\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            first part\r\n--boundary\r\n\
+            Content-Disposition: form-data; name=\"field\"\r\n\r\n\
+            value\r\n--boundary\r\n\r\n\
+            no headers\r\n--boundary--\r\n";
+
+        let mut multipart = Multipart::with_body(&body[..], "boundary");
+
+        {
+            let mut part = multipart.read_part().unwrap().unwrap();
+            assert_eq!(part.headers(), "Content-Type: text/plain");
+            assert_eq!(part.content_type(), "text/plain".parse().ok());
+            assert_eq!(part.field_name(), None);
+            assert!(!part.is_message());
+
+            // Left partially read.
+            let mut buf = [0; 5];
+            part.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"first");
+        }
+
+        {
+            let part = multipart.read_part().unwrap().unwrap();
+            assert_eq!(part.field_name(), Some("field"));
+        }
+
+        {
+            let mut part = multipart.read_part().unwrap().unwrap();
+            assert_eq!(part.headers(), "");
+            assert_eq!(part.content_type(), None);
+            assert_eq!(part.effective_content_type().to_string(), "text/plain; charset=us-ascii");
+
+            let mut data = String::new();
+            part.read_to_string(&mut data).unwrap();
+            assert_eq!(data, "no headers");
+        }
+
+        assert!(multipart.read_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_digest_part() {
+        let body = b"--boundary\r\n\r\n\
+            Subject: first\r\n\r\n\
+            first message\r\n--boundary\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            not a message\r\n--boundary--\r\n";
+
+        let mut multipart = Multipart::with_body(&body[..], "boundary");
+
+        {
+            let mut part = multipart.read_digest_part().unwrap().unwrap();
+            assert!(part.is_message());
+
+            let mut message = String::new();
+            part.read_to_string(&mut message).unwrap();
+            assert_eq!(message, "Subject: first\r\n\r\nfirst message");
+        }
+
+        assert!(!multipart.read_digest_part().unwrap().unwrap().is_message());
+        assert!(multipart.read_digest_part().unwrap().is_none());
+    }
+}
@@ -0,0 +1,181 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Declarative lists of the content types accepted for file fields.
+use mime::{Mime, SubLevel, TopLevel};
+
+use std::collections::HashMap;
+use std::{error, fmt, io};
+
+/// Which `Content-Type`s are accepted for file fields, globally or per field.
+///
+/// Types may have a wildcard subtype, such as `image/*`; parameters are ignored. A type is
+/// accepted if it is not denied and it is allowed, either by the allow list of its field if it
+/// has one, or by the global allow list otherwise. Without any allow list, every type which is
+/// not denied is accepted.
+///
+/// Check the files of a request as they are saved, before their contents are written, with
+/// [`storage::TypeChecked`](storage/struct.TypeChecked.html):
+///
+/// ```rust
+/// use multipart::server::{Multipart, TypePolicy};
+/// use multipart::server::storage::{MemoryStorage, TypeChecked};
+///
+/// let body: &[u8] = b"--boundary\r\n\
+///     Content-Disposition: form-data; name=\"avatar\"; filename=\"avatar.exe\"\r\n\
+///     Content-Type: application/x-msdownload\r\n\r\n\
+///     MZ\r\n--boundary--\r\n";
+///
+/// let mut policy = TypePolicy::new();
+/// policy.allow_for_field("avatar", "image/*".parse().unwrap());
+///
+/// let res = Multipart::with_body(body, "boundary")
+///     .save_all_to(TypeChecked::new(MemoryStorage, policy));
+/// assert!(res.is_err());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TypePolicy {
+    allow: Option<Vec<Mime>>,
+    deny: Vec<Mime>,
+    fields: HashMap<String, Vec<Mime>>,
+}
+
+impl TypePolicy {
+    /// A policy which accepts every type.
+    pub fn new() -> Self {
+        TypePolicy::default()
+    }
+
+    /// Allow `content_type` for every field without an allow list of its own.
+    pub fn allow(&mut self, content_type: Mime) -> &mut Self {
+        self.allow.get_or_insert_with(Vec::new).push(content_type);
+        self
+    }
+
+    /// Deny `content_type` for every field, even if it is allowed.
+    pub fn deny(&mut self, content_type: Mime) -> &mut Self {
+        self.deny.push(content_type);
+        self
+    }
+
+    /// Allow `content_type` for the field named `field_name`, replacing the global allow list
+    /// for it.
+    pub fn allow_for_field<N: Into<String>>(&mut self, field_name: N, content_type: Mime) -> &mut Self {
+        self.fields.entry(field_name.into()).or_insert_with(Vec::new).push(content_type);
+        self
+    }
+
+    /// Check whether `content_type` is accepted for the field named `field_name`.
+    pub fn check(&self, field_name: &str, content_type: &Mime) -> Result<(), DisallowedType> {
+        let allow = self.fields.get(field_name).or(self.allow.as_ref());
+
+        let denied = self.deny.iter().any(|pattern| matches(pattern, content_type));
+        let allowed = allow.map_or(true, |allow| allow.iter().any(|pattern| matches(pattern, content_type)));
+
+        if allowed && !denied {
+            Ok(())
+        } else {
+            Err(DisallowedType {
+                field_name: field_name.into(),
+                content_type: content_type.clone(),
+            })
+        }
+    }
+}
+
+/// Returns `true` if `content_type` matches `pattern`, which may have wildcards.
+pub fn matches(pattern: &Mime, content_type: &Mime) -> bool {
+    (pattern.0 == TopLevel::Star || pattern.0.eq_ignore_ascii_case(&content_type.0))
+        && (pattern.1 == SubLevel::Star || pattern.1.eq_ignore_ascii_case(&content_type.1))
+}
+
+/// The error of a file field whose `Content-Type` is not accepted by a
+/// [`TypePolicy`](struct.TypePolicy.html).
+///
+/// Returned wrapped in an `io::Error` of kind `io::ErrorKind::InvalidData`, from which it can be
+/// recovered with `get_ref()` and `downcast_ref()`.
+#[derive(Clone, Debug)]
+pub struct DisallowedType {
+    /// The name of the field.
+    pub field_name: String,
+    /// The content type the field was sent with.
+    pub content_type: Mime,
+}
+
+impl error::Error for DisallowedType {
+    fn description(&self) -> &str {
+        "file field has a disallowed content type"
+    }
+}
+
+impl fmt::Display for DisallowedType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "file field {:?} has a disallowed content type: {}", self.field_name, self.content_type)
+    }
+}
+
+impl From<DisallowedType> for io::Error {
+    fn from(err: DisallowedType) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DisallowedType, TypePolicy};
+
+    use server::Multipart;
+    use server::storage::{MemoryStorage, TypeChecked};
+
+    #[test]
+    fn test_type_policy() {
+        let mut policy = TypePolicy::new();
+        policy.allow("image/*".parse().unwrap())
+            .allow("application/pdf".parse().unwrap())
+            .deny("image/svg+xml".parse().unwrap())
+            .allow_for_field("notes", "text/plain".parse().unwrap());
+
+        assert!(policy.check("file", &"image/png".parse().unwrap()).is_ok());
+        assert!(policy.check("file", &"application/pdf; charset=binary".parse().unwrap()).is_ok());
+        assert!(policy.check("file", &"image/svg+xml".parse().unwrap()).is_err());
+        assert!(policy.check("file", &"text/plain".parse().unwrap()).is_err());
+        assert!(policy.check("notes", &"text/plain".parse().unwrap()).is_ok());
+        assert!(policy.check("notes", &"image/png".parse().unwrap()).is_err());
+
+        let mut policy = TypePolicy::new();
+        policy.deny("application/x-msdownload".parse().unwrap());
+
+        assert!(policy.check("file", &"text/html".parse().unwrap()).is_ok());
+        assert!(policy.check("file", &"application/x-msdownload".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_type_checked() {
+        let body: &[u8] = b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+            text field\r\n--boundary\r\n\
+            Content-Disposition: form-data; name=\"image\"; filename=\"image.png\"\r\n\
+            Content-Type: image/png\r\n\r\n\
+            image\r\n--boundary\r\n\
+            Content-Disposition: form-data; name=\"script\"; filename=\"script.js\"\r\n\
+            Content-Type: application/javascript\r\n\r\n\
+            script\r\n--boundary--\r\n";
+
+        let mut policy = TypePolicy::new();
+        policy.allow("image/*".parse().unwrap());
+
+        let (entries, err) = Multipart::with_body(body, "boundary")
+            .save_all_to(TypeChecked::new(MemoryStorage, policy)).unwrap_err();
+
+        assert_eq!(entries.fields["text"], "text field");
+        assert_eq!(entries.files["image"].stored, b"image");
+        assert!(!entries.files.contains_key("script"));
+
+        let err = err.get_ref().and_then(|err| err.downcast_ref::<DisallowedType>()).unwrap();
+        assert_eq!(err.field_name, "script");
+        assert_eq!(err.content_type.to_string(), "application/javascript");
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::mem;
+
+/// A pool of buffers shared by the `Multipart` readers of many requests, so a busy server
+/// doesn't allocate and zero a new buffer for each of them.
+///
+/// Cloning a `BufferPool` returns another handle to the same pool. Buffers are taken from the
+/// pool by [`Multipart::with_pool()`](struct.Multipart.html#method.with_pool) and returned to it
+/// when the `Multipart` is dropped.
+///
+/// ```rust
+/// use multipart::server::{BufferPool, Multipart};
+///
+/// // Keep up to 32 idle 64 KiB buffers.
+/// let pool = BufferPool::new(64 * 1024, 32);
+///
+/// let body: &[u8] = b"--boundary--";
+/// let mut multipart = Multipart::with_pool(body, "boundary", &pool);
+/// assert!(multipart.read_entry().unwrap().is_none());
+/// ```
+#[derive(Clone, Debug)]
+pub struct BufferPool {
+    inner: Arc<PoolInner>,
+}
+
+#[derive(Debug)]
+struct PoolInner {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    buf_size: usize,
+    max_idle: usize,
+}
+
+impl BufferPool {
+    /// Create a new pool of buffers of `buf_size` bytes, keeping at most `max_idle` of them
+    /// when they are not in use.
+    pub fn new(buf_size: usize, max_idle: usize) -> Self {
+        BufferPool {
+            inner: Arc::new(PoolInner {
+                buffers: Mutex::new(Vec::new()),
+                buf_size: buf_size,
+                max_idle: max_idle,
+            }),
+        }
+    }
+
+    /// The size of the buffers in this pool, in bytes.
+    pub fn buf_size(&self) -> usize {
+        self.inner.buf_size
+    }
+
+    /// The number of buffers currently waiting in the pool.
+    pub fn idle(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Take a buffer of at least `min_size` bytes from the pool, or allocate a new one.
+    pub(crate) fn take(&self, min_size: usize) -> PooledBuf {
+        let buf_size = self.inner.buf_size;
+        let mut buf = self.lock().pop().unwrap_or_else(|| vec![0; buf_size]);
+
+        if buf.len() < min_size {
+            buf.resize(min_size, 0);
+        }
+
+        PooledBuf {
+            buf: buf,
+            pool: Some(self.clone()),
+        }
+    }
+
+    fn put(&self, buf: Vec<u8>) {
+        let mut buffers = self.lock();
+
+        if buffers.len() < self.inner.max_idle {
+            buffers.push(buf);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<Vec<Vec<u8>>> {
+        // The buffers are still valid if another thread panicked while holding the lock.
+        self.inner.buffers.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// A buffer which is returned to its pool, if any, when dropped.
+#[derive(Debug)]
+pub(crate) struct PooledBuf {
+    buf: Vec<u8>,
+    pool: Option<BufferPool>,
+}
+
+impl PooledBuf {
+    /// Allocate a buffer which doesn't belong to a pool.
+    pub(crate) fn unpooled(size: usize) -> Self {
+        PooledBuf {
+            buf: vec![0; size],
+            pool: None,
+        }
+    }
+}
+
+impl Deref for PooledBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        if let Some(ref pool) = self.pool {
+            pool.put(mem::replace(&mut self.buf, Vec::new()));
+        }
+    }
+}
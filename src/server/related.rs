@@ -0,0 +1,250 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Parsing of `multipart/related` bodies (RFC 2387), made of a root part and the parts it
+//! refers to by their `Content-ID`, as used by DICOMweb and by APIs which upload metadata along
+//! with binary data.
+//!
+//! [`Multipart::parse_related()`](../struct.Multipart.html#method.parse_related) splits a body
+//! which is entirely in memory into a [`Related`](struct.Related.html), whose parts are slices
+//! of the original buffer, like
+//! [`Multipart::parse_bytes()`](../struct.Multipart.html#method.parse_bytes). Parts may be
+//! looked up by `Content-ID`, which requires all of them to be available at once.
+//!
+//! ```rust
+//! use multipart::server::Multipart;
+//!
+//! let content_type = "multipart/related; boundary=boundary; type=\"application/json\"; \
+//!     start=\"<meta>\"";
+//!
+//! let body = b"--boundary\r\n\
+//!     Content-Type: application/json\r\n\
+//!     Content-ID: <meta>\r\n\r\n\
+//!     {\"image\": \"cid:image\"}\r\n--boundary\r\n\
+//!     Content-Type: image/png\r\n\
+//!     Content-ID: <image>\r\n\r\n\
+//!     PNG data\r\n--boundary--\r\n";
+//!
+//! let related = Multipart::parse_related(body, content_type).unwrap();
+//!
+//! assert_eq!(related.root_type, Some("application/json"));
+//! assert_eq!(related.root().unwrap().content_type, Some("application/json"));
+//! assert_eq!(related.get("cid:image").unwrap().data, b"PNG data");
+//! ```
+use std::{error, fmt, str};
+
+use super::boundary::parse_param;
+use super::push::{Event, ParseError, PushParser};
+use super::{find_header, parse_boundary, Multipart};
+
+/// A `multipart/related` body, borrowed from the buffer it was parsed from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Related<'a> {
+    /// The `type` parameter of the body's `Content-Type`: the media type of the root part.
+    pub root_type: Option<&'a str>,
+    /// The `start` parameter of the body's `Content-Type`: the `Content-ID` of the root part,
+    /// without the angle brackets.
+    pub start: Option<&'a str>,
+    /// The `start-info` parameter of the body's `Content-Type`, if supplied.
+    pub start_info: Option<&'a str>,
+    /// The parts of the body, in order.
+    pub parts: Vec<RelatedPart<'a>>,
+}
+
+impl<'a> Related<'a> {
+    /// Get the root part: the one whose `Content-ID` is `start`, or the first part if `start`
+    /// wasn't supplied.
+    pub fn root(&self) -> Option<&RelatedPart<'a>> {
+        match self.start {
+            Some(start) => self.get(start),
+            None => self.parts.first(),
+        }
+    }
+
+    /// Get the part with the given `Content-ID`, which may be given with or without the angle
+    /// brackets, or as a `cid:` URL (RFC 2392) without percent-encoding.
+    pub fn get(&self, content_id: &str) -> Option<&RelatedPart<'a>> {
+        let content_id = strip_content_id(content_id.trim_start_matches("cid:"));
+        self.parts.iter().find(|part| part.content_id == Some(content_id))
+    }
+}
+
+/// A single part of a `multipart/related` body, borrowed from the buffer it was parsed from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelatedPart<'a> {
+    /// The value of the `Content-Type` header of this part, if supplied, including any
+    /// parameters.
+    pub content_type: Option<&'a str>,
+    /// The `Content-ID` of this part, if supplied, without the angle brackets.
+    pub content_id: Option<&'a str>,
+    /// The entire header block of this part, without the terminating empty line.
+    pub headers: &'a str,
+    /// The contents of this part.
+    pub data: &'a [u8],
+}
+
+impl<'a> RelatedPart<'a> {
+    /// Get the value of the header `name` of this part, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        find_header(self.headers, name)
+    }
+
+    /// Get the contents of this part as a string, if it is valid UTF-8.
+    pub fn as_text(&self) -> Option<&'a str> {
+        str::from_utf8(self.data).ok()
+    }
+
+    fn from_headers(headers: &'a [u8]) -> Result<RelatedPart<'a>, ParseError> {
+        let headers = try!(str::from_utf8(headers).map_err(|_| ParseError::InvalidHeaders));
+
+        Ok(RelatedPart {
+            content_type: find_header(headers, "Content-Type"),
+            content_id: find_header(headers, "Content-ID").map(strip_content_id),
+            headers: headers,
+            data: &[],
+        })
+    }
+}
+
+/// An error returned by [`Multipart::parse_related()`](../struct.Multipart.html#method.parse_related).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelatedError {
+    /// The `Content-Type` of the body did not have a `boundary` parameter.
+    MissingBoundary,
+    /// The body could not be parsed.
+    Parse(ParseError),
+}
+
+impl error::Error for RelatedError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            RelatedError::MissingBoundary => None,
+            RelatedError::Parse(ref err) => Some(err),
+        }
+    }
+}
+
+impl fmt::Display for RelatedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RelatedError::MissingBoundary => f.write_str("multipart body has no boundary"),
+            RelatedError::Parse(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl From<ParseError> for RelatedError {
+    fn from(err: ParseError) -> RelatedError {
+        RelatedError::Parse(err)
+    }
+}
+
+impl<'a> Multipart<&'a [u8]> {
+    /// Parse a `multipart/related` body which is entirely in memory, without copying.
+    ///
+    /// `content_type` is the value of the `Content-Type` header of the body, from which the
+    /// boundary and the `type`, `start` and `start-info` parameters are read. Parts don't need
+    /// a `Content-Disposition` header.
+    ///
+    /// Returns an error if the boundary or the closing boundary is missing, or the headers of a
+    /// part aren't valid UTF-8.
+    pub fn parse_related(body: &'a [u8], content_type: &'a str) -> Result<Related<'a>, RelatedError> {
+        let boundary = try!(parse_boundary(content_type).ok_or(RelatedError::MissingBoundary));
+
+        let mut parser = PushParser::new(boundary);
+        let mut parts = Vec::new();
+        let mut part = None;
+        let mut pos = 0;
+
+        while !parser.is_done() {
+            let (consumed, event) = try!(parser.parse(&body[pos..], true));
+            pos += consumed;
+
+            match event {
+                Some(Event::Headers(headers)) => {
+                    part = Some((try!(RelatedPart::from_headers(headers)), pos));
+                },
+                Some(Event::PartEnd) => if let Some((mut part, data_start)) = part.take() {
+                    part.data = &body[data_start..pos];
+                    parts.push(part);
+                },
+                Some(Event::Data(_)) | Some(Event::End) | None => (),
+            }
+        }
+
+        Ok(Related {
+            root_type: parse_param(content_type, "type"),
+            start: parse_param(content_type, "start").map(strip_content_id),
+            start_info: parse_param(content_type, "start-info"),
+            parts: parts,
+        })
+    }
+}
+
+/// Strip the angle brackets around a `Content-ID`.
+fn strip_content_id(content_id: &str) -> &str {
+    let content_id = content_id.trim();
+
+    if content_id.starts_with('<') && content_id.ends_with('>') && content_id.len() >= 2 {
+        &content_id[1..content_id.len() - 1]
+    } else {
+        content_id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RelatedError;
+
+    use server::Multipart;
+    use server::push::ParseError;
+
+    const BODY: &[u8] = b"--boundary\r\n\
+        Content-Type: application/dicom+json\r\n\r\n\
+        [{}]\r\n--boundary\r\n\
+        content-type: application/dicom\r\n\
+        content-id: <instance@example.com>\r\n\r\n\
+        DICM\r\n--boundary--\r\n";
+
+    #[test]
+    fn test_parse_related() {
+        let related = Multipart::parse_related(BODY, "multipart/related; boundary=boundary; \
+                                                      type=\"application/dicom+json\"").unwrap();
+
+        assert_eq!(related.root_type, Some("application/dicom+json"));
+        assert_eq!(related.start, None);
+        assert_eq!(related.parts.len(), 2);
+
+        // Without `start`, the root is the first part.
+        assert_eq!(related.root().unwrap().as_text(), Some("[{}]"));
+
+        let instance = related.get("<instance@example.com>").unwrap();
+        assert_eq!(instance.content_type, Some("application/dicom"));
+        assert_eq!(instance.header("Content-Type"), Some("application/dicom"));
+        assert_eq!(instance.data, b"DICM");
+        assert_eq!(related.get("instance@example.com"), Some(instance));
+        assert_eq!(related.get("other@example.com"), None);
+
+        let related = Multipart::parse_related(BODY, "multipart/related; boundary=boundary; \
+                                                      start=\"<instance@example.com>\"").unwrap();
+        assert_eq!(related.start, Some("instance@example.com"));
+        assert_eq!(related.root().unwrap().data, b"DICM");
+
+        assert_eq!(Multipart::parse_related(BODY, "multipart/related"), Err(RelatedError::MissingBoundary));
+        assert_eq!(Multipart::parse_related(&BODY[..20], "multipart/related; boundary=boundary"),
+                   Err(RelatedError::Parse(ParseError::UnexpectedEof)));
+    }
+
+    #[test]
+    fn test_error_source() {
+        use std::error::Error;
+
+        assert!(RelatedError::MissingBoundary.source().is_none());
+
+        let err = RelatedError::Parse(ParseError::UnexpectedEof);
+        assert_eq!(err.source().unwrap().downcast_ref::<ParseError>(), Some(&ParseError::UnexpectedEof));
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Reading `multipart/x-mixed-replace` streams, in which each part replaces the previous one,
+//! as sent by MJPEG cameras and long-lived event streams.
+use mime::Mime;
+
+use std::io::{self, Read};
+
+use super::Multipart;
+
+/// A part of a `multipart/x-mixed-replace` stream, read entirely into memory by
+/// [`Multipart::read_replacement()`](struct.Multipart.html#method.read_replacement).
+#[derive(Clone, Debug)]
+pub struct Replacement {
+    /// The `Content-Type` of this part, if supplied and valid, such as `image/jpeg`.
+    pub content_type: Option<Mime>,
+    /// The entire header block of this part, without the terminating empty line.
+    pub headers: String,
+    /// The contents of this part.
+    pub data: Vec<u8>,
+}
+
+impl<B: Read> Multipart<B> {
+    /// Read the next part of a `multipart/x-mixed-replace` stream into memory, as soon as it
+    /// has arrived.
+    ///
+    /// If the part has a `Content-Length` header, exactly that many bytes are read, so the part
+    /// is returned without waiting for the boundary after it, which servers may only send along
+    /// with the next part. Otherwise, the part is read up to the next boundary.
+    ///
+    /// Returns `Ok(None)` at the closing boundary, or if the stream ends between parts.
+    ///
+    /// ##Errors
+    /// If the part is larger than `limit` bytes, with an error of kind `InvalidData`.
+    ///
+    /// ```rust
+    /// use multipart::server::Multipart;
+    ///
+    /// let body: &[u8] = b"--frame\r\n\
+    ///     Content-Type: image/jpeg\r\n\
+    ///     Content-Length: 4\r\n\r\n\
+    ///     \xFF\xD8\xFF\xD9\r\n--frame\r\n\
+    ///     Content-Type: image/jpeg\r\n\r\n\
+    ///     \xFF\xD8\xFF\xD9\r\n--frame--\r\n";
+    ///
+    /// let mut stream = Multipart::with_body(body, "frame");
+    /// let mut frames = 0;
+    ///
+    /// while let Some(frame) = stream.read_replacement(1024 * 1024).unwrap() {
+    ///     assert_eq!(frame.data, b"\xFF\xD8\xFF\xD9");
+    ///     frames += 1;
+    /// }
+    ///
+    /// assert_eq!(frames, 2);
+    /// ```
+    pub fn read_replacement(&mut self, limit: u64) -> io::Result<Option<Replacement>> {
+        let (content_type, headers, content_length) = match try!(self.read_part()) {
+            Some(part) => (
+                part.content_type(),
+                part.headers().to_owned(),
+                part.header("Content-Length").and_then(|len| len.parse::<u64>().ok()),
+            ),
+            None => return Ok(None),
+        };
+
+        let mut data = Vec::new();
+
+        match content_length {
+            Some(len) if len > limit => return Err(too_large(limit)),
+            Some(len) => {
+                data.resize(len as usize, 0);
+                try!(self.read_exact_unchecked(&mut data));
+            },
+            None => {
+                try!((&mut self.source).take(limit + 1).read_to_end(&mut data));
+
+                if data.len() as u64 > limit {
+                    return Err(too_large(limit));
+                }
+            },
+        }
+
+        Ok(Some(Replacement {
+            content_type: content_type,
+            headers: headers,
+            data: data,
+        }))
+    }
+
+    /// Fill `buf` from the current part without waiting for the boundary after it.
+    fn read_exact_unchecked(&mut self, mut buf: &mut [u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.source.read_unchecked(buf) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(read) => buf = &mut {buf}[read..],
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn too_large(limit: u64) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData,
+                   format!("multipart/x-mixed-replace part larger than {} bytes", limit))
+}
+
+#[cfg(test)]
+mod test {
+    use server::Multipart;
+
+    use std::io::{self, Read};
+
+    /// Returns an error once `data` has been read, like a connection whose next part hasn't
+    /// arrived yet.
+    struct Pending<'a>(&'a [u8]);
+
+    impl<'a> Read for Pending<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::Other, "would block"));
+            }
+
+            self.0.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_read_replacement() {
+        // The boundary after the second part hasn't been sent yet.
+        let body = b"--frame\r\n\
+            Content-Type: text/plain\r\n\
+            Content-Length: 5\r\n\r\n\
+            first\r\n--frame\r\n\
+            Content-Type: text/plain\r\n\
+            Content-Length: 6\r\n\r\n\
+            second";
+
+        let mut stream = Multipart::with_body(Pending(body), "frame");
+
+        let first = stream.read_replacement(1024).unwrap().unwrap();
+        assert_eq!(first.content_type, Some("text/plain".parse().unwrap()));
+        assert_eq!(first.data, b"first");
+
+        assert_eq!(stream.read_replacement(1024).unwrap().unwrap().data, b"second");
+        assert!(stream.read_replacement(1024).is_err());
+
+        let body = b"--frame\r\nContent-Type: text/plain\r\n\r\ntoo long\r\n--frame--\r\n";
+        let err = Multipart::with_body(&body[..], "frame").read_replacement(4).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_replace_writer() {
+        use client::replace::ReplaceWriter;
+        use server::parse_boundary;
+
+        let mut writer = ReplaceWriter::new(Vec::new());
+        let content_type = writer.content_type();
+
+        writer.write_part(&"text/plain".parse().unwrap(), b"first").unwrap();
+        writer.write_part(&"text/plain".parse().unwrap(), b"second").unwrap();
+        let body = writer.finish().unwrap();
+
+        let mut stream = Multipart::with_body(&body[..], parse_boundary(&content_type).unwrap());
+        assert_eq!(stream.read_replacement(1024).unwrap().unwrap().data, b"first");
+        assert_eq!(stream.read_replacement(1024).unwrap().unwrap().data, b"second");
+        assert!(stream.read_replacement(1024).unwrap().is_none());
+    }
+}
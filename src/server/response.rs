@@ -0,0 +1,237 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Writing multipart response bodies.
+use mime::Mime;
+
+use std::io::{self, Read, Write};
+
+use super::quote_param;
+
+/// The length of the boundaries generated by [`ResponseWriter`](struct.ResponseWriter.html).
+const BOUNDARY_LEN: usize = 32;
+
+/// A writer of multipart response bodies of any subtype, such as `form-data`, `mixed` or
+/// `related`, into any `Write`, for servers which return multipart payloads.
+///
+/// Like the client's `Multipart`, parts are written as they are added. Form fields are added
+/// with `write_text()` and `write_stream()`, and other parts with their own headers with
+/// `write_part()`. `finish()` writes the closing boundary.
+///
+/// ```rust
+/// use multipart::server::{Multipart, ResponseWriter};
+///
+/// let mut writer = ResponseWriter::new(Vec::new(), "mixed");
+///
+/// // Set as the `Content-Type` header of the response.
+/// let content_type = writer.content_type();
+///
+/// writer.write_part(&[("Content-Type", "application/json")], &b"{\"id\": 1}"[..]).unwrap()
+///     .write_part(&[("Content-Type", "application/json")], &b"{\"id\": 2}"[..]).unwrap();
+///
+/// let boundary = writer.boundary().to_owned();
+/// let body = writer.finish().unwrap();
+///
+/// let mut multipart = Multipart::with_body(&body[..], boundary);
+/// assert_eq!(multipart.read_part().unwrap().unwrap().header("Content-Type"), Some("application/json"));
+/// ```
+#[derive(Debug)]
+pub struct ResponseWriter<W: Write> {
+    inner: W,
+    subtype: String,
+    params: Vec<(String, String)>,
+    boundary: String,
+    data_written: bool,
+}
+
+impl<W: Write> ResponseWriter<W> {
+    /// Write a `multipart/{subtype}` body to `inner`, with a randomly generated boundary.
+    pub fn new<S: Into<String>>(inner: W, subtype: S) -> Self {
+        ResponseWriter {
+            inner: inner,
+            subtype: subtype.into(),
+            params: Vec::new(),
+            boundary: ::random_alphanumeric(BOUNDARY_LEN),
+            data_written: false,
+        }
+    }
+
+    /// Use the given boundary instead of a randomly generated one.
+    ///
+    /// The boundary must not occur in any of the parts.
+    ///
+    /// ## Panics
+    /// If a part was already written, or if `boundary` is not a valid boundary per RFC 2046: 1
+    /// to 70 characters, consisting of ASCII letters and digits, or any of `'()+_,-./:=?` and
+    /// space (not at the end).
+    pub fn set_boundary<B: Into<String>>(&mut self, boundary: B) -> &mut Self {
+        assert!(!self.data_written, "`set_boundary()` called after writing a part");
+
+        let boundary = boundary.into();
+        ::assert_valid_boundary(&boundary);
+        self.boundary = boundary;
+        self
+    }
+
+    /// Add a parameter to the `Content-Type` of the body, such as the `type` and `start`
+    /// parameters of `multipart/related`.
+    ///
+    /// ## Panics
+    /// If `name` is not a valid parameter name, or if `value` contains a line break.
+    pub fn set_param<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) -> &mut Self {
+        let (name, value) = (name.into(), value.into());
+        ::assert_valid_header(&name, &value);
+        self.params.push((name, value));
+        self
+    }
+
+    /// The boundary of the body.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Get the value of the `Content-Type` header for the body, including the boundary and any
+    /// parameters added with `set_param()`.
+    pub fn content_type(&self) -> String {
+        let mut content_type = format!("multipart/{}; boundary={}", self.subtype, quote_param(&self.boundary));
+
+        for &(ref name, ref value) in &self.params {
+            content_type.push_str(&format!("; {}={}", name, quote_param(value)));
+        }
+
+        content_type
+    }
+
+    /// Write a text field of a `multipart/form-data` body.
+    pub fn write_text<N: AsRef<str>, T: AsRef<str>>(&mut self, name: N, text: T) -> io::Result<&mut Self> {
+        self.write_field(name.as_ref(), None, None, text.as_ref().as_bytes())
+    }
+
+    /// Write a file field of a `multipart/form-data` body, whose contents are read from
+    /// `stream` to EOF.
+    ///
+    /// If `content_type` is `None`, `application/octet-stream` is sent.
+    pub fn write_stream<N: AsRef<str>, R: Read>(&mut self, name: N, stream: R, filename: Option<&str>,
+                                               content_type: Option<&Mime>) -> io::Result<&mut Self> {
+        let content_type = content_type.cloned().unwrap_or_else(::mime_guess::octet_stream);
+        self.write_field(name.as_ref(), filename, Some(&content_type), stream)
+    }
+
+    /// Write a part with the given headers, such as `Content-Type` and `Content-ID`, whose
+    /// contents are read from `data` to EOF.
+    ///
+    /// ## Panics
+    /// If a header name is invalid, or a header value contains a line break.
+    pub fn write_part<R: Read>(&mut self, headers: &[(&str, &str)], mut data: R) -> io::Result<&mut Self> {
+        for &(name, value) in headers {
+            ::assert_valid_header(name, value);
+        }
+
+        try!(self.write_boundary());
+
+        for &(name, value) in headers {
+            try!(write!(self.inner, "\r\n{}: {}", name, value));
+        }
+
+        try!(self.inner.write_all(b"\r\n\r\n"));
+        try!(io::copy(&mut data, &mut self.inner));
+        Ok(self)
+    }
+
+    /// Write the closing boundary, and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        try!(write!(self.inner, "\r\n--{}--\r\n", self.boundary));
+        try!(self.inner.flush());
+        Ok(self.inner)
+    }
+
+    fn write_field<R: Read>(&mut self, name: &str, filename: Option<&str>, content_type: Option<&Mime>,
+                            mut data: R) -> io::Result<&mut Self> {
+        try!(self.write_boundary());
+        try!(write!(self.inner, "\r\nContent-Disposition: form-data; name=\"{}\"", escape_quoted(name)));
+
+        if let Some(filename) = filename {
+            try!(write!(self.inner, "; filename=\"{}\"", escape_quoted(filename)));
+        }
+
+        if let Some(content_type) = content_type {
+            try!(write!(self.inner, "\r\nContent-Type: {}", content_type));
+        }
+
+        try!(self.inner.write_all(b"\r\n\r\n"));
+        try!(io::copy(&mut data, &mut self.inner));
+        Ok(self)
+    }
+
+    /// Write the boundary before a part, which is preceded by a line break unless it is the
+    /// first.
+    fn write_boundary(&mut self) -> io::Result<()> {
+        if self.data_written {
+            try!(self.inner.write_all(b"\r\n"));
+        }
+
+        self.data_written = true;
+        write!(self.inner, "--{}", self.boundary)
+    }
+}
+
+/// Escape a field name or filename for a quoted `Content-Disposition` parameter the way browsers
+/// do, replacing `"`, CR and LF with percent escapes.
+fn escape_quoted(val: &str) -> String {
+    val.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod test {
+    use super::ResponseWriter;
+
+    use server::Multipart;
+
+    use std::io::Read;
+
+    #[test]
+    fn test_form_data_response() {
+        let mut writer = ResponseWriter::new(Vec::new(), "form-data");
+        writer.set_boundary("boundary");
+        assert_eq!(writer.content_type(), "multipart/form-data; boundary=boundary");
+
+        writer.write_text("text", "value").unwrap()
+            .write_stream("file", &b"contents"[..], Some("a\"b.txt"), Some(&"text/plain".parse().unwrap()))
+            .unwrap();
+
+        let body = writer.finish().unwrap();
+        let mut multipart = Multipart::with_body(&body[..], "boundary");
+
+        {
+            let field = multipart.read_entry().unwrap().unwrap();
+            assert_eq!(field.name, "text");
+            assert_eq!(field.data.as_text(), Some("value"));
+        }
+
+        {
+            let mut field = multipart.read_entry().unwrap().unwrap();
+            assert_eq!(field.name, "file");
+
+            let file = field.data.as_file().unwrap();
+            assert_eq!(file.filename(), Some("a%22b.txt"));
+
+            let mut contents = String::new();
+            file.read_to_string(&mut contents).unwrap();
+            assert_eq!(contents, "contents");
+        }
+
+        assert!(multipart.read_entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_related_content_type() {
+        let mut writer = ResponseWriter::new(Vec::new(), "related");
+        writer.set_boundary("boundary").set_param("type", "application/json").set_param("start", "<root>");
+
+        assert_eq!(writer.content_type(),
+                   "multipart/related; boundary=boundary; type=\"application/json\"; start=\"<root>\"");
+    }
+}
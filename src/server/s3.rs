@@ -0,0 +1,483 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! A [`StorageBackend`](../storage/trait.StorageBackend.html) which streams files into Amazon S3
+//! multipart uploads with the [AWS SDK](https://github.com/awslabs/aws-sdk-rust). Enabled with
+//! the `s3` feature (optional).
+//!
+//! Each file field is sent to S3 in parts of [`part_size()`](struct.S3Storage.html#method.part_size)
+//! bytes as it is read, so at most one part per file is held in memory and nothing is written to
+//! local disk.
+//!
+//! The SDK is asynchronous, so [`S3Storage`](struct.S3Storage.html) blocks on its requests with a
+//! handle to a tokio runtime, and must be used from outside of it, such as with
+//! [`tokio::run_blocking()`](../tokio/fn.run_blocking.html) or on a thread of its own.
+//!
+//! This crate doesn't enable any of the SDK's optional features; configure the `Client` with an
+//! HTTP client and credentials as usual, such as with the `aws-config` crate.
+//!
+//! ```no_run
+//! # extern crate aws_sdk_s3;
+//! # extern crate multipart;
+//! use aws_sdk_s3::Client;
+//!
+//! use multipart::server::Multipart;
+//! use multipart::server::s3::S3Storage;
+//!
+//! use std::io::{self, Read};
+//!
+//! fn upload<R: Read>(multipart: &mut Multipart<R>, client: Client) -> io::Result<()> {
+//!     // Must be called within the runtime's context, such as from `spawn_blocking()`.
+//!     let mut storage = try!(S3Storage::new(client, "uploads-bucket"));
+//!     storage.key_prefix("incoming/");
+//!
+//!     match multipart.save_all_to(&mut storage) {
+//!         Ok(entries) => for (name, file) in entries.files {
+//!             println!("Uploaded {} to s3://{}/{}", name, file.stored.bucket, file.stored.key);
+//!         },
+//!         Err((_, err)) => println!("Error uploading files: {}", err),
+//!     }
+//!
+//!     Ok(())
+//! }
+//! # fn main() {}
+//! ```
+use aws_sdk_s3::Client;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+
+use tokio::runtime::Handle;
+
+use std::error::Error;
+use std::io::{self, Write};
+use std::{cmp, mem};
+
+use super::RANDOM_FILENAME_LEN;
+use super::storage::{FileMeta, StorageBackend};
+
+/// The smallest part size S3 accepts, other than for the last part of an upload.
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// The default size of the parts files are uploaded in.
+pub const DEFAULT_PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Stores files in an S3 bucket with multipart uploads, under random alphanumeric keys.
+#[derive(Clone, Debug)]
+pub struct S3Storage {
+    client: Client,
+    handle: Handle,
+    bucket: String,
+    key_prefix: String,
+    part_size: usize,
+}
+
+impl S3Storage {
+    /// Store files in `bucket` with `client`, blocking on its requests with the current tokio
+    /// runtime.
+    ///
+    /// ## Errors
+    /// If called outside of the context of a tokio runtime; use
+    /// [`with_handle()`](#method.with_handle) there instead.
+    pub fn new<B: Into<String>>(client: Client, bucket: B) -> io::Result<Self> {
+        let handle = try!(Handle::try_current().map_err(|err| io::Error::new(io::ErrorKind::Other, err)));
+        Ok(Self::with_handle(client, bucket, handle))
+    }
+
+    /// Store files in `bucket` with `client`, blocking on its requests with `handle`.
+    pub fn with_handle<B: Into<String>>(client: Client, bucket: B, handle: Handle) -> Self {
+        S3Storage {
+            client: client,
+            handle: handle,
+            bucket: bucket.into(),
+            key_prefix: String::new(),
+            part_size: DEFAULT_PART_SIZE,
+        }
+    }
+
+    /// Set a prefix for the keys of stored files, such as `"uploads/"`. Empty by default.
+    pub fn key_prefix<P: Into<String>>(&mut self, prefix: P) -> &mut Self {
+        self.key_prefix = prefix.into();
+        self
+    }
+
+    /// Set the size of the parts files are uploaded in, which is also the amount of memory
+    /// buffered for each file. Raised to at least [`MIN_PART_SIZE`](constant.MIN_PART_SIZE.html).
+    pub fn part_size(&mut self, part_size: usize) -> &mut Self {
+        self.part_size = cmp::max(part_size, MIN_PART_SIZE);
+        self
+    }
+
+    /// Get the bucket files are stored in.
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+}
+
+impl StorageBackend for S3Storage {
+    type Writer = S3Upload;
+    type Stored = S3Object;
+
+    fn open(&mut self, meta: &FileMeta) -> io::Result<S3Upload> {
+        let key = format!("{}{}", self.key_prefix, ::random_alphanumeric(RANDOM_FILENAME_LEN));
+
+        let output = try!(self.handle.block_on(
+            self.client.create_multipart_upload()
+                .bucket(&*self.bucket)
+                .key(&*key)
+                .content_type(meta.content_type.to_string())
+                .send()
+        ).map_err(s3_error));
+
+        let upload_id = try!(output.upload_id().ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidData, "S3 did not return an upload ID"
+        )));
+
+        debug!("Started S3 upload {} for s3://{}/{}", upload_id, self.bucket, key);
+
+        Ok(S3Upload {
+            client: self.client.clone(),
+            handle: self.handle.clone(),
+            bucket: self.bucket.clone(),
+            key: key,
+            upload_id: Some(upload_id.into()),
+            buf: Vec::with_capacity(self.part_size),
+            part_size: self.part_size,
+            parts: Vec::new(),
+        })
+    }
+
+    fn finalize(&mut self, upload: S3Upload, _: &FileMeta, _: u64) -> io::Result<S3Object> {
+        upload.complete()
+    }
+
+    /// Abort the upload, so S3 discards the parts uploaded so far.
+    fn discard(&mut self, upload: S3Upload, _: &FileMeta) -> io::Result<()> {
+        upload.abort()
+    }
+}
+
+/// A file stored in S3 by [`S3Storage`](struct.S3Storage.html).
+#[derive(Clone, Debug)]
+pub struct S3Object {
+    /// The bucket of the object.
+    pub bucket: String,
+    /// The key of the object.
+    pub key: String,
+    /// The entity tag of the object, if returned by S3.
+    pub e_tag: Option<String>,
+    /// The URL of the object, if returned by S3.
+    pub location: Option<String>,
+}
+
+/// A multipart upload in progress, started by [`S3Storage`](struct.S3Storage.html).
+///
+/// Data is uploaded whenever a full part has been written; `flush()` does nothing, as S3 does
+/// not accept smaller parts except for the last one. If dropped before it's completed or
+/// aborted, the abort is spawned on the runtime rather than blocked on, as this may be
+/// dropped anywhere, including on one of its threads.
+#[derive(Debug)]
+pub struct S3Upload {
+    client: Client,
+    handle: Handle,
+    bucket: String,
+    key: String,
+    /// Taken when the upload is completed.
+    upload_id: Option<String>,
+    buf: Vec<u8>,
+    part_size: usize,
+    parts: Vec<CompletedPart>,
+}
+
+impl S3Upload {
+    /// Get the key of the object being uploaded.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    fn upload_part(&mut self) -> io::Result<()> {
+        let body = mem::replace(&mut self.buf, Vec::with_capacity(self.part_size));
+        let part_number = self.parts.len() as i32 + 1;
+
+        let output = try!(self.handle.block_on(
+            self.client.upload_part()
+                .bucket(&*self.bucket)
+                .key(&*self.key)
+                .upload_id(self.upload_id.as_ref().map_or("", |id| &**id))
+                .part_number(part_number)
+                .body(ByteStream::from(body))
+                .send()
+        ).map_err(s3_error));
+
+        self.parts.push(CompletedPart::builder()
+            .part_number(part_number)
+            .set_e_tag(output.e_tag().map(Into::into))
+            .build());
+
+        Ok(())
+    }
+
+    fn complete(mut self) -> io::Result<S3Object> {
+        // An upload needs at least one part, even if it's empty.
+        if !self.buf.is_empty() || self.parts.is_empty() {
+            try!(self.upload_part());
+        }
+
+        let upload = CompletedMultipartUpload::builder()
+            .set_parts(Some(mem::replace(&mut self.parts, Vec::new())))
+            .build();
+
+        let output = try!(self.handle.block_on(
+            self.client.complete_multipart_upload()
+                .bucket(&*self.bucket)
+                .key(&*self.key)
+                .upload_id(self.upload_id.as_ref().map_or("", |id| &**id))
+                .multipart_upload(upload)
+                .send()
+        ).map_err(s3_error));
+
+        self.upload_id = None;
+
+        Ok(S3Object {
+            bucket: mem::replace(&mut self.bucket, String::new()),
+            key: mem::replace(&mut self.key, String::new()),
+            e_tag: output.e_tag().map(Into::into),
+            location: output.location().map(Into::into),
+        })
+    }
+
+    fn abort(mut self) -> io::Result<()> {
+        let upload_id = match self.upload_id.take() {
+            Some(upload_id) => upload_id,
+            None => return Ok(()),
+        };
+
+        try!(self.handle.block_on(
+            self.client.abort_multipart_upload()
+                .bucket(&*self.bucket)
+                .key(&*self.key)
+                .upload_id(&*upload_id)
+                .send()
+        ).map_err(s3_error));
+
+        debug!("Aborted S3 upload {} of s3://{}/{}", upload_id, self.bucket, self.key);
+
+        Ok(())
+    }
+}
+
+impl Write for S3Upload {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.buf.len() == self.part_size {
+            try!(self.upload_part());
+        }
+
+        let len = cmp::min(data.len(), self.part_size - self.buf.len());
+        self.buf.extend_from_slice(&data[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for S3Upload {
+    fn drop(&mut self) {
+        if let Some(upload_id) = self.upload_id.take() {
+            warn!("S3 upload {} of s3://{}/{} dropped, aborting it in the background", upload_id, self.bucket,
+                  self.key);
+
+            // Errors can't be reported from here; S3 bucket lifecycle rules can clean up
+            // incomplete uploads which weren't aborted.
+            self.handle.spawn(
+                self.client.abort_multipart_upload()
+                    .bucket(&*self.bucket)
+                    .key(&*self.key)
+                    .upload_id(upload_id)
+                    .send()
+            );
+        }
+    }
+}
+
+fn s3_error<E: Into<Box<Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use aws_sdk_s3::config::http::{HttpRequest, HttpResponse};
+    use aws_sdk_s3::config::retry::RetryConfig;
+    use aws_sdk_s3::config::{AsyncSleep, BehaviorVersion, Credentials, Region, Sleep};
+    use aws_sdk_s3::primitives::SdkBody;
+
+    use aws_smithy_runtime_api::client::http::{http_client_fn, HttpConnector, HttpConnectorFuture,
+                                                SharedHttpConnector};
+    use aws_smithy_runtime_api::http::StatusCode;
+
+    use tokio::runtime::{Builder, Runtime};
+
+    use std::convert::TryFrom;
+    use std::sync::{Arc, Mutex};
+
+    use server::{FieldHeaders, Multipart, Verdict};
+
+    const BODY: &'static str = "--boundary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        contents\r\n--boundary--\r\n";
+
+    /// Answers the requests of multipart uploads, recording their methods and whether they
+    /// start an upload or belong to one.
+    #[derive(Clone, Debug, Default)]
+    struct MockS3 {
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl MockS3 {
+        fn requests(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl HttpConnector for MockS3 {
+        fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+            let initiate = request.uri().ends_with("?uploads") || request.uri().contains("?uploads&");
+            let op = format!("{} {}", request.method(), if initiate { "uploads" } else { "uploadId" });
+
+            let (status, body): (u16, _) = match &*op {
+                "POST uploads" => (200, "<InitiateMultipartUploadResult><Bucket>bucket</Bucket>\
+                    <Key>key</Key><UploadId>upload-id</UploadId></InitiateMultipartUploadResult>"),
+                "PUT uploadId" => (200, ""),
+                "POST uploadId" => (200, "<CompleteMultipartUploadResult>\
+                    <Location>https://bucket.s3.amazonaws.com/key</Location><Bucket>bucket</Bucket>\
+                    <Key>key</Key><ETag>\"etag\"</ETag></CompleteMultipartUploadResult>"),
+                "DELETE uploadId" => (204, ""),
+                _ => (400, ""),
+            };
+
+            self.requests.lock().unwrap().push(op);
+
+            let mut response = HttpResponse::new(StatusCode::try_from(status).unwrap(), SdkBody::from(body));
+            response.headers_mut().insert("ETag", "\"part-etag\"");
+            HttpConnectorFuture::ready(Ok(response))
+        }
+    }
+
+    /// Sleeps forever, as the requests to `MockS3` can't time out.
+    #[derive(Debug)]
+    struct NoSleep;
+
+    impl AsyncSleep for NoSleep {
+        fn sleep(&self, _: ::std::time::Duration) -> Sleep {
+            Sleep::new(::std::future::pending())
+        }
+    }
+
+    /// A client which sends its requests to `mock`.
+    fn client(mock: &MockS3) -> Client {
+        let mock = mock.clone();
+
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("access-key", "secret-key", None, None, "test"))
+            .retry_config(RetryConfig::disabled())
+            .sleep_impl(NoSleep)
+            .http_client(http_client_fn(move |_, _| SharedHttpConnector::new(mock.clone())))
+            .build();
+
+        Client::from_conf(config)
+    }
+
+    fn runtime() -> Runtime {
+        Builder::new_current_thread().build().unwrap()
+    }
+
+    #[test]
+    fn test_new_outside_runtime() {
+        let _ = ::env_logger::init();
+
+        assert!(S3Storage::new(client(&MockS3::default()), "bucket").is_err());
+
+        let runtime = runtime();
+        let _guard = runtime.enter();
+        assert!(S3Storage::new(client(&MockS3::default()), "bucket").is_ok());
+    }
+
+    #[test]
+    fn test_upload() {
+        let _ = ::env_logger::init();
+
+        let runtime = runtime();
+        let mock = MockS3::default();
+        let mut storage = S3Storage::with_handle(client(&mock), "bucket", runtime.handle().clone());
+        storage.key_prefix("incoming/");
+
+        let entries = Multipart::with_body(BODY.as_bytes(), "boundary").save_all_to(&mut storage).unwrap();
+        let stored = &entries.files["file"].stored;
+        assert_eq!(stored.bucket, "bucket");
+        assert!(stored.key.starts_with("incoming/"));
+        assert_eq!(stored.e_tag.as_ref().map(|s| &**s), Some("\"etag\""));
+
+        assert_eq!(mock.requests(), ["POST uploads", "PUT uploadId", "POST uploadId"]);
+    }
+
+    #[test]
+    fn test_discard() {
+        let _ = ::env_logger::init();
+
+        let runtime = runtime();
+        let mock = MockS3::default();
+        let storage = S3Storage::with_handle(client(&mock), "bucket", runtime.handle().clone());
+
+        let reject = |_: &FieldHeaders, _: &mut ::std::io::Read| -> io::Result<Verdict> {
+            Ok(Verdict::Reject("rejected".into()))
+        };
+
+        Multipart::with_body(BODY.as_bytes(), "boundary").save_all_inspected(storage, reject).unwrap_err();
+
+        // The abort is sent before returning.
+        assert_eq!(mock.requests(), ["POST uploads", "DELETE uploadId"]);
+    }
+
+    #[test]
+    fn test_drop() {
+        let _ = ::env_logger::init();
+
+        let runtime = runtime();
+        let mock = MockS3::default();
+        let mut storage = S3Storage::with_handle(client(&mock), "bucket", runtime.handle().clone());
+
+        let content_type = ::mime::Mime(::mime::TopLevel::Text, ::mime::SubLevel::Plain, vec![]);
+        let meta = FileMeta {
+            field_name: "file",
+            filename: None,
+            content_type: &content_type,
+            #[cfg(feature = "infer")]
+            detected_type: None,
+        };
+
+        let mut upload = storage.open(&meta).unwrap();
+        upload.write_all(b"contents").unwrap();
+
+        // Dropping doesn't block on the abort, which runs once the runtime is driven.
+        drop(upload);
+        assert_eq!(mock.requests().len(), 1);
+
+        for _ in 0 .. 100 {
+            if mock.requests().len() == 2 {
+                break;
+            }
+
+            runtime.block_on(::tokio::task::yield_now());
+        }
+
+        assert_eq!(mock.requests(), ["POST uploads", "DELETE uploadId"]);
+    }
+}
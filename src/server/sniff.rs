@@ -0,0 +1,130 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Detection of the types of files from their magic bytes, to compare to their declared
+//! `Content-Type`.
+use mime::{Mime, SubLevel, TopLevel};
+
+use std::{error, fmt, io};
+
+/// Detect the type of a file from its first bytes, if it is a known binary format.
+pub fn detect(data: &[u8]) -> Option<Mime> {
+    ::infer::get(data).and_then(|ty| ty.mime_type().parse().ok())
+}
+
+/// Returns `true` if the `detected` type of a file contradicts its `declared` type.
+///
+/// `application/octet-stream` makes no claim about the contents of a file, so it never
+/// mismatches.
+pub fn is_mismatch(declared: &Mime, detected: &Mime) -> bool {
+    if declared.0 == TopLevel::Application && declared.1 == SubLevel::OctetStream {
+        return false;
+    }
+
+    !declared.0.eq_ignore_ascii_case(&detected.0) || !declared.1.eq_ignore_ascii_case(&detected.1)
+}
+
+/// The error of a file whose type detected from its contents contradicts its declared
+/// `Content-Type`, returned by
+/// [`LocalStorage::reject_type_mismatch()`](storage/struct.LocalStorage.html#method.reject_type_mismatch).
+///
+/// Returned wrapped in an `io::Error` of kind `io::ErrorKind::InvalidData`, from which it can be
+/// recovered with `get_ref()` and `downcast_ref()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeMismatch {
+    /// The `Content-Type` of the file, as sent by the client.
+    pub declared: Mime,
+    /// The type detected from the contents of the file.
+    pub detected: Mime,
+}
+
+impl error::Error for TypeMismatch {}
+
+impl fmt::Display for TypeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "multipart file of type {} declared as {}", self.detected, self.declared)
+    }
+}
+
+impl From<TypeMismatch> for io::Error {
+    fn from(err: TypeMismatch) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{detect, is_mismatch, TypeMismatch};
+
+    use server::Multipart;
+    use server::storage::LocalStorage;
+
+    use tempdir::TempDir;
+
+    use std::io;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[test]
+    fn test_detect() {
+        let png = detect(PNG).unwrap();
+        assert_eq!(png.to_string(), "image/png");
+        assert_eq!(detect(b"plain text"), None);
+
+        assert!(!is_mismatch(&"image/png".parse().unwrap(), &png));
+        assert!(!is_mismatch(&"IMAGE/PNG".parse().unwrap(), &png));
+        assert!(!is_mismatch(&"application/octet-stream".parse().unwrap(), &png));
+        assert!(is_mismatch(&"image/jpeg".parse().unwrap(), &png));
+        assert!(is_mismatch(&"text/plain".parse().unwrap(), &png));
+    }
+
+    fn body(content_type: &str, data: &[u8]) -> Vec<u8> {
+        let mut body = format!("--boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"file\"\r\n\
+            Content-Type: {}\r\n\r\n", content_type).into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n--boundary--\r\n");
+        body
+    }
+
+    #[test]
+    fn test_file_type_mismatch() {
+        // An ELF executable claiming to be a PNG.
+        let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+        elf.resize(64, 0);
+        let body = body("image/png", &elf);
+
+        let mut multipart = Multipart::with_body(&body[..], "boundary");
+        let mut field = multipart.read_entry().unwrap().unwrap();
+        let file = field.data.as_file().unwrap();
+
+        assert_eq!(file.detected_type().unwrap().unwrap().to_string(), "application/x-executable");
+        assert!(file.type_mismatch().unwrap());
+
+        // Sniffing doesn't consume any of the file.
+        let mut data = Vec::new();
+        file.save_to(&mut data).unwrap();
+        assert_eq!(data, elf);
+
+        let dir = TempDir::new("multipart-sniff").unwrap();
+        let mut storage = LocalStorage::new(dir.path());
+
+        let entries = Multipart::with_body(&body[..], "boundary").save_all_to(&mut storage).unwrap();
+        assert_eq!(entries.files["file"].detected_type.as_ref().unwrap().to_string(),
+                   "application/x-executable");
+
+        storage.reject_type_mismatch(true);
+        let (_, err) = Multipart::with_body(&body[..], "boundary").save_all_to(&mut storage).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mismatch = err.get_ref().and_then(|err| err.downcast_ref::<TypeMismatch>()).unwrap();
+        assert_eq!(mismatch.declared.to_string(), "image/png");
+        assert_eq!(mismatch.detected.to_string(), "application/x-executable");
+
+        let png = self::body("image/png", PNG);
+        Multipart::with_body(&png[..], "boundary").save_all_to(&mut storage).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Checks of the free space of the filesystem files are stored on, for
+//! `LocalStorage::check_free_space()`.
+use std::path::{Path, PathBuf};
+use std::{error, fmt, io};
+
+/// How many bytes are written to a file between checks of the free space.
+pub const FREE_SPACE_CHECK_INTERVAL: u64 = 16 * 1024 * 1024;
+
+/// Get the space available to unprivileged users on the filesystem of `dir`, in bytes.
+pub fn available(dir: &Path) -> io::Result<u64> {
+    let stat = try!(::rustix::fs::statvfs(dir));
+    Ok(stat.f_bavail.saturating_mul(stat.f_frsize))
+}
+
+/// Fail with [`InsufficientStorage`](struct.InsufficientStorage.html) if less than `required`
+/// bytes are available on the filesystem of `dir`.
+pub fn check(dir: &Path, required: u64) -> io::Result<()> {
+    let available = try!(available(dir));
+
+    if available < required {
+        return Err(InsufficientStorage {
+            available: available,
+            required: required,
+        }.into());
+    }
+
+    Ok(())
+}
+
+/// The free space required by a `LocalStorage`.
+#[derive(Clone, Debug)]
+pub struct FreeSpace {
+    /// The expected size of the request.
+    pub expected: u64,
+    /// The space to leave free.
+    pub reserve: u64,
+    /// The size of the files stored so far.
+    pub stored: u64,
+}
+
+impl FreeSpace {
+    /// The space required before storing the next file: the reserve, plus the part of the
+    /// request which hasn't been stored yet.
+    pub fn required(&self) -> u64 {
+        self.reserve.saturating_add(self.expected.saturating_sub(self.stored))
+    }
+}
+
+/// Checks that the reserve stays free while a file is written.
+#[derive(Debug)]
+pub struct SpaceCheck {
+    dir: PathBuf,
+    reserve: u64,
+    unchecked: u64,
+}
+
+impl SpaceCheck {
+    pub fn new(dir: PathBuf, reserve: u64) -> Self {
+        SpaceCheck {
+            dir: dir,
+            reserve: reserve,
+            unchecked: 0,
+        }
+    }
+
+    /// Count `len` bytes about to be written, checking the free space if enough were written
+    /// since the last check.
+    pub fn write(&mut self, len: usize) -> io::Result<()> {
+        self.unchecked += len as u64;
+
+        if self.unchecked < FREE_SPACE_CHECK_INTERVAL {
+            return Ok(());
+        }
+
+        self.unchecked = 0;
+        check(&self.dir, self.reserve.saturating_add(len as u64))
+    }
+}
+
+/// The error of a file which would not fit in the free space of the filesystem it is stored
+/// on, with `LocalStorage::check_free_space()`.
+///
+/// Returned wrapped in an `io::Error` of kind `io::ErrorKind::Other`, from which it can be
+/// recovered with `get_ref()` and `downcast_ref()`.
+///
+/// #### Feature: `diskspace` (Unix only)
+#[derive(Clone, Debug)]
+pub struct InsufficientStorage {
+    /// The space available, in bytes.
+    pub available: u64,
+    /// The space required, in bytes.
+    pub required: u64,
+}
+
+impl error::Error for InsufficientStorage {
+    fn description(&self) -> &str {
+        "insufficient free space to store file"
+    }
+}
+
+impl fmt::Display for InsufficientStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "insufficient free space to store file: {} bytes available, {} required",
+               self.available, self.required)
+    }
+}
+
+impl From<InsufficientStorage> for io::Error {
+    fn from(err: InsufficientStorage) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{available, InsufficientStorage};
+
+    use server::Multipart;
+    use server::storage::LocalStorage;
+
+    use tempdir::TempDir;
+
+    use std::fs;
+
+    const BODY: &[u8] = b"--boundary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        contents\r\n--boundary--\r\n";
+
+    #[test]
+    fn test_check_free_space() {
+        let dir = TempDir::new("multipart-space").unwrap();
+        let free = available(dir.path()).unwrap();
+        assert!(free > 0);
+
+        let mut storage = LocalStorage::new(dir.path());
+        storage.check_free_space(Some(BODY.len() as u64), 0);
+        Multipart::with_body(BODY, "boundary").save_all_to(&mut storage).unwrap();
+
+        storage.check_free_space(Some(u64::max_value() / 2), 0);
+        let (entries, err) = Multipart::with_body(BODY, "boundary").save_all_to(&mut storage).unwrap_err();
+        assert!(entries.files.is_empty());
+
+        let err = err.get_ref().and_then(|err| err.downcast_ref::<InsufficientStorage>()).unwrap();
+        assert_eq!(err.required, u64::max_value() / 2);
+
+        // Nothing was written for the rejected request.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
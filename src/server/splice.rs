@@ -0,0 +1,166 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Copying part bodies from a socket to a file with `splice()`, for
+//! `MultipartFile::save_as_splice()`.
+//!
+//! `sendfile()` can only read from files, so the data is moved from the socket to a pipe and
+//! from the pipe to the file instead, without being copied to userspace. It is only read back
+//! once from the page cache, to check that it doesn't contain the boundary.
+
+use libc;
+
+use memchr::memmem::Finder;
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+
+/// The most data moved by a single `splice()` call, which is the default capacity of a pipe.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Move up to `len` bytes from `src` to the end of `dst`, returning the number of bytes moved,
+/// which is less than `len` if `src` reached EOF.
+///
+/// Returns `Ok(None)` if `src` doesn't support `splice()`, before any data is moved.
+pub fn splice_to_file(src: RawFd, dst: &File, len: u64) -> io::Result<Option<u64>> {
+    let pipe = try!(Pipe::new());
+    let mut moved = 0;
+
+    while moved < len {
+        let chunk = chunk_len(len - moved);
+
+        // SAFETY: `src` is owned by the caller and the write end of `pipe` is owned by `pipe`,
+        // so both stay open for the duration of the call; the null offsets make the kernel use
+        // and advance the file positions instead of reading through any pointer.
+        #[allow(unsafe_code)]
+        let read = unsafe {
+            libc::splice(src, ptr::null_mut(), pipe.write, ptr::null_mut(), chunk,
+                         libc::SPLICE_F_MOVE | libc::SPLICE_F_MORE)
+        };
+
+        if read < 0 {
+            let err = io::Error::last_os_error();
+
+            match err.raw_os_error() {
+                Some(libc::EINTR) => continue,
+                Some(libc::EINVAL) if moved == 0 => return Ok(None),
+                _ => return Err(err),
+            }
+        }
+
+        if read == 0 {
+            break;
+        }
+
+        try!(drain_pipe(&pipe, dst, read as usize));
+        moved += read as u64;
+    }
+
+    Ok(Some(moved))
+}
+
+/// Returns `true` if the contents of `file` contain `needle`, reading them back from the start.
+///
+/// The data moved by `splice_to_file()` was never searched for the boundary, so this checks
+/// it afterwards; it is read from the page cache, as it was just written.
+pub fn file_contains(file: &mut File, needle: &[u8]) -> io::Result<bool> {
+    let finder = Finder::new(needle);
+    let mut buf = vec![0; CHUNK_SIZE + needle.len()];
+    // The bytes at the end of the last chunk, which may be the start of `needle`.
+    let mut kept = 0;
+
+    try!(file.seek(SeekFrom::Start(0)));
+
+    loop {
+        let read = match file.read(&mut buf[kept..]) {
+            Ok(0) => return Ok(false),
+            Ok(read) => read,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+
+        let end = kept + read;
+
+        if finder.find(&buf[..end]).is_some() {
+            return Ok(true);
+        }
+
+        kept = cmp::min(needle.len() - 1, end);
+        buf.copy_within(end - kept .. end, 0);
+    }
+}
+
+fn chunk_len(remaining: u64) -> usize {
+    if remaining < CHUNK_SIZE as u64 { remaining as usize } else { CHUNK_SIZE }
+}
+
+/// Move `len` bytes which were just written to `pipe` to `dst`.
+fn drain_pipe(pipe: &Pipe, dst: &File, mut len: usize) -> io::Result<()> {
+    while len > 0 {
+        // SAFETY: the read end of `pipe` is owned by `pipe` and `dst` is borrowed, so both
+        // stay open for the duration of the call; the offsets are null, as above.
+        #[allow(unsafe_code)]
+        let written = unsafe {
+            libc::splice(pipe.read, ptr::null_mut(), dst.as_raw_fd(), ptr::null_mut(), len,
+                         libc::SPLICE_F_MOVE | libc::SPLICE_F_MORE)
+        };
+
+        if written < 0 {
+            let err = io::Error::last_os_error();
+
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+
+            return Err(err);
+        }
+
+        if written == 0 {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write field data to file"));
+        }
+
+        len -= written as usize;
+    }
+
+    Ok(())
+}
+
+struct Pipe {
+    read: RawFd,
+    write: RawFd,
+}
+
+impl Pipe {
+    fn new() -> io::Result<Pipe> {
+        let mut fds = [0; 2];
+
+        // SAFETY: `pipe2()` writes exactly two file descriptors through the pointer, and `fds`
+        // is a live array with room for both.
+        #[allow(unsafe_code)]
+        let ret = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) };
+
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Pipe { read: fds[0], write: fds[1] })
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        // SAFETY: the file descriptors were opened by `Pipe::new()`, aren't shared with
+        // anything else and are closed only here, so they can't be closed twice.
+        #[allow(unsafe_code)]
+        unsafe {
+            libc::close(self.read);
+            libc::close(self.write);
+        }
+    }
+}
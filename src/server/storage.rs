@@ -0,0 +1,1010 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Pluggable destinations for the files saved from a request.
+//!
+//! [`Multipart::save_all_to()`](../struct.Multipart.html#method.save_all_to) reads the request
+//! fully, keeping text fields in memory and writing each file field to a
+//! [`StorageBackend`](trait.StorageBackend.html), so uploads can be sent to memory, object
+//! stores or databases without reimplementing the save loop. The `save_all*()` methods of
+//! `Multipart` use [`LocalStorage`](struct.LocalStorage.html), which writes files to a directory.
+//!
+//! ```rust
+//! use multipart::server::Multipart;
+//! use multipart::server::storage::MemoryStorage;
+//!
+//! let body: &[u8] = b"--boundary\r\n\
+//!     Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+//!     Content-Type: text/plain\r\n\r\n\
+//!     contents\r\n--boundary--\r\n";
+//!
+//! let entries = Multipart::with_body(body, "boundary").save_all_to(&mut MemoryStorage).unwrap();
+//! assert_eq!(entries.files["file"].stored, b"contents");
+//! ```
+use mime::Mime;
+
+use std::collections::HashMap;
+#[cfg(feature = "sha2")]
+use std::collections::hash_map::Entry;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "zstd")]
+use super::compress::Compress;
+#[cfg(feature = "encrypt")]
+use super::encrypt::{Encrypt, EncryptionKey};
+use super::filename;
+#[cfg(feature = "flate2")]
+use super::gunzip::Gunzip;
+use super::hash::HashWriter;
+use super::inspect::{PartInspector, PartRejected, Tee, Verdict};
+#[cfg(feature = "infer")]
+use super::sniff::{self, TypeMismatch};
+#[cfg(all(feature = "diskspace", unix))]
+use super::space::{self, FreeSpace, SpaceCheck};
+use super::{create_full_path, FieldHeaders, FilenamePolicy, Multipart, MultipartData,
+            SaveLimiter, SavePermit, SavedFile, TypePolicy, RANDOM_FILENAME_LEN};
+
+/// Information about a file field which is being stored.
+#[derive(Clone, Copy, Debug)]
+pub struct FileMeta<'a> {
+    /// The field's name from the form.
+    pub field_name: &'a str,
+    /// The filename of this field, if supplied.
+    ///
+    /// ##Warning
+    /// You should treat this value as untrustworthy because it is an arbitrary string provided by
+    /// the client.
+    pub filename: Option<&'a str>,
+    /// The MIME type of this field.
+    pub content_type: &'a Mime,
+    /// The type of this field detected from its contents, if it is a known binary format.
+    ///
+    /// #### Feature: `infer`
+    #[cfg(feature = "infer")]
+    pub detected_type: Option<&'a Mime>,
+}
+
+impl<'a> FileMeta<'a> {
+    /// Returns `true` if the detected type of this field contradicts its declared
+    /// `content_type`; see
+    /// [`MultipartFile::type_mismatch()`](../struct.MultipartFile.html#method.type_mismatch).
+    ///
+    /// #### Feature: `infer`
+    #[cfg(feature = "infer")]
+    pub fn type_mismatch(&self) -> bool {
+        self.detected_type.map_or(false, |detected| sniff::is_mismatch(self.content_type, detected))
+    }
+}
+
+/// A destination for the file fields of a request.
+pub trait StorageBackend {
+    /// The writer which receives the contents of a file.
+    type Writer: Write;
+    /// What a file is represented by once stored, such as a path, key or URL.
+    type Stored;
+
+    /// Open a new target for the file described by `meta`.
+    fn open(&mut self, meta: &FileMeta) -> io::Result<Self::Writer>;
+
+    /// Finish storing a file after `size` bytes of it were written to `writer`.
+    fn finalize(&mut self, writer: Self::Writer, meta: &FileMeta, size: u64) -> io::Result<Self::Stored>;
+
+    /// Throw away a file which was rejected after it was opened, such as by a
+    /// [`PartInspector`](../trait.PartInspector.html), or which couldn't be read or written in
+    /// full.
+    ///
+    /// By default, `writer` is dropped.
+    fn discard(&mut self, writer: Self::Writer, meta: &FileMeta) -> io::Result<()> {
+        let _ = meta;
+        drop(writer);
+        Ok(())
+    }
+}
+
+impl<'a, S: StorageBackend + ?Sized> StorageBackend for &'a mut S {
+    type Writer = S::Writer;
+    type Stored = S::Stored;
+
+    fn open(&mut self, meta: &FileMeta) -> io::Result<S::Writer> {
+        (**self).open(meta)
+    }
+
+    fn finalize(&mut self, writer: S::Writer, meta: &FileMeta, size: u64) -> io::Result<S::Stored> {
+        (**self).finalize(writer, meta, size)
+    }
+
+    fn discard(&mut self, writer: S::Writer, meta: &FileMeta) -> io::Result<()> {
+        (**self).discard(writer, meta)
+    }
+}
+
+/// Stores files in a directory under random alphanumeric filenames, or under their normalized
+/// original filenames with [`original_filenames()`](#method.original_filenames).
+///
+/// This is the backend used by [`Multipart::save_all()`](../struct.Multipart.html#method.save_all)
+/// and related methods.
+#[derive(Clone, Debug)]
+pub struct LocalStorage {
+    dir: PathBuf,
+    filenames: Option<FilenamePolicy>,
+    extensions: bool,
+    limiter: Option<SaveLimiter>,
+    /// The paths of the files stored so far by their digest and size, if deduplicating.
+    #[cfg(feature = "sha2")]
+    stored: Option<HashMap<(String, u64), PathBuf>>,
+    /// The maximum decompressed size of gzip files, if decompressing them.
+    #[cfg(feature = "flate2")]
+    gunzip_max: Option<u64>,
+    #[cfg(feature = "flate2")]
+    gunzip_types: Option<Vec<Mime>>,
+    #[cfg(feature = "encrypt")]
+    key: Option<EncryptionKey>,
+    #[cfg(feature = "zstd")]
+    zstd_level: Option<i32>,
+    #[cfg(feature = "infer")]
+    reject_type_mismatch: bool,
+    #[cfg(all(feature = "diskspace", unix))]
+    free_space: Option<FreeSpace>,
+}
+
+impl LocalStorage {
+    /// Store files in `dir`, which is created if it does not exist.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        LocalStorage {
+            dir: dir.into(),
+            filenames: None,
+            extensions: false,
+            limiter: None,
+            #[cfg(feature = "sha2")]
+            stored: None,
+            #[cfg(feature = "flate2")]
+            gunzip_max: None,
+            #[cfg(feature = "flate2")]
+            gunzip_types: None,
+            #[cfg(feature = "encrypt")]
+            key: None,
+            #[cfg(feature = "zstd")]
+            zstd_level: None,
+            #[cfg(feature = "infer")]
+            reject_type_mismatch: false,
+            #[cfg(all(feature = "diskspace", unix))]
+            free_space: None,
+        }
+    }
+
+    /// Store files under their original filenames, normalized by `policy`, rather than random
+    /// ones.
+    ///
+    /// A file is numbered like `name (1).txt` if a file with its name already exists, and gets a
+    /// random name if it has no filename or nothing usable remains of it.
+    ///
+    /// ##Errors
+    /// Storing a file fails with a [`FilenameTooLong`](../struct.FilenameTooLong.html) error if
+    /// its filename is too long and `policy` rejects long filenames.
+    pub fn original_filenames(&mut self, policy: FilenamePolicy) -> &mut Self {
+        self.filenames = Some(policy);
+        self
+    }
+
+    /// If `extensions` is `true`, append the usual extension for the declared `Content-Type` of
+    /// a file to its generated name, such as `.png` for `image/png`, so that the stored files
+    /// can be identified by other tools. Files of unknown types and `application/octet-stream`
+    /// get no extension.
+    ///
+    /// The extension is recorded in the `extension` of the `SavedFile`. Files stored under their
+    /// original filenames are not renamed.
+    ///
+    /// ```rust
+    /// # extern crate multipart;
+    /// # extern crate tempdir;
+    /// use multipart::server::Multipart;
+    /// use multipart::server::storage::LocalStorage;
+    ///
+    /// # fn main() {
+    /// let body: &[u8] = b"--boundary\r\n\
+    ///     Content-Disposition: form-data; name=\"image\"; filename=\"image\"\r\n\
+    ///     Content-Type: image/png\r\n\r\n\
+    ///     \x89PNG\r\n--boundary--\r\n";
+    ///
+    /// let dir = tempdir::TempDir::new("multipart").unwrap();
+    /// let mut storage = LocalStorage::new(dir.path());
+    /// storage.extensions(true);
+    ///
+    /// let entries = Multipart::with_body(body, "boundary").save_all_to(&mut storage).unwrap();
+    /// let saved = &entries.files["image"].stored;
+    /// assert_eq!(saved.extension.as_ref().map(String::as_str), Some("png"));
+    /// assert_eq!(saved.path.extension().unwrap(), "png");
+    /// # }
+    /// ```
+    pub fn extensions(&mut self, extensions: bool) -> &mut Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Limit how many files are written at once with `limiter`, which may be shared with other
+    /// backends. Opening a file blocks until a permit is available, which is held until the
+    /// file is finalized or discarded.
+    pub fn limit_concurrency(&mut self, limiter: SaveLimiter) -> &mut Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// If `dedup` is `true`, store files with the same contents as a file stored earlier by
+    /// this backend only once: the new copy is deleted, and its `SavedFile` has the path of the
+    /// earlier one.
+    ///
+    /// Useful for forms where the same attachment may be submitted in several fields. Files are
+    /// compared by their SHA-256 digest and size.
+    ///
+    /// ```rust
+    /// # extern crate multipart;
+    /// # extern crate tempdir;
+    /// use multipart::server::Multipart;
+    /// use multipart::server::storage::LocalStorage;
+    ///
+    /// # fn main() {
+    /// let body: &[u8] = b"--boundary\r\n\
+    ///     Content-Disposition: form-data; name=\"first\"; filename=\"a.txt\"\r\n\
+    ///     Content-Type: text/plain\r\n\r\n\
+    ///     contents\r\n--boundary\r\n\
+    ///     Content-Disposition: form-data; name=\"second\"; filename=\"b.txt\"\r\n\
+    ///     Content-Type: text/plain\r\n\r\n\
+    ///     contents\r\n--boundary--\r\n";
+    ///
+    /// let dir = tempdir::TempDir::new("multipart").unwrap();
+    /// let mut storage = LocalStorage::new(dir.path());
+    /// storage.deduplicate(true);
+    ///
+    /// let entries = Multipart::with_body(body, "boundary").save_all_to(&mut storage).unwrap();
+    /// assert_eq!(entries.files["first"].stored.path, entries.files["second"].stored.path);
+    /// # }
+    /// ```
+    ///
+    /// #### Feature: `sha2`
+    #[cfg(feature = "sha2")]
+    pub fn deduplicate(&mut self, dedup: bool) -> &mut Self {
+        self.stored = if dedup { Some(HashMap::new()) } else { None };
+        self
+    }
+
+    /// Decompress gzip files, recognized by their magic bytes, before storing them.
+    ///
+    /// The `size` of their `SavedFile` is their decompressed size, and their `compressed_size`
+    /// is their size as uploaded. Their `sha256` is the digest of the decompressed data.
+    ///
+    /// ##Errors
+    /// Storing a file fails with an error of kind `io::ErrorKind::InvalidData` if it
+    /// decompresses to more than `max_size` bytes, guarding against decompression bombs, and
+    /// with an error of kind `io::ErrorKind::InvalidInput` if its compressed data is corrupt
+    /// or truncated, such as by
+    /// [`Multipart::save_all_limited()`](../struct.Multipart.html#method.save_all_limited).
+    ///
+    /// #### Feature: `flate2`
+    #[cfg(feature = "flate2")]
+    pub fn gunzip(&mut self, max_size: u64) -> &mut Self {
+        self.gunzip_max = Some(max_size);
+        self
+    }
+
+    /// Only decompress files with one of `content_types`, such as `application/gzip`, rather
+    /// than any file which starts with the gzip magic bytes. Files of these types must be gzip.
+    ///
+    /// Has no effect unless decompression is enabled with [`gunzip()`](#method.gunzip).
+    ///
+    /// #### Feature: `flate2`
+    #[cfg(feature = "flate2")]
+    pub fn gunzip_types<I: IntoIterator<Item = Mime>>(&mut self, content_types: I) -> &mut Self {
+        self.gunzip_types = Some(content_types.into_iter().collect());
+        self
+    }
+
+    /// Encrypt files with `key` as they are stored, so their plaintext is never written to disk.
+    ///
+    /// The `size` and `sha256` of their `SavedFile` are those of the plaintext. Read them with
+    /// [`EncryptionKey::open()`](../struct.EncryptionKey.html#method.open).
+    ///
+    /// #### Feature: `encrypt`
+    #[cfg(feature = "encrypt")]
+    pub fn encrypt(&mut self, key: EncryptionKey) -> &mut Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Compress files with zstd at `level` as they are stored, trading CPU time for less disk
+    /// usage. Useful for servers which spool many large text uploads before processing them.
+    ///
+    /// The `size` and `sha256` of their `SavedFile` are those of the uncompressed data. Read
+    /// them with `zstd::Decoder`; if they are also [encrypted](#method.encrypt), they are
+    /// compressed first, so decrypt them before decompressing them.
+    ///
+    /// #### Feature: `zstd`
+    #[cfg(feature = "zstd")]
+    pub fn zstd(&mut self, level: i32) -> &mut Self {
+        self.zstd_level = Some(level);
+        self
+    }
+
+    /// If `reject` is `true`, refuse to store files whose type detected from their contents
+    /// contradicts their declared `Content-Type`, such as an executable claiming to be
+    /// `image/png`, failing with an error wrapping a [`TypeMismatch`](../struct.TypeMismatch.html).
+    ///
+    /// #### Feature: `infer`
+    #[cfg(feature = "infer")]
+    pub fn reject_type_mismatch(&mut self, reject: bool) -> &mut Self {
+        self.reject_type_mismatch = reject;
+        self
+    }
+
+    /// Check the free space of the filesystem before and while storing files, failing fast
+    /// rather than filling the disk.
+    ///
+    /// Before each file is stored, the space left for the rest of the request, `expected` less
+    /// the size of the files stored so far, plus `reserve` must be available; pass the
+    /// `Content-Length` of the request as `expected`, if it has one. The space is checked again
+    /// every [`FREE_SPACE_CHECK_INTERVAL`](../constant.FREE_SPACE_CHECK_INTERVAL.html) bytes
+    /// written to a file, and at least `reserve` must remain.
+    ///
+    /// ##Errors
+    /// Storing a file fails with an [`InsufficientStorage`](../struct.InsufficientStorage.html)
+    /// error if there is not enough space, and a partially written file is deleted.
+    ///
+    /// #### Feature: `diskspace` (Unix only)
+    #[cfg(all(feature = "diskspace", unix))]
+    pub fn check_free_space(&mut self, expected: Option<u64>, reserve: u64) -> &mut Self {
+        self.free_space = Some(FreeSpace {
+            expected: expected.unwrap_or(0),
+            reserve: reserve,
+            stored: 0,
+        });
+        self
+    }
+
+    /// Get the directory files are stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+#[cfg(not(feature = "encrypt"))]
+type Spool = File;
+#[cfg(feature = "encrypt")]
+type Spool = Encrypt<File>;
+
+#[cfg(not(feature = "zstd"))]
+type Compressed = Spool;
+#[cfg(feature = "zstd")]
+type Compressed = Compress<Spool>;
+
+/// A file being written by [`LocalStorage`](struct.LocalStorage.html).
+#[derive(Debug)]
+pub struct LocalFile {
+    path: PathBuf,
+    extension: Option<&'static str>,
+    #[cfg(not(feature = "flate2"))]
+    file: HashWriter<Compressed>,
+    #[cfg(feature = "flate2")]
+    file: Gunzip<HashWriter<Compressed>>,
+    #[cfg(all(feature = "diskspace", unix))]
+    space: Option<SpaceCheck>,
+    permit: Option<SavePermit>,
+}
+
+impl Write for LocalFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        #[cfg(all(feature = "diskspace", unix))]
+        {
+            if let Some(ref mut space) = self.space {
+                if let Err(err) = space.write(buf.len()) {
+                    // Unix allows deleting open files.
+                    let _ = fs::remove_file(&self.path);
+                    return Err(err);
+                }
+            }
+        }
+
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    type Writer = LocalFile;
+    type Stored = SavedFile;
+
+    fn open(&mut self, meta: &FileMeta) -> io::Result<LocalFile> {
+        #[cfg(feature = "infer")]
+        {
+            if let Some(detected) = meta.detected_type.filter(|_| self.reject_type_mismatch) {
+                if sniff::is_mismatch(meta.content_type, detected) {
+                    return Err(TypeMismatch {
+                        declared: meta.content_type.clone(),
+                        detected: detected.clone(),
+                    }.into());
+                }
+            }
+        }
+
+        let filename = match (self.filenames.as_ref(), meta.filename) {
+            (Some(policy), Some(filename)) => try!(policy.normalize(filename)),
+            _ => None,
+        };
+
+        #[cfg(all(feature = "diskspace", unix))]
+        let space = match self.free_space {
+            Some(ref free_space) => {
+                try!(fs::create_dir_all(&self.dir));
+                try!(space::check(&self.dir, free_space.required()));
+                Some(SpaceCheck::new(self.dir.clone(), free_space.reserve))
+            },
+            None => None,
+        };
+
+        let permit = self.limiter.as_ref().map(SaveLimiter::acquire);
+
+        let extension = match filename {
+            None if self.extensions => filename::extension_for(meta.content_type),
+            _ => None,
+        };
+
+        let (path, file) = match filename {
+            Some(filename) => try!(filename::create_unique(&self.dir, &filename)),
+            None => {
+                let mut name = ::random_alphanumeric(RANDOM_FILENAME_LEN);
+
+                if let Some(extension) = extension {
+                    name.push('.');
+                    name.push_str(extension);
+                }
+
+                let path = self.dir.join(name);
+                let file = try!(create_full_path(&path));
+                (path, file)
+            },
+        };
+
+        #[cfg(feature = "encrypt")]
+        let file = try!(Encrypt::new(file, self.key.as_ref()));
+
+        #[cfg(feature = "zstd")]
+        let file = try!(Compress::new(file, self.zstd_level));
+
+        let file = HashWriter::new(file);
+
+        #[cfg(feature = "flate2")]
+        let file = match (self.gunzip_max, self.gunzip_types.as_ref()) {
+            (Some(max_size), Some(types)) => {
+                let content_type = meta.content_type;
+
+                if types.iter().any(|ty| ty.0 == content_type.0 && ty.1 == content_type.1) {
+                    Gunzip::force(file, max_size)
+                } else {
+                    Gunzip::plain(file)
+                }
+            },
+            (Some(max_size), None) => Gunzip::detect(file, max_size),
+            (None, _) => Gunzip::plain(file),
+        };
+
+        Ok(LocalFile {
+            path: path,
+            extension: extension,
+            file: file,
+            #[cfg(all(feature = "diskspace", unix))]
+            space: space,
+            permit: permit,
+        })
+    }
+
+    fn finalize(&mut self, writer: LocalFile, meta: &FileMeta, size: u64) -> io::Result<SavedFile> {
+        // The permit is held until the file is complete.
+        let LocalFile { path, extension, file, permit: _permit, .. } = writer;
+
+        #[cfg(feature = "flate2")]
+        let (mut file, decompressed_size) = try!(file.finish());
+        #[cfg(not(feature = "flate2"))]
+        let (mut file, decompressed_size) = (file, None);
+
+        #[cfg(all(feature = "diskspace", unix))]
+        {
+            if let Some(ref mut free_space) = self.free_space {
+                free_space.stored += size;
+            }
+        }
+
+        let (size, compressed_size) = match decompressed_size {
+            Some(decompressed_size) => (decompressed_size, Some(size)),
+            None => (size, None),
+        };
+
+        let sha256 = file.finish();
+
+        #[cfg(feature = "zstd")]
+        #[cfg_attr(not(feature = "sha2"), allow(unused_variables))]
+        let file = try!(file.into_inner().finish());
+
+        #[cfg(feature = "sha2")]
+        let path = match (self.stored.as_mut(), sha256.as_ref()) {
+            (Some(stored), Some(sha256)) => match stored.entry((sha256.clone(), size)) {
+                Entry::Occupied(entry) => {
+                    // Closed before deleting it, for Windows.
+                    drop(file);
+                    try!(fs::remove_file(&path));
+                    entry.get().clone()
+                },
+                Entry::Vacant(entry) => entry.insert(path).clone(),
+            },
+            _ => path,
+        };
+
+        Ok(SavedFile {
+            path: path,
+            filename: meta.filename.map(Into::into),
+            size: size,
+            sha256: sha256,
+            compressed_size: compressed_size,
+            extension: extension.map(Into::into),
+        })
+    }
+
+    /// Delete the file.
+    fn discard(&mut self, writer: LocalFile, _: &FileMeta) -> io::Result<()> {
+        let LocalFile { path, file, .. } = writer;
+        // Closed before deleting it, for Windows.
+        drop(file);
+        fs::remove_file(&path)
+    }
+}
+
+/// Stores files in memory as byte vectors.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemoryStorage;
+
+impl StorageBackend for MemoryStorage {
+    type Writer = Vec<u8>;
+    type Stored = Vec<u8>;
+
+    fn open(&mut self, _: &FileMeta) -> io::Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    fn finalize(&mut self, writer: Vec<u8>, _: &FileMeta, _: u64) -> io::Result<Vec<u8>> {
+        Ok(writer)
+    }
+}
+
+/// Checks the `Content-Type` of each file against a [`TypePolicy`](../struct.TypePolicy.html)
+/// before passing it to the wrapped backend.
+///
+/// Files with a disallowed type are rejected before any of their contents are read, failing
+/// with an error wrapping a [`DisallowedType`](../struct.DisallowedType.html).
+#[derive(Clone, Debug)]
+pub struct TypeChecked<S> {
+    backend: S,
+    policy: TypePolicy,
+}
+
+impl<S: StorageBackend> TypeChecked<S> {
+    /// Check files against `policy` before storing them with `backend`.
+    pub fn new(backend: S, policy: TypePolicy) -> Self {
+        TypeChecked {
+            backend: backend,
+            policy: policy,
+        }
+    }
+
+    /// Unwrap the inner backend.
+    pub fn into_inner(self) -> S {
+        self.backend
+    }
+}
+
+impl<S: StorageBackend> StorageBackend for TypeChecked<S> {
+    type Writer = S::Writer;
+    type Stored = S::Stored;
+
+    fn open(&mut self, meta: &FileMeta) -> io::Result<S::Writer> {
+        try!(self.policy.check(meta.field_name, meta.content_type));
+        self.backend.open(meta)
+    }
+
+    fn finalize(&mut self, writer: S::Writer, meta: &FileMeta, size: u64) -> io::Result<S::Stored> {
+        self.backend.finalize(writer, meta, size)
+    }
+
+    fn discard(&mut self, writer: S::Writer, meta: &FileMeta) -> io::Result<()> {
+        self.backend.discard(writer, meta)
+    }
+}
+
+/// A file field stored by a [`StorageBackend`](trait.StorageBackend.html).
+#[derive(Clone, Debug)]
+pub struct StoredFile<T> {
+    /// What the backend returned for this file.
+    pub stored: T,
+    /// The filename of this file, if supplied.
+    ///
+    /// ##Warning
+    /// You should treat this value as untrustworthy because it is an arbitrary string provided by
+    /// the client.
+    pub filename: Option<String>,
+    /// The MIME type of this file.
+    pub content_type: Mime,
+    /// The type of this file detected from its contents, if it is a known binary format.
+    ///
+    /// #### Feature: `infer`
+    #[cfg(feature = "infer")]
+    pub detected_type: Option<Mime>,
+    /// The number of bytes stored; may be truncated.
+    pub size: u64,
+}
+
+/// The fields of a request read by
+/// [`Multipart::save_all_to()`](../struct.Multipart.html#method.save_all_to).
+#[derive(Clone, Debug)]
+pub struct StoredEntries<T> {
+    /// The text fields of the request, mapped by field name -> value.
+    pub fields: HashMap<String, String>,
+    /// The file fields of the request, mapped by field name -> stored file.
+    pub files: HashMap<String, StoredFile<T>>,
+}
+
+impl<T> StoredEntries<T> {
+    fn new() -> Self {
+        StoredEntries {
+            fields: HashMap::new(),
+            files: HashMap::new(),
+        }
+    }
+}
+
+impl<B: Read> Multipart<B> {
+    /// Read the request fully, keeping text fields in memory and writing file fields to
+    /// `backend`.
+    ///
+    /// ##Errors
+    /// If reading the request or storing a file fails, returns the entries read so far along
+    /// with the error.
+    pub fn save_all_to<S: StorageBackend>(&mut self, mut backend: S)
+    -> Result<StoredEntries<S::Stored>, (StoredEntries<S::Stored>, io::Error)> {
+        let mut entries = StoredEntries::new();
+
+        let res = {
+            let files = &mut entries.files;
+            self.store_entries(&mut backend, None, None, &mut entries.fields, |name, file| {
+                files.insert(name, file);
+            })
+        };
+
+        match res {
+            Ok(()) => Ok(entries),
+            Err(err) => Err((entries, err)),
+        }
+    }
+
+    /// Read the request fully like [`save_all_to()`](#method.save_all_to), passing each part
+    /// to `inspector` as it is saved, which may veto it.
+    ///
+    /// ##Errors
+    /// If a part is rejected, it is discarded from `backend`, and the entries read so far are
+    /// returned with an error wrapping a [`PartRejected`](../struct.PartRejected.html).
+    pub fn save_all_inspected<S, I>(&mut self, mut backend: S, mut inspector: I)
+    -> Result<StoredEntries<S::Stored>, (StoredEntries<S::Stored>, io::Error)>
+    where S: StorageBackend, I: PartInspector {
+        let mut entries = StoredEntries::new();
+
+        let res = {
+            let files = &mut entries.files;
+            self.store_entries(&mut backend, None, Some(&mut inspector), &mut entries.fields, |name, file| {
+                files.insert(name, file);
+            })
+        };
+
+        match res {
+            Ok(()) => Ok(entries),
+            Err(err) => Err((entries, err)),
+        }
+    }
+
+    /// Read all fields, storing text fields in `fields` and files with `backend`, truncated to
+    /// `limit` if set, and passing the stored files to `add_file`.
+    ///
+    /// Each part is passed to `inspector`, if set, as it is stored.
+    pub(crate) fn store_entries<S, F>(&mut self, backend: &mut S, limit: Option<u64>,
+                                      mut inspector: Option<&mut PartInspector>,
+                                      fields: &mut HashMap<String, String>, mut add_file: F)
+    -> io::Result<()> where S: StorageBackend, F: FnMut(String, StoredFile<S::Stored>) {
+        while let Some(field) = try!(self.read_entry()) {
+            match field.data {
+                MultipartData::File(mut file) => {
+                    let filename = file.filename().map(String::from);
+                    let content_type = file.content_type().clone();
+                    #[cfg(feature = "infer")]
+                    let detected_type = try!(file.detected_type()).cloned();
+
+                    let (stored, size) = {
+                        let meta = FileMeta {
+                            field_name: &field.name,
+                            filename: filename.as_ref().map(|s| &**s),
+                            content_type: &content_type,
+                            #[cfg(feature = "infer")]
+                            detected_type: detected_type.as_ref(),
+                        };
+
+                        let mut writer = try!(backend.open(&meta));
+
+                        let res = match inspector {
+                            Some(ref mut inspector) => {
+                                let limit = limit.unwrap_or(u64::max_value());
+                                let mut tee = Tee::new((&mut file).take(limit), &mut writer);
+
+                                inspector.inspect(&field.headers, &mut tee)
+                                    .and_then(|verdict| tee.finish().map(|size| (verdict, size)))
+                            },
+                            None => match limit {
+                                Some(limit) => file.save_to_limited(&mut writer, limit),
+                                None => file.save_to(&mut writer),
+                            }.map(|size| (Verdict::Accept, size)),
+                        };
+
+                        let res = match res {
+                            Ok((Verdict::Accept, size)) => writer.flush().map(|_| (Verdict::Accept, size)),
+                            res => res,
+                        };
+
+                        let size = match res {
+                            Ok((Verdict::Accept, size)) => size,
+                            Ok((Verdict::Reject(reason), _)) => {
+                                try!(backend.discard(writer, &meta));
+                                return Err(PartRejected { field_name: field.name, reason: reason }.into());
+                            },
+                            Err(err) => {
+                                // The error which stopped the file is more useful than one from
+                                // cleaning up after it.
+                                if let Err(discard_err) = backend.discard(writer, &meta) {
+                                    warn!("Failed to discard file field {:?}: {}", field.name, discard_err);
+                                }
+
+                                return Err(err);
+                            },
+                        };
+
+                        (try!(backend.finalize(writer, &meta, size)), size)
+                    };
+
+                    add_file(field.name, StoredFile {
+                        stored: stored,
+                        filename: filename,
+                        content_type: content_type,
+                        #[cfg(feature = "infer")]
+                        detected_type: detected_type,
+                        size: size,
+                    });
+                },
+                MultipartData::Text(text) =>
+                    try!(store_text(&mut inspector, fields, &field.headers, text.as_bytes())),
+                MultipartData::Bytes(bytes) =>
+                    try!(store_text(&mut inspector, fields, &field.headers, bytes)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Pass a text field to `inspector`, if set, and store it in `fields`, replacing any invalid
+/// UTF-8.
+fn store_text(inspector: &mut Option<&mut PartInspector>, fields: &mut HashMap<String, String>,
+              headers: &FieldHeaders, text: &[u8]) -> io::Result<()> {
+    if let Some(ref mut inspector) = *inspector {
+        if let Verdict::Reject(reason) = try!(inspector.inspect(headers, &mut &text[..])) {
+            return Err(PartRejected { field_name: headers.name.clone(), reason: reason }.into());
+        }
+    }
+
+    fields.insert(headers.name.clone(), String::from_utf8_lossy(text).into_owned());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BODY: &'static str = "--boundary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        contents\r\n--boundary--\r\n";
+
+    /// A body which ends with a connection error before the file field is complete.
+    struct Truncated(&'static [u8]);
+
+    impl Read for Truncated {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "connection reset"));
+            }
+
+            self.0.read(buf)
+        }
+    }
+
+    fn truncated() -> Multipart<Truncated> {
+        // Ends in the middle of the closing boundary, so the whole header block is buffered.
+        let len = BODY.find("--boundary--").unwrap() + 3;
+        Multipart::with_body(Truncated(BODY[..len].as_bytes()), "boundary")
+    }
+
+    struct TestWriter {
+        fail_write: bool,
+        fail_flush: bool,
+    }
+
+    impl Write for TestWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.fail_write {
+                return Err(io::Error::new(io::ErrorKind::Other, "write failed"));
+            }
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            if self.fail_flush {
+                return Err(io::Error::new(io::ErrorKind::Other, "flush failed"));
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Counts the files finalized and discarded.
+    #[derive(Default)]
+    struct Recorder {
+        fail_write: bool,
+        fail_flush: bool,
+        finalized: usize,
+        discarded: usize,
+    }
+
+    impl StorageBackend for Recorder {
+        type Writer = TestWriter;
+        type Stored = u64;
+
+        fn open(&mut self, _: &FileMeta) -> io::Result<TestWriter> {
+            Ok(TestWriter { fail_write: self.fail_write, fail_flush: self.fail_flush })
+        }
+
+        fn finalize(&mut self, _: TestWriter, _: &FileMeta, size: u64) -> io::Result<u64> {
+            self.finalized += 1;
+            Ok(size)
+        }
+
+        fn discard(&mut self, _: TestWriter, _: &FileMeta) -> io::Result<()> {
+            self.discarded += 1;
+            Ok(())
+        }
+    }
+
+    fn accept(_: &FieldHeaders, data: &mut Read) -> io::Result<Verdict> {
+        try!(io::copy(data, &mut io::sink()));
+        Ok(Verdict::Accept)
+    }
+
+    #[test]
+    fn test_store_entries() {
+        let _ = ::env_logger::init();
+
+        let mut backend = Recorder::default();
+        let entries = Multipart::with_body(BODY.as_bytes(), "boundary").save_all_to(&mut backend).unwrap();
+        assert_eq!(entries.files["file"].stored, 8);
+        assert_eq!((backend.finalized, backend.discarded), (1, 0));
+
+        let mut backend = Recorder::default();
+        Multipart::with_body(BODY.as_bytes(), "boundary").save_all_inspected(&mut backend, accept).unwrap();
+        assert_eq!((backend.finalized, backend.discarded), (1, 0));
+    }
+
+    #[test]
+    fn test_inspected_headers() {
+        let body = "--boundary\r\n\
+            Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+            value\r\n--boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"; size=8\r\n\
+            Content-Type: text/plain\r\n\
+            Content-Length: 8\r\n\
+            Content-MD5: mL99jBV4Two9YyBEQeHiqg==\r\n\r\n\
+            contents\r\n--boundary--\r\n";
+
+        let mut seen = Vec::new();
+
+        {
+            let record = |headers: &FieldHeaders, data: &mut Read| {
+                seen.push(headers.clone());
+                accept(headers, data)
+            };
+
+            Multipart::with_body(body.as_bytes(), "boundary").save_all_inspected(Recorder::default(), record)
+                .unwrap();
+        }
+
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[0].name, "text");
+        assert_eq!(seen[0].content_type, None);
+
+        assert_eq!(seen[1].name, "file");
+        assert_eq!(seen[1].filename.as_ref().map(|s| &**s), Some("file.txt"));
+        assert_eq!(seen[1].content_length, Some(8));
+        assert_eq!(seen[1].disposition_params.size, Some(8));
+
+        #[cfg(feature = "checksum")]
+        {
+            assert!(seen[1].checksum.is_some());
+            assert!(seen[0].checksum.is_none());
+        }
+    }
+
+    #[test]
+    fn test_store_entries_discard() {
+        let _ = ::env_logger::init();
+
+        let mut backend = Recorder::default();
+        let (_, err) = truncated().save_all_to(&mut backend).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!((backend.finalized, backend.discarded), (0, 1));
+
+        let mut backend = Recorder::default();
+        let (_, err) = truncated().save_all_inspected(&mut backend, accept).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!((backend.finalized, backend.discarded), (0, 1));
+
+        let mut backend = Recorder { fail_write: true, .. Recorder::default() };
+        Multipart::with_body(BODY.as_bytes(), "boundary").save_all_to(&mut backend).unwrap_err();
+        assert_eq!((backend.finalized, backend.discarded), (0, 1));
+
+        let mut backend = Recorder { fail_flush: true, .. Recorder::default() };
+        Multipart::with_body(BODY.as_bytes(), "boundary").save_all_to(&mut backend).unwrap_err();
+        assert_eq!((backend.finalized, backend.discarded), (0, 1));
+
+        let mut backend = Recorder::default();
+        let fail = |_: &FieldHeaders, _: &mut Read| -> io::Result<Verdict> {
+            Err(io::Error::new(io::ErrorKind::Other, "inspector failed"))
+        };
+        Multipart::with_body(BODY.as_bytes(), "boundary").save_all_inspected(&mut backend, fail).unwrap_err();
+        assert_eq!((backend.finalized, backend.discarded), (0, 1));
+
+        let mut backend = Recorder::default();
+        let reject = |_: &FieldHeaders, _: &mut Read| -> io::Result<Verdict> {
+            Ok(Verdict::Reject("rejected".into()))
+        };
+        Multipart::with_body(BODY.as_bytes(), "boundary").save_all_inspected(&mut backend, reject).unwrap_err();
+        assert_eq!((backend.finalized, backend.discarded), (0, 1));
+    }
+
+    #[test]
+    fn test_local_storage_discard() {
+        let _ = ::env_logger::init();
+
+        let dir = ::tempdir::TempDir::new("multipart").unwrap();
+        let file_count = || fs::read_dir(dir.path()).map(|entries| entries.count()).unwrap_or(0);
+
+        let (_, err) = truncated().save_all_to(LocalStorage::new(dir.path())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(file_count(), 0);
+
+        let (_, err) = truncated().save_all_inspected(LocalStorage::new(dir.path()), accept).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(file_count(), 0);
+
+        let entries = Multipart::with_body(BODY.as_bytes(), "boundary")
+            .save_all_to(LocalStorage::new(dir.path())).unwrap();
+        assert_eq!(fs::read(&entries.files["file"].stored.path).unwrap(), b"contents");
+        assert_eq!(file_count(), 1);
+    }
+}
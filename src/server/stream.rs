@@ -0,0 +1,1021 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Asynchronous multipart parsing from a `Stream` of byte chunks, enabled with the `async`
+//! feature (optional).
+//!
+//! [`StreamMultipart`](struct.StreamMultipart.html) consumes a `futures::Stream` of
+//! `bytes::Bytes` chunks, such as the body of a Hyper request, and splits it into fields as
+//! the chunks arrive, even when a boundary straddles two chunks. Nothing is buffered beyond
+//! what is needed to recognize a boundary or a header block.
+//!
+//! Field contents are yielded as `Bytes` slices of the chunks received from the stream, sharing
+//! their buffers instead of copying; only the few bytes around a boundary which straddles two
+//! chunks are copied.
+//!
+//! Bodies which are a `futures::io::AsyncRead` instead, such as those of async-std and Tide,
+//! can be parsed with [`StreamMultipart::from_reader()`](struct.StreamMultipart.html#method.from_reader).
+//! [`StreamMultipart::save_all_with()`](struct.StreamMultipart.html#method.save_all_with) saves
+//! all fields with the file system API of any runtime, via the
+//! [`CreateFile`](trait.CreateFile.html) trait.
+//!
+//! [`StreamMultipart::next_field()`](struct.StreamMultipart.html#method.next_field) yields each
+//! field as an [`AsyncField`](struct.AsyncField.html) implementing `futures::io::AsyncRead`, for
+//! copying its contents into a file or socket like the blocking `MultipartFile`, and as a
+//! `Stream` of its `Bytes` chunks, for forwarding it elsewhere.
+//!
+//! For bodies which are a `futures::io::AsyncBufRead`, [`AsyncBoundaryReader`](struct.AsyncBoundaryReader.html)
+//! reads the contents of each part directly, so they can be consumed with the combinators of
+//! `futures::io`.
+//!
+//! ### Runtimes
+//! Everything here is written against the traits of `futures` alone, with the boundary logic in
+//! the sans-I/O [`PushParser`](../push/struct.PushParser.html), so it works on any runtime. The
+//! runtime-specific parts are small adapters:
+//!
+//! * Bodies are read from a `Stream` of `Bytes`, or from a `futures::io::AsyncRead`. Runtimes
+//! whose I/O traits are those of `futures-io`, like async-std and smol, need no adapter; tokio's
+//! are adapted by [`server::tokio::Compat`](../tokio/struct.Compat.html).
+//!
+//! * Files are created and written by an implementation of [`CreateFile`](trait.CreateFile.html),
+//! which is implemented for closures, so `smol::fs::File::create` can be passed to
+//! [`StreamMultipart::save_all_with()`](struct.StreamMultipart.html#method.save_all_with)
+//! directly. The [`server::async_std`](../async_std/index.html) and
+//! [`server::tokio`](../tokio/index.html) modules do the same for their runtimes.
+use bytes::{Buf, Bytes, BytesMut};
+
+use futures::future::Future;
+use futures::io::{AsyncBufRead, AsyncRead, AsyncWrite};
+use futures::stream::Stream;
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::{cmp, io, mem};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+pub use super::boundary::AsyncBoundaryReader;
+
+use super::hash::Hasher;
+use super::{Entries, FieldHeaders, InvalidUtf8, SavedFile, SaveResult, Utf8Policy, RANDOM_FILENAME_LEN};
+use super::push::{Event, ParseError, PushParser};
+
+/// The number of bytes copied from the start of a new chunk when input from the previous
+/// chunk is still pending.
+const JOIN_WINDOW: usize = 256;
+
+/// The size of the chunks read by [`ReaderStream`](struct.ReaderStream.html).
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// An event yielded by [`StreamMultipart`](struct.StreamMultipart.html).
+#[derive(Debug)]
+pub enum StreamEvent {
+    /// A new field has started.
+    Field(FieldHeaders),
+    /// A chunk of the current field's contents.
+    Data(Bytes),
+    /// The current field is complete.
+    FieldEnd,
+}
+
+/// A multipart parser reading from a `Stream` of `Bytes` chunks.
+///
+/// Implements `Stream<Item = io::Result<StreamEvent>>`, which ends after the closing boundary.
+/// Errors from the wrapped stream are converted to `io::Error` with the kind `Other`.
+pub struct StreamMultipart<S> {
+    stream: S,
+    parser: PushParser,
+    /// Input left over from previous chunks, which is parsed before `chunk`.
+    buf: BytesMut,
+    /// The unconsumed part of the last chunk received.
+    chunk: Bytes,
+    stream_done: bool,
+    on_consumed: Option<Box<FnMut(usize) + Send>>,
+    /// The number of input bytes consumed but not yet passed to `on_consumed`.
+    unreleased: usize,
+    utf8: Utf8Policy,
+    file_size_limit: Option<u64>,
+    text_size_limit: Option<usize>,
+}
+
+impl<S> StreamMultipart<S> {
+    /// Construct a new `StreamMultipart` with the given body stream and boundary.
+    ///
+    /// The boundary is as it appears in the `Content-Type` header, without the leading `"--"`.
+    pub fn with_body<Bnd: Into<String>>(stream: S, boundary: Bnd) -> Self {
+        let boundary = boundary.into();
+
+        debug!("Boundary: {}", boundary);
+
+        StreamMultipart {
+            stream: stream,
+            parser: PushParser::new(&boundary),
+            buf: BytesMut::new(),
+            chunk: Bytes::new(),
+            stream_done: false,
+            on_consumed: None,
+            unreleased: 0,
+            utf8: Utf8Policy::Strict,
+            file_size_limit: None,
+            text_size_limit: None,
+        }
+    }
+
+    /// Set how text fields which aren't valid UTF-8 are saved by the `save_all*()` methods.
+    ///
+    /// Default value: `Utf8Policy::Strict`
+    pub fn utf8_policy(&mut self, policy: Utf8Policy) -> &mut Self {
+        self.utf8 = policy;
+        self
+    }
+
+    /// Truncate files saved by the `save_all*()` methods to `limit` bytes, like
+    /// [`Multipart::save_all_limited()`](../struct.Multipart.html#method.save_all_limited).
+    ///
+    /// Default value: `None` (no limit).
+    pub fn file_size_limit(&mut self, limit: u64) -> &mut Self {
+        self.file_size_limit = Some(limit);
+        self
+    }
+
+    /// Stop the `save_all*()` methods with an error of kind `io::ErrorKind::InvalidData` at a
+    /// text field longer than `limit` bytes, as text fields are kept in memory.
+    ///
+    /// Default value: `None` (no limit).
+    pub fn text_size_limit(&mut self, limit: usize) -> &mut Self {
+        self.text_size_limit = Some(limit);
+        self
+    }
+
+    /// Call `on_consumed` with the number of input bytes the parser is done with, so flow
+    /// control capacity can be handed back to the sender, as with HTTP/2 bodies.
+    ///
+    /// Chunks are only read from the stream when the parser needs more input. The bytes of an
+    /// event are reported when the next event is requested, and all other consumed bytes before
+    /// more input is read.
+    pub fn on_consumed<F: FnMut(usize) + Send + 'static>(&mut self, on_consumed: F) -> &mut Self {
+        self.on_consumed = Some(Box::new(on_consumed));
+        self
+    }
+
+    fn release_consumed(&mut self) {
+        if self.unreleased == 0 {
+            return;
+        }
+
+        if let Some(ref mut on_consumed) = self.on_consumed {
+            on_consumed(self.unreleased);
+        }
+
+        self.unreleased = 0;
+    }
+
+    /// Read the request fully, saving text fields in memory and files in a new temporary
+    /// directory under the [temporary root](../fn.temp_root.html). Files are created and written
+    /// with `create`, which allows any async runtime's file system API to be used.
+    ///
+    /// The temporary directory itself is created before this returns; if that fails, the future
+    /// resolves to `SaveResult::Error`.
+    pub fn save_all_with<C: CreateFile>(self, create: C) -> SaveAll<S, C> {
+        SaveAll::new(self, Entries::new_tempdir(), create)
+    }
+
+    /// Read the request fully, saving text fields in memory and files in a new temporary
+    /// directory under `dir`. Files are created and written with `create`.
+    ///
+    /// The temporary directory itself is created before this returns; if that fails, the future
+    /// resolves to `SaveResult::Error`.
+    pub fn save_all_under_with<P: AsRef<Path>, C: CreateFile>(self, dir: P, create: C) -> SaveAll<S, C> {
+        SaveAll::new(self, Entries::new_tempdir_in(dir), create)
+    }
+
+    /// Parse the next event from the buffered input, if possible.
+    ///
+    /// If this returns `Ok(None)` and parsing is not done, `self.chunk` is empty and the next
+    /// chunk must be read from the stream.
+    fn next_event(&mut self) -> io::Result<Option<StreamEvent>> {
+        loop {
+            let joining = !self.buf.is_empty();
+            // Only the last input is followed by nothing.
+            let eof = self.stream_done && (!joining || self.chunk.is_empty());
+
+            let (consumed, event, is_data) = {
+                let input: &[u8] = if joining { &self.buf } else { &self.chunk };
+                let (consumed, event) = try!(self.parser.parse(input, eof));
+
+                match event {
+                    Some(Event::Headers(headers)) => (consumed, Some(try!(read_headers(headers))), false),
+                    Some(Event::Data(_)) => (consumed, None, true),
+                    Some(Event::PartEnd) => (consumed, Some(StreamEvent::FieldEnd), false),
+                    Some(Event::End) | None => (consumed, None, false),
+                }
+            };
+
+            self.unreleased += consumed;
+
+            let data = if joining {
+                self.buf.split_to(consumed).freeze()
+            } else {
+                self.chunk.split_to(consumed)
+            };
+
+            if is_data {
+                return Ok(Some(StreamEvent::Data(data)));
+            }
+
+            if event.is_some() || self.parser.is_done() {
+                return Ok(event);
+            }
+
+            if consumed != 0 {
+                continue;
+            }
+
+            // More input is needed to continue.
+            if self.chunk.is_empty() {
+                return Ok(None);
+            }
+
+            if joining {
+                let take = cmp::min(self.chunk.len(), JOIN_WINDOW);
+                self.buf.extend_from_slice(&self.chunk[..take]);
+                self.chunk.advance(take);
+            } else {
+                self.buf.extend_from_slice(&self.chunk);
+                self.chunk.clear();
+                return Ok(None);
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead> StreamMultipart<ReaderStream<R>> {
+    /// Construct a new `StreamMultipart` reading the body from `reader`, which may be any
+    /// `futures::io::AsyncRead`, such as an `async_std::io::Read` or a Tide request.
+    ///
+    /// The boundary is as it appears in the `Content-Type` header, without the leading `"--"`.
+    pub fn from_reader<Bnd: Into<String>>(reader: R, boundary: Bnd) -> Self {
+        StreamMultipart::with_body(ReaderStream::new(reader), boundary)
+    }
+}
+
+impl<S, E> Stream for StreamMultipart<S>
+where S: Stream<Item = Result<Bytes, E>> + Unpin, E: Into<Box<Error + Send + Sync>> {
+    type Item = io::Result<StreamEvent>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        // The caller is done with the previous event.
+        this.release_consumed();
+
+        loop {
+            match this.next_event() {
+                Ok(Some(event)) => return Poll::Ready(Some(Ok(event))),
+                Ok(None) => (),
+                Err(err) => return Poll::Ready(Some(Err(err))),
+            }
+
+            this.release_consumed();
+
+            if this.parser.is_done() {
+                return Poll::Ready(None);
+            }
+
+            if this.stream_done {
+                return Poll::Ready(Some(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof, "unexpected end of multipart body"
+                ))));
+            }
+
+            let chunk = match Pin::new(&mut this.stream).poll_next(cx) {
+                Poll::Ready(chunk) => chunk,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            match chunk {
+                Some(Ok(chunk)) => this.chunk = chunk,
+                Some(Err(err)) => return Poll::Ready(Some(Err(io::Error::new(io::ErrorKind::Other, err)))),
+                None => this.stream_done = true,
+            }
+        }
+    }
+}
+
+impl<S, E> StreamMultipart<S>
+where S: Stream<Item = Result<Bytes, E>> + Unpin, E: Into<Box<Error + Send + Sync>> {
+    /// Get a future which resolves to the next field of the request, whose contents can be read
+    /// with `futures::io::AsyncRead`, or `None` after the closing boundary.
+    ///
+    /// This is an alternative to using this parser as a `Stream` of events; the two should not
+    /// be mixed.
+    ///
+    /// ##Warning: Risk of Data Loss
+    /// If the previously returned field was not read to the end, its unread contents are
+    /// discarded.
+    pub fn next_field(&mut self) -> NextField<S> {
+        NextField {
+            multipart: Some(self),
+        }
+    }
+}
+
+/// A future which resolves to the next field of a `StreamMultipart`.
+///
+/// Returned by [`StreamMultipart::next_field()`](struct.StreamMultipart.html#method.next_field).
+pub struct NextField<'a, S: 'a> {
+    multipart: Option<&'a mut StreamMultipart<S>>,
+}
+
+impl<'a, S, E> Future for NextField<'a, S>
+where S: Stream<Item = Result<Bytes, E>> + Unpin, E: Into<Box<Error + Send + Sync>> {
+    type Output = io::Result<Option<AsyncField<'a, S>>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        loop {
+            let event = {
+                let multipart = this.multipart.as_mut().expect("`NextField` polled after completion");
+
+                match Pin::new(&mut **multipart).poll_next(cx) {
+                    Poll::Ready(Some(Ok(event))) => event,
+                    Poll::Ready(Some(Err(err))) => return Poll::Ready(Err(err)),
+                    Poll::Ready(None) => return Poll::Ready(Ok(None)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            };
+
+            // Skip the remainder of the previous field.
+            if let StreamEvent::Field(headers) = event {
+                return Poll::Ready(Ok(Some(AsyncField {
+                    headers: headers,
+                    multipart: this.multipart.take().unwrap(),
+                    data: Bytes::new(),
+                    done: false,
+                })));
+            }
+        }
+    }
+}
+
+/// A field of a `StreamMultipart`, whose contents are read with `futures::io::AsyncRead` or
+/// `AsyncBufRead`, such as with `futures::io::copy()`.
+///
+/// The asynchronous counterpart of [`MultipartFile`](../struct.MultipartFile.html), for both text
+/// and file fields.
+///
+/// Also implements `Stream<Item = io::Result<Bytes>>`, yielding the contents in the chunks
+/// received from the request without copying them, so a proxy can forward them to another
+/// request body as they arrive; as this borrows the `StreamMultipart`, send them through a
+/// channel such as `futures::channel::mpsc` if the body must be `'static`. Reading with both
+/// `AsyncRead` and `Stream` yields each byte once.
+pub struct AsyncField<'a, S: 'a> {
+    /// The headers of this field, including its name and, for files, filename and
+    /// `Content-Type`.
+    pub headers: FieldHeaders,
+    multipart: &'a mut StreamMultipart<S>,
+    /// The unread remainder of the last chunk.
+    data: Bytes,
+    done: bool,
+}
+
+impl<'a, S, E> AsyncField<'a, S>
+where S: Stream<Item = Result<Bytes, E>> + Unpin, E: Into<Box<Error + Send + Sync>> {
+    fn poll_data(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while self.data.is_empty() && !self.done {
+            match Pin::new(&mut *self.multipart).poll_next(cx) {
+                Poll::Ready(Some(Ok(StreamEvent::Data(data)))) => self.data = data,
+                Poll::Ready(Some(Ok(_))) | Poll::Ready(None) => self.done = true,
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<'a, S, E> AsyncRead for AsyncField<'a, S>
+where S: Stream<Item = Result<Bytes, E>> + Unpin, E: Into<Box<Error + Send + Sync>> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        match this.poll_data(cx) {
+            Poll::Ready(Ok(())) => (),
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        }
+
+        let read = cmp::min(buf.len(), this.data.len());
+        buf[..read].copy_from_slice(&this.data[..read]);
+        this.data.advance(read);
+
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl<'a, S, E> AsyncBufRead for AsyncField<'a, S>
+where S: Stream<Item = Result<Bytes, E>> + Unpin, E: Into<Box<Error + Send + Sync>> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+
+        match this.poll_data(cx) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(&this.data)),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().data.advance(amt);
+    }
+}
+
+impl<'a, S, E> Stream for AsyncField<'a, S>
+where S: Stream<Item = Result<Bytes, E>> + Unpin, E: Into<Box<Error + Send + Sync>> {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<io::Result<Bytes>>> {
+        let this = self.get_mut();
+
+        match this.poll_data(cx) {
+            Poll::Ready(Ok(())) if this.data.is_empty() => Poll::Ready(None),
+            Poll::Ready(Ok(())) => Poll::Ready(Some(Ok(mem::replace(&mut this.data, Bytes::new())))),
+            Poll::Ready(Err(err)) => Poll::Ready(Some(Err(err))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Adapts a `futures::io::AsyncRead` to a `Stream` of `Bytes` chunks, for
+/// [`StreamMultipart::from_reader()`](struct.StreamMultipart.html#method.from_reader).
+pub struct ReaderStream<R> {
+    reader: Pin<Box<R>>,
+    buf: BytesMut,
+}
+
+impl<R: AsyncRead> ReaderStream<R> {
+    /// Wrap `reader`, reading chunks of up to 8 KiB.
+    pub fn new(reader: R) -> Self {
+        ReaderStream {
+            reader: Box::pin(reader),
+            buf: BytesMut::new(),
+        }
+    }
+}
+
+impl<R: AsyncRead> Stream for ReaderStream<R> {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<io::Result<Bytes>>> {
+        let this = self.get_mut();
+        this.buf.resize(READ_CHUNK_SIZE, 0);
+
+        loop {
+            match this.reader.as_mut().poll_read(cx, &mut this.buf) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(None),
+                Poll::Ready(Ok(read)) => return Poll::Ready(Some(Ok(this.buf.split_to(read).freeze()))),
+                Poll::Ready(Err(ref err)) if err.kind() == io::ErrorKind::Interrupted => (),
+                Poll::Ready(Err(err)) => return Poll::Ready(Some(Err(err))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Creates the files written by [`SaveAll`](struct.SaveAll.html), abstracting over the file
+/// system API of the async runtime.
+///
+/// Implemented for any `FnMut(PathBuf) -> Fut` where `Fut` resolves to a
+/// `futures::io::AsyncWrite`, such as `async_std::fs::File::create`.
+pub trait CreateFile {
+    /// The type of the created files.
+    type File: AsyncWrite + Unpin;
+    /// The future returned by `create()`.
+    type Future: Future<Output = io::Result<Self::File>>;
+
+    /// Create a new file at `path`, which does not exist yet.
+    fn create(&mut self, path: PathBuf) -> Self::Future;
+}
+
+impl<F, Fut, W> CreateFile for F
+where F: FnMut(PathBuf) -> Fut, Fut: Future<Output = io::Result<W>>, W: AsyncWrite + Unpin {
+    type File = W;
+    type Future = Fut;
+
+    fn create(&mut self, path: PathBuf) -> Fut {
+        self(path)
+    }
+}
+
+/// A future which reads a `StreamMultipart` fully, saving its text fields in memory and its
+/// files in a directory. Resolves to a `SaveResult`, like
+/// [`Multipart::save_all()`](../struct.Multipart.html#method.save_all).
+///
+/// Returned by [`StreamMultipart::save_all_with()`](struct.StreamMultipart.html#method.save_all_with).
+pub struct SaveAll<S, C: CreateFile> {
+    multipart: StreamMultipart<S>,
+    create: C,
+    entries: Option<Entries>,
+    /// The error creating the save directory, if any.
+    error: Option<io::Error>,
+    state: SaveState<C>,
+    /// Hashes the data of the file being written.
+    hasher: Hasher,
+}
+
+enum SaveState<C: CreateFile> {
+    /// Between fields.
+    Idle,
+    /// Reading a text field.
+    Text(String, Vec<u8>),
+    /// Creating the file of a file field.
+    Creating(String, SavedFile, Pin<Box<C::Future>>),
+    /// Writing a file field; the `Bytes` are the data not yet written.
+    Writing(String, SavedFile, C::File, Bytes),
+    /// Closing the file at the end of a file field.
+    Closing(String, SavedFile, C::File),
+}
+
+impl<S, C: CreateFile> SaveAll<S, C> {
+    fn new(multipart: StreamMultipart<S>, entries: io::Result<Entries>, create: C) -> Self {
+        let (entries, error) = match entries {
+            Ok(entries) => (Some(entries), None),
+            Err(err) => (None, Some(err)),
+        };
+
+        SaveAll {
+            multipart: multipart,
+            create: create,
+            entries: entries,
+            error: error,
+            state: SaveState::Idle,
+            hasher: Hasher::new(),
+        }
+    }
+
+    fn next_state(&mut self, state: SaveState<C>, event: StreamEvent) -> io::Result<SaveState<C>> {
+        Ok(match (state, event) {
+            (_, StreamEvent::Field(headers)) => if headers.content_type.is_some() {
+                let path = match self.entries {
+                    Some(ref entries) => entries.dir.as_path().join(::random_alphanumeric(RANDOM_FILENAME_LEN)),
+                    None => unreachable!(),
+                };
+
+                let saved = SavedFile {
+                    path: path.clone(),
+                    filename: headers.filename,
+                    size: 0,
+                    sha256: None,
+                    compressed_size: None,
+                    extension: None,
+                };
+
+                SaveState::Creating(headers.name, saved, Box::pin(self.create.create(path)))
+            } else {
+                SaveState::Text(headers.name, Vec::new())
+            },
+            (SaveState::Text(name, mut text), StreamEvent::Data(data)) => {
+                if let Some(limit) = self.multipart.text_size_limit {
+                    if text.len() + data.len() > limit {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                            "text field {:?} is longer than the limit of {} bytes", name, limit
+                        )));
+                    }
+                }
+
+                text.extend_from_slice(&data);
+                SaveState::Text(name, text)
+            },
+            (SaveState::Writing(name, saved, file, _), StreamEvent::Data(mut data)) => {
+                // The previous data has been written, so `saved.size` is up to date.
+                if let Some(limit) = self.multipart.file_size_limit {
+                    let left = limit.saturating_sub(saved.size);
+
+                    if data.len() as u64 > left {
+                        data.truncate(left as usize);
+                    }
+                }
+
+                SaveState::Writing(name, saved, file, data)
+            },
+            (SaveState::Text(name, text), StreamEvent::FieldEnd) => {
+                let text = match String::from_utf8(text) {
+                    Ok(text) => text,
+                    Err(err) => match self.multipart.utf8 {
+                        Utf8Policy::Strict => return Err(InvalidUtf8 {
+                            field_name: name,
+                            valid_up_to: err.utf8_error().valid_up_to(),
+                        }.into()),
+                        // Kept as if read with `Lossy`, like `Multipart::save_all()` does.
+                        Utf8Policy::Lossy | Utf8Policy::Raw =>
+                            String::from_utf8_lossy(err.as_bytes()).into_owned(),
+                    },
+                };
+
+                if let Some(ref mut entries) = self.entries {
+                    entries.fields.insert(name, text);
+                }
+
+                SaveState::Idle
+            },
+            (SaveState::Writing(name, saved, file, _), StreamEvent::FieldEnd) =>
+                SaveState::Closing(name, saved, file),
+            (state, _) => state,
+        })
+    }
+}
+
+impl<S, E, C> SaveAll<S, C>
+where S: Stream<Item = Result<Bytes, E>> + Unpin, E: Into<Box<Error + Send + Sync>>, C: CreateFile {
+    fn poll_save(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        loop {
+            self.state = match mem::replace(&mut self.state, SaveState::Idle) {
+                SaveState::Creating(name, saved, mut future) => match future.as_mut().poll(cx) {
+                    Poll::Ready(Ok(file)) => SaveState::Writing(name, saved, file, Bytes::new()),
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending => {
+                        self.state = SaveState::Creating(name, saved, future);
+                        return Poll::Pending;
+                    },
+                },
+                SaveState::Writing(name, mut saved, mut file, mut data) if !data.is_empty() => {
+                    match Pin::new(&mut file).poll_write(cx, &data) {
+                        Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::WriteZero, "failed to write field data to file"
+                        ))),
+                        Poll::Ready(Ok(written)) => {
+                            self.hasher.update(&data[..written]);
+                            data.advance(written);
+                            saved.size += written as u64;
+                        },
+                        Poll::Ready(Err(err)) => if err.kind() != io::ErrorKind::Interrupted {
+                            return Poll::Ready(Err(err));
+                        },
+                        Poll::Pending => {
+                            self.state = SaveState::Writing(name, saved, file, data);
+                            return Poll::Pending;
+                        },
+                    }
+
+                    SaveState::Writing(name, saved, file, data)
+                },
+                SaveState::Closing(name, mut saved, mut file) => match Pin::new(&mut file).poll_close(cx) {
+                    Poll::Ready(Ok(())) => {
+                        saved.sha256 = self.hasher.finish();
+
+                        if let Some(ref mut entries) = self.entries {
+                            entries.files.insert(name, saved);
+                        }
+
+                        SaveState::Idle
+                    },
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending => {
+                        self.state = SaveState::Closing(name, saved, file);
+                        return Poll::Pending;
+                    },
+                },
+                // Waiting for the next event.
+                state => {
+                    let event = match Pin::new(&mut self.multipart).poll_next(cx) {
+                        Poll::Ready(Some(Ok(event))) => event,
+                        Poll::Ready(Some(Err(err))) => return Poll::Ready(Err(err)),
+                        Poll::Ready(None) => return Poll::Ready(Ok(())),
+                        Poll::Pending => {
+                            self.state = state;
+                            return Poll::Pending;
+                        },
+                    };
+
+                    match self.next_state(state, event) {
+                        Ok(state) => state,
+                        Err(err) => return Poll::Ready(Err(err)),
+                    }
+                },
+            };
+        }
+    }
+}
+
+impl<S, E, C> Future for SaveAll<S, C>
+where S: Stream<Item = Result<Bytes, E>> + Unpin, E: Into<Box<Error + Send + Sync>>, C: CreateFile + Unpin {
+    type Output = SaveResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<SaveResult> {
+        let this = self.get_mut();
+
+        if this.entries.is_none() {
+            let err = this.error.take().expect("`SaveAll` polled after completion");
+            return Poll::Ready(SaveResult::Error(err));
+        }
+
+        let res = match this.poll_save(cx) {
+            Poll::Ready(res) => res,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let entries = this.entries.take().unwrap();
+
+        Poll::Ready(match res {
+            Ok(()) => SaveResult::Full(entries),
+            Err(err) => SaveResult::Partial(entries, err),
+        })
+    }
+}
+
+fn read_headers(headers: &[u8]) -> io::Result<StreamEvent> {
+    ::std::str::from_utf8(headers).ok()
+        .and_then(FieldHeaders::parse)
+        .map(StreamEvent::Field)
+        .ok_or_else(|| ParseError::InvalidHeaders.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use futures::executor::block_on;
+    use futures::future;
+    use futures::io::AllowStdIo;
+    use futures::stream::{self, StreamExt};
+
+    use std::fs::{self, File};
+
+    const BODY: &'static [u8] = b"--boundary\r\n\
+        Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+        text value\r\n--boundary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        file\r\ncontents\r\n--boundary--\r\n";
+
+    type ChunkStream = stream::Iter<::std::vec::IntoIter<io::Result<Bytes>>>;
+
+    fn multipart(chunks: &[&[u8]]) -> StreamMultipart<ChunkStream> {
+        let chunks: Vec<_> = chunks.iter().map(|chunk| Ok(Bytes::copy_from_slice(chunk))).collect();
+        StreamMultipart::with_body(stream::iter(chunks), "boundary")
+    }
+
+    /// Read all events, merging adjacent data so the result doesn't depend on chunking.
+    fn read_events(mut multipart: StreamMultipart<ChunkStream>) -> io::Result<Vec<String>> {
+        let mut events = Vec::new();
+
+        while let Some(event) = block_on(multipart.next()) {
+            match try!(event) {
+                StreamEvent::Field(headers) => events.push(format!("F:{}", headers.name)),
+                StreamEvent::Data(data) => {
+                    let data = String::from_utf8_lossy(&data).into_owned();
+
+                    if events.last().map_or(false, |e: &String| e.starts_with("D:")) {
+                        events.last_mut().unwrap().push_str(&data);
+                    } else {
+                        events.push(format!("D:{}", data));
+                    }
+                },
+                StreamEvent::FieldEnd => events.push("E".into()),
+            }
+        }
+
+        Ok(events)
+    }
+
+    fn create_file(path: PathBuf) -> future::Ready<io::Result<AllowStdIo<File>>> {
+        future::ready(File::create(path).map(AllowStdIo::new))
+    }
+
+    fn save_all(multipart: StreamMultipart<ChunkStream>, dir: &Path) -> SaveResult {
+        block_on(multipart.save_all_under_with(dir, create_file))
+    }
+
+    fn save_full(multipart: StreamMultipart<ChunkStream>, dir: &Path) -> Entries {
+        match save_all(multipart, dir) {
+            SaveResult::Full(entries) => entries,
+            res => panic!("failed to save request: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_stream_multipart() {
+        let _ = ::env_logger::init();
+
+        let expected = vec!["F:text", "D:text value", "E", "F:file", "D:file\r\ncontents", "E"];
+
+        assert_eq!(read_events(multipart(&[BODY])).unwrap(), expected);
+
+        // Covers splits inside every boundary and every header terminator.
+        for split_at in 0 .. BODY.len() + 1 {
+            let (left, right) = BODY.split_at(split_at);
+            assert_eq!(read_events(multipart(&[left, right])).unwrap(), expected, "split at {}", split_at);
+        }
+
+        let bytes: Vec<&[u8]> = BODY.chunks(1).collect();
+        assert_eq!(read_events(multipart(&bytes)).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_data_shares_chunks() {
+        let _ = ::env_logger::init();
+
+        let split_at = BODY.windows(5).position(|w| w == b"value").unwrap();
+        let chunks = [Bytes::copy_from_slice(&BODY[..split_at]), Bytes::copy_from_slice(&BODY[split_at..])];
+        let is_in_chunk = |data: &Bytes| chunks.iter().any(|chunk| {
+            let range = chunk.as_ptr() as usize .. chunk.as_ptr() as usize + chunk.len();
+            range.start <= data.as_ptr() as usize && data.as_ptr() as usize + data.len() <= range.end
+        });
+
+        let body = chunks.iter().cloned().map(Ok::<_, io::Error>).collect::<Vec<_>>();
+        let mut multipart = StreamMultipart::with_body(stream::iter(body), "boundary");
+        let mut data_len = 0;
+
+        while let Some(event) = block_on(multipart.next()) {
+            if let StreamEvent::Data(data) = event.unwrap() {
+                assert!(is_in_chunk(&data), "{:?} was copied", data);
+                data_len += data.len();
+            }
+        }
+
+        assert_eq!(data_len, "text value".len() + "file\r\ncontents".len());
+    }
+
+    #[test]
+    fn test_on_consumed() {
+        use std::sync::{Arc, Mutex};
+
+        let _ = ::env_logger::init();
+
+        for &chunk_size in &[1, 7, BODY.len()] {
+            let received = Arc::new(Mutex::new(0));
+            let released = Arc::new(Mutex::new(0));
+
+            let chunks: Vec<_> = BODY.chunks(chunk_size)
+                .map(|chunk| Ok::<_, io::Error>(Bytes::copy_from_slice(chunk)))
+                .collect();
+            let received_ = received.clone();
+            let body = stream::iter(chunks).inspect(move |chunk| {
+                *received_.lock().unwrap() += chunk.as_ref().unwrap().len();
+            });
+
+            let mut multipart = StreamMultipart::with_body(body, "boundary");
+            let released_ = released.clone();
+            multipart.on_consumed(move |consumed| *released_.lock().unwrap() += consumed);
+
+            while let Some(event) = block_on(multipart.next()) {
+                event.unwrap();
+                assert!(*released.lock().unwrap() <= *received.lock().unwrap());
+            }
+
+            // Everything but the CRLF after the closing boundary, which is the epilogue.
+            assert_eq!(*released.lock().unwrap(), BODY.len() - 2, "chunk size {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_async_field_read() {
+        use futures::io::AsyncReadExt;
+
+        let _ = ::env_logger::init();
+
+        for split_at in 0 .. BODY.len() + 1 {
+            let (left, right) = BODY.split_at(split_at);
+            let mut multipart = multipart(&[left, right]);
+            let mut fields = Vec::new();
+
+            while let Some(mut field) = block_on(multipart.next_field()).unwrap() {
+                let mut data = String::new();
+                block_on(field.read_to_string(&mut data)).unwrap();
+                fields.push((field.headers.name.clone(), data));
+            }
+
+            assert_eq!(fields, vec![
+                ("text".to_string(), "text value".to_string()),
+                ("file".to_string(), "file\r\ncontents".to_string()),
+            ], "split at {}", split_at);
+        }
+
+        // The unread remainder of a field is skipped.
+        let mut multipart = multipart(&[BODY]);
+
+        {
+            let mut field = block_on(multipart.next_field()).unwrap().unwrap();
+            let mut buf = [0; 4];
+            block_on(field.read_exact(&mut buf)).unwrap();
+            assert_eq!(&buf, b"text");
+        }
+
+        let field = block_on(multipart.next_field()).unwrap().unwrap();
+        assert_eq!(field.headers.name, "file");
+    }
+
+    #[test]
+    fn test_async_field_stream() {
+        let _ = ::env_logger::init();
+
+        let bytes: Vec<&[u8]> = BODY.chunks(5).collect();
+        let mut multipart = multipart(&bytes);
+
+        let fields = [("text", &b"text value"[..]), ("file", &b"file\r\ncontents"[..])];
+
+        for &(name, expected) in &fields {
+            let mut field = block_on(multipart.next_field()).unwrap().unwrap();
+            assert_eq!(field.headers.name, name);
+
+            let mut data = Vec::new();
+
+            while let Some(chunk) = block_on(field.next()) {
+                data.extend_from_slice(&chunk.unwrap());
+            }
+
+            assert_eq!(data, expected);
+            // The stream stays ended instead of running into the next field.
+            assert!(block_on(field.next()).is_none());
+        }
+
+        assert!(block_on(multipart.next_field()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_stream_multipart_eof() {
+        let _ = ::env_logger::init();
+
+        // Every prefix which ends before the closing boundary is complete.
+        let end = BODY.len() - "\r\n".len();
+
+        for len in 0 .. end {
+            let err = read_events(multipart(&[&BODY[..len]])).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "length {}", len);
+        }
+
+        assert!(read_events(multipart(&[&BODY[..end]])).is_ok());
+    }
+
+    #[test]
+    fn test_save_all() {
+        let _ = ::env_logger::init();
+
+        let dir = ::tempdir::TempDir::new("multipart_stream").unwrap();
+
+        for split_at in 0 .. BODY.len() + 1 {
+            let (left, right) = BODY.split_at(split_at);
+            let entries = save_full(multipart(&[left, right]), dir.path());
+
+            assert_eq!(entries.fields["text"], "text value");
+
+            let file = &entries.files["file"];
+            assert_eq!(file.filename.as_ref().map(|s| &**s), Some("file.txt"));
+            assert_eq!(file.size, 14);
+            assert_eq!(fs::read(&file.path).unwrap(), b"file\r\ncontents");
+        }
+
+        let (entries, err) = save_all(multipart(&[&BODY[..BODY.len() - 10]]), dir.path()).to_opt();
+        assert_eq!(entries.unwrap().fields["text"], "text value");
+        assert_eq!(err.unwrap().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_save_all_limits() {
+        let _ = ::env_logger::init();
+
+        let dir = ::tempdir::TempDir::new("multipart_stream").unwrap();
+        let bytes: Vec<&[u8]> = BODY.chunks(3).collect();
+
+        let mut limited = multipart(&bytes);
+        limited.file_size_limit(4);
+        let entries = save_full(limited, dir.path());
+        assert_eq!(entries.files["file"].size, 4);
+        assert_eq!(fs::read(&entries.files["file"].path).unwrap(), b"file");
+
+        let mut limited = multipart(&bytes);
+        limited.text_size_limit(10);
+        assert_eq!(save_full(limited, dir.path()).fields["text"], "text value");
+
+        let mut limited = multipart(&bytes);
+        limited.text_size_limit(9);
+        let (entries, err) = save_all(limited, dir.path()).to_opt();
+        assert!(entries.unwrap().fields.is_empty());
+        assert_eq!(err.unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_save_all_utf8_policy() {
+        let _ = ::env_logger::init();
+
+        let dir = ::tempdir::TempDir::new("multipart_stream").unwrap();
+        let body: &[u8] = b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+            ab\xffcd\r\n--boundary--";
+
+        let (_, err) = save_all(multipart(&[body]), dir.path()).to_opt();
+        let err = err.unwrap();
+        let invalid = err.get_ref().and_then(|err| err.downcast_ref::<InvalidUtf8>()).unwrap();
+        assert_eq!((&*invalid.field_name, invalid.valid_up_to), ("text", 2));
+
+        for &policy in &[Utf8Policy::Lossy, Utf8Policy::Raw] {
+            let mut multipart = multipart(&[body]);
+            multipart.utf8_policy(policy);
+            assert_eq!(save_full(multipart, dir.path()).fields["text"], "ab\u{fffd}cd");
+        }
+    }
+}
@@ -0,0 +1,297 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Strict validation of `multipart/form-data` bodies against RFC 7578.
+use std::io::Read;
+use std::{error, fmt, io};
+
+use super::{find_header, Multipart};
+
+/// A rule of RFC 7578 broken by a request body read with
+/// [`Multipart::strict()`](struct.Multipart.html#method.strict).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// A part didn't have a `Content-Disposition: form-data` header with a `name` parameter.
+    MissingName,
+    /// A part had more than one `Content-Disposition` header, or more than one `name`
+    /// parameter.
+    DuplicateName,
+    /// A part had a `Content-Transfer-Encoding` header, which RFC 7578 deprecates.
+    ContentTransferEncoding,
+    /// A header line of a part ended with a bare LF instead of a CRLF.
+    BareLineFeed,
+    /// A boundary wasn't preceded by a CRLF, or wasn't followed by a CRLF or `--`.
+    InvalidDelimiter,
+    /// The body ended before the closing boundary.
+    MissingTerminator,
+}
+
+/// The error of a request body which breaks a rule of RFC 7578, read with
+/// [`Multipart::strict()`](struct.Multipart.html#method.strict).
+///
+/// Returned wrapped in an `io::Error` of kind `io::ErrorKind::InvalidData`, from which it can be
+/// recovered with `get_ref()` and `downcast_ref()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StrictViolation {
+    /// The index of the part with the violation, counting from 0.
+    ///
+    /// For violations of a boundary, this is the index of the part after it; for
+    /// `MissingTerminator`, it is the number of parts in the body.
+    pub part: usize,
+    /// The rule which was broken.
+    pub violation: Violation,
+}
+
+impl error::Error for StrictViolation {}
+
+impl fmt::Display for StrictViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self.violation {
+            Violation::MissingName => "multipart part has no form field name",
+            Violation::DuplicateName => "multipart part has more than one form field name",
+            Violation::ContentTransferEncoding => "multipart part has a Content-Transfer-Encoding",
+            Violation::BareLineFeed => "multipart part header ends with a bare LF",
+            Violation::InvalidDelimiter => "invalid multipart boundary line",
+            Violation::MissingTerminator => "multipart body has no closing boundary",
+        };
+
+        write!(f, "{} (part {})", msg, self.part)
+    }
+}
+
+impl From<StrictViolation> for io::Error {
+    fn from(err: StrictViolation) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// The strict validation state of a `Multipart`.
+#[derive(Debug, Default)]
+pub(crate) struct Strict {
+    enabled: bool,
+    /// The number of parts whose boundary has been read.
+    parts: usize,
+    /// Set once the closing boundary has been read.
+    ended: bool,
+}
+
+impl Strict {
+    /// Returns `true` if strict validation is enabled and the closing boundary has been read,
+    /// so anything after it is the epilogue.
+    pub(crate) fn is_ended(&self) -> bool {
+        self.enabled && self.ended
+    }
+}
+
+impl<B: Read> Multipart<B> {
+    /// Enforce the rules of RFC 7578 when reading the body, returning a
+    /// [`StrictViolation`](struct.StrictViolation.html) error at the first one broken, for
+    /// services which need to reject nonconforming clients consistently:
+    ///
+    /// * every boundary must be preceded by a CRLF (except the first) and followed by a CRLF, or
+    /// `--` for the closing boundary, which must be present;
+    /// * header lines must end with a CRLF;
+    /// * every part read with `read_entry()` must have exactly one `Content-Disposition:
+    /// form-data` header with exactly one `name` parameter, and no `Content-Transfer-Encoding`
+    /// header.
+    ///
+    /// Parts read with `read_part()` are only checked for the boundary rules. A preamble before
+    /// the first boundary is allowed, as RFC 2046 requires.
+    ///
+    /// Default value: `false`
+    ///
+    /// ```rust
+    /// use multipart::server::{Multipart, StrictViolation, Violation};
+    ///
+    /// let body: &[u8] = b"--boundary\r\n\
+    ///     Content-Disposition: form-data; name=\"a\"; name=\"b\"\r\n\r\n\
+    ///     value\r\n--boundary--\r\n";
+    ///
+    /// // Accepted by default.
+    /// assert!(Multipart::with_body(body, "boundary").read_entry().unwrap().is_some());
+    ///
+    /// let err = Multipart::with_body(body, "boundary").strict(true).read_entry().unwrap_err();
+    /// let err = err.get_ref().and_then(|err| err.downcast_ref::<StrictViolation>()).unwrap();
+    /// assert_eq!(*err, StrictViolation { part: 0, violation: Violation::DuplicateName });
+    /// ```
+    pub fn strict(&mut self, strict: bool) -> &mut Self {
+        self.strict.enabled = strict;
+        self
+    }
+
+    /// Check the delimiter of the next part, whose boundary was just skipped, and the two bytes
+    /// after it. Returns `Ok(true)` if a part follows.
+    pub(crate) fn check_delimiter(&mut self, found: bool, after: &[u8]) -> io::Result<bool> {
+        let strict = &mut self.strict;
+
+        if !strict.enabled {
+            return Ok(after == b"\r\n");
+        }
+
+        let violation = if !found || after.len() < 2 {
+            Violation::MissingTerminator
+        } else if strict.parts > 0 && !self.source.crlf_before_boundary() {
+            Violation::InvalidDelimiter
+        } else if after == b"\r\n" {
+            strict.parts += 1;
+            return Ok(true);
+        } else if after == b"--" {
+            strict.ended = true;
+            return Ok(false);
+        } else {
+            Violation::InvalidDelimiter
+        };
+
+        Err(StrictViolation { part: strict.parts, violation: violation }.into())
+    }
+
+    /// Check the header block of the current part, as read by `read_entry()`.
+    pub(crate) fn check_headers(&self, headers: &str) -> io::Result<()> {
+        if !self.strict.enabled {
+            return Ok(());
+        }
+
+        match header_violation(headers) {
+            Some(violation) => Err(StrictViolation {
+                part: self.strict.parts - 1,
+                violation: violation,
+            }.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+fn header_violation(headers: &str) -> Option<Violation> {
+    if headers.split('\n').rev().skip(1).any(|line| !line.ends_with('\r')) {
+        return Some(Violation::BareLineFeed);
+    }
+
+    if find_header(headers, "Content-Transfer-Encoding").is_some() {
+        return Some(Violation::ContentTransferEncoding);
+    }
+
+    let mut dispositions = headers.split("\r\n").filter(|line| {
+        line.find(':').map_or(false, |colon| line[..colon].trim().eq_ignore_ascii_case("Content-Disposition"))
+    });
+
+    let disposition = match (dispositions.next(), dispositions.next()) {
+        (Some(disposition), None) => &disposition[disposition.find(':').unwrap() + 1..],
+        (Some(_), Some(_)) => return Some(Violation::DuplicateName),
+        (None, _) => return Some(Violation::MissingName),
+    };
+
+    let mut params = split_params(disposition);
+
+    if !params.next().map_or(false, |disp_type| disp_type.trim().eq_ignore_ascii_case("form-data")) {
+        return Some(Violation::MissingName);
+    }
+
+    let names = params.filter(|param| {
+        param.find('=').map_or(false, |eq| param[..eq].trim().eq_ignore_ascii_case("name"))
+    }).count();
+
+    match names {
+        0 => Some(Violation::MissingName),
+        1 => None,
+        _ => Some(Violation::DuplicateName),
+    }
+}
+
+/// Split a header value at the semicolons which aren't in quoted strings.
+fn split_params(value: &str) -> ::std::vec::IntoIter<&str> {
+    let (mut params, mut start) = (Vec::new(), 0);
+    let (mut quoted, mut escaped) = (false, false);
+
+    for (idx, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                params.push(&value[start..idx]);
+                start = idx + 1;
+            },
+            _ => (),
+        }
+    }
+
+    params.push(&value[start..]);
+    params.into_iter()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{StrictViolation, Violation};
+
+    use server::Multipart;
+
+    fn violation(body: &[u8]) -> Option<StrictViolation> {
+        let mut multipart = Multipart::with_body(body, "boundary");
+        multipart.strict(true);
+
+        loop {
+            match multipart.read_entry() {
+                Ok(Some(_)) => (),
+                Ok(None) => return None,
+                Err(err) => return Some(*err.get_ref().unwrap().downcast_ref::<StrictViolation>().unwrap()),
+            }
+        }
+    }
+
+    fn assert_violation(body: &[u8], part: usize, violation: Violation) {
+        assert_eq!(self::violation(body), Some(StrictViolation { part: part, violation: violation }));
+    }
+
+    #[test]
+    fn test_strict() {
+        assert_eq!(violation(b"preamble\r\n--boundary\r\n\
+            Content-Disposition: form-data; name=\"a;b\"\r\n\r\n\
+            first\r\n--boundary\r\n\
+            content-disposition: form-data; filename=\"name=x\"; NAME=\"file\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            second\r\n--boundary--"), None);
+
+        assert_violation(b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+            first\r\n--boundary\r\n\
+            Content-Disposition: form-data; filename=\"file\"\r\n\r\n\
+            second\r\n--boundary--\r\n", 1, Violation::MissingName);
+
+        assert_violation(b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"a\"\r\n\
+            Content-Disposition: form-data; name=\"b\"\r\n\r\n\
+            first\r\n--boundary--\r\n", 0, Violation::DuplicateName);
+
+        assert_violation(b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"a\"\r\n\
+            Content-Transfer-Encoding: base64\r\n\r\n\
+            Zmlyc3Q=\r\n--boundary--\r\n", 0, Violation::ContentTransferEncoding);
+
+        assert_violation(b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"a\"\n\r\n\
+            first\r\n--boundary--\r\n", 0, Violation::BareLineFeed);
+
+        assert_violation(b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+            first\n--boundary\r\n\
+            Content-Disposition: form-data; name=\"b\"\r\n\r\n\
+            second\r\n--boundary--\r\n", 1, Violation::InvalidDelimiter);
+
+        assert_violation(b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+            first\r\n--boundary  \r\n", 1, Violation::InvalidDelimiter);
+
+        assert_violation(b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+            first\r\n", 1, Violation::MissingTerminator);
+    }
+
+    #[test]
+    fn test_display() {
+        let err = StrictViolation { part: 2, violation: Violation::MissingTerminator };
+        assert_eq!(err.to_string(), "multipart body has no closing boundary (part 2)");
+    }
+}
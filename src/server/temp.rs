@@ -0,0 +1,239 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Temporary directories with unguessable names, which only their owner can access.
+use std::fs::{self, DirBuilder};
+use std::path::{Path, PathBuf};
+use std::{env, io};
+
+/// The length of the random part of temporary directory names, for about 131 bits of entropy.
+const RANDOM_DIRNAME_LEN: usize = 22;
+
+/// The environment variable which, if set, overrides the directory temporary directories are
+/// created in by default.
+pub const TEMP_DIR_ENV: &'static str = "MULTIPART_TEMP_DIR";
+
+/// How many names are tried for a temporary directory before giving up.
+const NUM_RETRIES: u32 = 8;
+
+/// What happens to the directory of saved files when their
+/// [`Entries`](struct.Entries.html) are dropped; set with
+/// [`Entries::drop_policy()`](struct.Entries.html#method.drop_policy).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Delete the directory and its contents. The default for the `save_all*()` methods.
+    DeleteOnDrop,
+    /// Leave the directory on the filesystem.
+    Keep,
+    /// Rename the directory to this path, which must be on the same filesystem and must not
+    /// exist or be an empty directory. If renaming fails, the directory is left in place.
+    MoveTo(PathBuf),
+}
+
+/// A directory which is deleted, along with its contents, when this is dropped, unless it is
+/// set to be moved instead.
+///
+/// Its name is generated with the operating system's cryptographically secure random number
+/// generator, so it can't be guessed by other users of a shared system. On Unix, it is created
+/// with mode `0700` in the same call, so no other user can ever list or access its contents.
+#[derive(Debug)]
+pub struct TempDir {
+    path: Option<PathBuf>,
+    move_to: Option<PathBuf>,
+}
+
+impl TempDir {
+    /// Create a new temporary directory in the [temporary root](fn.temp_root.html), with a
+    /// name starting with `prefix`.
+    pub fn new(prefix: &str) -> io::Result<TempDir> {
+        TempDir::new_in(temp_root(), prefix)
+    }
+
+    /// Create a new temporary directory in `dir`, which must exist, with a name starting with
+    /// `prefix`.
+    pub fn new_in<P: AsRef<Path>>(dir: P, prefix: &str) -> io::Result<TempDir> {
+        let dir = dir.as_ref();
+        // Keep the directory reachable if the working directory changes.
+        let dir = if dir.is_relative() { try!(env::current_dir()).join(dir) } else { dir.to_owned() };
+
+        for _ in 0..NUM_RETRIES {
+            let name = format!("{}.{}", prefix, try!(::secure_random_alphanumeric(RANDOM_DIRNAME_LEN)));
+            let path = dir.join(name);
+
+            match create_private_dir(&path) {
+                Ok(()) => return Ok(TempDir::adopt(path)),
+                Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::AlreadyExists, "too many temporary directories already exist"))
+    }
+
+    /// Take ownership of an existing directory, deleting it when this is dropped.
+    pub(crate) fn adopt(path: PathBuf) -> TempDir {
+        TempDir {
+            path: Some(path),
+            move_to: None,
+        }
+    }
+
+    /// Rename this directory to `dest` when this is dropped, rather than deleting it.
+    ///
+    /// `dest` must be on the same filesystem and must not exist or be an empty directory. If
+    /// renaming fails, the directory is left in place.
+    pub fn move_on_drop<P: Into<PathBuf>>(&mut self, dest: P) {
+        self.move_to = Some(dest.into());
+    }
+
+    /// Get the path of this directory.
+    pub fn path(&self) -> &Path {
+        self.path.as_ref().expect("TempDir already closed")
+    }
+
+    /// Keep this directory on the filesystem, returning its path.
+    pub fn into_path(mut self) -> PathBuf {
+        self.path.take().expect("TempDir already closed")
+    }
+
+    /// Delete this directory and its contents, returning any error.
+    pub fn close(mut self) -> io::Result<()> {
+        fs::remove_dir_all(self.path.take().expect("TempDir already closed"))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let path = match self.path.take() {
+            Some(path) => path,
+            None => return,
+        };
+
+        match self.move_to.take() {
+            Some(dest) => if let Err(err) = fs::rename(&path, &dest) {
+                warn!("Failed to move {:?} to {:?}, leaving it in place: {}", path, dest, err);
+            },
+            None => {
+                let _ = fs::remove_dir_all(&path);
+            },
+        }
+    }
+}
+
+/// Get the directory temporary directories are created in by default: the value of the
+/// `MULTIPART_TEMP_DIR` environment variable if it is set, or the OS temporary directory.
+///
+/// Use this to keep uploads on a specific volume, such as in containers. The directory must
+/// exist.
+pub fn temp_root() -> PathBuf {
+    match env::var_os(TEMP_DIR_ENV) {
+        Some(ref dir) if !dir.is_empty() => dir.into(),
+        _ => env::temp_dir(),
+    }
+}
+
+#[cfg(unix)]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    DirBuilder::new().mode(0o700).create(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    DirBuilder::new().create(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DropPolicy, TempDir};
+
+    use secure_random_alphanumeric;
+
+    use server::Multipart;
+
+    use std::fs;
+
+    const BODY: &[u8] = b"--boundary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        contents\r\n--boundary--\r\n";
+
+    #[test]
+    fn test_secure_random_alphanumeric() {
+        let string = secure_random_alphanumeric(100).unwrap();
+        assert_eq!(string.len(), 100);
+        assert!(string.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert!(string != secure_random_alphanumeric(100).unwrap());
+    }
+
+    #[test]
+    fn test_temp_dir() {
+        let dir = TempDir::new("multipart-test").unwrap();
+        let path = dir.path().to_owned();
+
+        assert!(path.is_dir());
+        assert!(path.file_name().unwrap().to_str().unwrap().starts_with("multipart-test."));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(path.metadata().unwrap().permissions().mode() & 0o777, 0o700);
+        }
+
+        drop(dir);
+        assert!(!path.exists());
+
+        let path = TempDir::new("multipart-test").unwrap().into_path();
+        assert!(path.is_dir());
+        fs::remove_dir(path).unwrap();
+    }
+
+    #[test]
+    fn test_multipart_temp_root() {
+        let root = TempDir::new("multipart-root").unwrap();
+
+        let entries = Multipart::with_body(BODY, "boundary").temp_root(root.path()).save_all()
+            .to_result().unwrap();
+
+        assert_eq!(entries.dir.as_path().parent().unwrap(), root.path());
+        assert!(entries.files["file"].path.starts_with(root.path()));
+    }
+
+    #[test]
+    fn test_drop_policy() {
+        let root = TempDir::new("multipart-root").unwrap();
+        let save = || Multipart::with_body(BODY, "boundary").temp_root(root.path()).save_all()
+            .to_result().unwrap();
+
+        let entries = save();
+        let dir = entries.dir.as_path().to_owned();
+        drop(entries);
+        assert!(!dir.exists());
+
+        let mut entries = save();
+        let dir = entries.dir.as_path().to_owned();
+        entries.drop_policy(DropPolicy::Keep);
+        drop(entries);
+        assert!(dir.is_dir());
+
+        let mut entries = save();
+        let dest = root.path().join("moved");
+        entries.drop_policy(DropPolicy::MoveTo(dest.clone()));
+        drop(entries);
+        assert_eq!(fs::read_dir(&dest).unwrap().count(), 1);
+
+        let mut entries = save();
+        let dir = entries.dir.as_path().to_owned();
+        let dest = root.path().join("persisted");
+        entries.persist(&dest).unwrap();
+        assert!(!dir.exists());
+        assert!(!entries.dir.is_temporary());
+        assert_eq!(fs::read(&entries.files["file"].path).unwrap(), b"contents");
+        assert!(entries.files["file"].path.starts_with(&dest));
+        drop(entries);
+        assert!(dest.is_dir());
+    }
+}
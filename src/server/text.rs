@@ -0,0 +1,99 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Handling of text fields which aren't valid UTF-8.
+use std::{error, fmt, io};
+
+/// How text fields which aren't valid UTF-8 are read by
+/// [`Multipart::read_entry()`](struct.Multipart.html#method.read_entry); set with
+/// [`Multipart::utf8_policy()`](struct.Multipart.html#method.utf8_policy).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Utf8Policy {
+    /// Fail with an [`InvalidUtf8`](struct.InvalidUtf8.html) error. The default.
+    Strict,
+    /// Replace invalid sequences with U+FFFD REPLACEMENT CHARACTER.
+    Lossy,
+    /// Return the field as `MultipartData::Bytes`.
+    ///
+    /// The `save_all*()` methods keep such fields as if they were read with `Lossy`.
+    Raw,
+}
+
+impl Default for Utf8Policy {
+    fn default() -> Self {
+        Utf8Policy::Strict
+    }
+}
+
+/// The error of a text field which isn't valid UTF-8, with `Utf8Policy::Strict`.
+///
+/// Returned wrapped in an `io::Error` of kind `io::ErrorKind::InvalidData`, from which it can be
+/// recovered with `get_ref()` and `downcast_ref()`.
+#[derive(Clone, Debug)]
+pub struct InvalidUtf8 {
+    /// The name of the field.
+    pub field_name: String,
+    /// The length of the valid UTF-8 at the start of the field, in bytes.
+    pub valid_up_to: usize,
+}
+
+impl error::Error for InvalidUtf8 {
+    fn description(&self) -> &str {
+        "text field is not valid UTF-8"
+    }
+}
+
+impl fmt::Display for InvalidUtf8 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "text field {:?} is not valid UTF-8 after {} bytes", self.field_name, self.valid_up_to)
+    }
+}
+
+impl From<InvalidUtf8> for io::Error {
+    fn from(err: InvalidUtf8) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{InvalidUtf8, Utf8Policy};
+
+    use server::{Multipart, MultipartData};
+
+    const BODY: &[u8] = b"--boundary\r\n\
+        Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+        caf\xe9\r\n--boundary--\r\n";
+
+    #[test]
+    fn test_utf8_policy() {
+        let mut multipart = Multipart::with_body(BODY, "boundary");
+        let err = multipart.read_entry().unwrap_err();
+        let err = err.get_ref().and_then(|err| err.downcast_ref::<InvalidUtf8>()).unwrap();
+        assert_eq!(err.field_name, "text");
+        assert_eq!(err.valid_up_to, 3);
+
+        let mut multipart = Multipart::with_body(BODY, "boundary");
+        multipart.utf8_policy(Utf8Policy::Lossy);
+        let field = multipart.read_entry().unwrap().unwrap();
+        assert_eq!(field.data.as_text(), Some("caf\u{fffd}"));
+
+        let mut multipart = Multipart::with_body(BODY, "boundary");
+        multipart.utf8_policy(Utf8Policy::Raw);
+        {
+            let field = multipart.read_entry().unwrap().unwrap();
+            match field.data {
+                MultipartData::Bytes(bytes) => assert_eq!(bytes, b"caf\xe9"),
+                data => panic!("expected raw bytes, got {:?}", data),
+            }
+        }
+
+        let mut multipart = Multipart::with_body(BODY, "boundary");
+        multipart.utf8_policy(Utf8Policy::Raw);
+        let entries = multipart.save_all_to(::server::storage::MemoryStorage).unwrap();
+        assert_eq!(entries.fields["text"], "caf\u{fffd}");
+    }
+}
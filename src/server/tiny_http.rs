@@ -6,7 +6,9 @@
 
 pub use tiny_http::Request as TinyHttpRequest;
 
-use super::HttpRequest;
+use tiny_http::Method;
+
+use super::{accepts_subtype, parse_boundary, HttpRequest};
 
 use std::io::Read;
 
@@ -14,13 +16,32 @@ impl<'r> HttpRequest for &'r mut TinyHttpRequest {
     type Body = &'r mut Read;
     
     fn multipart_boundary(&self) -> Option<&str> {
-        const BOUNDARY: &'static str = "boundary=";
+        let content_type = try_opt!(self.headers().iter().find(|header| header.field.equiv("Content-Type"))).value.as_str();
+        parse_boundary(content_type)
+    }
+
+    fn multipart_boundary_for(&self, methods: &[&str]) -> Option<&str> {
+        let method = self.method();
+
+        if !methods.iter().any(|name| name.parse::<Method>().ok().as_ref() == Some(method)) {
+            return None;
+        }
 
+        self.multipart_boundary()
+    }
+
+    fn multipart_boundary_accepting(&self, methods: &[&str], subtypes: &[&str]) -> Option<&str> {
         let content_type = try_opt!(self.headers().iter().find(|header| header.field.equiv("Content-Type"))).value.as_str();
-        let start = try_opt!(content_type.find(BOUNDARY)) + BOUNDARY.len();
-        let end = content_type[start..].find(';').map_or(content_type.len(), |end| start + end);
+        let mut essence = content_type.split(';').next().unwrap_or("").trim().splitn(2, '/');
+
+        let top = try_opt!(essence.next());
+        let sub = try_opt!(essence.next());
+
+        if !top.eq_ignore_ascii_case("multipart") || !accepts_subtype(subtypes, sub) {
+            return None;
+        }
 
-        Some(&content_type[start .. end])
+        self.multipart_boundary_for(methods)
     }
 
     fn body(self) -> Self::Body {
@@ -0,0 +1,206 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Saving asynchronously parsed requests with [tokio](https://tokio.rs)'s file system API.
+//! Enabled with the `tokio_` feature (optional).
+//!
+//! [`StreamMultipart`](../stream/struct.StreamMultipart.html) can be saved with blocking file
+//! I/O, but that stalls the reactor while large uploads are spooled to disk. The functions here
+//! write files with `tokio::fs::File` instead, which hands the blocking writes to tokio's
+//! blocking thread pool.
+//!
+//! ```no_run
+//! # extern crate futures;
+//! # #[cfg(feature = "hyper1_")]
+//! # extern crate hyper1 as hyper;
+//! # extern crate multipart;
+//! # #[cfg(feature = "hyper1_")]
+//! # mod example {
+//! use futures::future::{self, Either, Future, FutureExt};
+//! use hyper::{Request, Response};
+//! use hyper::body::Incoming;
+//!
+//! use multipart::server::hyper1::stream_request;
+//! use multipart::server::tokio::save_all;
+//!
+//! fn handle(req: Request<Incoming>) -> impl Future<Output = Response<String>> {
+//!     match stream_request(req) {
+//!         Ok(multipart) => Either::Left(save_all(multipart).map(|res| match res.to_result() {
+//!             Ok(entries) => Response::new(format!("Saved files: {:?}", entries.files)),
+//!             Err(err) => Response::new(format!("Error saving request: {}", err)),
+//!         })),
+//!         Err(_) => Either::Right(future::ready(Response::new("Not multipart".into()))),
+//!     }
+//! }
+//! # }
+//! # fn main() {}
+//! ```
+//!
+//! Bodies implementing tokio's `AsyncRead` rather than that of `futures` can be parsed with
+//! [`stream_reader()`](fn.stream_reader.html), which wraps them in [`Compat`](struct.Compat.html).
+//!
+//! For servers which still use the blocking [`Multipart`](../struct.Multipart.html),
+//! [`run_blocking()`](fn.run_blocking.html) and [`save_all_blocking()`](fn.save_all_blocking.html)
+//! move it onto tokio's blocking thread pool, so it can be awaited without blocking the reactor.
+use bytes::Bytes;
+
+use futures::future::{self, Future, FutureExt, TryFutureExt};
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::stream::Stream;
+
+use tokio::fs::File;
+use tokio::io::{AsyncRead as TokioAsyncRead, AsyncWrite as TokioAsyncWrite, ReadBuf};
+
+use std::error::Error;
+use std::io::{self, Read};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::{Multipart, SaveResult};
+use super::stream::{ReaderStream, StreamMultipart};
+
+/// Construct a new `StreamMultipart` reading the body from `reader`, which implements tokio's
+/// `AsyncRead`, such as a `tokio::net::TcpStream`.
+///
+/// The boundary is as it appears in the `Content-Type` header, without the leading `"--"`.
+pub fn stream_reader<R, Bnd>(reader: R, boundary: Bnd) -> StreamMultipart<ReaderStream<Compat<R>>>
+where R: TokioAsyncRead + Unpin, Bnd: Into<String> {
+    StreamMultipart::from_reader(Compat(reader), boundary)
+}
+
+/// Read the request fully, saving text fields in memory and files in a new temporary directory
+/// under the [temporary root](../fn.temp_root.html), using `tokio::fs`.
+///
+/// See [`StreamMultipart::save_all_with()`](../stream/struct.StreamMultipart.html#method.save_all_with)
+/// for more information.
+pub fn save_all<S, E>(multipart: StreamMultipart<S>) -> impl Future<Output = SaveResult>
+where S: Stream<Item = Result<Bytes, E>> + Unpin, E: Into<Box<Error + Send + Sync>> {
+    multipart.save_all_with(create_file)
+}
+
+/// Read the request fully, saving text fields in memory and files in a new temporary directory
+/// under `dir`, using `tokio::fs`.
+///
+/// See [`StreamMultipart::save_all_with()`](../stream/struct.StreamMultipart.html#method.save_all_with)
+/// for more information.
+pub fn save_all_under<S, E, P>(multipart: StreamMultipart<S>, dir: P) -> impl Future<Output = SaveResult>
+where S: Stream<Item = Result<Bytes, E>> + Unpin, E: Into<Box<Error + Send + Sync>>, P: AsRef<Path> {
+    multipart.save_all_under_with(dir, create_file)
+}
+
+/// Run `f` with `multipart` on tokio's blocking thread pool, resolving to its result.
+///
+/// The blocking `Multipart` reads from a `Read`, such as the buffered body from
+/// [`hyper1::read_request()`](../hyper1/fn.read_request.html); this is an alternative to
+/// [`StreamMultipart`](../stream/struct.StreamMultipart.html) for code which is written against
+/// the blocking API.
+///
+/// ## Panics
+/// If the returned future is polled outside of a tokio runtime. If `f` panics, the panic is
+/// resumed when the returned future is polled.
+pub fn run_blocking<B, F, T>(multipart: Multipart<B>, f: F) -> impl Future<Output = T>
+where B: Read + Send + 'static, F: FnOnce(Multipart<B>) -> T + Send + 'static, T: Send + 'static {
+    // Spawned when first polled, so the future may be created outside of the runtime.
+    future::lazy(move |_| ::tokio::task::spawn_blocking(move || f(multipart))).flatten().map(|res| match res {
+        Ok(val) => val,
+        Err(err) => match err.try_into_panic() {
+            Ok(payload) => panic::resume_unwind(payload),
+            Err(_) => panic!("blocking multipart task was cancelled"),
+        },
+    })
+}
+
+/// Run [`Multipart::save_all()`](../struct.Multipart.html#method.save_all) on tokio's blocking
+/// thread pool, resolving to its result.
+///
+/// ## Panics
+/// If the returned future is polled outside of a tokio runtime.
+pub fn save_all_blocking<B>(multipart: Multipart<B>) -> impl Future<Output = SaveResult>
+where B: Read + Send + 'static {
+    run_blocking(multipart, |mut multipart| multipart.save_all())
+}
+
+fn create_file(path: PathBuf) -> impl Future<Output = io::Result<Compat<File>>> {
+    File::create(path).map_ok(Compat)
+}
+
+/// Adapts tokio's `AsyncRead` and `AsyncWrite` to those of `futures::io`.
+#[derive(Debug)]
+pub struct Compat<T>(pub T);
+
+impl<T: TokioAsyncRead + Unpin> AsyncRead for Compat<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+
+        match Pin::new(&mut self.get_mut().0).poll_read(cx, &mut buf) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(buf.filled().len())),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T: TokioAsyncWrite + Unpin> AsyncWrite for Compat<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        // For `tokio::fs::File`, this waits for the pending write to complete.
+        Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use futures::stream;
+
+    use tokio::runtime::Builder;
+
+    use std::fs;
+
+    #[test]
+    fn test_save_all() {
+        let _ = ::env_logger::init();
+
+        let contents: Vec<u8> = (0 .. 100 * 1024).map(|i| (i % 251) as u8).collect();
+
+        let mut body = b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+            text value\r\n--boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"file.bin\"\r\n\
+            Content-Type: application/octet-stream\r\n\r\n".to_vec();
+        body.extend_from_slice(&contents);
+        body.extend_from_slice(b"\r\n--boundary--\r\n");
+
+        let chunks: Vec<_> = body.chunks(4096)
+            .map(|chunk| Ok::<_, io::Error>(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let multipart = StreamMultipart::with_body(stream::iter(chunks), "boundary");
+
+        let dir = ::tempdir::TempDir::new("multipart_tokio").unwrap();
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let entries = match runtime.block_on(save_all_under(multipart, dir.path())) {
+            SaveResult::Full(entries) => entries,
+            res => panic!("failed to save request: {:?}", res),
+        };
+
+        assert_eq!(entries.fields["text"], "text value");
+
+        let file = &entries.files["file"];
+        assert_eq!(file.size, contents.len() as u64);
+        assert!(file.path.starts_with(dir.path()));
+        assert_eq!(fs::read(&file.path).unwrap(), contents);
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Saving asynchronously parsed requests with io_uring via
+//! [tokio-uring](https://github.com/tokio-rs/tokio-uring), on Linux only. Enabled with the
+//! `tokio-uring_` feature (optional).
+//!
+//! Files are written with io_uring submissions rather than `write()` calls on a blocking thread
+//! pool, which reduces syscall overhead for servers receiving many large uploads. The futures
+//! returned here must be run within `tokio_uring::start()`.
+//!
+//! ```no_run
+//! # extern crate futures;
+//! # extern crate multipart;
+//! # extern crate tokio_uring;
+//! use multipart::server::stream::StreamMultipart;
+//! use multipart::server::tokio_uring::save_all;
+//!
+//! # fn main() {
+//! let body = std::io::Cursor::new(&b"--boundary--"[..]);
+//! let multipart = StreamMultipart::from_reader(futures::io::AllowStdIo::new(body), "boundary");
+//!
+//! let entries = tokio_uring::start(save_all(multipart)).to_result().unwrap();
+//! println!("Saved files: {:?}", entries.files);
+//! # }
+//! ```
+use bytes::Bytes;
+
+use futures::future::{Future, TryFutureExt};
+use futures::io::AsyncWrite;
+use futures::stream::Stream;
+
+use tokio_uring::fs::File;
+
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::SaveResult;
+use super::stream::StreamMultipart;
+
+/// Read the request fully, saving text fields in memory and files in a new temporary directory
+/// under the [temporary root](../fn.temp_root.html), using io_uring.
+///
+/// See [`StreamMultipart::save_all_with()`](../stream/struct.StreamMultipart.html#method.save_all_with)
+/// for more information.
+pub fn save_all<S, E>(multipart: StreamMultipart<S>) -> impl Future<Output = SaveResult>
+where S: Stream<Item = Result<Bytes, E>> + Unpin, E: Into<Box<Error + Send + Sync>> {
+    multipart.save_all_with(create_file)
+}
+
+/// Read the request fully, saving text fields in memory and files in a new temporary directory
+/// under `dir`, using io_uring.
+///
+/// See [`StreamMultipart::save_all_with()`](../stream/struct.StreamMultipart.html#method.save_all_with)
+/// for more information.
+pub fn save_all_under<S, E, P>(multipart: StreamMultipart<S>, dir: P) -> impl Future<Output = SaveResult>
+where S: Stream<Item = Result<Bytes, E>> + Unpin, E: Into<Box<Error + Send + Sync>>, P: AsRef<Path> {
+    multipart.save_all_under_with(dir, create_file)
+}
+
+fn create_file(path: PathBuf) -> impl Future<Output = io::Result<UringFile>> {
+    File::create(path).map_ok(UringFile::new)
+}
+
+type WriteAt<'a> = Pin<Box<Future<Output = (io::Result<usize>, Vec<u8>)> + 'a>>;
+
+self_cell!(
+    /// A write in flight, which owns the file it borrows, so the file lives as long as the write
+    /// and is handed back when it completes.
+    struct WriteOp {
+        owner: File,
+
+        #[covariant]
+        dependent: WriteAt,
+    }
+);
+
+/// Adapts `tokio_uring::fs::File`, whose writes take ownership of a buffer, to
+/// `futures::io::AsyncWrite`.
+///
+/// Like `tokio::fs::File`, each write is submitted in the background and `poll_write()` returns
+/// immediately; errors are reported by the next call, or by `poll_flush()` or `poll_close()`.
+struct UringFile {
+    /// The file while no write is in flight.
+    file: Option<File>,
+    /// The write in flight, if any, which holds the file until it completes.
+    op: Option<WriteOp>,
+    /// The offset at which the data of `op` is written.
+    op_pos: u64,
+    /// The offset at which the next write starts.
+    pos: u64,
+    /// A buffer to reuse for the next write.
+    buf: Vec<u8>,
+}
+
+impl UringFile {
+    fn new(file: File) -> Self {
+        UringFile {
+            file: Some(file),
+            op: None,
+            op_pos: 0,
+            pos: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Submit a write of `buf` at `pos`; the previous write must have completed.
+    fn start_write(&mut self, buf: Vec<u8>, pos: u64) {
+        let file = self.file.take().expect("a write is already in flight");
+
+        self.op_pos = pos;
+        self.op = Some(WriteOp::new(file, move |file| Box::pin(file.write_at(buf, pos))));
+    }
+
+    /// Drive the write in flight, if any, to completion, resubmitting the remainder of
+    /// short writes.
+    fn poll_op(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        loop {
+            let (res, mut buf) = match self.op {
+                Some(ref mut op) => match op.with_dependent_mut(|_, write| write.as_mut().poll(cx)) {
+                    Poll::Ready(done) => done,
+                    Poll::Pending => return Poll::Pending,
+                },
+                None => return Poll::Ready(Ok(())),
+            };
+
+            self.file = self.op.take().map(WriteOp::into_owner);
+
+            let written = match res {
+                Ok(0) => return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::WriteZero, "failed to write field data to file"
+                ))),
+                Ok(written) => written,
+                Err(err) => return Poll::Ready(Err(err)),
+            };
+
+            if written < buf.len() {
+                buf.drain(..written);
+                let pos = self.op_pos + written as u64;
+                self.start_write(buf, pos);
+            } else {
+                buf.clear();
+                self.buf = buf;
+            }
+        }
+    }
+}
+
+impl AsyncWrite for UringFile {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, data: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        match this.poll_op(cx) {
+            Poll::Ready(Ok(())) => (),
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        }
+
+        let mut buf = ::std::mem::replace(&mut this.buf, Vec::new());
+        buf.extend_from_slice(data);
+
+        let pos = this.pos;
+        this.pos += data.len() as u64;
+        this.start_write(buf, pos);
+
+        Poll::Ready(Ok(data.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.get_mut().poll_op(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        // The file is closed when dropped.
+        self.get_mut().poll_op(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::io::AllowStdIo;
+
+    use tempdir::TempDir;
+
+    use tokio_uring;
+
+    use std::fs;
+    use std::io::Cursor;
+
+    use server::stream::StreamMultipart;
+
+    use super::save_all_under;
+
+    #[test]
+    fn test_save_all() {
+        let contents = vec![b'a'; 100 * 1024];
+        let mut body = b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"file.bin\"\r\n\
+            Content-Type: application/octet-stream\r\n\r\n".to_vec();
+        body.extend_from_slice(&contents);
+        body.extend_from_slice(b"\r\n--boundary--\r\n");
+
+        let dir = TempDir::new("multipart-test").unwrap();
+        let multipart = StreamMultipart::from_reader(AllowStdIo::new(Cursor::new(body)), "boundary");
+
+        let entries = tokio_uring::start(save_all_under(multipart, dir.path())).to_result().unwrap();
+        assert_eq!(fs::read(&entries.files["file"].path).unwrap(), contents);
+    }
+}
@@ -0,0 +1,318 @@
+// Copyright 2016 `multipart` Crate Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! Integration with [Tower](https://github.com/tower-rs/tower) via the `tower_` feature
+//! (optional, enables the `http` feature). Includes a `Layer` and `Service` implementation.
+//!
+//! [`InterceptLayer`](struct.InterceptLayer.html) wraps any service accepting `http::Request`s,
+//! so it can be dropped into any Tower-based stack (Hyper, Axum, Tonic, etc.).
+//!
+//! Successful reads will be placed in the request extensions as an `Arc<Entries>`
+//! (extensions must be `Clone`), and the body handed to the inner service will be empty:
+//!
+//! ```no_run
+//! # extern crate http;
+//! # extern crate http_body;
+//! # extern crate multipart;
+//! # extern crate tower_layer;
+//! use http::Request;
+//! use multipart::server::Entries;
+//! use multipart::server::tower::{InterceptBody, InterceptLayer};
+//! use std::sync::Arc;
+//! use tower_layer::Layer;
+//!
+//! # fn wrap<S, B>(inner: S) {
+//! let service = InterceptLayer::default().file_size_limit(16 * 1024 * 1024).layer(inner);
+//!
+//! // In the inner service:
+//! fn handle<B: http_body::Body>(req: Request<InterceptBody<B>>) {
+//!     if let Some(entries) = req.extensions().get::<Arc<Entries>>() {
+//!         println!("{:?}", entries);
+//!     }
+//! }
+//! # }
+//! # fn main() {}
+//! ```
+//!
+//! The body is collected in memory, up to `body_size_limit` bytes, and the files are then saved
+//! on tokio's blocking thread pool, so the service must be called within a tokio runtime.
+//!
+//! If the request body is longer than `body_size_limit`, a `413 Payload Too Large` response with
+//! an empty body is returned without calling the inner service. If it cannot be read or parsed,
+//! a `400 Bad Request` response is returned instead.
+use bytes::Bytes;
+
+use futures::future::{self, Either, Future, FutureExt};
+
+use http::{Request, Response, StatusCode};
+
+use http_body::Body;
+
+use http_body_util::{BodyExt, Empty, LengthLimitError, Limited};
+
+use tower_layer::Layer;
+use tower_service::Service;
+
+use std::error::Error;
+use std::io;
+use std::mem;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use super::{Entries, Multipart, SaveResult};
+use super::tokio::run_blocking;
+
+/// The default file size limit for [`InterceptLayer`](struct.InterceptLayer.html), in bytes.
+pub const DEFAULT_FILE_SIZE_LIMIT: u64 = 2 * 1024 * 1024;
+
+/// The default request body size limit for [`InterceptLayer`](struct.InterceptLayer.html), in
+/// bytes.
+pub const DEFAULT_BODY_SIZE_LIMIT: usize = 16 * 1024 * 1024;
+
+/// The request body passed to the inner service of [`Intercept`](struct.Intercept.html).
+///
+/// Requests which were not intercepted keep their original body (`Left`), while intercepted
+/// requests have had their body consumed (`Right`).
+pub type InterceptBody<B> = ::http_body_util::Either<B, Empty<<B as Body>::Data>>;
+
+/// A Tower `Layer` which intercepts multipart requests, saves their contents and places the
+/// result in the request extensions as `Arc<Entries>`.
+#[derive(Clone, Debug)]
+pub struct InterceptLayer {
+    /// The parent directory for all temporary directories created by this middleware.
+    /// Will be created if it doesn't exist (lazy).
+    ///
+    /// If omitted, uses the [temporary root](../fn.temp_root.html).
+    ///
+    /// Default value: `None`.
+    pub temp_dir_path: Option<PathBuf>,
+    /// The size limit of uploaded files, in bytes. Files which exceed this size will be truncated.
+    ///
+    /// Default value: [`DEFAULT_FILE_SIZE_LIMIT`](constant.DEFAULT_FILE_SIZE_LIMIT.html)
+    pub file_size_limit: u64,
+    /// The size limit of the whole request body, in bytes, which is collected in memory before
+    /// it is parsed.
+    ///
+    /// Default value: [`DEFAULT_BODY_SIZE_LIMIT`](constant.DEFAULT_BODY_SIZE_LIMIT.html)
+    pub body_size_limit: usize,
+}
+
+impl InterceptLayer {
+    /// Set the `temp_dir_path` for this middleware.
+    pub fn temp_dir_path<P: Into<PathBuf>>(self, path: P) -> Self {
+        InterceptLayer { temp_dir_path: Some(path.into()), .. self }
+    }
+
+    /// Set the `file_size_limit` for this middleware.
+    pub fn file_size_limit(self, limit: u64) -> Self {
+        InterceptLayer { file_size_limit: limit, .. self }
+    }
+
+    /// Set the `body_size_limit` for this middleware.
+    pub fn body_size_limit(self, limit: usize) -> Self {
+        InterceptLayer { body_size_limit: limit, .. self }
+    }
+
+    fn read_entries(&self, multipart: &mut Multipart<io::Cursor<Bytes>>) -> io::Result<Entries> {
+        let result = match self.temp_dir_path {
+            Some(ref dir) => multipart.save_all_under_limited(dir, self.file_size_limit),
+            None => multipart.save_all_limited(self.file_size_limit),
+        };
+
+        match result {
+            SaveResult::Full(entries) => Ok(entries),
+            SaveResult::Partial(_, err) | SaveResult::Error(err) => Err(err),
+        }
+    }
+}
+
+impl Default for InterceptLayer {
+    fn default() -> Self {
+        InterceptLayer {
+            temp_dir_path: None,
+            file_size_limit: DEFAULT_FILE_SIZE_LIMIT,
+            body_size_limit: DEFAULT_BODY_SIZE_LIMIT,
+        }
+    }
+}
+
+impl<S> Layer<S> for InterceptLayer {
+    type Service = Intercept<S>;
+
+    fn layer(&self, inner: S) -> Intercept<S> {
+        Intercept {
+            inner: inner,
+            config: Arc::new(self.clone()),
+        }
+    }
+}
+
+/// The `Service` created by [`InterceptLayer`](struct.InterceptLayer.html).
+#[derive(Clone, Debug)]
+pub struct Intercept<S> {
+    inner: S,
+    config: Arc<InterceptLayer>,
+}
+
+impl<S> Intercept<S> {
+    /// Get a reference to the wrapped service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Unwrap the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, B, ResBody> Service<Request<B>> for Intercept<S>
+where S: Service<Request<InterceptBody<B>>, Response = Response<ResBody>> + Clone + Send + 'static,
+      S::Future: Send + 'static, S::Error: Send + 'static,
+      B: Body + Send + 'static, B::Data: Send, B::Error: Into<Box<Error + Send + Sync>>,
+      ResBody: Default + Send + 'static {
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<Future<Output = Result<Response<ResBody>, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let boundary = match super::http::request_boundary(&req).map(String::from) {
+            Some(boundary) => boundary,
+            None => return self.inner.call(req.map(InterceptBody::Left)).boxed(),
+        };
+
+        // The clone is not guaranteed to be ready, so we keep it and take the ready service.
+        let clone = self.inner.clone();
+        let mut inner = mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+
+        let (parts, body) = req.into_parts();
+
+        Limited::new(body, config.body_size_limit).collect().then(move |res| {
+            let body = match res {
+                Ok(collected) => collected.to_bytes(),
+                Err(err) => {
+                    warn!("Error reading multipart request body: {}", err);
+
+                    let status = if err.is::<LengthLimitError>() {
+                        StatusCode::PAYLOAD_TOO_LARGE
+                    } else {
+                        StatusCode::BAD_REQUEST
+                    };
+
+                    return Either::Right(future::ok(error_response(status)));
+                },
+            };
+
+            let multipart = Multipart::with_body(io::Cursor::new(body), boundary);
+
+            let entries = run_blocking(multipart, move |mut multipart| config.read_entries(&mut multipart));
+
+            Either::Left(entries.then(move |entries| match entries {
+                Ok(entries) => {
+                    let mut req = Request::from_parts(parts, InterceptBody::Right(Empty::new()));
+                    req.extensions_mut().insert(Arc::new(entries));
+                    Either::Left(inner.call(req))
+                },
+                Err(err) => {
+                    warn!("Error reading multipart request: {}", err);
+                    Either::Right(future::ok(error_response(StatusCode::BAD_REQUEST)))
+                },
+            }))
+        }).boxed()
+    }
+}
+
+fn error_response<B: Default>(status: StatusCode) -> Response<B> {
+    let mut res = Response::new(B::default());
+    *res.status_mut() = status;
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use futures::future::{self, Ready};
+
+    use http::{Request, Response, StatusCode};
+
+    use http_body_util::Full;
+
+    use tokio::runtime::Builder;
+
+    use tower_layer::Layer;
+    use tower_service::Service;
+
+    use std::convert::Infallible;
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+
+    use server::Entries;
+
+    use super::{InterceptBody, InterceptLayer};
+
+    const BODY: &'static str = "--boundary\r\n\
+        Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+        value\r\n--boundary--\r\n";
+
+    #[derive(Clone)]
+    struct Inner;
+
+    impl Service<Request<InterceptBody<Full<Bytes>>>> for Inner {
+        type Response = Response<String>;
+        type Error = Infallible;
+        type Future = Ready<Result<Response<String>, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request<InterceptBody<Full<Bytes>>>) -> Self::Future {
+            let body = match req.extensions().get::<Arc<Entries>>() {
+                Some(entries) => format!("{:?}", entries.fields.get("text")),
+                None => "not intercepted".to_string(),
+            };
+
+            future::ok(Response::new(body))
+        }
+    }
+
+    fn request<T: Into<Bytes>>(body: T) -> Request<Full<Bytes>> {
+        Request::post("/")
+            .header("Content-Type", "multipart/form-data; boundary=boundary")
+            .body(Full::new(body.into()))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_intercept() {
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let mut service = InterceptLayer::default().body_size_limit(16 * 1024).layer(Inner);
+
+        let res = runtime.block_on(service.call(request(BODY))).unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.body(), "Some(\"value\")");
+
+        let res = runtime.block_on(service.call(Request::get("/").body(Full::new(Bytes::new())).unwrap()))
+            .unwrap();
+        assert_eq!(res.body(), "not intercepted");
+
+        let headers_too_large = format!("--boundary\r\nX-Padding: {}\r\n\r\n", "a".repeat(10000));
+        let res = runtime.block_on(service.call(request(headers_too_large))).unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let mut service = InterceptLayer::default().body_size_limit(BODY.len() - 1).layer(Inner);
+        let res = runtime.block_on(service.call(request(BODY))).unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}